@@ -1,63 +1,157 @@
-use crate::common::metric::{Metric, MetricRawUnit, MetricUnit, MetricValue, OrderOfMagnitude};
-use std::collections::{HashMap, VecDeque};
-
+use crate::aggregator::alert::{Alert, AlertCondition, AlertInput, AlertRule};
+use crate::aggregator::expression::{DerivedMetric, Selector, Series};
+use crate::aggregator::history::{HistoryConfig, MetricHistory, RateWindow, RollupSpec, CLOCK_STEP_TOLERANCE_US};
+use crate::aggregator::user_rule::UserMetricRule;
+use crate::export::{ExportOptions, ExportScope, ExportSeries};
+use crate::common::message::MetricCollection;
+use crate::common::metric::{Metric, MetricKind, MetricLabels, MetricRawUnit, MetricUnit, MetricValue, OrderOfMagnitude};
+use std::collections::btree_map::Entry;
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::fmt;
+use std::fmt::Formatter;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum AutoMetricRuleType {
     TimeDifferentiate,
     MovingAverage { depth: usize },
     ExpFalloffAverage { alpha: f32 },
 }
 
+/// Identifies a metric within the aggregator.
+///
+/// A metric is identified by its name plus its labels, metrics received from different sources are kept apart.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct MetricKey {
+    source: String,
+    name: String,
+    labels: MetricLabels,
+}
+
 pub struct AutoMetricRule {
-    src_metric_name: String,
+    src_key: MetricKey,
     dst_metric_name: String,
     rule_type: AutoMetricRuleType,
+    // Id of the user rule this rule was instantiated from, None for built-in rules
+    user_rule_id: Option<usize>,
 }
 
 enum MetricStorage {
     CurrentOnly(Metric),
     History {
         current: Metric,
-        history: VecDeque<(u64, MetricValue)>,
+        history: MetricHistory,
     },
 }
 
+/// Width at which counters wrap around to zero. Without a width every decrease is taken as a reset.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CounterWrap {
+    None,
+    Bits32,
+    Bits64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CounterDiscontinuity {
+    // The counter restarted from zero, e.g. because the orchestrator restarted
+    Reset,
+    // The counter overflowed its width
+    Wrap,
+}
+
+/// A counter that went backwards, recorded on the metric entry it occurred on.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CounterEvent {
+    pub key: MetricKey,
+    pub timestamp: u64,
+    pub discontinuity: CounterDiscontinuity,
+    pub previous: f64,
+    pub current: f64,
+}
+
 struct MetricEntry {
     storage: MetricStorage,
-    parent_metric: Option<String>,
 
+    // Metrics this one is computed from, empty for received metrics
+    parent_metrics: Vec<MetricKey>,
+
+    // Set if the metric is time differentiated, only then decreasing values are discontinuities
+    is_counter: bool,
+
+    counter_events: VecDeque<CounterEvent>,
+
+    // Set if the metric was generated by a user rule
+    from_user_rule: bool,
+
+    // Local time of the last update, absence alerts are based on it as timestamps of different sources
+    // aren't comparable
+    last_seen: Instant,
 }
 
 pub struct MetricAggregator {
-    metrics: HashMap<String, MetricEntry>,
+    metrics: HashMap<MetricKey, MetricEntry>,
 
     last_timestamp: u64,
 
     auto_metric_rules: Vec<AutoMetricRule>,
 
-    max_history: usize,
+    history_config: HistoryConfig,
+
+    // Time span counter rates are taken over
+    rate_window_us: u64,
 
     desired_deltat_diffs_us: u64,
 
     messages_received: u64,
+
+    counter_wrap: CounterWrap,
+
+    // Counter events not yet collected via take_counter_events
+    pending_counter_events: Vec<CounterEvent>,
+
+    user_rules: Vec<(usize, UserMetricRule)>,
+
+    next_user_rule_id: usize,
+
+    // Evaluated in order after the auto rules, so expressions can refer to earlier results
+    derived_metrics: Vec<DerivedMetric>,
+
+    // Metrics computed by auto rules and derived metrics during the last handle_metrics call
+    generated_metrics: Vec<Metric>,
+
+    alert_rules: Vec<(usize, AlertRule)>,
+
+    next_alert_rule_id: usize,
+
+    // Alerts by rule id and metric, an alert exists once its condition held at least once
+    alerts: BTreeMap<(usize, MetricKey), Alert>,
+
+    // Alert state changes not yet collected via take_alert_events
+    pending_alert_events: Vec<Alert>,
+
+    // Absence alerts are timed in microseconds since this instant
+    clock_origin: Instant,
 }
 
 pub struct MetricIterator<'a> {
-    internal_it: std::collections::hash_map::Iter<'a, String, MetricEntry>,
+    internal_it: std::collections::hash_map::Iter<'a, MetricKey, MetricEntry>,
 }
 
 impl<'a> Iterator for MetricIterator<'a> {
-    type Item = &'a Metric;
+    type Item = (&'a MetricKey, &'a Metric);
 
     fn next(&mut self) -> Option<Self::Item> {
         let n = self.internal_it.next();
 
-        if let Some((_name_, metric_entry)) = n {
+        if let Some((key, metric_entry)) = n {
             match &metric_entry.storage {
-                MetricStorage::CurrentOnly(current) => Some(current),
+                MetricStorage::CurrentOnly(current) => Some((key, current)),
                 MetricStorage::History {
                     current,
                     history: _,
-                } => Some(current),
+                } => Some((key, current)),
             }
         } else {
             None
@@ -65,30 +159,170 @@ impl<'a> Iterator for MetricIterator<'a> {
     }
 }
 
+impl MetricKey {
+    pub fn new(source: &str, name: &str) -> MetricKey {
+        MetricKey::with_labels(source, name, MetricLabels::new())
+    }
+
+    pub fn with_labels(source: &str, name: &str, labels: MetricLabels) -> MetricKey {
+        MetricKey {
+            source: source.to_string(),
+            name: name.to_string(),
+            labels,
+        }
+    }
+
+    pub fn from_metric(source: &str, metric: &Metric) -> MetricKey {
+        MetricKey::with_labels(source, metric.get_label(), metric.get_labels().clone())
+    }
+
+    pub fn get_source(&self) -> &str {
+        &self.source
+    }
+
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn get_labels(&self) -> &MetricLabels {
+        &self.labels
+    }
+}
+
+impl fmt::Display for MetricKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "[{}] {}{}", self.source, self.name, self.labels)
+    }
+}
+
 impl MetricEntry {
-    fn new(storage: MetricStorage, parent_metric: Option<String>) -> MetricEntry {
+    fn new(storage: MetricStorage, parent_metrics: Vec<MetricKey>, is_counter: bool, from_user_rule: bool) -> MetricEntry {
         MetricEntry {
             storage,
-            parent_metric,
+            parent_metrics,
+            is_counter,
+            counter_events: VecDeque::new(),
+            from_user_rule,
+            last_seen: Instant::now(),
+        }
+    }
+
+    // Numeric current value and history (if kept) as input of derived metric expressions
+    fn get_series<'a>(&'a self, key: &'a MetricKey) -> Option<Series<'a>> {
+        match &self.storage {
+            MetricStorage::History { current, history } if is_numeric(current.get_value()) => Some(Series {
+                key,
+                value: f64::from(current.get_value()),
+                history: Some(history),
+            }),
+            MetricStorage::CurrentOnly(current) if is_numeric(current.get_value()) => Some(Series {
+                key,
+                value: f64::from(current.get_value()),
+                history: None,
+            }),
+            _ => None,
+        }
+    }
+
+    // Current metric if it keeps a numeric history, only those can be fed into auto rules
+    fn get_numeric_history_metric(&self) -> Option<&Metric> {
+        match &self.storage {
+            MetricStorage::History { current, history: _ } if is_numeric(current.get_value()) => Some(current),
+            _ => None,
         }
     }
 }
 
-const DEFAULT_MAX_HISTORY: usize = 128;
+impl AutoMetricRule {
+    fn from_user_rule(user_rule_id: usize, user_rule: &UserMetricRule, src_key: &MetricKey) -> AutoMetricRule {
+        AutoMetricRule {
+            src_key: src_key.clone(),
+            dst_metric_name: user_rule.get_dst_metric_name(src_key.get_name()),
+            rule_type: user_rule.rule_type.clone(),
+            user_rule_id: Some(user_rule_id),
+        }
+    }
 
-const DEFAULT_DELTAT: u64 = 250000;
+    fn get_dst_key(&self) -> MetricKey {
+        MetricKey::with_labels(self.src_key.get_source(), &self.dst_metric_name, self.src_key.get_labels().clone())
+    }
+
+    fn is_time_differentiate(&self) -> bool {
+        self.rule_type == AutoMetricRuleType::TimeDifferentiate
+    }
+}
+
+impl CounterWrap {
+    pub fn get_max(&self) -> Option<f64> {
+        match self {
+            CounterWrap::None => None,
+            CounterWrap::Bits32 => Some(u32::MAX as f64),
+            CounterWrap::Bits64 => Some(u64::MAX as f64),
+        }
+    }
+}
+
+impl FromStr for CounterWrap {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(CounterWrap::None),
+            "32" => Ok(CounterWrap::Bits32),
+            "64" => Ok(CounterWrap::Bits64),
+            _ => Err(format!("invalid counter width '{}', expected none, 32 or 64", s)),
+        }
+    }
+}
+
+impl fmt::Display for CounterDiscontinuity {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            CounterDiscontinuity::Reset => write!(f, "reset"),
+            CounterDiscontinuity::Wrap => write!(f, "wraparound"),
+        }
+    }
+}
+
+// About what the previous fixed 128 sample history covered at the default publish interval
+pub const DEFAULT_RATE_WINDOW: Duration = Duration::from_secs(30);
+
+// Relative weight below which older values no longer affect an exponential average
+const EWMA_MIN_WEIGHT: f64 = 1e-6;
+
+pub const DEFAULT_DELTAT: u64 = 250000;
+
+// Counter events kept per metric entry
+const MAX_COUNTER_EVENTS: usize = 16;
+
+/// Increase of a counter from `previous` to `current`, like the prometheus `rate()` function.
+///
+/// A decrease is a wrap if the counter width is known and the wrapped increase is less than half the
+/// value range, otherwise a reset after which the counter started from zero.
+pub fn counter_increase(previous: f64, current: f64, counter_wrap: CounterWrap) -> (f64, Option<CounterDiscontinuity>) {
+    if current >= previous {
+        return (current - previous, None);
+    }
+
+    if let Some(max) = counter_wrap.get_max() {
+        let wrapped_increase = max - previous + current + 1.0;
+
+        if previous <= max && wrapped_increase < max / 2.0 {
+            return (wrapped_increase, Some(CounterDiscontinuity::Wrap));
+        }
+    }
+
+    (current, Some(CounterDiscontinuity::Reset))
+}
 
 fn metric_from_time_diff(
-    first: &MetricValue,
-    second: &MetricValue,
+    value_diff: f64,
     src_unit: &MetricUnit,
     dst_name: &str,
     time_diff_us: u64,
 ) -> Option<Metric> {
     let time_diff_s = time_diff_us as f64 * 1e-6f64;
 
-    let value_diff = f64::from(first) - f64::from(second);
-
     let order_of_magnitude = match src_unit.get_raw_unit().0 {
         MetricRawUnit::Bytes => OrderOfMagnitude::Kilo,
         MetricRawUnit::Bits => OrderOfMagnitude::Mega,
@@ -97,7 +331,8 @@ fn metric_from_time_diff(
 
     let scaling = 1.0f64 / order_of_magnitude.get_factor();
 
-    let rate_value = ((scaling * value_diff) / time_diff_s) as i64;
+    // Not rounded, rates below one unit per second are common
+    let rate_value = (scaling * value_diff) / time_diff_s;
 
     Some(Metric::new(
         dst_name.to_string(),
@@ -106,12 +341,60 @@ fn metric_from_time_diff(
             MetricRawUnit::Seconds,
             order_of_magnitude,
         ),
-        MetricValue::Integer(rate_value),
-    ))
+        MetricValue::Number(rate_value),
+    )
+    .with_kind(MetricKind::Gauge))
+}
+
+fn is_numeric(value: &MetricValue) -> bool {
+    std::mem::discriminant(value) == std::mem::discriminant(&MetricValue::Number(0f64)) ||
+        std::mem::discriminant(value) == std::mem::discriminant(&MetricValue::Integer(0))
+}
+
+// Samples carrying a timestamp of their own, e.g. from a Prometheus exposition, are recorded at that time
+fn get_sample_timestamp(metric: &Metric, collection_timestamp: u64) -> u64 {
+    metric.get_timestamp().map(|t| t.max(0) as u64).unwrap_or(collection_timestamp)
+}
+
+// Picks the built-in rule for a metric from its kind, falling back to its unit
+fn get_builtin_auto_rule(metric: &Metric) -> Option<(AutoMetricRuleType, String)> {
+    let (raw_unit_num, raw_unit_den) = metric.get_unit().get_raw_unit();
+
+    let is_rate = raw_unit_den == &MetricRawUnit::Seconds;
+
+    let rule_type = match metric.get_kind() {
+        MetricKind::Counter => Some(AutoMetricRuleType::TimeDifferentiate),
+        // Buckets, sums and counts are cumulative, only the quantiles of a summary are not
+        MetricKind::Histogram | MetricKind::Summary if metric.get_labels().get("quantile").is_none() => {
+            Some(AutoMetricRuleType::TimeDifferentiate)
+        }
+        MetricKind::Gauge if is_rate => Some(AutoMetricRuleType::MovingAverage { depth: 32 }),
+        // Without a kind the unit has to do, amounts are assumed to be counters and rates get smoothed
+        MetricKind::Untyped => {
+            if is_rate {
+                Some(AutoMetricRuleType::MovingAverage { depth: 32 })
+            } else if raw_unit_num != &MetricRawUnit::None && raw_unit_num != &MetricRawUnit::Seconds {
+                Some(AutoMetricRuleType::TimeDifferentiate)
+            } else {
+                None
+            }
+        }
+        _ => None,
+    };
+
+    match rule_type {
+        Some(AutoMetricRuleType::TimeDifferentiate) => {
+            Some((AutoMetricRuleType::TimeDifferentiate, format!("{}-ps", metric.get_label())))
+        }
+        Some(rule_type @ AutoMetricRuleType::MovingAverage { .. }) if !metric.get_label().ends_with(&"-avg") => {
+            Some((rule_type, format!("{}-avg", metric.get_label())))
+        }
+        _ => None,
+    }
 }
 
 fn metric_from_avg(value: f64, src_unit: &MetricUnit, dst_name: &str) -> Option<Metric> {
-    Some(Metric::new(dst_name.to_string(), src_unit.clone(), MetricValue::Number(value)))
+    Some(Metric::new(dst_name.to_string(), src_unit.clone(), MetricValue::Number(value)).with_kind(MetricKind::Gauge))
 }
 
 impl MetricAggregator {
@@ -120,40 +403,327 @@ impl MetricAggregator {
             metrics: HashMap::new(),
             last_timestamp: 0u64,
             auto_metric_rules: Vec::new(),
-            max_history: DEFAULT_MAX_HISTORY,
+            history_config: HistoryConfig::default(),
+            rate_window_us: DEFAULT_RATE_WINDOW.as_micros() as u64,
             desired_deltat_diffs_us: DEFAULT_DELTAT,
             messages_received: 0,
+            counter_wrap: CounterWrap::None,
+            pending_counter_events: Vec::new(),
+            user_rules: Vec::new(),
+            next_user_rule_id: 0,
+            derived_metrics: Vec::new(),
+            generated_metrics: Vec::new(),
+            alert_rules: Vec::new(),
+            next_alert_rule_id: 0,
+            alerts: BTreeMap::new(),
+            pending_alert_events: Vec::new(),
+            clock_origin: Instant::now(),
         }
     }
 
-    pub fn handle_metrics(&mut self, new_timestamp: u64, metrics: &[Metric]) {
-        //let last_timestamp = self.last_timestamp;
+    /// Adds a derived metric, it is computed for every source on each update of that source.
+    pub fn add_derived_metric(&mut self, derived_metric: DerivedMetric) {
+        self.derived_metrics.push(derived_metric);
+    }
 
-        self.last_timestamp = new_timestamp;
+    /// Removes a derived metric together with the metrics computed by it, returns false if it isn't known.
+    pub fn remove_derived_metric(&mut self, derived_metric: &DerivedMetric) -> bool {
+        let derived_idx = match self.derived_metrics.iter().position(|d| d == derived_metric) {
+            Some(idx) => idx,
+            None => return false,
+        };
+
+        self.derived_metrics.remove(derived_idx);
+
+        // Another definition under the same name keeps computing into the same metrics
+        if self.derived_metrics.iter().all(|d| d.name != derived_metric.name) {
+            let keys: Vec<MetricKey> = self
+                .metrics
+                .iter()
+                .filter(|(key, entry)| key.get_name() == derived_metric.name && !entry.parent_metrics.is_empty())
+                .map(|(key, _)| key.clone())
+                .collect();
+
+            for key in keys {
+                self.remove_metric(&key);
+            }
+        }
+
+        true
+    }
+
+    pub fn get_derived_metrics(&self) -> Vec<DerivedMetric> {
+        self.derived_metrics.clone()
+    }
+
+    /// Returns the metrics a derived metric was computed from.
+    pub fn get_parent_metrics(&self, key: &MetricKey) -> Vec<MetricKey> {
+        self.metrics.get(key).map(|entry| entry.parent_metrics.clone()).unwrap_or_default()
+    }
+
+    /// Adds a user rule and applies it to all matching metrics, including the ones already known.
+    /// Returns the id to remove the rule with.
+    pub fn add_user_rule(&mut self, user_rule: UserMetricRule) -> usize {
+        let user_rule_id = self.next_user_rule_id;
+
+        self.next_user_rule_id += 1;
+
+        let new_rules: Vec<AutoMetricRule> = self
+            .metrics
+            .iter()
+            .filter(|(key, entry)| {
+                !entry.from_user_rule
+                    && entry.get_numeric_history_metric().is_some()
+                    && user_rule.matches(key.get_source(), key.get_name())
+            })
+            .map(|(key, _)| AutoMetricRule::from_user_rule(user_rule_id, &user_rule, key))
+            .collect();
+
+        for rule in new_rules.iter().filter(|r| r.is_time_differentiate()) {
+            if let Some(entry) = self.metrics.get_mut(&rule.src_key) {
+                entry.is_counter = true;
+            }
+        }
+
+        self.auto_metric_rules.extend(new_rules);
+
+        self.user_rules.push((user_rule_id, user_rule));
+
+        user_rule_id
+    }
+
+    /// Removes a user rule together with the metrics it generated, returns false for an unknown id.
+    pub fn remove_user_rule(&mut self, user_rule_id: usize) -> bool {
+        let user_rule_idx = match self.user_rules.iter().position(|(id, _)| *id == user_rule_id) {
+            Some(idx) => idx,
+            None => return false,
+        };
+
+        self.user_rules.remove(user_rule_idx);
+
+        let (removed_rules, remaining_rules): (Vec<AutoMetricRule>, Vec<AutoMetricRule>) =
+            std::mem::take(&mut self.auto_metric_rules)
+                .into_iter()
+                .partition(|rule| rule.user_rule_id == Some(user_rule_id));
+
+        self.auto_metric_rules = remaining_rules;
+
+        for rule in &removed_rules {
+            self.remove_metric(&rule.get_dst_key());
+
+            if rule.is_time_differentiate() {
+                let still_counter = self
+                    .auto_metric_rules
+                    .iter()
+                    .any(|r| r.src_key == rule.src_key && r.is_time_differentiate());
+
+                if let Some(entry) = self.metrics.get_mut(&rule.src_key) {
+                    entry.is_counter = still_counter;
+                }
+            }
+        }
+
+        true
+    }
+
+    pub fn get_user_rules(&self) -> Vec<(usize, UserMetricRule)> {
+        self.user_rules.clone()
+    }
+
+    /// Adds an alert rule, it is evaluated on every update from then on. Returns the id to remove the rule with.
+    pub fn add_alert_rule(&mut self, alert_rule: AlertRule) -> usize {
+        let alert_rule_id = self.next_alert_rule_id;
+
+        self.next_alert_rule_id += 1;
+
+        self.alert_rules.push((alert_rule_id, alert_rule));
+
+        alert_rule_id
+    }
+
+    /// Removes an alert rule together with its alerts, returns false for an unknown id.
+    pub fn remove_alert_rule(&mut self, alert_rule_id: usize) -> bool {
+        let alert_rule_idx = match self.alert_rules.iter().position(|(id, _)| *id == alert_rule_id) {
+            Some(idx) => idx,
+            None => return false,
+        };
+
+        self.alert_rules.remove(alert_rule_idx);
+
+        self.alerts.retain(|(rule_id, _), _| *rule_id != alert_rule_id);
+
+        true
+    }
+
+    pub fn get_alert_rules(&self) -> Vec<(usize, AlertRule)> {
+        self.alert_rules.clone()
+    }
+
+    /// Returns the pending, firing and resolved alerts, ordered by rule and metric.
+    pub fn get_alerts(&self) -> Vec<Alert> {
+        self.alerts.values().cloned().collect()
+    }
+
+    /// Returns the alerts that changed state since the last call, in the order of the changes.
+    pub fn take_alert_events(&mut self) -> Vec<Alert> {
+        std::mem::take(&mut self.pending_alert_events)
+    }
+
+    // Removes a metric, the rules fed by it and recursively the metrics those rules generated
+    fn remove_metric(&mut self, key: &MetricKey) {
+        if self.metrics.remove(key).is_none() {
+            return;
+        }
+
+        self.alerts.retain(|(_, alert_key), _| alert_key != key);
+
+        let (removed_rules, remaining_rules): (Vec<AutoMetricRule>, Vec<AutoMetricRule>) =
+            std::mem::take(&mut self.auto_metric_rules)
+                .into_iter()
+                .partition(|rule| &rule.src_key == key);
+
+        self.auto_metric_rules = remaining_rules;
+
+        for rule in removed_rules {
+            self.remove_metric(&rule.get_dst_key());
+        }
+    }
+
+    pub fn set_counter_wrap(&mut self, counter_wrap: CounterWrap) {
+        self.counter_wrap = counter_wrap;
+    }
+
+    /// Sets how long raw samples are kept, older samples are dropped as new ones arrive.
+    pub fn set_history_retention(&mut self, retention: Duration) {
+        self.set_history_config(HistoryConfig { raw_retention: retention, ..self.history_config.clone() });
+    }
+
+    /// Sets the rollup tiers kept next to the raw samples.
+    pub fn set_rollups(&mut self, rollups: Vec<RollupSpec>) {
+        self.set_history_config(HistoryConfig { rollups, ..self.history_config.clone() });
+    }
+
+    /// Sets retention and rollup tiers, the histories of existing metrics are converted right away.
+    pub fn set_history_config(&mut self, history_config: HistoryConfig) {
+        if history_config == self.history_config {
+            return;
+        }
+
+        for metric_entry in self.metrics.values_mut() {
+            if let MetricStorage::History { current: _, history } = &mut metric_entry.storage {
+                history.reconfigure(&history_config);
+            }
+        }
+
+        self.history_config = history_config;
+    }
+
+    /// Sets the shortest time span rates of change are computed over.
+    pub fn set_deltat(&mut self, deltat: Duration) {
+        self.desired_deltat_diffs_us = deltat.as_micros() as u64;
+    }
 
+    /// Sets the time span rules like rate and alert rate conditions look back over.
+    pub fn set_rate_window(&mut self, rate_window: Duration) {
+        self.rate_window_us = rate_window.as_micros() as u64;
+    }
+
+    fn get_rate_window(&self) -> RateWindow {
+        RateWindow {
+            window_us: self.rate_window_us,
+            counter_wrap: self.counter_wrap,
+        }
+    }
+
+    /// Returns the counter events recorded since the last call.
+    pub fn take_counter_events(&mut self) -> Vec<CounterEvent> {
+        std::mem::take(&mut self.pending_counter_events)
+    }
+
+    /// Returns the most recent counter events of a metric, oldest first.
+    pub fn get_counter_events(&self, key: &MetricKey) -> Vec<CounterEvent> {
+        self.metrics
+            .get(key)
+            .map(|entry| entry.counter_events.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Handles the metrics of a received collection, namespaced by its subscription.
+    pub fn handle_collection(&mut self, collection: &MetricCollection) {
+        self.handle_metrics(&collection.get_namespace(), collection.get_timestamp(), collection.get_metrics_ref().as_slice());
+    }
+
+    pub fn handle_metrics(&mut self, src: &str, new_timestamp: u64, metrics: &[Metric]) {
+        self.last_timestamp = new_timestamp;
 
         self.messages_received += 1;
 
+        self.generated_metrics.clear();
+
         for metric in metrics {
-            self.handle_incoming_metric(metric, &None);
+            self.handle_incoming_metric(src, metric, &[], false);
         }
 
-        self.handle_auto_rules();
+        self.handle_auto_rules(src);
+
+        self.handle_derived_metrics(src);
+
+        self.handle_alert_rules(Some(src));
+    }
+
+    /// Checks the absence conditions of all sources. Has to be called periodically, a source that stopped
+    /// sending doesn't trigger any evaluation on its own.
+    pub fn evaluate_alerts(&mut self) {
+        self.handle_alert_rules(None);
     }
 
-    fn handle_incoming_metric(&mut self, metric: &Metric, parent_metric: &Option<String>) {
-        if let Some(metric_entry) = self.metrics.get_mut(metric.get_label()) {
+    fn handle_incoming_metric(&mut self, src: &str, metric: &Metric, parent_metrics: &[MetricKey], from_user_rule: bool) {
+        let key = MetricKey::from_metric(src, metric);
+
+        if let Some(metric_entry) = self.metrics.get_mut(&key) {
+            // The inputs of a derived metric can change as series come and go
+            if !parent_metrics.is_empty() {
+                metric_entry.parent_metrics = parent_metrics.to_vec();
+            }
+
+            metric_entry.last_seen = Instant::now();
+
             let metric_storage = &mut metric_entry.storage;
 
             match metric_storage {
                 MetricStorage::History { current, history } => {
+                    if metric_entry.is_counter {
+                        let previous = f64::from(current.get_value());
+                        let new_value = f64::from(metric.get_value());
+
+                        if let (_, Some(discontinuity)) = counter_increase(previous, new_value, self.counter_wrap) {
+                            let counter_event = CounterEvent {
+                                key: key.clone(),
+                                timestamp: self.last_timestamp,
+                                discontinuity,
+                                previous,
+                                current: new_value,
+                            };
+
+                            crate::common::vec_shift(&mut metric_entry.counter_events, counter_event.clone(), MAX_COUNTER_EVENTS);
+
+                            self.pending_counter_events.push(counter_event);
+                        }
+                    }
+
                     *current = metric.clone();
 
-                    crate::common::vec_shift(
-                        history,
-                        (self.last_timestamp, current.get_value().clone()),
-                        self.max_history,
-                    );
+                    let sample_timestamp = get_sample_timestamp(metric, self.last_timestamp);
+
+                    // A scrape repeating a timestamped sample doesn't add a new one, a restarted source going
+                    // further back does
+                    let repeated = metric.get_timestamp().is_some()
+                        && matches!(history.newest(), Some((newest, _))
+                            if newest >= sample_timestamp && newest - sample_timestamp <= CLOCK_STEP_TOLERANCE_US);
+
+                    if !repeated {
+                        history.push(sample_timestamp, f64::from(current.get_value()));
+                    }
                 }
                 MetricStorage::CurrentOnly(current) => {
                     *current = metric.clone();
@@ -163,12 +733,15 @@ impl MetricAggregator {
             let metric_unit = metric.get_unit();
 
             let metric_storage = match metric_unit.get_raw_unit().0 {
+                // Info metrics only carry their labels, there is nothing to plot
+                _ if metric.get_kind() == MetricKind::Info => MetricStorage::CurrentOnly(metric.clone()),
                 MetricRawUnit::Bytes
                 | MetricRawUnit::Bits
                 | MetricRawUnit::Packets
                 | MetricRawUnit::None => {
-                    let metric_history =
-                        VecDeque::from([(self.last_timestamp, metric.get_value().clone())]);
+                    let mut metric_history = MetricHistory::new(&self.history_config);
+
+                    metric_history.push(get_sample_timestamp(metric, self.last_timestamp), f64::from(metric.get_value()));
 
                     MetricStorage::History {
                         current: metric.clone(),
@@ -178,50 +751,63 @@ impl MetricAggregator {
                 _ => MetricStorage::CurrentOnly(metric.clone()),
             };
 
-            self.create_auto_rules(&metric_storage);
+            let metric_entry = MetricEntry::new(metric_storage, parent_metrics.to_vec(), false, from_user_rule);
+
+            let is_counter = self.create_auto_rules(&key, &metric_entry);
 
-            self.metrics
-                .insert(metric.get_label().to_string(), MetricEntry::new(metric_storage, parent_metric.clone()));
+            self.metrics.insert(key, MetricEntry { is_counter, ..metric_entry });
         }
     }
 
-    fn create_auto_rules(&mut self, metric_storage: &MetricStorage) {
-        if let MetricStorage::History {
-            current,
-            history: _,
-        } = metric_storage
-        {
-            if std::mem::discriminant(current.get_value()) == std::mem::discriminant(&MetricValue::Number(0f64)) ||
-                std::mem::discriminant(current.get_value()) == std::mem::discriminant(&MetricValue::Integer(0)) {
-                if current.get_unit().get_raw_unit().1 != &MetricRawUnit::Seconds {
-                    if current.get_unit().get_raw_unit().0 != &MetricRawUnit::None && current.get_unit().get_raw_unit().0 != &MetricRawUnit::Seconds {
-                        self.auto_metric_rules.push(AutoMetricRule {
-                            src_metric_name: current.get_label().to_string(),
-                            dst_metric_name: format!("{}-ps", current.get_label()),
-                            rule_type: AutoMetricRuleType::TimeDifferentiate,
-                        });
-                    }
-                } else if !current.get_label().ends_with(&"-avg") {
-                    self.auto_metric_rules.push(AutoMetricRule {
-                        src_metric_name: current.get_label().to_string(),
-                        dst_metric_name: format!("{}-avg", current.get_label()),
-                        rule_type: AutoMetricRuleType::MovingAverage { depth: 32 },
-                    });
+    // Returns true if the metric is time differentiated as a counter
+    fn create_auto_rules(&mut self, key: &MetricKey, metric_entry: &MetricEntry) -> bool {
+        let current = match metric_entry.get_numeric_history_metric() {
+            Some(current) => current,
+            None => return false,
+        };
+
+        let mut new_rules = Vec::new();
+
+        if let Some((rule_type, dst_metric_name)) = get_builtin_auto_rule(current) {
+            new_rules.push(AutoMetricRule {
+                src_key: key.clone(),
+                dst_metric_name,
+                rule_type,
+                user_rule_id: None,
+            });
+        }
+
+        // Metrics generated by user rules are not fed into user rules again, a glob like `*`
+        // would otherwise keep matching its own output
+        if !metric_entry.from_user_rule {
+            for (user_rule_id, user_rule) in &self.user_rules {
+                if user_rule.matches(key.get_source(), key.get_name()) {
+                    new_rules.push(AutoMetricRule::from_user_rule(*user_rule_id, user_rule, key));
                 }
             }
         }
+
+        let is_counter = new_rules.iter().any(|r| r.is_time_differentiate());
+
+        self.auto_metric_rules.extend(new_rules);
+
+        is_counter
     }
 
-    fn handle_auto_rules(&mut self) {
+    fn handle_auto_rules(&mut self, src: &str) {
         for auto_rule_index in 0..self.auto_metric_rules.len() {
             let mut generated_metric = Option::None;
-            let mut parent_metric = Option::None;
 
             let auto_rule = self.auto_metric_rules.get(auto_rule_index).unwrap();
 
-            if let Some(metric_entry) = self.metrics.get(&auto_rule.src_metric_name) {
-                parent_metric = Some(auto_rule.src_metric_name.clone());
+            let from_user_rule = auto_rule.user_rule_id.is_some();
+
+            // Only rules fed by the source that just delivered new data have anything to compute
+            if auto_rule.src_key.get_source() != src {
+                continue;
+            }
 
+            if let Some(metric_entry) = self.metrics.get(&auto_rule.src_key) {
                 match auto_rule.rule_type {
                     AutoMetricRuleType::TimeDifferentiate => {
                         if let MetricStorage::History {
@@ -229,23 +815,17 @@ impl MetricAggregator {
                             history,
                         } = &metric_entry.storage
                         {
-                            let mut history_iter = history.iter();
-
-                            let first = history_iter.next();
-
-                            if let Some(first) = first {
-                                while let Some(current) = history_iter.next() {
-                                    let time_diff_us = first.0 - current.0;
-
-                                    if time_diff_us >= self.desired_deltat_diffs_us {
-                                        generated_metric = metric_from_time_diff(
-                                            &first.1,
-                                            &current.1,
-                                            current_metric.get_unit(),
-                                            &auto_rule.dst_metric_name,
-                                            time_diff_us,
-                                        );
-                                    }
+                            // The rate is taken over the rate window, once it spans the desired time difference
+                            if let Some((value_diff, time_diff_us)) = history.get_increase(&self.get_rate_window()) {
+                                if time_diff_us >= self.desired_deltat_diffs_us {
+                                    generated_metric = metric_from_time_diff(
+                                        value_diff,
+                                        current_metric.get_unit(),
+                                        &auto_rule.dst_metric_name,
+                                        time_diff_us,
+                                    )
+                                    // Derived metrics keep the labels of the series they are computed from
+                                    .map(|m| m.with_labels(current_metric.get_labels().clone()));
                                 }
                             }
                         }
@@ -256,56 +836,219 @@ impl MetricAggregator {
                             history,
                         } = &metric_entry.storage
                         {
-                            let mut history_iter = history.iter().take(depth);
+                            let history_iter = history.iter().rev().take(depth);
 
                             let mut count = 0;
                             let mut sum = 0.0f64;
 
-                            while let Some(history_element) = history_iter.next() {
-                                sum += f64::from(&history_element.1);
+                            for history_element in history_iter {
+                                sum += history_element.1;
                                 count += 1;
                             }
 
                             if count > 0 {
                                 let avg = sum / count as f64;
-                                generated_metric = metric_from_avg(avg, current_metric.get_unit(), &auto_rule.dst_metric_name);
+                                generated_metric = metric_from_avg(avg, current_metric.get_unit(), &auto_rule.dst_metric_name)
+                                    .map(|m| m.with_labels(current_metric.get_labels().clone()));
+                            }
+                        }
+                    }
+                    AutoMetricRuleType::ExpFalloffAverage { alpha } => {
+                        if let MetricStorage::History {
+                            current: current_metric,
+                            history,
+                        } = &metric_entry.storage
+                        {
+                            let alpha = alpha as f64;
+
+                            // Only the values with a noticeable weight are taken into account
+                            let depth = if alpha < 1.0 {
+                                (EWMA_MIN_WEIGHT.ln() / (1.0 - alpha).ln()).ceil() as usize
+                            } else {
+                                1
+                            };
+
+                            // The average starts at the oldest value
+                            let mut values = history.iter().rev().take(depth).rev().map(|e| e.1);
+
+                            if let Some(oldest) = values.next() {
+                                let ewma = values.fold(oldest, |ewma, value| alpha * value + (1.0 - alpha) * ewma);
+
+                                generated_metric = metric_from_avg(ewma, current_metric.get_unit(), &auto_rule.dst_metric_name)
+                                    .map(|m| m.with_labels(current_metric.get_labels().clone()));
                             }
                         }
                     }
-                    _ => (),
                 }
             }
 
             if let Some(generated_metric) = generated_metric {
-                self.handle_incoming_metric(&generated_metric, &parent_metric);
+                let parent_metric = self.auto_metric_rules[auto_rule_index].src_key.clone();
+
+                self.handle_incoming_metric(src, &generated_metric, &[parent_metric], from_user_rule);
+
+                self.generated_metrics.push(generated_metric);
+            }
+        }
+    }
+
+    fn handle_derived_metrics(&mut self, src: &str) {
+        for derived_idx in 0..self.derived_metrics.len() {
+            let metrics = &self.metrics;
+
+            let select = |selector: &Selector| -> Vec<Series> {
+                metrics
+                    .iter()
+                    .filter(|(key, _)| key.get_source() == src && selector.matches(key))
+                    .filter_map(|(key, metric_entry)| metric_entry.get_series(key))
+                    .collect()
+            };
+
+            let derived_metric = &self.derived_metrics[derived_idx];
+
+            let samples = derived_metric.evaluate(&select, &self.get_rate_window());
+
+            let name = derived_metric.name.clone();
+
+            for mut sample in samples {
+                sample.inputs.sort();
+                sample.inputs.dedup();
+
+                let generated_metric = Metric::new(name.clone(), MetricUnit::empty(), MetricValue::Number(sample.value))
+                    .with_kind(MetricKind::Gauge)
+                    .with_labels(sample.labels);
+
+                self.handle_incoming_metric(src, &generated_metric, &sample.inputs, false);
+
+                self.generated_metrics.push(generated_metric);
+            }
+        }
+    }
+
+    // Values and rates are only checked for the source that just delivered new data and timed by its
+    // timestamps. Absence is checked for all sources and timed by the local clock.
+    fn handle_alert_rules(&mut self, src: Option<&str>) {
+        let local_now = self.clock_origin.elapsed().as_micros() as u64;
+
+        let rate_window = self.get_rate_window();
+
+        let mut observations = Vec::new();
+
+        for (alert_rule_id, alert_rule) in &self.alert_rules {
+            let is_absence = matches!(alert_rule.condition, AlertCondition::Absent { .. });
+
+            if src.is_none() && !is_absence {
+                continue;
+            }
+
+            let now = if is_absence { local_now } else { self.last_timestamp };
+
+            for (key, metric_entry) in &self.metrics {
+                if (!is_absence && Some(key.get_source()) != src) || !alert_rule.matches(key) {
+                    continue;
+                }
+
+                let series = match metric_entry.get_series(key) {
+                    Some(series) => series,
+                    None if is_absence => Series { key, value: 0.0, history: None },
+                    None => continue,
+                };
+
+                // Counters are rated like the auto rules, a reset or wrap isn't a drop
+                let rate = series.history.and_then(|history| {
+                    if metric_entry.is_counter {
+                        return history.get_increase(&rate_window).map(|(increase, time_diff_us)| increase * 1e6 / time_diff_us as f64);
+                    }
+
+                    let mut window = history.window(rate_window.window_us);
+
+                    match (window.next(), window.last()) {
+                        (Some(first), Some(last)) if last.0 > first.0 => Some((last.1 - first.1) * 1e6 / (last.0 - first.0) as f64),
+                        _ => None,
+                    }
+                });
+
+                let input = AlertInput {
+                    value: series.value,
+                    rate,
+                    age_us: metric_entry.last_seen.elapsed().as_micros() as u64,
+                };
+
+                let firing = self.alerts.get(&(*alert_rule_id, key.clone())).is_some_and(|alert| alert.is_firing());
+
+                let holds = alert_rule.condition.holds(&input, firing);
+
+                observations.push((*alert_rule_id, key.clone(), holds, alert_rule.condition.get_reported_value(&input), now));
+            }
+        }
+
+        for (alert_rule_id, key, holds, value, now) in observations {
+            let alert_rule = match self.alert_rules.iter().find(|(id, _)| *id == alert_rule_id) {
+                Some((_, alert_rule)) => alert_rule,
+                None => continue,
+            };
+
+            let alert = match self.alerts.entry((alert_rule_id, key)) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                // Alerts come into existence once their condition holds
+                Entry::Vacant(entry) if holds => {
+                    let alert = Alert::new(alert_rule_id, alert_rule, &entry.key().1, now);
+
+                    entry.insert(alert)
+                }
+                Entry::Vacant(_) => continue,
+            };
+
+            alert.value = value;
+
+            if alert.advance(holds, now, alert_rule.for_duration) {
+                self.pending_alert_events.push(alert.clone());
             }
         }
     }
 
-    pub fn walk_metrics(&self, cb: impl Fn(&Metric)) {
-        for (_, metric_entry) in &self.metrics {
+    /// Metrics computed from the collection passed to the last `handle_metrics` call, in computation order.
+    pub fn get_generated_metrics(&self) -> &[Metric] {
+        &self.generated_metrics
+    }
+
+    pub fn walk_metrics(&self, cb: impl Fn(&MetricKey, &Metric)) {
+        for (key, metric_entry) in &self.metrics {
             match &metric_entry.storage {
                 MetricStorage::History {
                     current,
                     history: _,
                 } => {
-                    cb(current);
+                    cb(key, current);
                 }
                 MetricStorage::CurrentOnly(current) => {
-                    cb(current);
+                    cb(key, current);
                 }
             }
         }
     }
 
-    pub fn metric_iter(&self) -> MetricIterator {
+    pub fn get_sources(&self) -> BTreeSet<&str> {
+        self.metrics.keys().map(|k| k.get_source()).collect()
+    }
+
+    pub fn get_label_keys(&self) -> BTreeSet<&str> {
+        self.metrics.keys().flat_map(|k| k.get_labels().iter().map(|(label_key, _)| label_key)).collect()
+    }
+
+    /// Returns all values the label `label_key` takes on, across all sources.
+    pub fn get_label_values(&self, label_key: &str) -> BTreeSet<&str> {
+        self.metrics.keys().filter_map(|k| k.get_labels().get(label_key)).collect()
+    }
+
+    pub fn metric_iter(&self) -> MetricIterator<'_> {
         MetricIterator {
             internal_it: self.metrics.iter(),
         }
     }
 
-    pub fn get_metric(&self, name: &str) -> Option<&Metric> {
-        if let Some(metric_entry) = self.metrics.get(name) {
+    pub fn get_metric(&self, key: &MetricKey) -> Option<&Metric> {
+        if let Some(metric_entry) = self.metrics.get(key) {
             match &metric_entry.storage {
                 MetricStorage::History {
                     current,
@@ -322,51 +1065,229 @@ impl MetricAggregator {
         self.last_timestamp
     }
 
+    /// Fills `data` with at most about `max_points` (seconds, value) points of the last `range` before
+    /// the newest sample, oldest first, taken from the raw samples or a rollup tier depending on the range.
+    /// Returns the minimum and maximum value within the range.
     pub fn get_metric_history(
         &self,
-        name: &str,
+        key: &MetricKey,
         data: &mut Vec<(f64, f64)>,
-        max_len: usize,
+        range: Duration,
+        max_points: usize,
     ) -> Option<(f64, f64)> {
-        if let Some(metric_entry) = self.metrics.get(name) {
+        if let Some(metric_entry) = self.metrics.get(key) {
             if let MetricStorage::History {
                 current,
                 history,
             } = &metric_entry.storage {
-                if std::mem::discriminant(current.get_value()) == std::mem::discriminant(&MetricValue::Number(0f64)) ||
-                    std::mem::discriminant(current.get_value()) == std::mem::discriminant(&MetricValue::Integer(0)) {
-                    let requested_len = max_len.min(history.len());
+                if is_numeric(current.get_value()) {
+                    return history.query(range.as_micros() as u64, max_points, data);
+                }
+            }
+        }
+
+        None
+    }
 
-                    if data.len() != requested_len {
-                        data.resize(requested_len, (0.0f64, 0.0f64));
+    /// Collects the metrics selected by `options`, ordered by key.
+    ///
+    /// Current values are stamped with the time of their newest sample, history is only exported
+    /// for numeric metrics that keep one.
+    pub fn get_export_series(&self, options: &ExportOptions) -> Vec<ExportSeries> {
+        let mut series: Vec<ExportSeries> = self
+            .metrics
+            .iter()
+            .filter(|(key, _)| options.selects(key))
+            .filter_map(|(key, metric_entry)| {
+                let (current, history) = match &metric_entry.storage {
+                    MetricStorage::History { current, history } => (current, Some(history)),
+                    MetricStorage::CurrentOnly(current) => (current, None),
+                };
+
+                let samples = match options.scope {
+                    ExportScope::Current => {
+                        let timestamp = history
+                            .and_then(|h| h.newest())
+                            .map(|(t, _)| t)
+                            .or_else(|| current.get_timestamp().map(|t| t.max(0) as u64))
+                            .unwrap_or(self.last_timestamp);
+
+                        vec![(timestamp, current.get_value().clone())]
                     }
+                    ExportScope::History(range) if is_numeric(current.get_value()) => history?
+                        .get_samples(range.as_micros() as u64)
+                        .into_iter()
+                        .map(|(t, value)| (t, MetricValue::Number(value)))
+                        .collect(),
+                    ExportScope::History(_) => return None,
+                };
+
+                Some(ExportSeries {
+                    key: key.clone(),
+                    unit: current.get_unit().to_string(),
+                    samples,
+                })
+            })
+            .collect();
+
+        series.sort_by(|a, b| a.key.cmp(&b.key));
+
+        series
+    }
+}
 
-                    let mut max_val = None;
-                    let mut min_val = None;
+#[test]
+fn metric_aggregator_source_separation_test01() {
+    let mut aggregator = MetricAggregator::new();
 
-                    for idx in 0..history.len() {
-                        let current = &history[idx];
+    aggregator.handle_metrics("tcp://host-a:5555", 1000, &[Metric::new("rx_packets".to_string(), MetricUnit::empty(), MetricValue::Integer(1))]);
+    aggregator.handle_metrics("tcp://host-b:5555", 1000, &[Metric::new("rx_packets".to_string(), MetricUnit::empty(), MetricValue::Integer(2))]);
 
-                        let current_metric_val = f64::from(&current.1);
+    assert_eq!(aggregator.get_metric(&MetricKey::new("tcp://host-a:5555", "rx_packets")).unwrap().get_value(), &MetricValue::Integer(1));
+    assert_eq!(aggregator.get_metric(&MetricKey::new("tcp://host-b:5555", "rx_packets")).unwrap().get_value(), &MetricValue::Integer(2));
+    assert_eq!(aggregator.get_sources().len(), 2);
+}
 
-                        if idx < requested_len {
-                            data[requested_len - idx - 1] =
-                                (current.0 as f64 / 1e6f64, current_metric_val);
-                        }
+#[test]
+fn metric_aggregator_label_identity_test01() {
+    let mut aggregator = MetricAggregator::new();
 
-                        max_val = Some(current_metric_val.max(max_val.unwrap_or(0.0f64)));
+    let metrics: Vec<Metric> = ["0", "1"]
+        .iter()
+        .map(|queue| {
+            Metric::new("rx_packets".to_string(), MetricUnit::new(MetricRawUnit::Packets, MetricRawUnit::None, OrderOfMagnitude::One), MetricValue::Integer(1))
+                .with_labels([("queue", *queue)].into_iter().collect())
+        })
+        .collect();
 
-                        min_val =
-                            Some(current_metric_val.min(min_val.unwrap_or(current_metric_val)));
-                    }
+    aggregator.handle_metrics("tcp://host-a:5555", 1000, &metrics);
 
-                    if let (Some(max_val), Some(min_val)) = (max_val, min_val) {
-                        return Some((min_val, max_val));
-                    }
-                }
-            }
-        }
+    let key = MetricKey::with_labels("tcp://host-a:5555", "rx_packets", [("queue", "1")].into_iter().collect());
 
-        None
-    }
+    assert!(aggregator.get_metric(&key).is_some());
+    assert!(aggregator.get_metric(&MetricKey::new("tcp://host-a:5555", "rx_packets")).is_none());
+    assert_eq!(key.to_string(), "[tcp://host-a:5555] rx_packets{queue=\"1\"}");
+
+    assert_eq!(aggregator.get_label_keys().into_iter().collect::<Vec<_>>(), vec!["queue"]);
+    assert_eq!(aggregator.get_label_values("queue").len(), 2);
+
+    aggregator.handle_metrics("tcp://host-a:5555", 500000, &metrics);
+
+    // The derived rate metric keeps the labels of its source series
+    let rate_key = MetricKey::with_labels("tcp://host-a:5555", "rx_packets-ps", [("queue", "0")].into_iter().collect());
+
+    assert!(aggregator.get_metric(&rate_key).is_some());
+
+    // Timestamped samples are recorded at their own time, repeating one adds nothing
+    let stamped = vec![metrics[0].clone().with_timestamp(Some(200000))];
+
+    aggregator.handle_metrics("tcp://host-b:5555", 900000, &stamped);
+    aggregator.handle_metrics("tcp://host-b:5555", 950000, &stamped);
+
+    let mut data = Vec::new();
+
+    aggregator.get_metric_history(&MetricKey::from_metric("tcp://host-b:5555", &stamped[0]), &mut data, Duration::from_secs(1), 100);
+
+    assert_eq!(data, vec![(0.2, 1.0)]);
+}
+
+#[test]
+fn metric_aggregator_kind_auto_rules_test01() {
+    let mut aggregator = MetricAggregator::new();
+
+    let packets = MetricUnit::new(MetricRawUnit::Packets, MetricRawUnit::None, OrderOfMagnitude::One);
+
+    let metrics = [
+        Metric::new("queue_depth".to_string(), packets.clone(), MetricValue::Integer(10)).with_kind(MetricKind::Gauge),
+        Metric::new("rx_packets".to_string(), packets.clone(), MetricValue::Integer(10)).with_kind(MetricKind::Counter),
+        Metric::new("tx_packets".to_string(), packets, MetricValue::Integer(10)),
+        Metric::new("requests_count".to_string(), MetricUnit::empty(), MetricValue::Integer(10)).with_kind(MetricKind::Summary),
+        Metric::new("build".to_string(), MetricUnit::empty(), MetricValue::Integer(1)).with_kind(MetricKind::Info),
+    ];
+
+    aggregator.handle_metrics("src", 0, &metrics);
+    aggregator.handle_metrics("src", 500000, &metrics);
+
+    assert!(aggregator.get_metric(&MetricKey::new("src", "queue_depth-ps")).is_none());
+    assert!(aggregator.get_metric(&MetricKey::new("src", "rx_packets-ps")).is_some());
+    assert!(aggregator.get_metric(&MetricKey::new("src", "tx_packets-ps")).is_some());
+    assert!(aggregator.get_metric(&MetricKey::new("src", "requests_count-ps")).is_some());
+
+    // No change within half a second is a rate of zero, a fraction of a packet per second is kept
+    assert_eq!(aggregator.get_metric(&MetricKey::new("src", "rx_packets-ps")).unwrap().get_value(), &MetricValue::Number(0.0));
+
+    let mut metrics = metrics;
+
+    metrics[1] = Metric::new("rx_packets".to_string(), MetricUnit::empty(), MetricValue::Integer(11)).with_kind(MetricKind::Counter);
+
+    aggregator.handle_metrics("src", 4000000, &metrics);
+
+    assert_eq!(aggregator.get_metric(&MetricKey::new("src", "rx_packets-ps")).unwrap().get_value(), &MetricValue::Number(0.25));
+
+    let mut history = Vec::new();
+
+    assert!(aggregator.get_metric_history(&MetricKey::new("src", "build"), &mut history, Duration::from_secs(60), 16).is_none());
+}
+
+#[test]
+fn counter_increase_reset_wrap_test01() {
+    assert_eq!(counter_increase(10.0, 15.0, CounterWrap::None), (5.0, None));
+    assert_eq!(counter_increase(100.0, 7.0, CounterWrap::None), (7.0, Some(CounterDiscontinuity::Reset)));
+    assert_eq!(counter_increase(4294967290.0, 5.0, CounterWrap::Bits32), (11.0, Some(CounterDiscontinuity::Wrap)));
+
+    // Far from the wrap point a decrease is still a reset
+    assert_eq!(counter_increase(1000.0, 5.0, CounterWrap::Bits32), (5.0, Some(CounterDiscontinuity::Reset)));
+
+    let mut aggregator = MetricAggregator::new();
+
+    // The reset must not look like a falling rate to alert rules either
+    aggregator.add_alert_rule(AlertRule::from_str("stalled: rx_packets rate < 1").unwrap());
+
+    let rx_packets = |value: i64| {
+        [Metric::new("rx_packets".to_string(), MetricUnit::empty(), MetricValue::Integer(value)).with_kind(MetricKind::Counter)]
+    };
+
+    aggregator.handle_metrics("src", 0, &rx_packets(1000));
+    aggregator.handle_metrics("src", 500000, &rx_packets(1500));
+    aggregator.handle_metrics("src", 1000000, &rx_packets(100));
+
+    let key = MetricKey::new("src", "rx_packets");
+
+    let counter_events = aggregator.take_counter_events();
+
+    assert_eq!(counter_events.len(), 1);
+    assert_eq!(counter_events[0].discontinuity, CounterDiscontinuity::Reset);
+    assert_eq!(aggregator.get_counter_events(&key), counter_events);
+    assert!(aggregator.take_counter_events().is_empty());
+
+    // 500 before and 100 after the reset within one second
+    let rate = aggregator.get_metric(&MetricKey::new("src", "rx_packets-ps")).unwrap();
+
+    assert_eq!(rate.get_value(), &MetricValue::Number(600.0));
+
+    assert!(aggregator.take_alert_events().is_empty());
+}
+
+#[test]
+fn metric_aggregator_derived_metric_test01() {
+    let mut aggregator = MetricAggregator::new();
+
+    aggregator.add_derived_metric("drop_ratio = rx_dropped / rx_packets".parse().unwrap());
+
+    let metrics = [
+        Metric::new("rx_dropped".to_string(), MetricUnit::empty(), MetricValue::Integer(5)).with_labels([("device", "eth0")].into_iter().collect()),
+        Metric::new("rx_packets".to_string(), MetricUnit::empty(), MetricValue::Integer(50)).with_labels([("device", "eth0")].into_iter().collect()),
+    ];
+
+    aggregator.handle_metrics("src", 0, &metrics);
+
+    let key = MetricKey::with_labels("src", "drop_ratio", [("device", "eth0")].into_iter().collect());
+
+    assert_eq!(aggregator.get_metric(&key).unwrap().get_value(), &MetricValue::Number(0.1));
+    assert_eq!(aggregator.get_parent_metrics(&key), vec![MetricKey::from_metric("src", &metrics[0]), MetricKey::from_metric("src", &metrics[1])]);
+
+    // Expressions only see the metrics of the source being updated
+    aggregator.handle_metrics("other", 0, &metrics[..1]);
+
+    assert!(aggregator.get_metric(&MetricKey::with_labels("other", "drop_ratio", [("device", "eth0")].into_iter().collect())).is_none());
 }
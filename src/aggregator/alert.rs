@@ -0,0 +1,498 @@
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use std::time::Duration;
+
+use json::JsonValue;
+
+use crate::aggregator::aggregator::MetricKey;
+use crate::aggregator::user_rule::glob_match;
+use crate::common::parse_duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompareOp {
+    Greater,
+    GreaterEqual,
+    Less,
+    LessEqual,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AlertCondition {
+    // The current value crosses `threshold`, a firing alert only resolves once it is back past `clear`
+    Threshold { op: CompareOp, threshold: f64, clear: Option<f64> },
+    // Same as a threshold, applied to the per second change over the rate window
+    RateOfChange { op: CompareOp, threshold: f64, clear: Option<f64> },
+    // The metric wasn't updated for longer than `after`
+    Absent { after: Duration },
+}
+
+/// Alert declared by the user, evaluated for every metric whose source and name match the globs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AlertRule {
+    pub name: String,
+    pub source: String,
+    pub metric: String,
+    pub condition: AlertCondition,
+    // How long the condition has to hold before a pending alert fires
+    pub for_duration: Duration,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum AlertState {
+    // The condition holds, but not yet for the rule's duration
+    Pending,
+    Firing,
+    // The condition stopped holding, kept until it holds again
+    Resolved,
+}
+
+/// What a condition is checked against, taken from one metric.
+pub struct AlertInput {
+    pub value: f64,
+
+    // Per second change over the rate window, None until the history spans two samples
+    pub rate: Option<f64>,
+
+    // Microseconds since the metric was last updated, on the local clock
+    pub age_us: u64,
+}
+
+/// An alert rule applied to one metric.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Alert {
+    pub rule_id: usize,
+    pub rule_name: String,
+    pub key: MetricKey,
+    pub state: AlertState,
+
+    // Timestamp at which the alert entered its state, local time for absence alerts
+    pub since: u64,
+
+    // Value or rate the condition was last checked against, None for absence alerts
+    pub value: Option<f64>,
+}
+
+fn format_duration(duration: &Duration) -> String {
+    let ms = duration.as_millis();
+
+    if ms % 1000 != 0 {
+        format!("{}ms", ms)
+    } else if ms % 3600000 == 0 && ms > 0 {
+        format!("{}h", ms / 3600000)
+    } else if ms % 60000 == 0 && ms > 0 {
+        format!("{}m", ms / 60000)
+    } else {
+        format!("{}s", ms / 1000)
+    }
+}
+
+impl CompareOp {
+    pub fn compare(&self, value: f64, level: f64) -> bool {
+        match self {
+            CompareOp::Greater => value > level,
+            CompareOp::GreaterEqual => value >= level,
+            CompareOp::Less => value < level,
+            CompareOp::LessEqual => value <= level,
+        }
+    }
+
+    // Splits a leading operator off a token, e.g. `>=90`
+    fn split_prefix(token: &str) -> Option<(CompareOp, &str)> {
+        [(">=", CompareOp::GreaterEqual), ("<=", CompareOp::LessEqual), (">", CompareOp::Greater), ("<", CompareOp::Less)]
+            .into_iter()
+            .find_map(|(prefix, op)| token.strip_prefix(prefix).map(|rest| (op, rest)))
+    }
+}
+
+impl Display for CompareOp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            CompareOp::Greater => write!(f, ">"),
+            CompareOp::GreaterEqual => write!(f, ">="),
+            CompareOp::Less => write!(f, "<"),
+            CompareOp::LessEqual => write!(f, "<="),
+        }
+    }
+}
+
+impl FromStr for CompareOp {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match CompareOp::split_prefix(s) {
+            Some((op, "")) => Ok(op),
+            _ => Err(format!("unknown comparison '{}', expected >, >=, < or <=", s)),
+        }
+    }
+}
+
+impl AlertCondition {
+    /// Whether the condition holds for `input`, a `firing` alert is checked against the clear level.
+    pub fn holds(&self, input: &AlertInput, firing: bool) -> bool {
+        let crosses = |op: &CompareOp, threshold: f64, clear: Option<f64>, value: f64| {
+            let level = if firing { clear.unwrap_or(threshold) } else { threshold };
+
+            op.compare(value, level)
+        };
+
+        match self {
+            AlertCondition::Threshold { op, threshold, clear } => crosses(op, *threshold, *clear, input.value),
+            AlertCondition::RateOfChange { op, threshold, clear } => {
+                matches!(input.rate, Some(rate) if crosses(op, *threshold, *clear, rate))
+            }
+            AlertCondition::Absent { after } => input.age_us > after.as_micros() as u64,
+        }
+    }
+
+    /// The value reported with the alert, the current value or rate.
+    pub fn get_reported_value(&self, input: &AlertInput) -> Option<f64> {
+        match self {
+            AlertCondition::Threshold { .. } => Some(input.value),
+            AlertCondition::RateOfChange { .. } => input.rate,
+            AlertCondition::Absent { .. } => None,
+        }
+    }
+
+    fn check_clear(&self) -> Result<(), String> {
+        if let AlertCondition::Threshold { op, threshold, clear: Some(clear) }
+        | AlertCondition::RateOfChange { op, threshold, clear: Some(clear) } = self
+        {
+            // The clear level must lie on the side the value returns to
+            if op.compare(*clear, *threshold) && clear != threshold {
+                return Err(format!("clear level {} is beyond the threshold {}", clear, threshold));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Display for AlertCondition {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            AlertCondition::Threshold { op, threshold, clear } | AlertCondition::RateOfChange { op, threshold, clear } => {
+                if matches!(self, AlertCondition::RateOfChange { .. }) {
+                    write!(f, "rate ")?;
+                }
+
+                write!(f, "{} {}", op, threshold)?;
+
+                if let Some(clear) = clear {
+                    write!(f, " clear {}", clear)?;
+                }
+
+                Ok(())
+            }
+            AlertCondition::Absent { after } => write!(f, "absent {}", format_duration(after)),
+        }
+    }
+}
+
+impl Display for AlertState {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            AlertState::Pending => write!(f, "pending"),
+            AlertState::Firing => write!(f, "firing"),
+            AlertState::Resolved => write!(f, "resolved"),
+        }
+    }
+}
+
+impl AlertRule {
+    /// Creates a rule for all sources that fires as soon as the condition holds, named after the metric glob.
+    pub fn new(metric: &str, condition: AlertCondition) -> AlertRule {
+        AlertRule {
+            name: metric.to_string(),
+            source: String::from("*"),
+            metric: metric.to_string(),
+            condition,
+            for_duration: Duration::ZERO,
+        }
+    }
+
+    pub fn matches(&self, key: &MetricKey) -> bool {
+        glob_match(&self.source, key.get_source()) && glob_match(&self.metric, key.get_name())
+    }
+}
+
+/// Formats in the syntax accepted by `from_str`.
+impl Display for AlertRule {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {} {}", self.name, self.metric, self.condition)?;
+
+        if !self.for_duration.is_zero() {
+            write!(f, " for {}", format_duration(&self.for_duration))?;
+        }
+
+        if self.source != "*" {
+            write!(f, " @{}", self.source)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Parses `[<name>:] <metric glob> <condition> [clear <level>] [for <duration>] [@<source glob>]`,
+/// the condition being `<op> <threshold>`, `rate <op> <threshold>` or `absent <duration>`,
+/// e.g. `hot: temp_* > 90 clear 80 for 30s @tcp://*`.
+impl FromStr for AlertRule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut tokens = s.split_whitespace().peekable();
+
+        let mut name = None;
+
+        if let Some(rule_name) = tokens.peek().and_then(|t| t.strip_suffix(':')) {
+            name = Some(rule_name.to_string());
+            tokens.next();
+        }
+
+        let metric = tokens.next().ok_or_else(|| String::from("missing metric name or glob"))?;
+
+        let mut condition_token = tokens.next().ok_or_else(|| String::from("missing alert condition"))?;
+
+        let parse_level = |level: &str| f64::from_str(level).map_err(|_| format!("invalid level '{}'", level));
+
+        let condition = if condition_token == "absent" {
+            let after = tokens.next().ok_or_else(|| String::from("missing duration after 'absent'"))?;
+
+            AlertCondition::Absent { after: parse_duration(after)? }
+        } else {
+            let is_rate = condition_token == "rate";
+
+            if is_rate {
+                condition_token = tokens.next().ok_or_else(|| String::from("missing comparison after 'rate'"))?;
+            }
+
+            let (op, mut threshold) = CompareOp::split_prefix(condition_token)
+                .ok_or_else(|| format!("unknown condition '{}', expected a comparison, rate or absent", condition_token))?;
+
+            if threshold.is_empty() {
+                threshold = tokens.next().ok_or_else(|| format!("missing threshold after '{}'", op))?;
+            }
+
+            let threshold = parse_level(threshold)?;
+
+            if is_rate {
+                AlertCondition::RateOfChange { op, threshold, clear: None }
+            } else {
+                AlertCondition::Threshold { op, threshold, clear: None }
+            }
+        };
+
+        let mut rule = AlertRule::new(metric, condition);
+
+        if let Some(name) = name {
+            rule.name = name;
+        }
+
+        while let Some(token) = tokens.next() {
+            if let Some(source) = token.strip_prefix('@') {
+                rule.source = source.to_string();
+                continue;
+            }
+
+            let value = tokens.next().ok_or_else(|| format!("missing value after '{}'", token))?;
+
+            match (token, &mut rule.condition) {
+                ("clear", AlertCondition::Threshold { clear, .. } | AlertCondition::RateOfChange { clear, .. }) => {
+                    *clear = Some(parse_level(value)?);
+                }
+                ("for", _) => rule.for_duration = parse_duration(value)?,
+                _ => return Err(format!("unexpected '{}'", token)),
+            }
+        }
+
+        rule.condition.check_clear()?;
+
+        Ok(rule)
+    }
+}
+
+/// Reads `{"name": "...", "source": "...", "metric": "...", "op": ">", "threshold": 90, "clear": 80,
+/// "rate": true, "for": "30s"}` or `{"metric": "...", "absent": "10s"}`, `metric` is always required.
+impl TryFrom<&JsonValue> for AlertRule {
+    type Error = String;
+
+    fn try_from(value: &JsonValue) -> Result<Self, Self::Error> {
+        if !value.is_object() {
+            return Err(String::from("alert must be an object"));
+        }
+
+        let metric = value["metric"].as_str().ok_or_else(|| String::from("alert is missing 'metric'"))?;
+
+        let condition = if let Some(after) = value["absent"].as_str() {
+            AlertCondition::Absent { after: parse_duration(after)? }
+        } else {
+            let op = CompareOp::from_str(value["op"].as_str().ok_or_else(|| format!("alert for '{}' is missing 'op'", metric))?)?;
+
+            let threshold = value["threshold"].as_f64().ok_or_else(|| format!("alert for '{}' is missing 'threshold'", metric))?;
+
+            let clear = value["clear"].as_f64();
+
+            if value["rate"].as_bool().unwrap_or(false) {
+                AlertCondition::RateOfChange { op, threshold, clear }
+            } else {
+                AlertCondition::Threshold { op, threshold, clear }
+            }
+        };
+
+        condition.check_clear()?;
+
+        let mut rule = AlertRule::new(metric, condition);
+
+        if let Some(name) = value["name"].as_str() {
+            rule.name = name.to_string();
+        }
+
+        if let Some(source) = value["source"].as_str() {
+            rule.source = source.to_string();
+        }
+
+        if let Some(for_duration) = value["for"].as_str() {
+            rule.for_duration = parse_duration(for_duration)?;
+        }
+
+        Ok(rule)
+    }
+}
+
+/// Parses an alerts file, a json array of alert objects.
+pub fn parse_alerts_file(content: &str) -> Result<Vec<AlertRule>, String> {
+    let alerts_obj = json::parse(content).map_err(|e| e.to_string())?;
+
+    if !alerts_obj.is_array() {
+        return Err(String::from("alerts file must contain an array of alerts"));
+    }
+
+    alerts_obj
+        .members()
+        .enumerate()
+        .map(|(idx, alert_obj)| AlertRule::try_from(alert_obj).map_err(|msg| format!("alert {}: {}", idx + 1, msg)))
+        .collect()
+}
+
+impl Alert {
+    /// Creates the alert of a metric whose condition just started to hold.
+    pub fn new(rule_id: usize, rule: &AlertRule, key: &MetricKey, now: u64) -> Alert {
+        Alert {
+            rule_id,
+            rule_name: rule.name.clone(),
+            key: key.clone(),
+            state: AlertState::Resolved,
+            since: now,
+            value: None,
+        }
+    }
+
+    pub fn is_firing(&self) -> bool {
+        self.state == AlertState::Firing
+    }
+
+    /// Moves the alert on given whether its condition holds at `now`, returns true if the state changed.
+    pub fn advance(&mut self, holds: bool, now: u64, for_duration: Duration) -> bool {
+        let previous_state = self.state;
+
+        if holds && self.state == AlertState::Resolved {
+            self.state = AlertState::Pending;
+            self.since = now;
+        }
+
+        self.state = match (self.state, holds) {
+            (AlertState::Pending, true) if now.saturating_sub(self.since) >= for_duration.as_micros() as u64 => AlertState::Firing,
+            (AlertState::Pending | AlertState::Firing, false) => AlertState::Resolved,
+            (state, _) => state,
+        };
+
+        if self.state != previous_state {
+            if self.state != AlertState::Pending {
+                self.since = now;
+            }
+
+            return true;
+        }
+
+        false
+    }
+}
+
+impl Display for Alert {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} on {}{}", self.rule_name, self.state, self.key.get_name(), self.key.get_labels())?;
+
+        if let Some(value) = self.value {
+            write!(f, " ({})", value)?;
+        }
+
+        Ok(())
+    }
+}
+
+
+#[test]
+fn alert_rule_test01() {
+    use crate::aggregator::aggregator::MetricAggregator;
+    use crate::common::metric::{Metric, MetricUnit, MetricValue};
+
+    let rule = AlertRule::from_str("hot: temp_* > 90 clear 80 for 1s @tcp://*").unwrap();
+
+    assert_eq!(rule.condition, AlertCondition::Threshold { op: CompareOp::Greater, threshold: 90.0, clear: Some(80.0) });
+    assert_eq!(rule.for_duration, Duration::from_secs(1));
+    assert_eq!(AlertRule::from_str(&rule.to_string()).unwrap(), rule);
+
+    let rate = AlertRule::from_str("rx_bytes rate >=1000").unwrap();
+
+    assert_eq!(rate.name, "rx_bytes");
+    assert_eq!(AlertRule::from_str(&rate.to_string()).unwrap(), rate);
+    assert_eq!(AlertRule::from_str("stale: * absent 10s").unwrap().condition, AlertCondition::Absent { after: Duration::from_secs(10) });
+
+    for invalid in ["temp > 90 clear 95", "temp ~ 90", "temp absent", "temp > x", "temp > 1 for"] {
+        assert!(AlertRule::from_str(invalid).is_err(), "{}", invalid);
+    }
+
+    let alerts = parse_alerts_file(r#"[
+        {"name": "hot", "metric": "temp", "op": ">", "threshold": 90, "clear": 80, "for": "1s"},
+        {"metric": "temp", "absent": "100ms"}
+    ]"#).unwrap();
+
+    assert_eq!(alerts[0], AlertRule { source: String::from("*"), metric: String::from("temp"), ..rule });
+    assert!(parse_alerts_file(r#"[{"metric": "temp", "op": ">"}]"#).is_err());
+
+    let mut aggregator = MetricAggregator::new();
+
+    for alert_rule in alerts {
+        aggregator.add_alert_rule(alert_rule);
+    }
+
+    let temp = |value: i64| [Metric::new(String::from("temp"), MetricUnit::empty(), MetricValue::Integer(value))];
+
+    let key = MetricKey::new("src", "temp");
+
+    let mut states = Vec::new();
+
+    // Pending above 90, firing after a second, kept firing above the clear level of 80
+    for (timestamp, value) in [(0, 95), (500000, 92), (1000000, 93), (1500000, 85), (2000000, 75)] {
+        aggregator.handle_metrics("src", timestamp, &temp(value));
+
+        states.extend(aggregator.take_alert_events().into_iter().map(|alert| (timestamp, alert.state)));
+    }
+
+    assert_eq!(states, vec![(0, AlertState::Pending), (1000000, AlertState::Firing), (2000000, AlertState::Resolved)]);
+
+    // Absence is measured on the local clock, no collection is needed to notice it
+    aggregator.evaluate_alerts();
+
+    assert!(aggregator.take_alert_events().is_empty());
+
+    std::thread::sleep(Duration::from_millis(150));
+
+    aggregator.evaluate_alerts();
+
+    let absent = aggregator.take_alert_events();
+
+    assert_eq!(absent.len(), 1);
+    assert_eq!((&absent[0].key, absent[0].state), (&key, AlertState::Firing));
+    assert!(aggregator.get_alerts().iter().any(|alert| alert.key == key && alert.is_firing()));
+}
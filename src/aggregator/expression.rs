@@ -0,0 +1,847 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+use crate::aggregator::aggregator::MetricKey;
+use crate::aggregator::history::{MetricHistory, RateWindow};
+use crate::common::label_view::LabelFilter;
+use crate::common::metric::MetricLabels;
+
+/// Selects all series of one metric name, optionally narrowed down by a label filter,
+/// e.g. `rx_packets{device=eth0}`. Names that aren't plain identifiers are quoted, e.g. `"rx_packets-ps"`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Selector {
+    pub name: String,
+    pub filter: LabelFilter,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Min,
+    Max,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AggregateOp {
+    Sum,
+    Avg,
+    Min,
+    Max,
+}
+
+/// Parsed derived metric expression.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Number(f64),
+    Select(Selector),
+    // Per second increase of a counter over the rate window
+    Rate(Selector),
+    // Average over the most recent `depth` history values
+    AvgOver(Selector, usize),
+    Neg(Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    Aggregate {
+        op: AggregateOp,
+        by: Vec<String>,
+        expr: Box<Expr>,
+    },
+    Clamp {
+        expr: Box<Expr>,
+        min: f64,
+        max: f64,
+    },
+}
+
+/// Metric computed from an expression over other metrics, e.g. `drop_rate = rx_dropped / rx_packets`.
+///
+/// Expressions are evaluated per source, every resulting series becomes a metric named `name`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DerivedMetric {
+    pub name: String,
+    pub expr: Expr,
+}
+
+/// Series handed to the evaluation, `history` is None for metrics without history.
+pub struct Series<'a> {
+    pub key: &'a MetricKey,
+    pub value: f64,
+    pub history: Option<&'a MetricHistory>,
+}
+
+/// One series of an evaluation result, `inputs` are the metrics it was computed from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sample {
+    pub labels: MetricLabels,
+    pub value: f64,
+    pub inputs: Vec<MetricKey>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Scalar(f64),
+    Vector(Vec<Sample>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Name(String),
+    Quoted(String),
+    Labels(String),
+    LParen,
+    RParen,
+    Comma,
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    End,
+}
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    pos: usize,
+}
+
+impl Selector {
+    pub fn matches(&self, key: &MetricKey) -> bool {
+        key.get_name() == self.name && self.filter.matches(key.get_labels())
+    }
+}
+
+fn is_name_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_' || c == ':'
+}
+
+fn is_name_char(c: char) -> bool {
+    is_name_start(c) || c.is_ascii_digit() || c == '.'
+}
+
+impl Display for Selector {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if self.name.starts_with(is_name_start) && self.name.chars().all(is_name_char) {
+            write!(f, "{}", self.name)?;
+        } else {
+            write!(f, "\"{}\"", self.name.replace('\\', "\\\\").replace('"', "\\\""))?;
+        }
+
+        if !self.filter.is_empty() {
+            write!(f, "{{{}}}", self.filter)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl BinaryOp {
+    fn apply(&self, lhs: f64, rhs: f64) -> f64 {
+        match self {
+            BinaryOp::Add => lhs + rhs,
+            BinaryOp::Sub => lhs - rhs,
+            BinaryOp::Mul => lhs * rhs,
+            BinaryOp::Div => lhs / rhs,
+            BinaryOp::Min => lhs.min(rhs),
+            BinaryOp::Max => lhs.max(rhs),
+        }
+    }
+
+    fn get_symbol(&self) -> &'static str {
+        match self {
+            BinaryOp::Add => "+",
+            BinaryOp::Sub => "-",
+            BinaryOp::Mul => "*",
+            BinaryOp::Div => "/",
+            BinaryOp::Min => "min",
+            BinaryOp::Max => "max",
+        }
+    }
+}
+
+impl AggregateOp {
+    fn from_name(name: &str) -> Option<AggregateOp> {
+        match name {
+            "sum" => Some(AggregateOp::Sum),
+            "avg" => Some(AggregateOp::Avg),
+            "min" => Some(AggregateOp::Min),
+            "max" => Some(AggregateOp::Max),
+            _ => None,
+        }
+    }
+
+    fn get_name(&self) -> &'static str {
+        match self {
+            AggregateOp::Sum => "sum",
+            AggregateOp::Avg => "avg",
+            AggregateOp::Min => "min",
+            AggregateOp::Max => "max",
+        }
+    }
+
+    fn apply(&self, values: &[f64]) -> f64 {
+        match self {
+            AggregateOp::Sum => values.iter().sum(),
+            AggregateOp::Avg => values.iter().sum::<f64>() / values.len() as f64,
+            AggregateOp::Min => values.iter().cloned().fold(f64::INFINITY, f64::min),
+            AggregateOp::Max => values.iter().cloned().fold(f64::NEG_INFINITY, f64::max),
+        }
+    }
+}
+
+impl Expr {
+    fn is_arithmetic(&self) -> bool {
+        matches!(self, Expr::Binary(op, _, _) if *op != BinaryOp::Min && *op != BinaryOp::Max)
+    }
+
+    // Writes a sub expression, arithmetic is put in parentheses to keep the structure when parsed again
+    fn fmt_operand(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if self.is_arithmetic() {
+            write!(f, "({})", self)
+        } else {
+            write!(f, "{}", self)
+        }
+    }
+
+    /// Evaluates the expression, `select` returns the series matching a selector.
+    pub fn eval<'a>(&self, select: &dyn Fn(&Selector) -> Vec<Series<'a>>, rate_window: &RateWindow) -> Value {
+        match self {
+            Expr::Number(value) => Value::Scalar(*value),
+            Expr::Select(selector) => Value::Vector(
+                select(selector)
+                    .into_iter()
+                    .map(|series| Sample::from_series(&series, series.value))
+                    .collect(),
+            ),
+            Expr::Rate(selector) => Value::Vector(
+                select(selector)
+                    .into_iter()
+                    .filter_map(|series| {
+                        let (increase, time_diff_us) = series.history?.get_increase(rate_window)?;
+
+                        Some(Sample::from_series(&series, increase / (time_diff_us as f64 * 1e-6f64)))
+                    })
+                    .collect(),
+            ),
+            Expr::AvgOver(selector, depth) => Value::Vector(
+                select(selector)
+                    .into_iter()
+                    .filter_map(|series| {
+                        let values: Vec<f64> = series.history?.iter().rev().take(*depth).map(|e| e.1).collect();
+
+                        if values.is_empty() {
+                            return None;
+                        }
+
+                        Some(Sample::from_series(&series, AggregateOp::Avg.apply(&values)))
+                    })
+                    .collect(),
+            ),
+            Expr::Neg(expr) => expr.eval(select, rate_window).map(|value| -value),
+            Expr::Binary(op, lhs, rhs) => eval_binary(*op, lhs.eval(select, rate_window), rhs.eval(select, rate_window)),
+            Expr::Aggregate { op, by, expr } => match expr.eval(select, rate_window) {
+                Value::Scalar(value) => Value::Scalar(value),
+                Value::Vector(samples) => {
+                    let mut groups: BTreeMap<MetricLabels, (Vec<f64>, Vec<MetricKey>)> = BTreeMap::new();
+
+                    for sample in samples {
+                        let group_labels: MetricLabels = sample.labels.iter().filter(|(k, _)| by.iter().any(|b| b == k)).collect();
+
+                        let group = groups.entry(group_labels).or_default();
+
+                        group.0.push(sample.value);
+                        group.1.extend(sample.inputs);
+                    }
+
+                    Value::Vector(
+                        groups
+                            .into_iter()
+                            .map(|(labels, (values, inputs))| Sample {
+                                labels,
+                                value: op.apply(&values),
+                                inputs,
+                            })
+                            .collect(),
+                    )
+                }
+            },
+            Expr::Clamp { expr, min, max } => expr.eval(select, rate_window).map(|value| value.clamp(*min, *max)),
+        }
+    }
+}
+
+impl Display for Expr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Expr::Number(value) => write!(f, "{}", value),
+            Expr::Select(selector) => write!(f, "{}", selector),
+            Expr::Rate(selector) => write!(f, "rate({})", selector),
+            Expr::AvgOver(selector, depth) => write!(f, "avg_over({}, {})", selector, depth),
+            Expr::Neg(expr) => {
+                write!(f, "-")?;
+                expr.fmt_operand(f)
+            }
+            Expr::Binary(op @ (BinaryOp::Min | BinaryOp::Max), lhs, rhs) => {
+                write!(f, "{}({}, {})", op.get_symbol(), lhs, rhs)
+            }
+            Expr::Binary(op, lhs, rhs) => {
+                lhs.fmt_operand(f)?;
+                write!(f, " {} ", op.get_symbol())?;
+                rhs.fmt_operand(f)
+            }
+            Expr::Aggregate { op, by, expr } => {
+                write!(f, "{}", op.get_name())?;
+
+                if !by.is_empty() {
+                    write!(f, " by ({}) ", by.join(", "))?;
+                }
+
+                write!(f, "({})", expr)
+            }
+            Expr::Clamp { expr, min, max } => write!(f, "clamp({}, {}, {})", expr, min, max),
+        }
+    }
+}
+
+impl Sample {
+    fn from_series(series: &Series, value: f64) -> Sample {
+        Sample {
+            labels: series.key.get_labels().clone(),
+            value,
+            inputs: vec![series.key.clone()],
+        }
+    }
+}
+
+impl Value {
+    fn map(self, f: impl Fn(f64) -> f64) -> Value {
+        match self {
+            Value::Scalar(value) => Value::Scalar(f(value)),
+            Value::Vector(samples) => Value::Vector(
+                samples
+                    .into_iter()
+                    .map(|sample| Sample { value: f(sample.value), ..sample })
+                    .collect(),
+            ),
+        }
+    }
+
+    // A vector holding a single series without labels, like the result of `sum(...)`
+    fn as_unlabeled(&self) -> Option<&Sample> {
+        match self {
+            Value::Vector(samples) if samples.len() == 1 && samples[0].labels.is_empty() => Some(&samples[0]),
+            _ => None,
+        }
+    }
+}
+
+fn broadcast(samples: Vec<Sample>, other: &Sample, f: impl Fn(f64, f64) -> f64) -> Value {
+    Value::Vector(
+        samples
+            .into_iter()
+            .map(|mut sample| {
+                sample.value = f(sample.value, other.value);
+                sample.inputs.extend(other.inputs.iter().cloned());
+                sample
+            })
+            .collect(),
+    )
+}
+
+// Vectors are matched on identical labels, series without a counterpart are dropped. A vector holding
+// one unlabeled series is applied to every series of the other side, just like a scalar.
+fn eval_binary(op: BinaryOp, lhs: Value, rhs: Value) -> Value {
+    if let Some(rhs_sample) = rhs.as_unlabeled().cloned() {
+        if let Value::Vector(lhs_samples) = lhs {
+            return broadcast(lhs_samples, &rhs_sample, |l, r| op.apply(l, r));
+        }
+    }
+
+    if let Some(lhs_sample) = lhs.as_unlabeled().cloned() {
+        if let Value::Vector(rhs_samples) = rhs {
+            return broadcast(rhs_samples, &lhs_sample, |r, l| op.apply(l, r));
+        }
+    }
+
+    match (lhs, rhs) {
+        (Value::Scalar(l), Value::Scalar(r)) => Value::Scalar(op.apply(l, r)),
+        (Value::Vector(samples), Value::Scalar(r)) => Value::Vector(samples).map(|l| op.apply(l, r)),
+        (Value::Scalar(l), Value::Vector(samples)) => Value::Vector(samples).map(|r| op.apply(l, r)),
+        (Value::Vector(lhs_samples), Value::Vector(rhs_samples)) => {
+            let rhs_by_labels: HashMap<&MetricLabels, &Sample> = rhs_samples.iter().map(|s| (&s.labels, s)).collect();
+
+            Value::Vector(
+                lhs_samples
+                    .into_iter()
+                    .filter_map(|mut sample| {
+                        let rhs_sample = rhs_by_labels.get(&sample.labels)?;
+
+                        sample.value = op.apply(sample.value, rhs_sample.value);
+                        sample.inputs.extend(rhs_sample.inputs.iter().cloned());
+
+                        Some(sample)
+                    })
+                    .collect(),
+            )
+        }
+    }
+}
+
+fn tokenize(text: &str) -> Result<Vec<(usize, Token)>, String> {
+    let chars: Vec<(usize, char)> = text.char_indices().collect();
+
+    let mut tokens = Vec::new();
+
+    let mut idx = 0;
+
+    while idx < chars.len() {
+        let (pos, c) = chars[idx];
+
+        let single = match c {
+            '(' => Some(Token::LParen),
+            ')' => Some(Token::RParen),
+            ',' => Some(Token::Comma),
+            '+' => Some(Token::Plus),
+            '-' => Some(Token::Minus),
+            '*' => Some(Token::Star),
+            '/' => Some(Token::Slash),
+            _ => None,
+        };
+
+        if let Some(token) = single {
+            tokens.push((pos, token));
+            idx += 1;
+        } else if c.is_whitespace() {
+            idx += 1;
+        } else if c.is_ascii_digit() || c == '.' {
+            let start = idx;
+
+            while idx < chars.len() && (chars[idx].1.is_ascii_digit() || chars[idx].1 == '.') {
+                idx += 1;
+            }
+
+            if idx < chars.len() && (chars[idx].1 == 'e' || chars[idx].1 == 'E') {
+                idx += 1;
+
+                if idx < chars.len() && (chars[idx].1 == '+' || chars[idx].1 == '-') {
+                    idx += 1;
+                }
+
+                while idx < chars.len() && chars[idx].1.is_ascii_digit() {
+                    idx += 1;
+                }
+            }
+
+            let number_str: String = chars[start..idx].iter().map(|(_, c)| c).collect();
+
+            let number = f64::from_str(&number_str).map_err(|_| format!("invalid number '{}' at position {}", number_str, pos))?;
+
+            tokens.push((pos, Token::Number(number)));
+        } else if is_name_start(c) {
+            let start = idx;
+
+            while idx < chars.len() && is_name_char(chars[idx].1) {
+                idx += 1;
+            }
+
+            tokens.push((pos, Token::Name(chars[start..idx].iter().map(|(_, c)| c).collect())));
+        } else if c == '"' {
+            let mut name = String::new();
+
+            idx += 1;
+
+            loop {
+                match chars.get(idx).map(|(_, c)| *c) {
+                    Some('"') => break,
+                    Some('\\') if idx + 1 < chars.len() => {
+                        name.push(chars[idx + 1].1);
+                        idx += 2;
+                    }
+                    Some(c) => {
+                        name.push(c);
+                        idx += 1;
+                    }
+                    None => return Err(format!("unterminated quoted name at position {}", pos)),
+                }
+            }
+
+            idx += 1;
+
+            tokens.push((pos, Token::Quoted(name)));
+        } else if c == '{' {
+            let start = idx + 1;
+
+            while idx < chars.len() && chars[idx].1 != '}' {
+                idx += 1;
+            }
+
+            if idx == chars.len() {
+                return Err(format!("unterminated label filter at position {}", pos));
+            }
+
+            tokens.push((pos, Token::Labels(chars[start..idx].iter().map(|(_, c)| c).collect())));
+
+            idx += 1;
+        } else {
+            return Err(format!("unexpected '{}' at position {}", c, pos));
+        }
+    }
+
+    tokens.push((text.len(), Token::End));
+
+    Ok(tokens)
+}
+
+impl Parser {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.pos].1
+    }
+
+    fn next(&mut self) -> Token {
+        let token = self.tokens[self.pos].1.clone();
+
+        if token != Token::End {
+            self.pos += 1;
+        }
+
+        token
+    }
+
+    fn error<T>(&self, msg: &str) -> Result<T, String> {
+        Err(format!("{} at position {}", msg, self.tokens[self.pos].0))
+    }
+
+    fn expect(&mut self, expected: Token, what: &str) -> Result<(), String> {
+        if *self.peek() == expected {
+            self.next();
+            Ok(())
+        } else {
+            self.error(&format!("expected {}", what))
+        }
+    }
+
+    fn parse_expr(&mut self) -> Result<Expr, String> {
+        let mut expr = self.parse_term()?;
+
+        loop {
+            let op = match self.peek() {
+                Token::Plus => BinaryOp::Add,
+                Token::Minus => BinaryOp::Sub,
+                _ => return Ok(expr),
+            };
+
+            self.next();
+
+            expr = Expr::Binary(op, Box::new(expr), Box::new(self.parse_term()?));
+        }
+    }
+
+    fn parse_term(&mut self) -> Result<Expr, String> {
+        let mut expr = self.parse_unary()?;
+
+        loop {
+            let op = match self.peek() {
+                Token::Star => BinaryOp::Mul,
+                Token::Slash => BinaryOp::Div,
+                _ => return Ok(expr),
+            };
+
+            self.next();
+
+            expr = Expr::Binary(op, Box::new(expr), Box::new(self.parse_unary()?));
+        }
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, String> {
+        if *self.peek() != Token::Minus {
+            return self.parse_primary();
+        }
+
+        self.next();
+
+        Ok(match self.parse_unary()? {
+            Expr::Number(value) => Expr::Number(-value),
+            expr => Expr::Neg(Box::new(expr)),
+        })
+    }
+
+    fn parse_number(&mut self) -> Result<f64, String> {
+        match self.parse_unary()? {
+            Expr::Number(value) => Ok(value),
+            _ => self.error("expected a number"),
+        }
+    }
+
+    fn parse_selector(&mut self, name: String) -> Result<Selector, String> {
+        let filter = if let Token::Labels(filter_str) = self.peek() {
+            let filter = LabelFilter::try_from(filter_str.as_str())?;
+
+            self.next();
+
+            filter
+        } else {
+            LabelFilter::default()
+        };
+
+        Ok(Selector { name, filter })
+    }
+
+    fn parse_selector_arg(&mut self) -> Result<Selector, String> {
+        match self.next() {
+            Token::Name(name) | Token::Quoted(name) => self.parse_selector(name),
+            _ => self.error("expected a metric selector"),
+        }
+    }
+
+    // Optional `by (label, ...)` clause of an aggregation
+    fn parse_by(&mut self) -> Result<Option<Vec<String>>, String> {
+        if *self.peek() != Token::Name(String::from("by")) {
+            return Ok(None);
+        }
+
+        self.next();
+
+        self.expect(Token::LParen, "'(' after by")?;
+
+        let mut by = Vec::new();
+
+        loop {
+            match self.next() {
+                Token::Name(label_key) => by.push(label_key),
+                _ => return self.error("expected a label key"),
+            }
+
+            match self.next() {
+                Token::Comma => continue,
+                Token::RParen => return Ok(Some(by)),
+                _ => return self.error("expected ',' or ')'"),
+            }
+        }
+    }
+
+    fn parse_aggregation(&mut self, op: AggregateOp) -> Result<Expr, String> {
+        let by_before = self.parse_by()?;
+
+        self.expect(Token::LParen, "'('")?;
+
+        let expr = self.parse_expr()?;
+
+        // min and max with two arguments compare per series instead of aggregating
+        if by_before.is_none() && *self.peek() == Token::Comma && (op == AggregateOp::Min || op == AggregateOp::Max) {
+            self.next();
+
+            let rhs = self.parse_expr()?;
+
+            self.expect(Token::RParen, "')'")?;
+
+            let binary_op = if op == AggregateOp::Min { BinaryOp::Min } else { BinaryOp::Max };
+
+            return Ok(Expr::Binary(binary_op, Box::new(expr), Box::new(rhs)));
+        }
+
+        self.expect(Token::RParen, "')'")?;
+
+        let by = match by_before {
+            Some(by) => by,
+            None => self.parse_by()?.unwrap_or_default(),
+        };
+
+        Ok(Expr::Aggregate { op, by, expr: Box::new(expr) })
+    }
+
+    fn parse_function(&mut self, name: &str) -> Result<Expr, String> {
+        if let Some(op) = AggregateOp::from_name(name) {
+            return self.parse_aggregation(op);
+        }
+
+        self.expect(Token::LParen, "'('")?;
+
+        let expr = match name {
+            "rate" => Expr::Rate(self.parse_selector_arg()?),
+            "avg_over" => {
+                let selector = self.parse_selector_arg()?;
+
+                self.expect(Token::Comma, "',' before the depth")?;
+
+                let depth = self.parse_number()?;
+
+                if depth < 1.0 || depth.fract() != 0.0 {
+                    return self.error(&format!("invalid average depth {}", depth));
+                }
+
+                Expr::AvgOver(selector, depth as usize)
+            }
+            "clamp" => {
+                let expr = self.parse_expr()?;
+
+                self.expect(Token::Comma, "',' before the lower bound")?;
+
+                let min = self.parse_number()?;
+
+                self.expect(Token::Comma, "',' before the upper bound")?;
+
+                let max = self.parse_number()?;
+
+                if min > max {
+                    return self.error(&format!("clamp bounds {} > {}", min, max));
+                }
+
+                Expr::Clamp { expr: Box::new(expr), min, max }
+            }
+            _ => return self.error(&format!("unknown function '{}'", name)),
+        };
+
+        self.expect(Token::RParen, "')'")?;
+
+        Ok(expr)
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, String> {
+        match self.next() {
+            Token::Number(value) => Ok(Expr::Number(value)),
+            Token::LParen => {
+                let expr = self.parse_expr()?;
+
+                self.expect(Token::RParen, "')'")?;
+
+                Ok(expr)
+            }
+            Token::Quoted(name) => Ok(Expr::Select(self.parse_selector(name)?)),
+            Token::Name(name) => {
+                let is_call = *self.peek() == Token::LParen
+                    || (AggregateOp::from_name(&name).is_some() && *self.peek() == Token::Name(String::from("by")));
+
+                if is_call {
+                    self.parse_function(&name)
+                } else {
+                    Ok(Expr::Select(self.parse_selector(name)?))
+                }
+            }
+            Token::End => self.error("unexpected end of expression"),
+            _ => {
+                self.pos -= 1;
+                self.error("expected a number, metric or function")
+            }
+        }
+    }
+}
+
+impl FromStr for Expr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser { tokens: tokenize(s)?, pos: 0 };
+
+        let expr = parser.parse_expr()?;
+
+        if *parser.peek() != Token::End {
+            return parser.error("unexpected trailing input");
+        }
+
+        Ok(expr)
+    }
+}
+
+impl DerivedMetric {
+    /// Evaluates the expression, a scalar result becomes a single series without labels.
+    pub fn evaluate<'a>(&self, select: &dyn Fn(&Selector) -> Vec<Series<'a>>, rate_window: &RateWindow) -> Vec<Sample> {
+        match self.expr.eval(select, rate_window) {
+            Value::Scalar(value) => vec![Sample { labels: MetricLabels::new(), value, inputs: Vec::new() }],
+            Value::Vector(samples) => samples,
+        }
+    }
+}
+
+impl Display for DerivedMetric {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{} = {}", self.name, self.expr)
+    }
+}
+
+/// Parses `<name> = <expression>`.
+impl FromStr for DerivedMetric {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, expr_str) = s.split_once('=').ok_or_else(|| String::from("expected '<name> = <expression>'"))?;
+
+        let name = name.trim();
+
+        if name.is_empty() || name.contains(char::is_whitespace) {
+            return Err(format!("invalid metric name '{}'", name));
+        }
+
+        Ok(DerivedMetric {
+            name: name.to_string(),
+            expr: Expr::from_str(expr_str)?,
+        })
+    }
+}
+
+
+#[test]
+fn expression_parse_eval_test01() {
+    use crate::aggregator::aggregator::CounterWrap;
+    use crate::aggregator::history::HistoryConfig;
+
+    let expr = Expr::from_str("sum by (device) (rx_dropped{queue!=1}) / sum(rx_packets) * 100").unwrap();
+
+    assert_eq!(Expr::from_str(&expr.to_string()).unwrap(), expr);
+
+    for text in ["clamp(-x + 1, 0, 1)", "max(rate(a), \"b-ps\"{k=v})", "avg_over(a, 8) - -2", "min(a) by (k)"] {
+        let expr = Expr::from_str(text).unwrap();
+
+        assert_eq!(Expr::from_str(&expr.to_string()).unwrap(), expr, "{}", text);
+    }
+
+    for invalid in ["a +", "rate(1)", "clamp(a, 2, 1)", "foo(a)", "a b", "a{=x}", "(a"] {
+        assert!(Expr::from_str(invalid).is_err(), "{}", invalid);
+    }
+
+    let keys: Vec<MetricKey> = [("rx_dropped", "eth0", 5), ("rx_dropped", "eth1", 0), ("rx_packets", "eth0", 100), ("rx_packets", "eth1", 300)]
+        .iter()
+        .map(|(name, device, _)| MetricKey::with_labels("src", name, [("device", *device)].into_iter().collect()))
+        .collect();
+
+    let histories: Vec<MetricHistory> = [5, 0, 100, 300]
+        .iter()
+        .map(|value| {
+            let mut history = MetricHistory::new(&HistoryConfig::default());
+
+            history.push(0, 0.0);
+            history.push(1000000, *value as f64);
+
+            history
+        })
+        .collect();
+
+    let rate_window = RateWindow { window_us: 60000000, counter_wrap: CounterWrap::None };
+
+    let select = |selector: &Selector| -> Vec<Series> {
+        keys.iter()
+            .zip(histories.iter())
+            .filter(|(key, _)| selector.matches(key))
+            .map(|(key, history)| Series { key, value: history.newest().unwrap().1, history: Some(history) })
+            .collect()
+    };
+
+    let drop_rate = DerivedMetric::from_str("drop_rate = rx_dropped / rx_packets").unwrap();
+
+    let samples = drop_rate.evaluate(&select, &rate_window);
+
+    assert_eq!(samples.len(), 2);
+    assert_eq!(samples[0].value, 0.05);
+    assert_eq!(samples[0].inputs, vec![keys[0].clone(), keys[2].clone()]);
+
+    let share = DerivedMetric::from_str("share = rate(rx_packets) / sum(rate(rx_packets))").unwrap().evaluate(&select, &rate_window);
+
+    assert_eq!(share.iter().map(|s| s.value).collect::<Vec<_>>(), vec![0.25, 0.75]);
+
+    let total = DerivedMetric::from_str("total = clamp(sum(rx_dropped{device=eth0}) - 10, 0, 100)").unwrap().evaluate(&select, &rate_window);
+
+    assert_eq!(total[0].value, 0.0);
+    assert!(total[0].labels.is_empty());
+}
@@ -0,0 +1,500 @@
+use std::collections::VecDeque;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::aggregator::aggregator::{counter_increase, CounterWrap};
+
+// Upper bound on raw samples per metric regardless of retention, keeps memory in check for fast publishers
+const MAX_SAMPLES: usize = 1 << 16;
+
+/// Backward clock steps up to this are taken as jitter, larger ones as a restarted or reset source clock.
+pub const CLOCK_STEP_TOLERANCE_US: u64 = 1000000;
+
+/// Resolution and retention of one rollup tier, written as `<resolution>:<retention>`, e.g. `1s:1h`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RollupSpec {
+    pub resolution: Duration,
+    pub retention: Duration,
+}
+
+/// How long raw samples are kept and which rollup tiers are maintained next to them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HistoryConfig {
+    pub raw_retention: Duration,
+    pub rollups: Vec<RollupSpec>,
+}
+
+// Minimum, maximum and average of the samples within one rollup interval
+#[derive(Debug, Clone, PartialEq)]
+struct Rollup {
+    start: u64,
+    min: f64,
+    max: f64,
+    sum: f64,
+    count: u32,
+}
+
+#[derive(Debug, Clone)]
+struct RollupTier {
+    resolution_us: u64,
+    retention_us: u64,
+    rollups: VecDeque<Rollup>,
+}
+
+/// Time stamped values of one metric, raw samples for a short retention plus coarser rollups for long ranges.
+///
+/// Appending is O(1), samples and rollups that fall out of their retention are dropped from the front.
+#[derive(Debug, Clone)]
+pub struct MetricHistory {
+    // Raw samples, oldest first
+    samples: VecDeque<(u64, f64)>,
+
+    raw_retention_us: u64,
+
+    // Ordered from fine to coarse resolution
+    tiers: Vec<RollupTier>,
+}
+
+/// Time span and counter width counter rates are computed with.
+#[derive(Debug, Clone, Copy)]
+pub struct RateWindow {
+    pub window_us: u64,
+    pub counter_wrap: CounterWrap,
+}
+
+impl FromStr for RollupSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (resolution_str, retention_str) = s
+            .split_once(':')
+            .ok_or_else(|| format!("invalid rollup '{}', expected <resolution>:<retention>", s))?;
+
+        let resolution = crate::common::parse_duration(resolution_str)?;
+        let retention = crate::common::parse_duration(retention_str)?;
+
+        if resolution.as_micros() == 0 || retention < resolution {
+            return Err(format!("invalid rollup '{}', the retention must span at least one non-zero resolution", s));
+        }
+
+        Ok(RollupSpec { resolution, retention })
+    }
+}
+
+/// Parses a comma separated list of rollups, e.g. `1s:1h,1m:24h`.
+pub fn parse_rollup_specs(s: &str) -> Result<Vec<RollupSpec>, String> {
+    s.split(',').filter(|spec| !spec.trim().is_empty()).map(|spec| RollupSpec::from_str(spec.trim())).collect()
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        HistoryConfig {
+            raw_retention: Duration::from_secs(5 * 60),
+            rollups: vec![
+                RollupSpec { resolution: Duration::from_secs(1), retention: Duration::from_secs(60 * 60) },
+                RollupSpec { resolution: Duration::from_secs(60), retention: Duration::from_secs(24 * 60 * 60) },
+            ],
+        }
+    }
+}
+
+impl Rollup {
+    fn new(start: u64, value: f64) -> Rollup {
+        Rollup { start, min: value, max: value, sum: value, count: 1 }
+    }
+
+    fn add(&mut self, value: f64) {
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        self.sum += value;
+        self.count += 1;
+    }
+
+    fn avg(&self) -> f64 {
+        self.sum / self.count as f64
+    }
+}
+
+impl RollupTier {
+    fn push(&mut self, timestamp: u64, value: f64) {
+        let start = timestamp - timestamp % self.resolution_us;
+
+        match self.rollups.back_mut() {
+            Some(rollup) if rollup.start == start => rollup.add(value),
+            _ => self.rollups.push_back(Rollup::new(start, value)),
+        }
+
+        let oldest_kept = timestamp.saturating_sub(self.retention_us);
+
+        while matches!(self.rollups.front(), Some(rollup) if rollup.start + self.resolution_us <= oldest_kept) {
+            self.rollups.pop_front();
+        }
+    }
+
+    fn range_start(&self, from: u64) -> usize {
+        self.rollups.partition_point(|rollup| rollup.start + self.resolution_us <= from)
+    }
+}
+
+impl MetricHistory {
+    pub fn new(config: &HistoryConfig) -> MetricHistory {
+        let mut rollups = config.rollups.clone();
+
+        rollups.sort_by_key(|spec| spec.resolution);
+
+        MetricHistory {
+            samples: VecDeque::new(),
+            raw_retention_us: config.raw_retention.as_micros() as u64,
+            tiers: rollups
+                .iter()
+                .map(|spec| RollupTier {
+                    resolution_us: spec.resolution.as_micros() as u64,
+                    retention_us: spec.retention.as_micros() as u64,
+                    rollups: VecDeque::new(),
+                })
+                .collect(),
+        }
+    }
+
+    /// Appends a sample, updates the rollups and drops everything that fell out of its retention.
+    ///
+    /// A timestamp slightly before the newest sample is recorded at the time of the newest one, so
+    /// the history stays ordered. A step back beyond `CLOCK_STEP_TOLERANCE_US` is a discontinuity,
+    /// e.g. a restarted source or a looped replay, the history starts over from that sample.
+    pub fn push(&mut self, timestamp: u64, value: f64) {
+        let timestamp = match self.samples.back().map(|(newest, _)| *newest) {
+            Some(newest) if timestamp.saturating_add(CLOCK_STEP_TOLERANCE_US) < newest => {
+                self.clear();
+
+                timestamp
+            }
+            Some(newest) => timestamp.max(newest),
+            None => timestamp,
+        };
+
+        self.samples.push_back((timestamp, value));
+
+        let oldest_kept = timestamp.saturating_sub(self.raw_retention_us);
+
+        while self.samples.len() > MAX_SAMPLES || matches!(self.samples.front(), Some((t, _)) if *t < oldest_kept) {
+            self.samples.pop_front();
+        }
+
+        for tier in &mut self.tiers {
+            tier.push(timestamp, value);
+        }
+    }
+
+    /// Switches to `config`, raw samples still within the new retention are kept. Tiers of both configs
+    /// keep their rollups, tiers that are new are rolled up from the raw samples.
+    pub fn reconfigure(&mut self, config: &HistoryConfig) {
+        let mut history = MetricHistory::new(config);
+
+        // Indexes of the new tiers
+        let mut rolled_up = Vec::new();
+
+        for (index, tier) in history.tiers.iter_mut().enumerate() {
+            let kept = self.tiers.iter_mut().find(|old| old.resolution_us == tier.resolution_us && old.retention_us == tier.retention_us);
+
+            match kept {
+                Some(old) => tier.rollups = std::mem::take(&mut old.rollups),
+                None => rolled_up.push(index),
+            }
+        }
+
+        let oldest_kept = self.newest().map(|(newest, _)| newest.saturating_sub(history.raw_retention_us)).unwrap_or(0);
+
+        for (timestamp, value) in self.samples.drain(..) {
+            for index in &rolled_up {
+                history.tiers[*index].push(timestamp, value);
+            }
+
+            if timestamp >= oldest_kept {
+                history.samples.push_back((timestamp, value));
+            }
+        }
+
+        *self = history;
+    }
+
+    /// Drops all raw samples and rollups.
+    pub fn clear(&mut self) {
+        self.samples.clear();
+
+        for tier in &mut self.tiers {
+            tier.rollups.clear();
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    pub fn newest(&self) -> Option<(u64, f64)> {
+        self.samples.back().cloned()
+    }
+
+    pub fn oldest(&self) -> Option<(u64, f64)> {
+        self.samples.front().cloned()
+    }
+
+    /// All raw samples, oldest first.
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &(u64, f64)> + ExactSizeIterator {
+        self.samples.iter()
+    }
+
+    /// Raw samples no older than `duration_us` relative to the newest sample, oldest first.
+    pub fn window(&self, duration_us: u64) -> impl DoubleEndedIterator<Item = &(u64, f64)> + ExactSizeIterator {
+        let start = match self.samples.back() {
+            Some((newest, _)) => {
+                let oldest_included = newest.saturating_sub(duration_us);
+
+                self.samples.partition_point(|(t, _)| *t < oldest_included)
+            }
+            None => 0,
+        };
+
+        self.samples.range(start..)
+    }
+
+    /// Counter increase within the rate window and the time it spans in microseconds,
+    /// None unless the window holds at least two samples.
+    pub fn get_increase(&self, rate_window: &RateWindow) -> Option<(f64, u64)> {
+        let mut samples = self.window(rate_window.window_us);
+
+        let first = *samples.next()?;
+
+        let (increase, last) = samples.fold((0.0f64, first), |(increase, previous), next| {
+            (increase + counter_increase(previous.1, next.1, rate_window.counter_wrap).0, *next)
+        });
+
+        if last.0 > first.0 {
+            Some((increase, last.0 - first.0))
+        } else {
+            None
+        }
+    }
+
+    /// (microseconds, value) samples of the last `range_us` before the newest sample, oldest first.
+    ///
+    /// Raw samples if the raw retention covers the range, otherwise the averages of the finest rollup
+    /// tier that does, or of the coarsest tier if none does.
+    pub fn get_samples(&self, range_us: u64) -> Vec<(u64, f64)> {
+        let newest = match self.samples.back() {
+            Some((newest, _)) => *newest,
+            None => return Vec::new(),
+        };
+
+        let from = newest.saturating_sub(range_us);
+
+        let tier = if self.raw_retention_us >= range_us {
+            None
+        } else {
+            self.tiers.iter().find(|tier| tier.retention_us >= range_us).or_else(|| self.tiers.last())
+        };
+
+        match tier {
+            Some(tier) => tier.rollups.range(tier.range_start(from)..).map(|rollup| (rollup.start, rollup.avg())).collect(),
+            None => self.samples.iter().filter(|(t, _)| *t >= from).cloned().collect(),
+        }
+    }
+
+    /// Fills `data` with (seconds, value) points of the last `range_us` before the newest sample, oldest first.
+    ///
+    /// Raw samples are used if they reach back far enough and fit into `max_points`, otherwise the
+    /// finest rollup tier that does, rollups contribute their average. Falls back to the coarsest tier
+    /// if none fits. Returns the minimum and maximum value within the range.
+    pub fn query(&self, range_us: u64, max_points: usize, data: &mut Vec<(f64, f64)>) -> Option<(f64, f64)> {
+        let newest = self.samples.back()?.0;
+
+        let from = newest.saturating_sub(range_us);
+
+        data.clear();
+
+        let raw_start = self.samples.partition_point(|(t, _)| *t < from);
+
+        let raw_fits = self.raw_retention_us >= range_us && self.samples.len() - raw_start <= max_points;
+
+        let tier = if raw_fits {
+            None
+        } else {
+            self.tiers
+                .iter()
+                .find(|tier| tier.retention_us >= range_us && tier.rollups.len() - tier.range_start(from) <= max_points)
+                .or_else(|| self.tiers.last())
+        };
+
+        let mut limits: Option<(f64, f64)> = None;
+
+        let mut extend_limits = |min: f64, max: f64| {
+            limits = Some(match limits {
+                Some((lower, upper)) => (lower.min(min), upper.max(max)),
+                None => (min, max),
+            });
+        };
+
+        match tier {
+            Some(tier) => {
+                for rollup in tier.rollups.range(tier.range_start(from)..) {
+                    data.push((rollup.start as f64 / 1e6f64, rollup.avg()));
+
+                    extend_limits(rollup.min, rollup.max);
+                }
+            }
+            None => {
+                for (timestamp, value) in self.samples.range(raw_start..) {
+                    data.push((*timestamp as f64 / 1e6f64, *value));
+
+                    extend_limits(*value, *value);
+                }
+            }
+        }
+
+        limits
+    }
+}
+
+
+#[test]
+fn metric_history_retention_test01() {
+    let config = HistoryConfig {
+        raw_retention: Duration::from_secs(60),
+        rollups: parse_rollup_specs("1m:24h, 10s:10m").unwrap(),
+    };
+
+    let mut history = MetricHistory::new(&config);
+
+    for second in 0..1000u64 {
+        history.push(second * 1000000, second as f64);
+    }
+
+    assert_eq!(history.len(), 61);
+    assert_eq!(history.oldest(), Some((939000000, 939.0)));
+    assert_eq!(history.window(10 * 1000000).len(), 11);
+
+    let rate_window = RateWindow { window_us: 10 * 1000000, counter_wrap: CounterWrap::None };
+
+    assert_eq!(history.get_increase(&rate_window), Some((10.0, 10000000)));
+
+    let mut data = Vec::new();
+
+    // Raw samples cover the last minute
+    assert_eq!(history.query(30 * 1000000, 100, &mut data), Some((969.0, 999.0)));
+    assert_eq!(data.len(), 31);
+
+    // Too many raw points, the 10 s rollups fit
+    history.query(60 * 1000000, 10, &mut data);
+
+    assert_eq!(data.len(), 7);
+    assert_eq!(data[0], (930.0, 934.5));
+
+    // Beyond the 10 s tier retention only the minute rollups remain
+    assert_eq!(history.query(15 * 60 * 1000000, 100, &mut data), Some((60.0, 999.0)));
+    assert_eq!(data.len(), 16);
+
+    assert!(RollupSpec::from_str("0s:1h").is_err());
+    assert!(RollupSpec::from_str("1h:1m").is_err());
+
+    // A small backward step is clamped to the newest time
+    history.push(998500000, 1.0);
+
+    assert_eq!(history.len(), 62);
+    assert_eq!(history.newest(), Some((999000000, 1.0)));
+
+    // A large one starts the history over, no rate spans the step
+    history.push(5000000, 2.0);
+
+    assert_eq!(history.len(), 1);
+    assert_eq!(history.newest(), Some((5000000, 2.0)));
+    assert_eq!(history.get_increase(&rate_window), None);
+    assert_eq!(history.query(15 * 60 * 1000000, 100, &mut data), Some((2.0, 2.0)));
+}
+
+#[test]
+fn metric_history_reconfigure_test01() {
+    let mut history = MetricHistory::new(&HistoryConfig {
+        raw_retention: Duration::from_secs(60),
+        rollups: parse_rollup_specs("1m:1h").unwrap(),
+    });
+
+    for second in 0..600u64 {
+        history.push(second * 1000000, second as f64);
+    }
+
+    history.reconfigure(&HistoryConfig {
+        raw_retention: Duration::from_secs(10),
+        rollups: parse_rollup_specs("1m:1h, 10s:10m").unwrap(),
+    });
+
+    // Raw samples are cut to the new retention
+    assert_eq!(history.len(), 11);
+    assert_eq!(history.oldest(), Some((589000000, 589.0)));
+
+    // The minute tier keeps its rollups, the 10 s tier is rolled up from the raw samples it got
+    assert_eq!(history.get_samples(10 * 60 * 1000000).len(), 7);
+    assert_eq!(history.get_samples(30 * 60 * 1000000).len(), 10);
+
+    let mut data = Vec::new();
+
+    // The oldest raw sample of the old retention went into the 10 s tier
+    assert_eq!(history.query(10 * 60 * 1000000, 100, &mut data), Some((539.0, 599.0)));
+}
+
+#[test]
+fn metric_history_rollup_test01() {
+    let config = HistoryConfig {
+        raw_retention: Duration::from_secs(10),
+        rollups: parse_rollup_specs("10s:10m, 1m:1h").unwrap(),
+    };
+
+    let mut history = MetricHistory::new(&config);
+
+    // Two samples per second alternating between value and value + 10
+    for half_second in 0..2 * 30 * 60u64 {
+        history.push(half_second * 500000, (half_second / 2) as f64 + (half_second % 2) as f64 * 10.0);
+    }
+
+    let mut data = Vec::new();
+
+    // The raw retention covers the range
+    assert_eq!(history.get_samples(5 * 1000000).len(), 11);
+    assert_eq!(history.query(5 * 1000000, 100, &mut data), Some((1795.0, 1809.0)));
+
+    // Minimum and maximum of a rollup come from its samples, its point is their average
+    let samples = history.get_samples(60 * 1000000);
+
+    assert_eq!(samples.len(), 7);
+    assert_eq!(samples[1], (1740000000, 1749.5));
+
+    assert_eq!(history.query(60 * 1000000, 100, &mut data), Some((1730.0, 1809.0)));
+    assert_eq!(data[1], (1740.0, 1749.5));
+
+    // Beyond the retention of the 10 s tier the minute rollups are used
+    let samples = history.get_samples(20 * 60 * 1000000);
+
+    assert_eq!(samples.len(), 21);
+    assert_eq!(samples[0], (540000000, 574.5));
+
+    // The finest tier has too many points, the next one fits
+    history.query(5 * 60 * 1000000, 10, &mut data);
+
+    assert_eq!(data.len(), 6);
+    assert_eq!(data[5], (1740.0, 1774.5));
+
+    // Ranges beyond all tiers are served by the coarsest one
+    assert_eq!(history.get_samples(24 * 60 * 60 * 1000000).len(), 30);
+
+    // A large backwards step drops raw samples and rollups, they'd pile up in the buckets of the old times
+    history.push(0, 0.0);
+
+    assert_eq!(history.get_samples(20 * 60 * 1000000), vec![(0, 0.0)]);
+    assert_eq!(history.query(60 * 60 * 1000000, 100, &mut data), Some((0.0, 0.0)));
+
+    history.push(500000, 1.0);
+
+    assert_eq!(history.get_samples(5 * 1000000), vec![(0, 0.0), (500000, 1.0)]);
+}
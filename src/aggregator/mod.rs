@@ -1,2 +1,6 @@
-
-pub mod aggregator;
\ No newline at end of file
+#[allow(clippy::module_inception)]
+pub mod aggregator;
+pub mod alert;
+pub mod expression;
+pub mod history;
+pub mod user_rule;
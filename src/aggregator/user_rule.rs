@@ -0,0 +1,252 @@
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+use json::JsonValue;
+
+use crate::aggregator::aggregator::AutoMetricRuleType;
+
+/// Auto rule declared by the user, applied to every metric whose source and name match the globs.
+///
+/// `{name}` in the destination name is replaced by the name of the matching metric.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UserMetricRule {
+    pub source: String,
+    pub metric: String,
+    pub dst_metric_name: String,
+    pub rule_type: AutoMetricRuleType,
+}
+
+const NAME_PLACEHOLDER: &str = "{name}";
+
+/// Matches `text` against a glob pattern supporting `*` (any sequence) and `?` (any single character).
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+
+    let (mut p, mut t) = (0, 0);
+
+    // Position of the last `*` and the text position it was tried at
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = backtrack {
+            p = star_p + 1;
+            t = star_t + 1;
+            backtrack = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+impl AutoMetricRuleType {
+    pub fn get_type_name(&self) -> &'static str {
+        match self {
+            AutoMetricRuleType::TimeDifferentiate => "rate",
+            AutoMetricRuleType::MovingAverage { .. } => "avg",
+            AutoMetricRuleType::ExpFalloffAverage { .. } => "ewma",
+        }
+    }
+
+    fn get_default_suffix(&self) -> &'static str {
+        match self {
+            AutoMetricRuleType::TimeDifferentiate => "-ps",
+            AutoMetricRuleType::MovingAverage { .. } => "-avg",
+            AutoMetricRuleType::ExpFalloffAverage { .. } => "-ewma",
+        }
+    }
+
+    /// Creates a rule type from its name and optional parameter (depth or alpha).
+    pub fn from_name(type_name: &str, param: Option<f64>) -> Result<AutoMetricRuleType, String> {
+        match type_name {
+            "rate" => Ok(AutoMetricRuleType::TimeDifferentiate),
+            "avg" => {
+                let depth = param.unwrap_or(32.0);
+
+                if depth < 1.0 || depth.fract() != 0.0 {
+                    return Err(format!("invalid average depth {}", depth));
+                }
+
+                Ok(AutoMetricRuleType::MovingAverage { depth: depth as usize })
+            }
+            "ewma" => {
+                let alpha = param.unwrap_or(0.2);
+
+                if alpha <= 0.0 || alpha > 1.0 {
+                    return Err(format!("ewma alpha {} not within (0, 1]", alpha));
+                }
+
+                Ok(AutoMetricRuleType::ExpFalloffAverage { alpha: alpha as f32 })
+            }
+            _ => Err(format!("unknown rule type '{}', expected rate, avg or ewma", type_name)),
+        }
+    }
+}
+
+impl Display for AutoMetricRuleType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            AutoMetricRuleType::TimeDifferentiate => write!(f, "rate"),
+            AutoMetricRuleType::MovingAverage { depth } => write!(f, "avg:{}", depth),
+            AutoMetricRuleType::ExpFalloffAverage { alpha } => write!(f, "ewma:{}", alpha),
+        }
+    }
+}
+
+impl UserMetricRule {
+    /// Creates a rule for all sources, the destination name defaults to the metric name plus a type suffix.
+    pub fn new(metric: &str, rule_type: AutoMetricRuleType) -> UserMetricRule {
+        UserMetricRule {
+            source: String::from("*"),
+            metric: metric.to_string(),
+            dst_metric_name: format!("{}{}", NAME_PLACEHOLDER, rule_type.get_default_suffix()),
+            rule_type,
+        }
+    }
+
+    pub fn matches(&self, source: &str, metric_name: &str) -> bool {
+        glob_match(&self.source, source) && glob_match(&self.metric, metric_name)
+    }
+
+    pub fn get_dst_metric_name(&self, metric_name: &str) -> String {
+        self.dst_metric_name.replace(NAME_PLACEHOLDER, metric_name)
+    }
+}
+
+/// Formats in the syntax accepted by `from_str`.
+impl Display for UserMetricRule {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} {}", self.metric, self.rule_type, self.dst_metric_name)?;
+
+        if self.source != "*" {
+            write!(f, " @{}", self.source)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Parses `<metric glob> <type>[:<param>] [<destination name>] [@<source glob>]`,
+/// e.g. `rx_* ewma:0.1 {name}-smooth @tcp://*`.
+impl FromStr for UserMetricRule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut tokens = s.split_whitespace();
+
+        let metric = tokens.next().ok_or_else(|| String::from("missing metric name or glob"))?;
+
+        let type_token = tokens.next().ok_or_else(|| String::from("missing rule type"))?;
+
+        let (type_name, param) = match type_token.split_once(':') {
+            Some((type_name, param_str)) => (
+                type_name,
+                Some(f64::from_str(param_str).map_err(|_| format!("invalid rule parameter '{}'", param_str))?),
+            ),
+            None => (type_token, None),
+        };
+
+        let mut rule = UserMetricRule::new(metric, AutoMetricRuleType::from_name(type_name, param)?);
+
+        let mut has_dst_metric_name = false;
+
+        for token in tokens {
+            if let Some(source) = token.strip_prefix('@') {
+                rule.source = source.to_string();
+            } else if !has_dst_metric_name {
+                rule.dst_metric_name = token.to_string();
+                has_dst_metric_name = true;
+            } else {
+                return Err(format!("unexpected '{}'", token));
+            }
+        }
+
+        Ok(rule)
+    }
+}
+
+/// Reads `{"source": "...", "metric": "...", "name": "...", "type": "...", "depth"/"alpha": ...}`,
+/// only `metric` and `type` are required.
+impl TryFrom<&JsonValue> for UserMetricRule {
+    type Error = String;
+
+    fn try_from(value: &JsonValue) -> Result<Self, Self::Error> {
+        if !value.is_object() {
+            return Err(String::from("rule must be an object"));
+        }
+
+        let metric = value["metric"].as_str().ok_or_else(|| String::from("rule is missing 'metric'"))?;
+
+        let type_name = value["type"].as_str().ok_or_else(|| format!("rule for '{}' is missing 'type'", metric))?;
+
+        let param = value["depth"].as_f64().or_else(|| value["alpha"].as_f64());
+
+        let mut rule = UserMetricRule::new(metric, AutoMetricRuleType::from_name(type_name, param)?);
+
+        if let Some(source) = value["source"].as_str() {
+            rule.source = source.to_string();
+        }
+
+        if let Some(dst_metric_name) = value["name"].as_str() {
+            rule.dst_metric_name = dst_metric_name.to_string();
+        }
+
+        Ok(rule)
+    }
+}
+
+/// Parses a rules file, a json array of rule objects.
+pub fn parse_rules_file(content: &str) -> Result<Vec<UserMetricRule>, String> {
+    let rules_obj = json::parse(content).map_err(|e| e.to_string())?;
+
+    if !rules_obj.is_array() {
+        return Err(String::from("rules file must contain an array of rules"));
+    }
+
+    rules_obj
+        .members()
+        .enumerate()
+        .map(|(idx, rule_obj)| UserMetricRule::try_from(rule_obj).map_err(|msg| format!("rule {}: {}", idx + 1, msg)))
+        .collect()
+}
+
+
+#[test]
+fn user_rule_parse_test01() {
+    assert!(glob_match("rx_*", "rx_packets"));
+    assert!(glob_match("*_bytes*", "node_network_receive_bytes_total"));
+    assert!(glob_match("q?eue", "queue"));
+    assert!(!glob_match("rx_*", "tx_packets"));
+    assert!(glob_match("*", ""));
+
+    let rule = UserMetricRule::from_str("rx_* ewma:0.5 {name}-smooth @tcp://*").unwrap();
+
+    assert_eq!(rule.rule_type, AutoMetricRuleType::ExpFalloffAverage { alpha: 0.5 });
+    assert_eq!(rule.get_dst_metric_name("rx_packets"), "rx_packets-smooth");
+    assert!(rule.matches("tcp://host-a:5555", "rx_bytes"));
+    assert!(!rule.matches("http://host-a/metrics", "rx_bytes"));
+    assert_eq!(UserMetricRule::from_str(&rule.to_string()).unwrap(), rule);
+
+    assert_eq!(UserMetricRule::from_str("queue_depth avg").unwrap().get_dst_metric_name("queue_depth"), "queue_depth-avg");
+    assert!(UserMetricRule::from_str("queue_depth ewma:2").is_err());
+    assert!(UserMetricRule::from_str("queue_depth median").is_err());
+
+    let rules = parse_rules_file(r#"[
+        {"metric": "queue_depth", "type": "avg", "depth": 8},
+        {"source": "tcp://*", "metric": "rx_*", "name": "{name}-rate", "type": "rate"}
+    ]"#).unwrap();
+
+    assert_eq!(rules[0].rule_type, AutoMetricRuleType::MovingAverage { depth: 8 });
+    assert_eq!(rules[1].get_dst_metric_name("rx_bytes"), "rx_bytes-rate");
+
+    assert!(parse_rules_file(r#"[{"metric": "x"}]"#).is_err());
+}
@@ -0,0 +1,563 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::io::Write;
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use json::JsonValue;
+use tokio::process::Command;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{broadcast, mpsc, oneshot};
+use tokio::task::JoinHandle;
+use tokio::{select, time};
+
+use crate::aggregator::alert::AlertState;
+use crate::event_log::{BackendEvent, BackendEventKind, EventLog};
+
+// Span the notification rate limit applies to
+const RATE_PERIOD: Duration = Duration::from_secs(60);
+
+// Notifications waiting for delivery, the ones beyond are dropped
+const DELIVERY_QUEUE_LEN: usize = 32;
+
+// How often held back firings are checked for the end of their repeat interval
+const REPEAT_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NotifyChannel {
+    // Shell command run with the alert in ALERT_* environment variables
+    Command { command: String },
+    // URL the alert is POSTed to as a json object
+    Webhook { url: String },
+    // Rings the terminal bell
+    Bell,
+}
+
+/// Where alert notifications go and how often.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NotifierConfig {
+    pub channels: Vec<NotifyChannel>,
+
+    // An alert that fires again is only notified again after this interval
+    pub repeat_interval: Duration,
+
+    // Notifications per minute at most, the ones beyond are dropped
+    pub max_per_minute: usize,
+
+    // Also notify when a notified alert resolves
+    pub notify_resolved: bool,
+
+    // Time a command or webhook request may take
+    pub timeout: Duration,
+}
+
+/// An alert state change as handed to the notification channels.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AlertNotification {
+    pub rule: String,
+    pub state: AlertState,
+    pub metric: String,
+
+    // Endpoint the alerting metric was received from
+    pub endpoint: String,
+
+    pub value: Option<f64>,
+
+    pub timestamp: SystemTime,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Admission {
+    Send,
+    // Pending alerts and resolved ones that were never notified as firing
+    Ignored,
+    // Notified as firing within the repeat interval, also when it resolved in between. A firing
+    // that follows a resolve is held back until the interval is over
+    Duplicate,
+    RateLimited,
+}
+
+// Deduplication and rate limiting, kept apart from the delivery
+struct NotifyFilter {
+    repeat_interval: Duration,
+
+    max_per_minute: usize,
+
+    notify_resolved: bool,
+
+    // Last notified state per (rule, endpoint, metric) and when it was last notified as firing
+    notified: HashMap<(String, String, String), (AlertState, Instant)>,
+
+    // Times of the notifications sent within the last rate period
+    recent: VecDeque<Instant>,
+
+    // Firings held back by the repeat interval, notified once it's over unless the alert stopped firing
+    held_back: HashMap<(String, String, String), AlertNotification>,
+}
+
+/// Task delivering a notification for every alert event published on the event log.
+pub struct AlertNotifier {
+    task_join_handle: JoinHandle<()>,
+
+    quit_signal: oneshot::Sender<()>,
+}
+
+impl Display for NotifyChannel {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            NotifyChannel::Command { command } => write!(f, "command '{}'", command),
+            NotifyChannel::Webhook { url } => write!(f, "webhook {}", url),
+            NotifyChannel::Bell => write!(f, "bell"),
+        }
+    }
+}
+
+impl NotifierConfig {
+    pub fn new(channels: Vec<NotifyChannel>) -> NotifierConfig {
+        NotifierConfig {
+            channels,
+            repeat_interval: Duration::from_secs(300),
+            max_per_minute: 20,
+            notify_resolved: true,
+            timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+impl AlertNotification {
+    /// Returns the notification for alert events, None for all other events.
+    pub fn from_event(event: &BackendEvent) -> Option<AlertNotification> {
+        match event.get_kind() {
+            BackendEventKind::Alert { rule, metric, state, value } => Some(AlertNotification {
+                rule: rule.clone(),
+                state: *state,
+                metric: metric.clone(),
+                endpoint: event.get_source().to_string(),
+                value: *value,
+                timestamp: event.get_timestamp(),
+            }),
+            _ => None,
+        }
+    }
+
+    fn get_timestamp_ms(&self) -> u64 {
+        self.timestamp.duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0)
+    }
+
+    /// The body POSTed to webhooks.
+    pub fn to_json(&self) -> JsonValue {
+        let mut obj = JsonValue::new_object();
+
+        obj["rule"] = self.rule.as_str().into();
+        obj["state"] = self.state.to_string().into();
+        obj["metric"] = self.metric.as_str().into();
+        obj["endpoint"] = self.endpoint.as_str().into();
+        obj["value"] = self.value.map(JsonValue::from).unwrap_or(JsonValue::Null);
+        obj["timestamp_ms"] = self.get_timestamp_ms().into();
+
+        obj
+    }
+
+    /// Environment of the notification command, the value is empty for absence alerts.
+    pub fn get_env(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("ALERT_RULE", self.rule.clone()),
+            ("ALERT_STATE", self.state.to_string()),
+            ("ALERT_METRIC", self.metric.clone()),
+            ("ALERT_ENDPOINT", self.endpoint.clone()),
+            ("ALERT_VALUE", self.value.map(|v| v.to_string()).unwrap_or_default()),
+            ("ALERT_TIMESTAMP_MS", self.get_timestamp_ms().to_string()),
+        ]
+    }
+}
+
+impl NotifyFilter {
+    fn new(config: &NotifierConfig) -> NotifyFilter {
+        NotifyFilter {
+            repeat_interval: config.repeat_interval,
+            max_per_minute: config.max_per_minute,
+            notify_resolved: config.notify_resolved,
+            notified: HashMap::new(),
+            recent: VecDeque::new(),
+            held_back: HashMap::new(),
+        }
+    }
+
+    fn admit(&mut self, notification: &AlertNotification, now: Instant) -> Admission {
+        let alert_id = (notification.rule.clone(), notification.endpoint.clone(), notification.metric.clone());
+
+        let last_notified = self.notified.get(&alert_id).copied();
+
+        if notification.state != AlertState::Firing {
+            self.held_back.remove(&alert_id);
+        }
+
+        match notification.state {
+            AlertState::Pending => return Admission::Ignored,
+            AlertState::Resolved if !self.notify_resolved || !matches!(last_notified, Some((AlertState::Firing, _))) => {
+                return Admission::Ignored;
+            }
+            AlertState::Firing => {
+                // A flapping alert is notified once per interval, not on every firing
+                if let Some((last_state, fired_at)) = last_notified {
+                    if now.duration_since(fired_at) < self.repeat_interval {
+                        // The state changes are all the aggregator reports, an alert that stays firing
+                        // would never be notified again otherwise
+                        if last_state != AlertState::Firing {
+                            self.held_back.insert(alert_id, notification.clone());
+                        }
+
+                        return Admission::Duplicate;
+                    }
+                }
+            }
+            AlertState::Resolved => {}
+        }
+
+        while matches!(self.recent.front(), Some(sent_at) if now.duration_since(*sent_at) >= RATE_PERIOD) {
+            self.recent.pop_front();
+        }
+
+        if self.recent.len() >= self.max_per_minute {
+            return Admission::RateLimited;
+        }
+
+        self.recent.push_back(now);
+
+        let fired_at = match (notification.state, last_notified) {
+            (AlertState::Resolved, Some((_, fired_at))) => fired_at,
+            _ => now,
+        };
+
+        self.notified.insert(alert_id, (notification.state, fired_at));
+
+        Admission::Send
+    }
+
+    // Held back firings whose repeat interval is over, to be admitted again
+    fn take_due(&mut self, now: Instant) -> Vec<AlertNotification> {
+        let due: Vec<(String, String, String)> = self
+            .held_back
+            .keys()
+            .filter(|alert_id| {
+                matches!(self.notified.get(*alert_id), Some((_, fired_at)) if now.duration_since(*fired_at) >= self.repeat_interval)
+            })
+            .cloned()
+            .collect();
+
+        due.iter().filter_map(|alert_id| self.held_back.remove(alert_id)).collect()
+    }
+}
+
+async fn run_command(command: &str, notification: &AlertNotification, timeout: Duration) -> Result<(), String> {
+    #[cfg(unix)]
+    let mut process = {
+        let mut process = Command::new("sh");
+
+        process.arg("-c").arg(command);
+
+        process
+    };
+
+    #[cfg(not(unix))]
+    let mut process = {
+        let mut process = Command::new("cmd");
+
+        process.arg("/C").arg(command);
+
+        process
+    };
+
+    // The output would end up in the middle of the frontend
+    process
+        .envs(notification.get_env())
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .kill_on_drop(true);
+
+    match time::timeout(timeout, process.status()).await {
+        Ok(Ok(status)) if status.success() => Ok(()),
+        Ok(Ok(status)) => Err(format!("command exited with {}", status)),
+        Ok(Err(err)) => Err(format!("could not run command: {}", err)),
+        Err(_) => Err(format!("command did not finish within {:?}", timeout)),
+    }
+}
+
+async fn post_webhook(client: &reqwest::Client, url: &str, notification: &AlertNotification, timeout: Duration) -> Result<(), String> {
+    let response = client
+        .post(url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .body(notification.to_json().dump())
+        .timeout(timeout)
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+
+    if response.status().is_success() {
+        Ok(())
+    } else {
+        Err(format!("webhook answered with status {}", response.status()))
+    }
+}
+
+impl AlertNotifier {
+    /// Subscribes to the event log and starts the task, must be called from within the runtime.
+    pub fn start(config: NotifierConfig, event_log: Arc<EventLog>) -> AlertNotifier {
+        let events = event_log.subscribe();
+
+        let (quit_signal, quit_signal_receiver) = oneshot::channel::<()>();
+
+        let task_join_handle = tokio::spawn(Self::notify_handler(config, events, quit_signal_receiver, event_log));
+
+        AlertNotifier {
+            task_join_handle,
+            quit_signal,
+        }
+    }
+
+    /// Ends the task, notifications still queued get the configured timeout to be delivered.
+    pub async fn stop(self) {
+        let _ = self.quit_signal.send(());
+
+        let _ = self.task_join_handle.await;
+    }
+
+    async fn deliver(channel: &NotifyChannel, client: &reqwest::Client, notification: &AlertNotification, timeout: Duration) -> Result<(), String> {
+        match channel {
+            NotifyChannel::Command { command } => run_command(command, notification, timeout).await,
+            NotifyChannel::Webhook { url } => post_webhook(client, url, notification, timeout).await,
+            NotifyChannel::Bell => {
+                // stderr, so the bell doesn't end up in piped metric output
+                let mut stderr = std::io::stderr();
+
+                stderr.write_all(b"\x07").and_then(|_| stderr.flush()).map_err(|e| e.to_string())
+            }
+        }
+    }
+
+    async fn notify_handler(
+        config: NotifierConfig,
+        mut events: broadcast::Receiver<BackendEvent>,
+        quit_signal_receiver: oneshot::Receiver<()>,
+        event_log: Arc<EventLog>,
+    ) {
+        let mut quit_signal_receiver = quit_signal_receiver;
+
+        // Delivered on a task of their own, a slow channel must not hold up the event subscription
+        let (delivery_sender, delivery_receiver) = mpsc::channel(DELIVERY_QUEUE_LEN);
+
+        let mut delivery_task = tokio::spawn(Self::delivery_handler(config.clone(), delivery_receiver, Arc::clone(&event_log)));
+
+        let mut filter = NotifyFilter::new(&config);
+
+        // Rate limited and undeliverable notifications not yet reported
+        let mut dropped = 0u64;
+
+        let report_dropped = |dropped: &mut u64| {
+            if *dropped > 0 {
+                event_log.publish(BackendEvent::new("alerts", BackendEventKind::NotificationsDropped { count: *dropped }));
+
+                *dropped = 0;
+            }
+        };
+
+        let mut report_interval = time::interval(RATE_PERIOD);
+
+        let mut repeat_check_interval = time::interval(REPEAT_CHECK_INTERVAL);
+
+        loop {
+            select! {
+                event = events.recv() => {
+                    let event = match event {
+                        Ok(event) => event,
+                        // Alerts among the skipped events are lost, there is nothing to catch up on
+                        Err(RecvError::Lagged(events)) => {
+                            event_log.publish(BackendEvent::new("alerts", BackendEventKind::NotifierLagged { events }));
+
+                            continue;
+                        }
+                        Err(RecvError::Closed) => break,
+                    };
+
+                    let notification = match AlertNotification::from_event(&event) {
+                        Some(notification) => notification,
+                        None => continue,
+                    };
+
+                    Self::submit(&mut filter, notification, Instant::now(), &delivery_sender, &mut dropped);
+                },
+                _ = repeat_check_interval.tick() => {
+                    let now = Instant::now();
+
+                    for notification in filter.take_due(now) {
+                        Self::submit(&mut filter, notification, now, &delivery_sender, &mut dropped);
+                    }
+                },
+                _ = report_interval.tick() => report_dropped(&mut dropped),
+                _ = &mut quit_signal_receiver => break,
+            }
+        }
+
+        // The delivery task ends once the queued notifications are delivered
+        drop(delivery_sender);
+
+        if time::timeout(config.timeout, &mut delivery_task).await.is_err() {
+            delivery_task.abort();
+
+            let msg = format!("notifications still queued {:?} after the shutdown were dropped", config.timeout);
+
+            event_log.publish(BackendEvent::new("alerts", BackendEventKind::NotificationFailed { msg }));
+        }
+
+        report_dropped(&mut dropped);
+    }
+
+    fn submit(
+        filter: &mut NotifyFilter,
+        notification: AlertNotification,
+        now: Instant,
+        delivery_sender: &mpsc::Sender<AlertNotification>,
+        dropped: &mut u64,
+    ) {
+        match filter.admit(&notification, now) {
+            Admission::Send => {
+                if delivery_sender.try_send(notification).is_err() {
+                    *dropped += 1;
+                }
+            }
+            Admission::RateLimited => *dropped += 1,
+            Admission::Ignored | Admission::Duplicate => {}
+        }
+    }
+
+    async fn delivery_handler(config: NotifierConfig, mut notifications: mpsc::Receiver<AlertNotification>, event_log: Arc<EventLog>) {
+        let client = reqwest::Client::new();
+
+        while let Some(notification) = notifications.recv().await {
+            for channel in &config.channels {
+                if let Err(msg) = Self::deliver(channel, &client, &notification, config.timeout).await {
+                    event_log.publish(BackendEvent::new(&channel.to_string(), BackendEventKind::NotificationFailed { msg }));
+                }
+            }
+        }
+    }
+}
+
+
+#[test]
+fn alert_notifier_webhook_test01() {
+    use std::sync::Mutex;
+
+    use crate::common::mock_http::MockHttpServer;
+
+    let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+
+    runtime.block_on(async {
+        // Stand-in for the webhook receiver, keeps the bodies and answers with 200
+        let received = Arc::new(Mutex::new(Vec::<JsonValue>::new()));
+
+        let mock_received = Arc::clone(&received);
+
+        let server = MockHttpServer::start(move |body| {
+            mock_received.lock().unwrap().push(json::parse(&body).unwrap());
+
+            "200 OK"
+        }).await;
+
+        let event_log = Arc::new(EventLog::new(32));
+
+        let mut config = NotifierConfig::new(vec![NotifyChannel::Webhook { url: format!("http://{}/hook", server.get_addr()) }]);
+
+        config.max_per_minute = 2;
+
+        let notifier = AlertNotifier::start(config, Arc::clone(&event_log));
+
+        let alert = |rule: &str, state: AlertState| {
+            BackendEvent::new("tcp://host:5555", BackendEventKind::Alert {
+                rule: rule.to_string(),
+                metric: String::from("temp"),
+                state,
+                value: Some(95.0),
+            })
+        };
+
+        // The repeated firing is a duplicate, pending isn't notified and the third notification exceeds the rate limit.
+        // Firing again right after resolving is held back, and forgotten as the alert resolves again
+        event_log.publish(alert("hot", AlertState::Pending));
+        event_log.publish(alert("hot", AlertState::Firing));
+        event_log.publish(alert("hot", AlertState::Firing));
+        event_log.publish(alert("hot", AlertState::Resolved));
+        event_log.publish(alert("hot", AlertState::Firing));
+        event_log.publish(alert("hot", AlertState::Resolved));
+        event_log.publish(alert("cold", AlertState::Firing));
+
+        for _ in 0..100 {
+            if received.lock().unwrap().len() >= 2 {
+                break;
+            }
+
+            time::sleep(Duration::from_millis(10)).await;
+        }
+
+        notifier.stop().await;
+
+        for _ in 0..100 {
+            if event_log.get_recent_events(32).iter().any(|e| e.get_kind() == &BackendEventKind::NotificationsDropped { count: 1 }) {
+                break;
+            }
+
+            time::sleep(Duration::from_millis(10)).await;
+        }
+
+        let received = received.lock().unwrap();
+
+        assert_eq!(received.len(), 2);
+        assert_eq!(received[0]["state"], "firing");
+        assert_eq!(received[0]["endpoint"], "tcp://host:5555");
+        assert_eq!(received[0]["value"], 95.0);
+        assert_eq!(received[1]["state"], "resolved");
+
+        assert!(event_log.get_recent_events(32).iter().any(|e| e.get_kind() == &BackendEventKind::NotificationsDropped { count: 1 }));
+    });
+}
+
+#[test]
+fn alert_notifier_filter_test01() {
+    let mut filter = NotifyFilter::new(&NotifierConfig::new(vec![NotifyChannel::Bell]));
+
+    let alert = |state: AlertState| AlertNotification {
+        rule: String::from("hot"),
+        state,
+        metric: String::from("temp"),
+        endpoint: String::from("tcp://host:5555"),
+        value: Some(95.0),
+        timestamp: SystemTime::now(),
+    };
+
+    let start = Instant::now();
+
+    let at = |s: u64| start + Duration::from_secs(s);
+
+    assert_eq!(filter.admit(&alert(AlertState::Firing), at(0)), Admission::Send);
+    assert_eq!(filter.admit(&alert(AlertState::Resolved), at(10)), Admission::Send);
+    assert_eq!(filter.admit(&alert(AlertState::Firing), at(20)), Admission::Duplicate);
+
+    // Still firing once the repeat interval is over, the held back firing goes out
+    assert!(filter.take_due(at(299)).is_empty());
+
+    let due = filter.take_due(at(300));
+
+    assert_eq!(due.len(), 1);
+    assert_eq!(due[0].state, AlertState::Firing);
+    assert_eq!(filter.admit(&due[0], at(300)), Admission::Send);
+
+    // A held back firing is dropped when the alert resolves before the interval is over
+    assert_eq!(filter.admit(&alert(AlertState::Resolved), at(310)), Admission::Send);
+    assert_eq!(filter.admit(&alert(AlertState::Firing), at(320)), Admission::Duplicate);
+    assert_eq!(filter.admit(&alert(AlertState::Resolved), at(330)), Admission::Ignored);
+
+    assert!(filter.take_due(at(900)).is_empty());
+}
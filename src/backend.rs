@@ -1,32 +1,114 @@
 
+use crate::aggregator::aggregator::{CounterEvent, CounterWrap, MetricKey};
+use crate::aggregator::alert::{Alert, AlertRule};
+use crate::alert_notifier::{AlertNotifier, NotifierConfig};
+use crate::aggregator::expression::DerivedMetric;
+use crate::aggregator::history::RollupSpec;
+use crate::aggregator::user_rule::UserMetricRule;
+use crate::capture::recorder::RecorderSink;
 use crate::common::metric::Metric;
-use crate::source::zmq_endpoint::ZmqEndpoint;
-use crate::{source, MetricAggregator};
+use crate::config::{diff_lists, Config, ConfigError};
+use crate::config_watcher::{ConfigWatcher, ReloadOutcome};
+use crate::event_log::{BackendEvent, BackendEventKind, EventLog, DEFAULT_EVENT_LOG_LEN};
+use crate::export::{self, ExportOptions, ExportSeries};
+use crate::source::connection::{ConnectionState, ReconnectPolicy};
+use crate::source::replay_endpoint::{ReplayControl, ReplayEndpoint, ReplayOptions};
+use crate::source::zmq_endpoint::{SubscriptionControl, TopicFilter, ZmqEndpoint, ZMQ_SCHEMES};
+use crate::source::{EndpointErrorKind, EndpointRegistry, MetricEndpoint};
+use crate::MetricAggregator;
+use crate::influx_forwarder::{InfluxForwarder, InfluxForwarderConfig};
+use crate::prometheus_exporter::PrometheusSink;
+use crate::sink::{OutputSink, ProcessedCollection, SinkTask};
 use std::fmt::{Debug, Display, Formatter};
+use std::io::{BufWriter, Write};
+use std::net::SocketAddr;
 use std::ops::Deref;
-use std::sync::{Arc, Mutex};
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard};
 use std::time::Duration;
+use tokio::net::TcpListener;
 use tokio::{select, task, time};
-use tokio::sync::oneshot;
+use tokio::sync::{broadcast, oneshot};
 use tokio::task::JoinHandle;
 use tokio::time::Instant;
-use zeromq::ZmqError;
-use crate::source::prometheus_poll_endpoint::PrometheusPollEndpoint;
 
 //type CbType = dyn Fn() + Send + 'static;
 
 pub type MetricCallback = dyn Fn() + Send + 'static;
 
+const MESSAGE_STATS_INTERVAL: Duration = Duration::from_secs(10);
 
-pub struct Backend {
+// How often absence alerts are checked in between collections
+const ALERT_EVALUATION_INTERVAL: Duration = Duration::from_millis(250);
+
+struct EndpointTask {
+    destination: String,
+
+    task_join_handle: JoinHandle<()>,
+
+    quit_signal: oneshot::Sender<()>,
+
+    connection_state: Arc<Mutex<ConnectionState>>,
+
+    // Set for replay endpoints
+    replay_control: Option<ReplayControl>,
+
+    // Set for endpoints with topics
+    subscription_control: Option<SubscriptionControl>,
+}
+
+// Task evaluating the alerts periodically, an endpoint that went silent doesn't trigger an evaluation
+struct AlertTicker {
+    task_join_handle: JoinHandle<()>,
+
+    quit_signal: oneshot::Sender<()>,
+}
+
+// Everything a receiver task shares with the backend
+struct ReceiverContext {
+    destination: String,
 
     aggregator: Arc<Mutex<MetricAggregator>>,
 
-    task_join_handle: Option<JoinHandle<()>>,
+    callbacks: Arc<Mutex<Vec<Box<MetricCallback>>>>,
+
+    connection_state: Arc<Mutex<ConnectionState>>,
+
+    event_log: Arc<EventLog>,
 
-    quit_signal: Option<oneshot::Sender<()>>,
+    sinks: Arc<Mutex<Vec<SinkTask>>>,
+
+    reconnect_policy: ReconnectPolicy,
+}
+
+/// Clones are handles to the same aggregator, endpoints and sinks, e.g. for the config watcher.
+#[derive(Clone)]
+pub struct Backend {
+
+    aggregator: Arc<Mutex<MetricAggregator>>,
+
+    endpoint_tasks: Arc<Mutex<Vec<EndpointTask>>>,
 
     callbacks: Arc<Mutex<Vec<Box<MetricCallback>>>>,
+
+    endpoint_registry: Arc<Mutex<EndpointRegistry>>,
+
+    reconnect_policy: Arc<Mutex<ReconnectPolicy>>,
+
+    event_log: Arc<EventLog>,
+
+    // Shared by all receiver tasks, every processed collection is passed to each sink
+    sinks: Arc<Mutex<Vec<SinkTask>>>,
+
+    // Name of the sink of the recording in progress
+    recording: Arc<Mutex<Option<String>>>,
+
+    alert_notifier: Arc<Mutex<Option<AlertNotifier>>>,
+
+    config_watcher: Arc<Mutex<Option<ConfigWatcher>>>,
+
+    // Started with the first endpoint
+    alert_ticker: Arc<Mutex<Option<AlertTicker>>>,
 }
 
 #[derive(Debug, Clone)]
@@ -43,8 +125,8 @@ impl Display for Error {
 impl std::error::Error for Error {}
 
 pub trait MetricAdapter {
-    // Name of the metric that's encapsulated
-    fn get_name(&self) -> &String;
+    // Key of the metric that's encapsulated
+    fn get_key(&self) -> &MetricKey;
 
     fn update_current(&mut self, metric: &Metric);
 }
@@ -53,62 +135,575 @@ impl Backend {
     pub fn new() -> Backend {
         Backend {
             aggregator: Arc::new(Mutex::new(MetricAggregator::new())),
-            task_join_handle: None,
-            quit_signal: None,
+            endpoint_tasks: Arc::new(Mutex::new(Vec::new())),
             callbacks: Arc::new(Mutex::new(Vec::new())),
+            endpoint_registry: Arc::new(Mutex::new(EndpointRegistry::with_default_endpoints())),
+            reconnect_policy: Arc::new(Mutex::new(ReconnectPolicy::default())),
+            event_log: Arc::new(EventLog::new(DEFAULT_EVENT_LOG_LEN)),
+            sinks: Arc::new(Mutex::new(Vec::new())),
+            recording: Arc::new(Mutex::new(None)),
+            alert_notifier: Arc::new(Mutex::new(None)),
+            config_watcher: Arc::new(Mutex::new(None)),
+            alert_ticker: Arc::new(Mutex::new(None)),
         }
     }
 
-    pub async fn connect<T: ToString>(&mut self, dst: T) -> Result<(), Error> {
-        if self.task_join_handle.is_some() {
+    /// Receives all backend events published from now on.
+    pub fn subscribe_events(&self) -> broadcast::Receiver<BackendEvent> {
+        self.event_log.subscribe()
+    }
+
+    /// Returns up to `max_count` of the most recent backend events, oldest first.
+    pub fn get_recent_events(&self, max_count: usize) -> Vec<BackendEvent> {
+        self.event_log.get_recent_events(max_count)
+    }
+
+    /// Sets the policy used by endpoints connected from now on.
+    pub fn set_reconnect_policy(&self, reconnect_policy: ReconnectPolicy) {
+        *self.reconnect_policy.lock().unwrap() = reconnect_policy;
+    }
+
+    /// Passes every collection processed from now on to `sink`, which is fed by a task of its own.
+    pub async fn add_sink<S: OutputSink + 'static>(&self, sink: S) -> Result<(), Error> {
+        let name = sink.get_name();
+
+        let mut sinks = self.sinks.lock().unwrap();
+
+        // Sinks that failed are gone already, their names can be taken again
+        sinks.retain(|s| s.is_running());
+
+        if sinks.iter().any(|s| s.get_name() == name) {
             return Err(Error {
-                msg: String::from("already connected"),
+                msg: format!("there already is an output sink named {}", name),
+            });
+        }
+
+        sinks.push(SinkTask::spawn(Box::new(sink), Arc::clone(&self.event_log)));
+
+        Ok(())
+    }
+
+    /// Closes the sink named `name` after it consumed what's queued for it, returns false for an unknown sink.
+    pub fn remove_sink(&self, name: &str) -> bool {
+        let mut sinks = self.sinks.lock().unwrap();
+
+        match sinks.iter().position(|s| s.get_name() == name) {
+            Some(idx) => {
+                sinks.remove(idx).stop();
+
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Names of all sinks that are running.
+    pub fn get_sink_names(&self) -> Vec<String> {
+        self.sinks.lock().unwrap().iter().filter(|s| s.is_running()).map(|s| s.get_name().to_string()).collect()
+    }
+
+    /// Appends every collection received from now on to the capture file at `path`.
+    ///
+    /// An existing capture file is continued, a recording already in progress is finished first.
+    pub async fn start_recording(&self, path: &Path) -> Result<(), Error> {
+        let sink = RecorderSink::create(path).map_err(|err| Error {
+            msg: format!("Could not record to {}: {}", path.display(), err),
+        })?;
+
+        self.stop_recording();
+
+        let name = sink.get_name();
+
+        self.add_sink(sink).await?;
+
+        self.recording.lock().unwrap().replace(name);
+
+        Ok(())
+    }
+
+    /// Finishes the recording in progress, returns false if there is none.
+    pub fn stop_recording(&self) -> bool {
+        match self.recording.lock().unwrap().take() {
+            Some(name) => self.remove_sink(&name),
+            None => false,
+        }
+    }
+
+    pub fn is_recording(&self) -> bool {
+        match self.recording.lock().unwrap().as_ref() {
+            Some(name) => self.get_sink_names().contains(name),
+            None => false,
+        }
+    }
+
+    /// Writes the metrics selected by `options` to `path`, returns the number of rows written.
+    ///
+    /// The outcome is published to the event log as well, with the path as source.
+    pub fn export_metrics(&self, path: &Path, options: &ExportOptions) -> Result<usize, Error> {
+        let series = self.aggregator.lock().unwrap().get_export_series(options);
+
+        let result = std::fs::File::create(path).and_then(|file| {
+            let mut out = BufWriter::new(file);
+
+            let rows = export::write_series(&series, options.format, &mut out)?;
+
+            out.flush()?;
+
+            Ok(rows)
+        });
+
+        let source = path.display().to_string();
+
+        match result {
+            Ok(rows) => {
+                self.event_log.publish(BackendEvent::new(&source, BackendEventKind::Exported { rows }));
+
+                Ok(rows)
+            }
+            Err(err) => {
+                let msg = format!("Could not export to {}: {}", source, err);
+
+                self.event_log.publish(BackendEvent::new(&source, BackendEventKind::ExportFailed { msg: err.to_string() }));
+
+                Err(Error { msg })
+            }
+        }
+    }
+
+    pub fn get_export_series(&self, options: &ExportOptions) -> Vec<ExportSeries> {
+        self.aggregator.lock().unwrap().get_export_series(options)
+    }
+
+    /// Serves all aggregated metrics in the prometheus text format on `http://<addr>/metrics`.
+    ///
+    /// Returns the address actually bound, which differs from `addr` if port 0 was requested.
+    pub async fn serve_prometheus(&self, addr: SocketAddr) -> Result<SocketAddr, Error> {
+        let bind_error = |err: std::io::Error| Error {
+            msg: format!("Could not serve prometheus metrics on {}: {}", addr, err),
+        };
+
+        let listener = TcpListener::bind(addr).await.map_err(bind_error)?;
+
+        let local_addr = listener.local_addr().map_err(bind_error)?;
+
+        let metrics = self
+            .aggregator
+            .lock()
+            .unwrap()
+            .metric_iter()
+            .map(|(key, metric)| (key.clone(), metric.clone()))
+            .collect();
+
+        self.add_sink(PrometheusSink::start(listener, metrics, Arc::clone(&self.event_log))).await?;
+
+        self.event_log.publish(BackendEvent::new(&local_addr.to_string(), BackendEventKind::ExporterStarted));
+
+        Ok(local_addr)
+    }
+
+    /// Forwards every collection received from now on, plus the metrics computed from it, as InfluxDB line protocol.
+    pub async fn forward_influx(&self, config: InfluxForwarderConfig) -> Result<(), Error> {
+        let destination = config.get_destination();
+
+        let forwarder = InfluxForwarder::start(config, Arc::clone(&self.event_log)).await.map_err(|msg| Error {
+            msg: format!("Could not forward to {}: {}", destination, msg),
+        })?;
+
+        self.add_sink(forwarder).await
+    }
+
+    /// Sends notifications for alert state changes through the configured channels, replaces
+    /// the notifier started before.
+    pub async fn notify_alerts(&self, config: NotifierConfig) {
+        let alert_notifier = AlertNotifier::start(config, Arc::clone(&self.event_log));
+
+        let previous = self.alert_notifier.lock().unwrap().replace(alert_notifier);
+
+        if let Some(previous) = previous {
+            previous.stop().await;
+        }
+    }
+
+    /// Reloads the config whenever the file at `path` changes or SIGHUP is received and applies it
+    /// to the running backend, see `apply_config`. `load` reads all config layers, `running` is the
+    /// config the backend was set up from. Replaces the watcher started before.
+    pub async fn watch_config<F>(&self, path: Option<String>, running: Config, load: F)
+    where
+        F: Fn() -> Result<Config, ConfigError> + Send + 'static,
+    {
+        let config_watcher = ConfigWatcher::start(path, running, load, self.clone(), Arc::clone(&self.event_log));
+
+        if let Some(previous) = self.config_watcher.lock().unwrap().replace(config_watcher) {
+            previous.stop();
+        }
+    }
+
+    /// Brings the backend from the state set up from `running` to the one described by `config`.
+    ///
+    /// Endpoints are connected and disconnected by url, the history of endpoints that stay is kept.
+    /// Rules, derived metrics and alert rules are swapped under a single aggregator lock, so no collection
+    /// is processed with half of them. Rules added at runtime are kept. The connection settings apply to
+    /// endpoints connected from now on, frontend settings only after a restart.
+    pub async fn apply_config(&mut self, running: &Config, config: &Config) -> ReloadOutcome {
+        let mut outcome = ReloadOutcome::default();
+
+        if running.topics != config.topics {
+            self.set_default_topics(config.topics.clone());
+
+            let (removed, added) = diff_lists(&running.topics, &config.topics);
+
+            for (destination, _) in self.get_subscriptions() {
+                for topic in &removed {
+                    let _ = self.unsubscribe_topic(&destination, topic);
+                }
+
+                for topic in &added {
+                    let _ = self.subscribe_topic(&destination, topic.clone());
+                }
+            }
+
+            outcome.settings_changed += 1;
+        }
+
+        if running.connection != config.connection {
+            self.set_reconnect_policy(config.connection.clone());
+
+            outcome.settings_changed += 1;
+        }
+
+        for url in &running.endpoints {
+            if !config.endpoints.contains(url) && self.disconnect_endpoint(url) {
+                outcome.endpoints_removed.push(url.clone());
+            }
+        }
+
+        for url in &config.endpoints {
+            if self.is_connected(url) {
+                continue;
+            }
+
+            match self.connect_url(url).await {
+                Ok(()) => outcome.endpoints_added.push(url.clone()),
+                Err(err) => {
+                    outcome.endpoints_failed.push(url.clone());
+                    outcome.errors.push(err.msg);
+                }
+            }
+        }
+
+        {
+            let mut aggregator_local = self.aggregator.lock().unwrap();
+
+            let (old, new) = (&running.aggregator, &config.aggregator);
+
+            if old.deltat != new.deltat || old.rate_window != new.rate_window || old.counter_wrap != new.counter_wrap {
+                aggregator_local.set_deltat(new.deltat);
+                aggregator_local.set_rate_window(new.rate_window);
+                aggregator_local.set_counter_wrap(new.counter_wrap);
+
+                outcome.settings_changed += 1;
+            }
+
+            if old.history != new.history {
+                aggregator_local.set_history_config(new.history.clone());
+
+                outcome.settings_changed += 1;
+            }
+
+            let (removed, added) = diff_lists(&old.rules, &new.rules);
+
+            for user_rule in &removed {
+                let user_rule_id = aggregator_local.get_user_rules().into_iter().find(|(_, r)| r == user_rule).map(|(id, _)| id);
+
+                if let Some(user_rule_id) = user_rule_id {
+                    aggregator_local.remove_user_rule(user_rule_id);
+                }
+            }
+
+            for user_rule in &added {
+                aggregator_local.add_user_rule(user_rule.clone());
+            }
+
+            outcome.rules_changed += removed.len() + added.len();
+
+            let (removed, added) = diff_lists(&old.derived_metrics, &new.derived_metrics);
+
+            for derived_metric in &removed {
+                aggregator_local.remove_derived_metric(derived_metric);
+            }
+
+            for derived_metric in &added {
+                aggregator_local.add_derived_metric(derived_metric.clone());
+            }
+
+            outcome.rules_changed += removed.len() + added.len();
+
+            let (removed, added) = diff_lists(&running.alerts.rules, &config.alerts.rules);
+
+            for alert_rule in &removed {
+                let alert_rule_id = aggregator_local.get_alert_rules().into_iter().find(|(_, r)| r == alert_rule).map(|(id, _)| id);
+
+                if let Some(alert_rule_id) = alert_rule_id {
+                    aggregator_local.remove_alert_rule(alert_rule_id);
+                }
+            }
+
+            for alert_rule in &added {
+                aggregator_local.add_alert_rule(alert_rule.clone());
+            }
+
+            outcome.rules_changed += removed.len() + added.len();
+        }
+
+        let notifier_config = config.alerts.get_notifier_config();
+
+        if running.alerts.get_notifier_config() != notifier_config {
+            match notifier_config {
+                Some(notifier_config) => self.notify_alerts(notifier_config).await,
+                None => {
+                    let alert_notifier = self.alert_notifier.lock().unwrap().take();
+
+                    if let Some(alert_notifier) = alert_notifier {
+                        alert_notifier.stop().await;
+                    }
+                }
+            }
+
+            outcome.settings_changed += 1;
+        }
+
+        if running.tui != config.tui {
+            outcome.restart_required.push(String::from("tui"));
+        }
+
+        if running.gui != config.gui {
+            outcome.restart_required.push(String::from("gui"));
+        }
+
+        if running.headless != config.headless {
+            outcome.restart_required.push(String::from("headless"));
+        }
+
+        outcome
+    }
+
+    pub fn get_connection_state(&self, destination: &str) -> Option<ConnectionState> {
+        self.endpoint_tasks
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|t| t.destination == destination)
+            .map(|t| t.connection_state.lock().unwrap().clone())
+    }
+
+    pub fn get_connection_states(&self) -> Vec<(String, ConnectionState)> {
+        self.endpoint_tasks
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|t| (t.destination.clone(), t.connection_state.lock().unwrap().clone()))
+            .collect()
+    }
+
+    pub fn get_endpoint_registry(&self) -> MutexGuard<'_, EndpointRegistry> {
+        self.endpoint_registry.lock().unwrap()
+    }
+
+    /// Creates the endpoint matching the scheme of `url` and connects to it.
+    pub async fn connect_url(&mut self, url: &str) -> Result<(), Error> {
+        let endpoint = self.endpoint_registry.lock().unwrap().create(url).map_err(|err| Error {
+            msg: err.msg,
+        })?;
+
+        self.connect(endpoint).await
+    }
+
+    /// Connects to all given endpoint urls, each one is served by its own receiver task.
+    ///
+    /// Stops at the first endpoint that fails, endpoints connected up to that point stay connected.
+    pub async fn connect_urls<T: AsRef<str>>(&mut self, urls: &[T]) -> Result<(), Error> {
+        for url in urls {
+            self.connect_url(url.as_ref()).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Sets the topics ZMQ endpoints connected from now on subscribe to.
+    pub fn set_default_topics(&self, topics: Vec<TopicFilter>) {
+        let mut endpoint_registry = self.endpoint_registry.lock().unwrap();
+
+        for scheme in ZMQ_SCHEMES {
+            let topics = topics.clone();
+
+            endpoint_registry.register(scheme, move |url| {
+                Ok(Box::new(ZmqEndpoint::with_topics(url, topics.clone())?))
             });
         }
+    }
+
+    /// Topics of all connected endpoints that have topics, by destination.
+    pub fn get_subscriptions(&self) -> Vec<(String, Vec<TopicFilter>)> {
+        self.endpoint_tasks
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|t| t.subscription_control.as_ref().map(|c| (t.destination.clone(), c.get_topics())))
+            .collect()
+    }
+
+    fn get_subscription_control(&self, destination: &str) -> Result<SubscriptionControl, Error> {
+        let endpoint_tasks = self.endpoint_tasks.lock().unwrap();
+
+        let endpoint_task = endpoint_tasks.iter().find(|t| t.destination == destination).ok_or_else(|| Error {
+            msg: format!("not connected to {}", destination),
+        })?;
+
+        endpoint_task.subscription_control.clone().ok_or_else(|| Error {
+            msg: format!("{} has no topics", destination),
+        })
+    }
+
+    /// Subscribes a connected endpoint to `topic`, returns false if it is subscribed already.
+    pub fn subscribe_topic(&self, destination: &str, topic: TopicFilter) -> Result<bool, Error> {
+        let topic_name = topic.to_string();
 
-        //let endpoint = source::zmq_endpoint::ZmqEndpoint::new(&dst.to_string());
-        let endpoint = source::prometheus_poll_endpoint::PrometheusPollEndpoint::new(&dst.to_string());
+        let subscribed = self.get_subscription_control(destination)?.subscribe(topic);
+
+        if subscribed {
+            self.event_log.publish(BackendEvent::new(destination, BackendEventKind::Subscribed { topic: topic_name }));
+        }
+
+        Ok(subscribed)
+    }
+
+    /// Unsubscribes a connected endpoint from `topic`, returns false if it wasn't subscribed.
+    ///
+    /// The metrics received on the topic so far are kept.
+    pub fn unsubscribe_topic(&self, destination: &str, topic: &TopicFilter) -> Result<bool, Error> {
+        let unsubscribed = self.get_subscription_control(destination)?.unsubscribe(topic);
+
+        if unsubscribed {
+            self.event_log.publish(BackendEvent::new(destination, BackendEventKind::Unsubscribed { topic: topic.to_string() }));
+        }
 
-        if endpoint.is_err() {
+        Ok(unsubscribed)
+    }
+
+    /// Replays the capture file at `path` as if it was received from an endpoint.
+    ///
+    /// The returned control (also available via `get_replay_controls`) pauses, seeks and changes the speed.
+    pub async fn connect_replay(&mut self, path: &Path, options: ReplayOptions) -> Result<ReplayControl, Error> {
+        let endpoint = ReplayEndpoint::open(path, options).map_err(|err| Error { msg: err.msg })?;
+
+        let replay_control = endpoint.get_control();
+
+        self.connect_endpoint(endpoint, Some(replay_control.clone())).await?;
+
+        Ok(replay_control)
+    }
+
+    /// Controls of all connected replay endpoints, by destination.
+    pub fn get_replay_controls(&self) -> Vec<(String, ReplayControl)> {
+        self.endpoint_tasks
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|t| t.replay_control.as_ref().map(|c| (t.destination.clone(), c.clone())))
+            .collect()
+    }
+
+    pub async fn connect<E>(&mut self, endpoint: E) -> Result<(), Error>
+    where
+        E: MetricEndpoint + 'static,
+    {
+        self.connect_endpoint(endpoint, None).await
+    }
+
+    async fn connect_endpoint<E>(&mut self, mut endpoint: E, replay_control: Option<ReplayControl>) -> Result<(), Error>
+    where
+        E: MetricEndpoint + 'static,
+    {
+        let destination = endpoint.get_destination().to_string();
+
+        let already_connected = || Error {
+            msg: format!("already connected to {}", destination),
+        };
+
+        if self.is_connected(&destination) {
+            return Err(already_connected());
+        }
+
+        if let Err(err) = endpoint.connect().await {
             return Err(Error {
-                msg: format!("Could not connect: {:?}", endpoint.err().unwrap()),
+                msg: format!("Could not connect to {}: {}", destination, err),
             });
-        } else {
-            let mut endpoint = endpoint.unwrap();
+        }
+
+        self.event_log.publish(BackendEvent::new(&destination, BackendEventKind::Connected));
 
-            //let connect_result: Result<(), ZmqError> = endpoint.connect().await;
+        let connection_state = Arc::new(Mutex::new(ConnectionState::Connecting));
 
-            //if connect_result.is_ok() {
+        let subscription_control = endpoint.get_subscription_control();
 
-                let aggregator = Arc::clone(&self.aggregator);
+        let context = ReceiverContext {
+            destination: destination.clone(),
+            aggregator: Arc::clone(&self.aggregator),
+            callbacks: Arc::clone(&self.callbacks),
+            connection_state: Arc::clone(&connection_state),
+            event_log: Arc::clone(&self.event_log),
+            sinks: Arc::clone(&self.sinks),
+            reconnect_policy: self.reconnect_policy.lock().unwrap().clone(),
+        };
 
-                let callbacks = Arc::clone(&self.callbacks);
+        let mut endpoint_tasks = self.endpoint_tasks.lock().unwrap();
 
-                let (quit_signal, quit_signal_receiver) = oneshot::channel::<()>();
+        // Another handle may have connected the same destination while this one was connecting
+        if endpoint_tasks.iter().any(|t| t.destination == destination) {
+            return Err(already_connected());
+        }
+
+        let (quit_signal, quit_signal_receiver) = oneshot::channel::<()>();
+
+        let task_join_handle = task::spawn(async move {
+            Self::receiver_handler(endpoint, quit_signal_receiver, context).await
+        });
+
+        endpoint_tasks.push(EndpointTask {
+            destination,
+            task_join_handle,
+            quit_signal,
+            connection_state,
+            replay_control,
+            subscription_control,
+        });
 
-                self.quit_signal = Some(quit_signal);
+        let mut alert_ticker = self.alert_ticker.lock().unwrap();
 
-                self.task_join_handle = Some(task::spawn(async move {
-                    Self::receiver_handler(endpoint, quit_signal_receiver, aggregator, callbacks)
-                        .await
-                }));
-            //} else {
-            //    return Err(Error {
-            //        msg: format!("{:?}", connect_result.err().unwrap()),
-            //    });
-            //}
+        if alert_ticker.is_none() {
+            let (quit_signal, quit_signal_receiver) = oneshot::channel::<()>();
+
+            let task_join_handle = task::spawn(Self::alert_tick_handler(
+                Arc::clone(&self.aggregator),
+                Arc::clone(&self.callbacks),
+                Arc::clone(&self.event_log),
+                quit_signal_receiver,
+            ));
+
+            alert_ticker.replace(AlertTicker { task_join_handle, quit_signal });
         }
 
         Ok(())
     }
 
+    /// Calls `cb` after every processed collection. It only signals that something changed, `add_sink` passes the data.
     pub fn add_callback<T : Fn() + Send + 'static>(&self, cb : T) {
         let mut callbacks_local = self.callbacks.lock().unwrap();
 
         callbacks_local.push(Box::new(cb));
     }
 
-    pub fn visit_metrics(&self, cb: impl Fn(&Metric)) {
+    pub fn visit_metrics(&self, cb: impl Fn(&MetricKey, &Metric)) {
         let aggregator = self.aggregator.lock().unwrap();
 
         aggregator.walk_metrics(cb);
@@ -116,28 +711,139 @@ impl Backend {
 
     pub fn map_metrics<T, F>(&self, cb: F) -> Vec<T>
     where
-        F: Fn(&Metric) -> T,
+        F: Fn(&MetricKey, &Metric) -> T,
     {
         let mut v = Vec::new();
 
         let aggregator_local = self.aggregator.lock().unwrap();
 
-        for m in aggregator_local.metric_iter() {
-            v.push(cb(m));
+        for (k, m) in aggregator_local.metric_iter() {
+            v.push(cb(k, m));
         }
 
         v
     }
 
+    pub fn get_sources(&self) -> Vec<String> {
+        let aggregator_local = self.aggregator.lock().unwrap();
+
+        aggregator_local.get_sources().into_iter().map(|s| s.to_string()).collect()
+    }
+
+    pub fn get_label_keys(&self) -> Vec<String> {
+        let aggregator_local = self.aggregator.lock().unwrap();
+
+        aggregator_local.get_label_keys().into_iter().map(|s| s.to_string()).collect()
+    }
+
+    pub fn get_connected_endpoints(&self) -> Vec<String> {
+        self.endpoint_tasks.lock().unwrap().iter().map(|t| t.destination.clone()).collect()
+    }
+
+    pub fn is_connected(&self, destination: &str) -> bool {
+        self.endpoint_tasks.lock().unwrap().iter().any(|t| t.destination == destination)
+    }
+
     pub fn get_metric_history(
         &self,
-        name: &str,
+        key: &MetricKey,
         history_data: &mut Vec<(f64, f64)>,
-        max_len: usize,
+        range: Duration,
+        max_points: usize,
     ) -> Option<(f64, f64)> {
         let aggregator_local = self.aggregator.lock().unwrap();
 
-        aggregator_local.get_metric_history(name, history_data, max_len)
+        aggregator_local.get_metric_history(key, history_data, range, max_points)
+    }
+
+    pub fn set_history_retention(&self, retention: Duration) {
+        let mut aggregator_local = self.aggregator.lock().unwrap();
+
+        aggregator_local.set_history_retention(retention);
+    }
+
+    pub fn set_rollups(&self, rollups: Vec<RollupSpec>) {
+        let mut aggregator_local = self.aggregator.lock().unwrap();
+
+        aggregator_local.set_rollups(rollups);
+    }
+
+    pub fn set_counter_wrap(&self, counter_wrap: CounterWrap) {
+        let mut aggregator_local = self.aggregator.lock().unwrap();
+
+        aggregator_local.set_counter_wrap(counter_wrap);
+    }
+
+    pub fn set_deltat(&self, deltat: Duration) {
+        let mut aggregator_local = self.aggregator.lock().unwrap();
+
+        aggregator_local.set_deltat(deltat);
+    }
+
+    pub fn set_rate_window(&self, rate_window: Duration) {
+        let mut aggregator_local = self.aggregator.lock().unwrap();
+
+        aggregator_local.set_rate_window(rate_window);
+    }
+
+    pub fn add_user_rule(&self, user_rule: UserMetricRule) -> usize {
+        let mut aggregator_local = self.aggregator.lock().unwrap();
+
+        aggregator_local.add_user_rule(user_rule)
+    }
+
+    pub fn remove_user_rule(&self, user_rule_id: usize) -> bool {
+        let mut aggregator_local = self.aggregator.lock().unwrap();
+
+        aggregator_local.remove_user_rule(user_rule_id)
+    }
+
+    pub fn get_user_rules(&self) -> Vec<(usize, UserMetricRule)> {
+        let aggregator_local = self.aggregator.lock().unwrap();
+
+        aggregator_local.get_user_rules()
+    }
+
+    pub fn add_derived_metric(&self, derived_metric: DerivedMetric) {
+        let mut aggregator_local = self.aggregator.lock().unwrap();
+
+        aggregator_local.add_derived_metric(derived_metric);
+    }
+
+    pub fn get_derived_metrics(&self) -> Vec<DerivedMetric> {
+        let aggregator_local = self.aggregator.lock().unwrap();
+
+        aggregator_local.get_derived_metrics()
+    }
+
+    pub fn add_alert_rule(&self, alert_rule: AlertRule) -> usize {
+        let mut aggregator_local = self.aggregator.lock().unwrap();
+
+        aggregator_local.add_alert_rule(alert_rule)
+    }
+
+    pub fn remove_alert_rule(&self, alert_rule_id: usize) -> bool {
+        let mut aggregator_local = self.aggregator.lock().unwrap();
+
+        aggregator_local.remove_alert_rule(alert_rule_id)
+    }
+
+    pub fn get_alert_rules(&self) -> Vec<(usize, AlertRule)> {
+        let aggregator_local = self.aggregator.lock().unwrap();
+
+        aggregator_local.get_alert_rules()
+    }
+
+    pub fn get_alerts(&self) -> Vec<Alert> {
+        let aggregator_local = self.aggregator.lock().unwrap();
+
+        aggregator_local.get_alerts()
+    }
+
+    pub fn get_counter_events(&self, key: &MetricKey) -> Vec<CounterEvent> {
+        let aggregator_local = self.aggregator.lock().unwrap();
+
+        aggregator_local.get_counter_events(key)
     }
 
     pub fn get_last_timestamp(&self) -> u64 {
@@ -146,106 +852,503 @@ impl Backend {
         aggregator_local.get_last_timestamp()
     }
 
-    pub fn fetch_updates<T>(&self, mut foreign_it: T)
+    pub fn fetch_updates<T>(&self, foreign_it: T)
     where
         T: Iterator,
         T::Item: AsMut<dyn MetricAdapter>,
     {
         let aggregator_local = self.aggregator.lock().unwrap();
 
-        while let Some(mut element) = foreign_it.next() {
-            if let Some(metric) = aggregator_local.get_metric(element.as_mut().get_name()) {
+        for mut element in foreign_it {
+            if let Some(metric) = aggregator_local.get_metric(element.as_mut().get_key()) {
                 element.as_mut().update_current(metric);
             }
         }
     }
 
+    /// Stops the receiver task of a single endpoint, returns false if it wasn't connected.
+    pub fn disconnect_endpoint(&mut self, destination: &str) -> bool {
+        let mut endpoint_tasks = self.endpoint_tasks.lock().unwrap();
+
+        if let Some(idx) = endpoint_tasks.iter().position(|t| t.destination == destination) {
+            Self::stop_endpoint_task(endpoint_tasks.remove(idx));
+
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Stops all endpoints, sinks and background tasks. Waits for the receiver, alert and sink tasks to end, so what is
+    /// in flight, e.g. the last collections of a recording, is written before the runtime shuts down.
     pub async fn disconnect(&mut self) {
-        if let Some(signal) = self.quit_signal.take() {
-            signal.send(()).unwrap();
+        let endpoint_tasks: Vec<EndpointTask> = self.endpoint_tasks.lock().unwrap().drain(..).collect();
 
-            if self.task_join_handle.is_some() {
+        // All are signalled first, so the receivers wind down in parallel
+        let task_join_handles: Vec<JoinHandle<()>> = endpoint_tasks
+            .into_iter()
+            .map(|endpoint_task| {
+                let _ = endpoint_task.quit_signal.send(());
 
-                let jh = self.task_join_handle.take().unwrap();
+                endpoint_task.task_join_handle
+            })
+            .collect();
 
-                let result = jh.await;
+        for task_join_handle in task_join_handles {
+            // A receiver task that panicked has nothing left to finish
+            let _ = task_join_handle.await;
+        }
 
-                if result.is_err() {
-                    // wahtever
-                }
-            }
+        self.recording.lock().unwrap().take();
+
+        let alert_ticker = self.alert_ticker.lock().unwrap().take();
+
+        // Awaited like the receivers, no alert event is published once disconnect returned
+        if let Some(alert_ticker) = alert_ticker {
+            let _ = alert_ticker.quit_signal.send(());
+
+            let _ = alert_ticker.task_join_handle.await;
+        }
+
+        if let Some(config_watcher) = self.config_watcher.lock().unwrap().take() {
+            config_watcher.stop();
+        }
+
+        let alert_notifier = self.alert_notifier.lock().unwrap().take();
+
+        if let Some(alert_notifier) = alert_notifier {
+            alert_notifier.stop().await;
+        }
+
+        let sink_tasks: Vec<SinkTask> = self.sinks.lock().unwrap().drain(..).collect();
+
+        for sink_task in sink_tasks {
+            sink_task.shutdown().await;
         }
     }
 
-    async fn receiver_handler(
-        mut endpoint: PrometheusPollEndpoint,
+    fn stop_endpoint_task(endpoint_task: EndpointTask) {
+        // The receiver task may already have terminated on its own, nothing left to signal then
+        let _ = endpoint_task.quit_signal.send(());
+
+        // Detach the receiver task, it finishes on its own once the quit signal is processed
+        drop(endpoint_task.task_join_handle);
+    }
+
+    async fn receiver_handler<E: MetricEndpoint>(
+        mut endpoint: E,
         quit_signal_receiver: oneshot::Receiver<()>,
-        aggregator: Arc<Mutex<MetricAggregator>>,
-        callbacks: Arc<Mutex<Vec<Box<MetricCallback>>>>,
+        context: ReceiverContext,
     ) {
 
         let mut quit_signal_receiver = quit_signal_receiver;
 
-        let recv_timeout = Duration::from_secs(1);
+        let recv_timeout = context.reconnect_policy.recv_timeout;
 
-        let sleep = time::sleep(recv_timeout.clone());
+        let sleep = time::sleep(recv_timeout);
         tokio::pin!(sleep);
 
+        let mut stats_interval = time::interval_at(Instant::now() + MESSAGE_STATS_INTERVAL, MESSAGE_STATS_INTERVAL);
+
+        let mut stats_messages = 0u64;
+        let mut stats_metrics = 0u64;
+
         loop {
             let mut do_reconnect = false;
 
             select! {
                 msg = endpoint.recv_msg() => {
-                    if let Ok(msg) = &msg {
-                        sleep.as_mut().reset(Instant::now() + recv_timeout.clone());
+                    match msg {
+                        Ok(msg) => {
+                            sleep.as_mut().reset(Instant::now() + recv_timeout);
 
-                        //let s = String::from_utf8(msg.get_data().clone());
+                            context.update_connection_state(ConnectionState::Connected);
 
+                            stats_messages += 1;
+                            stats_metrics += msg.get_metrics_ref().len() as u64;
 
-                        //let json_obj = json::parse(&s);
+                            for err in endpoint.take_decode_errors() {
+                                context.publish(BackendEventKind::DecodeError { msg: err.msg });
+                            }
 
-                        //if let Ok(json_obj) = json_obj {
+                            let processed = {
+                                let mut aggregator_local = context.aggregator.lock().unwrap();
 
-                        let mut aggregator_local = aggregator.lock().unwrap();
+                                aggregator_local.handle_collection(&msg);
 
-                        aggregator_local.handle_metrics(msg.get_timestamp(), msg.get_metrics_ref().as_slice());
+                                for counter_event in aggregator_local.take_counter_events() {
+                                    context.publish(BackendEventKind::CounterDiscontinuity {
+                                        metric: format!("{}{}", counter_event.key.get_name(), counter_event.key.get_labels()),
+                                        discontinuity: counter_event.discontinuity,
+                                    });
+                                }
 
-                        let callbacks_local = callbacks.lock().unwrap();
+                                for alert in aggregator_local.take_alert_events() {
+                                    context.publish(Self::get_alert_event_kind(alert));
+                                }
 
-                        for cb in callbacks_local.deref() {
-                            cb();
-                        }
+                                ProcessedCollection {
+                                    generated_metrics: aggregator_local.get_generated_metrics().to_vec(),
+                                    collection: msg,
+                                }
+                            };
 
-                        //}
+                            context.notify_callbacks();
 
-                    } else if let Err(err) = &msg {
-                        println!("Endpoint error: {:?}", err);
+                            context.dispatch(processed).await;
+                        }
+                        // The endpoint is still delivering, only this message is lost
+                        Err(err) if err.kind == EndpointErrorKind::Decode => {
+                            sleep.as_mut().reset(Instant::now() + recv_timeout);
 
-                        do_reconnect = true;
+                            context.publish(BackendEventKind::DecodeError { msg: err.msg });
+                        }
+                        Err(err) => {
+                            context.publish(BackendEventKind::EndpointError { msg: err.msg });
+
+                            do_reconnect = true;
+                        }
                     }
                 },
                 _ = (&mut quit_signal_receiver) => {
-                    println!("Quit signal received");
+                    context.publish(BackendEventKind::Disconnected);
                     break;
                 },
                 _ = (&mut sleep) => {
-                    println!("Timeout elapsed");
+                    if !endpoint.detects_stalls() {
+                        sleep.as_mut().reset(Instant::now() + recv_timeout);
+
+                        continue;
+                    }
 
-                    do_reconnect = true;
+                    context.publish(BackendEventKind::Timeout);
 
-                    sleep.as_mut().reset(Instant::now() + recv_timeout.clone());
+                    // The first timeout only marks the endpoint as stalled, if it stays silent for
+                    // another timeout period the connection is considered dead
+                    if *context.connection_state.lock().unwrap() == ConnectionState::Stalled {
+                        do_reconnect = true;
+                    } else {
+                        context.update_connection_state(ConnectionState::Stalled);
+                    }
+
+                    sleep.as_mut().reset(Instant::now() + recv_timeout);
+                },
+                _ = stats_interval.tick() => {
+                    context.publish(BackendEventKind::MessageStats {
+                        messages: stats_messages,
+                        metrics: stats_metrics,
+                        interval_s: MESSAGE_STATS_INTERVAL.as_secs_f64(),
+                    });
+
+                    stats_messages = 0;
+                    stats_metrics = 0;
                 }
             }
-/*
-            if do_reconnect {
-                let result = endpoint.try_reconnect().await;
 
-                if let Err(err) = result {
-                    println!("Could not reconnect: {:?}", err);
+            if do_reconnect {
+                let reconnected = Self::reconnect(&mut endpoint, &mut quit_signal_receiver, &context).await;
 
+                if !reconnected {
                     break;
                 }
-            }*/
+
+                sleep.as_mut().reset(Instant::now() + recv_timeout);
+            }
+        }
+    }
+
+    async fn alert_tick_handler(
+        aggregator: Arc<Mutex<MetricAggregator>>,
+        callbacks: Arc<Mutex<Vec<Box<MetricCallback>>>>,
+        event_log: Arc<EventLog>,
+        quit_signal_receiver: oneshot::Receiver<()>,
+    ) {
+        let mut quit_signal_receiver = quit_signal_receiver;
+
+        let mut evaluation_interval = time::interval(ALERT_EVALUATION_INTERVAL);
+
+        loop {
+            select! {
+                _ = &mut quit_signal_receiver => break,
+                _ = evaluation_interval.tick() => {}
+            }
+
+            let alerts = {
+                let mut aggregator_local = aggregator.lock().unwrap();
+
+                aggregator_local.evaluate_alerts();
+
+                aggregator_local.take_alert_events()
+            };
+
+            if alerts.is_empty() {
+                continue;
+            }
+
+            for alert in alerts {
+                let source = alert.key.get_source().to_string();
+
+                event_log.publish(BackendEvent::new(&source, Self::get_alert_event_kind(alert)));
+            }
+
+            for cb in callbacks.lock().unwrap().deref() {
+                cb();
+            }
+        }
+    }
+
+    fn get_alert_event_kind(alert: Alert) -> BackendEventKind {
+        BackendEventKind::Alert {
+            rule: alert.rule_name,
+            metric: format!("{}{}", alert.key.get_name(), alert.key.get_labels()),
+            state: alert.state,
+            value: alert.value,
+        }
+    }
+
+    /// Retries reconnecting with exponential backoff until it succeeds, the attempts of the
+    /// policy are exhausted or the quit signal is received. Returns true if reconnected.
+    async fn reconnect<E: MetricEndpoint>(
+        endpoint: &mut E,
+        quit_signal_receiver: &mut oneshot::Receiver<()>,
+        context: &ReceiverContext,
+    ) -> bool {
+        let reconnect_policy = &context.reconnect_policy;
+
+        let mut attempt = 0u32;
+
+        loop {
+            if reconnect_policy.attempts_exhausted(attempt) {
+                context.update_connection_state(ConnectionState::Failed);
+
+                return false;
+            }
+
+            context.update_connection_state(ConnectionState::Reconnecting { attempt: attempt + 1 });
+
+            select! {
+                _ = time::sleep(reconnect_policy.get_delay(attempt)) => {},
+                _ = &mut *quit_signal_receiver => {
+                    context.publish(BackendEventKind::Disconnected);
+
+                    return false;
+                }
+            }
+
+            match endpoint.try_reconnect().await {
+                Ok(()) => {
+                    context.publish(BackendEventKind::Reconnected);
+
+                    context.update_connection_state(ConnectionState::Connecting);
+
+                    return true;
+                }
+                Err(err) => {
+                    context.publish(BackendEventKind::ReconnectFailed { attempt: attempt + 1, msg: err.msg });
+
+                    attempt += 1;
+                }
+            }
+        }
+    }
+}
+
+impl ReceiverContext {
+    fn publish(&self, kind: BackendEventKind) {
+        self.event_log.publish(BackendEvent::new(&self.destination, kind));
+    }
+
+    // Waits for sinks that fell behind, which slows the receiver down to their pace
+    async fn dispatch(&self, processed: ProcessedCollection) {
+        let senders: Vec<_> = self.sinks.lock().unwrap().iter().map(|s| s.get_sender()).collect();
+
+        if senders.is_empty() {
+            return;
+        }
+
+        let processed = Arc::new(processed);
+
+        for sender in senders {
+            // Fails only if the sink failed, which is reported by its task
+            let _ = sender.send(Arc::clone(&processed)).await;
+        }
+    }
+
+    fn notify_callbacks(&self) {
+        let callbacks_local = self.callbacks.lock().unwrap();
+
+        for cb in callbacks_local.deref() {
+            cb();
+        }
+    }
+
+    fn update_connection_state(&self, new_state: ConnectionState) {
+        {
+            let mut connection_state_local = self.connection_state.lock().unwrap();
+
+            if *connection_state_local == new_state {
+                return;
+            }
+
+            *connection_state_local = new_state.clone();
+        }
+
+        self.publish(BackendEventKind::StateChanged { state: new_state });
+
+        self.notify_callbacks();
+    }
+}
+
+
+#[test]
+fn backend_endpoint_contract_test01() {
+    use async_trait::async_trait;
+
+    use crate::common::message::MetricCollection;
+    use crate::common::metric::{MetricUnit, MetricValue};
+    use crate::sink::ChannelSink;
+    use crate::source::EndpointError;
+
+    struct CountingEndpoint {
+        destination: String,
+
+        connected: bool,
+
+        count: i64,
+    }
+
+    #[async_trait]
+    impl MetricEndpoint for CountingEndpoint {
+        async fn connect(&mut self) -> Result<(), EndpointError> {
+            self.connected = true;
+
+            Ok(())
+        }
+
+        async fn try_reconnect(&mut self) -> Result<(), EndpointError> {
+            self.connect().await
+        }
+
+        async fn recv_msg(&mut self) -> Result<MetricCollection, EndpointError> {
+            assert!(self.connected);
+
+            time::sleep(Duration::from_millis(5)).await;
+
+            self.count += 1;
+
+            if self.count == 2 {
+                return Err(EndpointError::decode("unexpected payload"));
+            }
+
+            let metric = Metric::new(String::from("count"), MetricUnit::empty(), MetricValue::Integer(self.count));
+
+            Ok(MetricCollection::new(self.destination.clone(), String::new(), self.count as u64, vec![metric]))
+        }
+
+        fn get_destination(&self) -> &str {
+            &self.destination
         }
     }
+
+    let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+
+    runtime.block_on(async {
+        let mut backend = Backend::new();
+
+        // Schemes are matched case-insensitively, the factory gets the url as given
+        backend.get_endpoint_registry().register("Counting", |url| {
+            Ok(Box::new(CountingEndpoint { destination: url.to_string(), connected: false, count: 0 }))
+        });
+
+        let (sink, mut receiver) = ChannelSink::new("collections", 4);
+
+        backend.add_sink(sink).await.unwrap();
+
+        backend.connect_url("COUNTING://a").await.unwrap();
+
+        assert!(backend.is_connected("COUNTING://a"));
+        assert!(backend.connect_url("counting://a").await.is_ok());
+        assert!(backend.connect_url("COUNTING://a").await.is_err());
+
+        let processed = receiver.recv().await.unwrap();
+
+        assert_eq!(processed.collection.get_metrics_ref()[0].get_label(), "count");
+
+        // A message that can't be decoded is skipped without reconnecting
+        while receiver.recv().await.unwrap().collection.get_timestamp() < 3 {}
+
+        let events = backend.get_recent_events(64);
+
+        assert!(events.iter().any(|e| matches!(e.get_kind(), BackendEventKind::DecodeError { .. })));
+        assert!(!events.iter().any(|e| matches!(e.get_kind(), BackendEventKind::EndpointError { .. } | BackendEventKind::Reconnected)));
+
+        // Returns once the receivers and sinks are done, nothing is delivered afterwards
+        backend.disconnect().await;
+
+        let disconnected = backend.get_recent_events(64)
+            .iter()
+            .filter(|e| e.get_kind() == &BackendEventKind::Disconnected)
+            .count();
+
+        assert_eq!(disconnected, 2);
+
+        while receiver.recv().await.is_some() {}
+
+        assert!(backend.get_connected_endpoints().is_empty());
+    });
+}
+
+#[test]
+fn backend_absence_alert_test01() {
+    use std::str::FromStr;
+
+    use crate::aggregator::alert::AlertState;
+    use crate::capture::recorder::CaptureRecorder;
+    use crate::common::message::MetricCollection;
+    use crate::common::metric::{MetricUnit, MetricValue};
+    use crate::source::replay_endpoint::ReplaySpeed;
+
+    let path = std::env::temp_dir().join(format!("backend_absence_alert_test01_{}.cap", std::process::id()));
+
+    let _ = std::fs::remove_file(&path);
+
+    let mut recorder = CaptureRecorder::create(&path).unwrap();
+
+    for timestamp in 0..2 {
+        let temp = Metric::new(String::from("temp"), MetricUnit::empty(), MetricValue::Integer(20));
+
+        recorder.record(&MetricCollection::new(String::from("tcp://host-a:5555"), String::new(), timestamp, vec![temp])).unwrap();
+    }
+
+    recorder.finish().unwrap();
+
+    let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+
+    runtime.block_on(async {
+        let mut backend = Backend::new();
+
+        backend.add_alert_rule(AlertRule::from_str("gone: temp absent 300ms").unwrap());
+
+        let mut events = backend.subscribe_events();
+
+        let options = ReplayOptions { speed: ReplaySpeed::AsFastAsPossible, looping: false };
+
+        let control = backend.connect_replay(&path, options).await.unwrap();
+
+        // The only endpoint goes silent after its two collections, nothing triggers an evaluation but the tick
+        let alert = time::timeout(Duration::from_secs(5), async {
+            loop {
+                if let BackendEventKind::Alert { rule, state, .. } = events.recv().await.unwrap().get_kind() {
+                    return (rule.clone(), *state);
+                }
+            }
+        }).await.unwrap();
+
+        assert_eq!(alert, (String::from("gone"), AlertState::Firing));
+        assert!(control.get_status().finished);
+
+        backend.disconnect().await;
+    });
+
+    std::fs::remove_file(&path).unwrap();
 }
@@ -0,0 +1,403 @@
+// Capture files, an append-only recording of received metric collections.
+//
+// Layout, all integers little endian:
+//
+// ```text
+// header   magic "FOTCAP\0\0" | version u16 | reserved u16 | created µs u64
+// record   kind u8 | payload length u32 | payload checksum u32 (FNV-1a) | payload
+// trailer  magic "FOTCIDX\0" | offset of the index record u64
+// ```
+//
+// Collection records hold the local receive time, the collection timestamp, source, subscription and
+// metrics. Strings and counts are prefixed with their LEB128 encoded length. On a clean close an index
+// record (receive time and offset of every `INDEX_INTERVAL`th collection) and the trailer are appended.
+// Without the trailer, e.g. after a crash, readers scan the records and stop at the first incomplete one.
+
+use std::fmt;
+use std::fmt::{Display, Formatter};
+
+use crate::common::message::MetricCollection;
+use crate::common::metric::{Metric, MetricKind, MetricLabels, MetricRawUnit, MetricUnit, MetricValue, OrderOfMagnitude};
+
+pub mod reader;
+pub mod recorder;
+
+pub const CAPTURE_MAGIC: &[u8; 8] = b"FOTCAP\0\0";
+
+pub const TRAILER_MAGIC: &[u8; 8] = b"FOTCIDX\0";
+
+pub const CAPTURE_VERSION: u16 = 1;
+
+pub const HEADER_LEN: u64 = 20;
+
+pub const RECORD_HEADER_LEN: u64 = 9;
+
+pub const TRAILER_LEN: u64 = 16;
+
+// Every INDEX_INTERVAL-th collection gets an index entry
+pub const INDEX_INTERVAL: u64 = 64;
+
+pub const RECORD_COLLECTION: u8 = 1;
+
+pub const RECORD_INDEX: u8 = 2;
+
+// Upper bound on a record payload, anything larger is taken as corruption
+pub const MAX_RECORD_LEN: u32 = 64 * 1024 * 1024;
+
+#[derive(Debug, Clone)]
+pub struct CaptureError {
+    pub msg: String,
+}
+
+/// A collection read back from a capture file, `recv_time_us` is the local time it was received at.
+pub struct CapturedCollection {
+    pub recv_time_us: u64,
+    pub collection: MetricCollection,
+}
+
+/// Receive time and file offset of a collection record.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IndexEntry {
+    pub recv_time_us: u64,
+    pub offset: u64,
+}
+
+pub(crate) struct Encoder {
+    pub buf: Vec<u8>,
+}
+
+pub(crate) struct Decoder<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl CaptureError {
+    pub fn new(msg: &str) -> CaptureError {
+        CaptureError { msg: msg.to_string() }
+    }
+}
+
+impl Display for CaptureError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.msg)
+    }
+}
+
+impl std::error::Error for CaptureError {}
+
+impl From<std::io::Error> for CaptureError {
+    fn from(error: std::io::Error) -> Self {
+        CaptureError { msg: error.to_string() }
+    }
+}
+
+/// 32 bit FNV-1a hash, detects torn and corrupted records.
+pub fn checksum(data: &[u8]) -> u32 {
+    data.iter().fold(0x811c9dc5u32, |hash, b| (hash ^ *b as u32).wrapping_mul(0x01000193))
+}
+
+fn raw_unit_code(raw_unit: &MetricRawUnit) -> u8 {
+    match raw_unit {
+        MetricRawUnit::None => 0,
+        MetricRawUnit::Packets => 1,
+        MetricRawUnit::Bits => 2,
+        MetricRawUnit::Bytes => 3,
+        MetricRawUnit::Seconds => 4,
+    }
+}
+
+fn raw_unit_from_code(code: u8) -> Result<MetricRawUnit, CaptureError> {
+    match code {
+        0 => Ok(MetricRawUnit::None),
+        1 => Ok(MetricRawUnit::Packets),
+        2 => Ok(MetricRawUnit::Bits),
+        3 => Ok(MetricRawUnit::Bytes),
+        4 => Ok(MetricRawUnit::Seconds),
+        _ => Err(CaptureError::new(&format!("invalid unit code {}", code))),
+    }
+}
+
+fn order_of_magnitude_from_exponent(exponent: i8) -> Result<OrderOfMagnitude, CaptureError> {
+    match exponent {
+        -9 => Ok(OrderOfMagnitude::Nano),
+        -6 => Ok(OrderOfMagnitude::Micro),
+        -3 => Ok(OrderOfMagnitude::Milli),
+        0 => Ok(OrderOfMagnitude::One),
+        3 => Ok(OrderOfMagnitude::Kilo),
+        6 => Ok(OrderOfMagnitude::Mega),
+        9 => Ok(OrderOfMagnitude::Giga),
+        12 => Ok(OrderOfMagnitude::Tera),
+        _ => Err(CaptureError::new(&format!("invalid order of magnitude 10^{}", exponent))),
+    }
+}
+
+fn kind_code(kind: MetricKind) -> u8 {
+    match kind {
+        MetricKind::Untyped => 0,
+        MetricKind::Counter => 1,
+        MetricKind::Gauge => 2,
+        MetricKind::Histogram => 3,
+        MetricKind::Summary => 4,
+        MetricKind::Info => 5,
+    }
+}
+
+fn kind_from_code(code: u8) -> Result<MetricKind, CaptureError> {
+    match code {
+        0 => Ok(MetricKind::Untyped),
+        1 => Ok(MetricKind::Counter),
+        2 => Ok(MetricKind::Gauge),
+        3 => Ok(MetricKind::Histogram),
+        4 => Ok(MetricKind::Summary),
+        5 => Ok(MetricKind::Info),
+        _ => Err(CaptureError::new(&format!("invalid metric kind {}", code))),
+    }
+}
+
+impl Encoder {
+    pub fn new() -> Encoder {
+        Encoder { buf: Vec::new() }
+    }
+
+    pub fn u8(&mut self, value: u8) {
+        self.buf.push(value);
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.buf.push((value as u8) | 0x80);
+            value >>= 7;
+        }
+
+        self.buf.push(value as u8);
+    }
+
+    // Zigzag encoding keeps small negative numbers short
+    pub fn signed_varint(&mut self, value: i64) {
+        self.varint(((value << 1) ^ (value >> 63)) as u64);
+    }
+
+    pub fn str(&mut self, value: &str) {
+        self.varint(value.len() as u64);
+        self.buf.extend_from_slice(value.as_bytes());
+    }
+
+    fn metric(&mut self, metric: &Metric) {
+        self.str(metric.get_label());
+
+        let unit = metric.get_unit();
+        let (raw_unit_num, raw_unit_den) = unit.get_raw_unit();
+
+        self.u8(raw_unit_code(raw_unit_num));
+        self.u8(raw_unit_code(raw_unit_den));
+        self.u8(unit.get_order_of_magnitude().get_exponent() as i8 as u8);
+
+        self.u8(kind_code(metric.get_kind()));
+
+        match metric.get_value() {
+            MetricValue::Empty => self.u8(0),
+            MetricValue::Integer(value) => {
+                self.u8(1);
+                self.signed_varint(*value);
+            }
+            MetricValue::Number(value) => {
+                self.u8(2);
+                self.u64(value.to_bits());
+            }
+            MetricValue::String(value) => {
+                self.u8(3);
+                self.str(value);
+            }
+        }
+
+        self.varint(metric.get_labels().len() as u64);
+
+        for (label_key, label_value) in metric.get_labels().iter() {
+            self.str(label_key);
+            self.str(label_value);
+        }
+
+        match metric.get_help() {
+            Some(help) => {
+                self.u8(1);
+                self.str(help);
+            }
+            None => self.u8(0),
+        }
+
+        match metric.get_timestamp() {
+            Some(timestamp) => {
+                self.u8(1);
+                self.signed_varint(timestamp);
+            }
+            None => self.u8(0),
+        }
+    }
+
+    pub fn collection(&mut self, recv_time_us: u64, collection: &MetricCollection) {
+        self.u64(recv_time_us);
+        self.u64(collection.get_timestamp());
+        self.str(collection.get_src());
+        self.str(collection.get_subscription());
+
+        self.varint(collection.get_metrics_ref().len() as u64);
+
+        for metric in collection.get_metrics_ref() {
+            self.metric(metric);
+        }
+    }
+
+    pub fn index(&mut self, entries: &[IndexEntry]) {
+        self.varint(entries.len() as u64);
+
+        for entry in entries {
+            self.u64(entry.recv_time_us);
+            self.u64(entry.offset);
+        }
+    }
+}
+
+impl<'a> Decoder<'a> {
+    pub fn new(data: &'a [u8]) -> Decoder<'a> {
+        Decoder { data, pos: 0 }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], CaptureError> {
+        if self.data.len() - self.pos < len {
+            return Err(CaptureError::new("unexpected end of record"));
+        }
+
+        let bytes = &self.data[self.pos..self.pos + len];
+
+        self.pos += len;
+
+        Ok(bytes)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, CaptureError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn u64(&mut self) -> Result<u64, CaptureError> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    pub fn varint(&mut self) -> Result<u64, CaptureError> {
+        let mut value = 0u64;
+
+        for shift in (0..64).step_by(7) {
+            let b = self.u8()?;
+
+            value |= ((b & 0x7f) as u64) << shift;
+
+            if b & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+
+        Err(CaptureError::new("varint too long"))
+    }
+
+    pub fn signed_varint(&mut self) -> Result<i64, CaptureError> {
+        let value = self.varint()?;
+
+        Ok(((value >> 1) as i64) ^ -((value & 1) as i64))
+    }
+
+    // Counts are checked against the remaining data so corrupt input can't trigger huge allocations
+    fn count(&mut self) -> Result<usize, CaptureError> {
+        let count = self.varint()?;
+
+        if count > (self.data.len() - self.pos) as u64 {
+            return Err(CaptureError::new("invalid element count"));
+        }
+
+        Ok(count as usize)
+    }
+
+    pub fn str(&mut self) -> Result<String, CaptureError> {
+        let len = self.count()?;
+
+        String::from_utf8(self.take(len)?.to_vec()).map_err(|_| CaptureError::new("invalid utf-8 string"))
+    }
+
+    fn metric(&mut self) -> Result<Metric, CaptureError> {
+        let name = self.str()?;
+
+        let unit = MetricUnit::new(
+            raw_unit_from_code(self.u8()?)?,
+            raw_unit_from_code(self.u8()?)?,
+            order_of_magnitude_from_exponent(self.u8()? as i8)?,
+        );
+
+        let kind = kind_from_code(self.u8()?)?;
+
+        let value = match self.u8()? {
+            0 => MetricValue::Empty,
+            1 => MetricValue::Integer(self.signed_varint()?),
+            2 => MetricValue::Number(f64::from_bits(self.u64()?)),
+            3 => MetricValue::String(self.str()?),
+            tag => return Err(CaptureError::new(&format!("invalid value tag {}", tag))),
+        };
+
+        let mut labels = MetricLabels::new();
+
+        for _ in 0..self.count()? {
+            let label_key = self.str()?;
+            let label_value = self.str()?;
+
+            labels.insert(&label_key, &label_value);
+        }
+
+        let help = match self.u8()? {
+            0 => None,
+            _ => Some(self.str()?),
+        };
+
+        let timestamp = match self.u8()? {
+            0 => None,
+            _ => Some(self.signed_varint()?),
+        };
+
+        Ok(Metric::new(name, unit, value)
+            .with_kind(kind)
+            .with_labels(labels)
+            .with_help(help)
+            .with_timestamp(timestamp))
+    }
+
+    pub fn collection(&mut self) -> Result<CapturedCollection, CaptureError> {
+        let recv_time_us = self.u64()?;
+        let timestamp = self.u64()?;
+        let src = self.str()?;
+        let subscription = self.str()?;
+
+        let metric_count = self.count()?;
+
+        let mut metrics = Vec::with_capacity(metric_count);
+
+        for _ in 0..metric_count {
+            metrics.push(self.metric()?);
+        }
+
+        Ok(CapturedCollection {
+            recv_time_us,
+            collection: MetricCollection::new(src, subscription, timestamp, metrics),
+        })
+    }
+
+    pub fn index(&mut self) -> Result<Vec<IndexEntry>, CaptureError> {
+        (0..self.count()?)
+            .map(|_| {
+                Ok(IndexEntry {
+                    recv_time_us: self.u64()?,
+                    offset: self.u64()?,
+                })
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,245 @@
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use crate::capture::{
+    checksum, CaptureError, CapturedCollection, Decoder, IndexEntry, CAPTURE_MAGIC, CAPTURE_VERSION, HEADER_LEN,
+    INDEX_INTERVAL, MAX_RECORD_LEN, RECORD_COLLECTION, RECORD_HEADER_LEN, RECORD_INDEX, TRAILER_LEN, TRAILER_MAGIC,
+};
+
+/// Reads the collections of a capture file in recorded order.
+///
+/// Files that weren't closed cleanly are read up to their last complete record.
+pub struct CaptureReader {
+    file: BufReader<File>,
+
+    version: u16,
+
+    created_us: u64,
+
+    index: Vec<IndexEntry>,
+
+    // Offset of the next record to read
+    pos: u64,
+
+    // End of the last complete collection record
+    data_end: u64,
+
+    // Set if the file ends with an index and trailer
+    complete: bool,
+}
+
+impl CaptureReader {
+    pub fn open(path: &Path) -> Result<CaptureReader, CaptureError> {
+        let mut file = BufReader::new(File::open(path)?);
+
+        let file_len = file.get_ref().metadata()?.len();
+
+        let mut header = [0u8; HEADER_LEN as usize];
+
+        file.read_exact(&mut header)
+            .map_err(|_| CaptureError::new(&format!("{} is too short for a capture file", path.display())))?;
+
+        if &header[0..8] != CAPTURE_MAGIC {
+            return Err(CaptureError::new(&format!("{} is not a capture file", path.display())));
+        }
+
+        let version = u16::from_le_bytes([header[8], header[9]]);
+
+        if version > CAPTURE_VERSION {
+            return Err(CaptureError::new(&format!("unsupported capture version {}", version)));
+        }
+
+        let created_us = u64::from_le_bytes(header[12..20].try_into().unwrap());
+
+        let mut reader = CaptureReader {
+            file,
+            version,
+            created_us,
+            index: Vec::new(),
+            pos: HEADER_LEN,
+            data_end: file_len,
+            complete: false,
+        };
+
+        if !reader.read_trailer_index(file_len)? {
+            reader.scan()?;
+        }
+
+        Ok(reader)
+    }
+
+    // Loads the index of a cleanly closed file, returns false if there is no valid trailer
+    fn read_trailer_index(&mut self, file_len: u64) -> Result<bool, CaptureError> {
+        if file_len < HEADER_LEN + TRAILER_LEN {
+            return Ok(false);
+        }
+
+        let mut trailer = [0u8; TRAILER_LEN as usize];
+
+        self.file.seek(SeekFrom::Start(file_len - TRAILER_LEN))?;
+        self.file.read_exact(&mut trailer)?;
+
+        if &trailer[0..8] != TRAILER_MAGIC {
+            return Ok(false);
+        }
+
+        let index_offset = u64::from_le_bytes(trailer[8..16].try_into().unwrap());
+
+        if index_offset < HEADER_LEN || index_offset > file_len - TRAILER_LEN {
+            return Ok(false);
+        }
+
+        match self.read_record(index_offset, file_len - TRAILER_LEN)? {
+            Some((RECORD_INDEX, payload)) => {
+                self.index = Decoder::new(&payload).index()?;
+                self.data_end = index_offset;
+                self.complete = true;
+
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    // Rebuilds the index and finds the end of the last complete collection record
+    fn scan(&mut self) -> Result<(), CaptureError> {
+        let scan_end = self.data_end;
+
+        let mut offset = HEADER_LEN;
+        let mut collections = 0u64;
+
+        self.data_end = HEADER_LEN;
+
+        while let Some((kind, payload)) = self.read_record(offset, scan_end)? {
+            let record_end = offset + RECORD_HEADER_LEN + payload.len() as u64;
+
+            if kind == RECORD_COLLECTION {
+                if collections % INDEX_INTERVAL == 0 {
+                    self.index.push(IndexEntry {
+                        recv_time_us: Decoder::new(&payload).u64()?,
+                        offset,
+                    });
+                }
+
+                collections += 1;
+
+                self.data_end = record_end;
+            }
+
+            offset = record_end;
+        }
+
+        Ok(())
+    }
+
+    // Reads the record at `offset`, None if it is incomplete or corrupt
+    fn read_record(&mut self, offset: u64, end: u64) -> Result<Option<(u8, Vec<u8>)>, CaptureError> {
+        if offset + RECORD_HEADER_LEN > end {
+            return Ok(None);
+        }
+
+        let mut record_header = [0u8; RECORD_HEADER_LEN as usize];
+
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.file.read_exact(&mut record_header)?;
+
+        let kind = record_header[0];
+        let len = u32::from_le_bytes(record_header[1..5].try_into().unwrap());
+        let expected_checksum = u32::from_le_bytes(record_header[5..9].try_into().unwrap());
+
+        if len > MAX_RECORD_LEN || offset + RECORD_HEADER_LEN + len as u64 > end {
+            return Ok(None);
+        }
+
+        let mut payload = vec![0u8; len as usize];
+
+        self.file.read_exact(&mut payload)?;
+
+        if checksum(&payload) != expected_checksum {
+            return Ok(None);
+        }
+
+        Ok(Some((kind, payload)))
+    }
+
+    pub fn get_version(&self) -> u16 {
+        self.version
+    }
+
+    /// Creation time of the capture in microseconds since epoch.
+    pub fn get_created_us(&self) -> u64 {
+        self.created_us
+    }
+
+    /// Sparse index, receive time and offset of every `INDEX_INTERVAL`th collection.
+    pub fn get_index(&self) -> &[IndexEntry] {
+        &self.index
+    }
+
+    /// Offset behind the last complete collection record, appending continues there.
+    pub fn get_data_end(&self) -> u64 {
+        self.data_end
+    }
+
+    /// True if the file was closed cleanly, false if it was truncated, e.g. by a crash.
+    pub fn is_complete(&self) -> bool {
+        self.complete
+    }
+
+    /// Receive times of the first and the last collection, None for a capture without collections.
+    ///
+    /// Only the records behind the last index entry are read, the read position is kept.
+    pub fn get_time_range(&mut self) -> Result<Option<(u64, u64)>, CaptureError> {
+        let first = match self.index.first() {
+            Some(entry) => entry.recv_time_us,
+            None => return Ok(None),
+        };
+
+        let mut offset = self.index.last().map(|entry| entry.offset).unwrap_or(HEADER_LEN);
+        let mut last = first;
+
+        while let Some((kind, payload)) = self.read_record(offset, self.data_end)? {
+            if kind == RECORD_COLLECTION {
+                last = Decoder::new(&payload).u64()?;
+            }
+
+            offset += RECORD_HEADER_LEN + payload.len() as u64;
+        }
+
+        Ok(Some((first, last)))
+    }
+
+    /// Reads the next collection, None at the end of the capture.
+    pub fn next_collection(&mut self) -> Result<Option<CapturedCollection>, CaptureError> {
+        while let Some((kind, payload)) = self.read_record(self.pos, self.data_end)? {
+            self.pos += RECORD_HEADER_LEN + payload.len() as u64;
+
+            if kind == RECORD_COLLECTION {
+                return Decoder::new(&payload).collection().map(Some);
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Positions the reader at the first collection received at or after `recv_time_us`.
+    pub fn seek(&mut self, recv_time_us: u64) -> Result<(), CaptureError> {
+        let index_pos = self.index.partition_point(|entry| entry.recv_time_us <= recv_time_us);
+
+        self.pos = match index_pos {
+            0 => HEADER_LEN,
+            _ => self.index[index_pos - 1].offset,
+        };
+
+        while let Some((kind, payload)) = self.read_record(self.pos, self.data_end)? {
+            if kind == RECORD_COLLECTION && Decoder::new(&payload).u64()? >= recv_time_us {
+                break;
+            }
+
+            self.pos += RECORD_HEADER_LEN + payload.len() as u64;
+        }
+
+        Ok(())
+    }
+}
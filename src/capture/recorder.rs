@@ -0,0 +1,252 @@
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+
+use crate::capture::reader::CaptureReader;
+use crate::capture::{
+    checksum, CaptureError, Encoder, IndexEntry, CAPTURE_MAGIC, CAPTURE_VERSION, HEADER_LEN, INDEX_INTERVAL,
+    RECORD_COLLECTION, RECORD_HEADER_LEN, RECORD_INDEX, TRAILER_MAGIC,
+};
+use crate::common::message::MetricCollection;
+use crate::sink::{OutputSink, ProcessedCollection, SinkError};
+
+/// Appends received collections to a capture file.
+///
+/// The index and trailer are written by `finish`, or when the recorder is dropped.
+pub struct CaptureRecorder {
+    file: BufWriter<File>,
+
+    // Offset the next record is written at
+    offset: u64,
+
+    collections: u64,
+
+    index: Vec<IndexEntry>,
+
+    finished: bool,
+}
+
+/// Sink recording every received collection. Computed metrics are left out, a replay computes them again.
+pub struct RecorderSink {
+    name: String,
+
+    // Taken by close
+    recorder: Option<CaptureRecorder>,
+}
+
+fn now_us() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_micros() as u64).unwrap_or(0)
+}
+
+impl CaptureRecorder {
+    /// Creates a capture file. An existing capture is continued behind its last complete record,
+    /// dropping its index and any partially written record.
+    pub fn create(path: &Path) -> Result<CaptureRecorder, CaptureError> {
+        let existing_len = std::fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+
+        let (offset, index) = if existing_len > 0 {
+            let reader = CaptureReader::open(path)?;
+
+            (reader.get_data_end(), reader.get_index().to_vec())
+        } else {
+            (0, Vec::new())
+        };
+
+        let mut file = OpenOptions::new().create(true).write(true).truncate(false).open(path)?;
+
+        let offset = if offset > 0 {
+            file.set_len(offset)?;
+            file.seek(SeekFrom::Start(offset))?;
+
+            offset
+        } else {
+            file.set_len(0)?;
+
+            let mut header = Vec::with_capacity(HEADER_LEN as usize);
+
+            header.extend_from_slice(CAPTURE_MAGIC);
+            header.extend_from_slice(&CAPTURE_VERSION.to_le_bytes());
+            header.extend_from_slice(&0u16.to_le_bytes());
+            header.extend_from_slice(&now_us().to_le_bytes());
+
+            file.write_all(&header)?;
+
+            HEADER_LEN
+        };
+
+        Ok(CaptureRecorder {
+            file: BufWriter::new(file),
+            offset,
+            collections: 0,
+            index,
+            finished: false,
+        })
+    }
+
+    fn write_record(&mut self, kind: u8, payload: &[u8]) -> Result<u64, CaptureError> {
+        let record_offset = self.offset;
+
+        self.file.write_all(&[kind])?;
+        self.file.write_all(&(payload.len() as u32).to_le_bytes())?;
+        self.file.write_all(&checksum(payload).to_le_bytes())?;
+        self.file.write_all(payload)?;
+
+        // Every record is flushed, so a crash loses at most the record being written
+        self.file.flush()?;
+
+        self.offset += RECORD_HEADER_LEN + payload.len() as u64;
+
+        Ok(record_offset)
+    }
+
+    pub fn record(&mut self, collection: &MetricCollection) -> Result<(), CaptureError> {
+        let recv_time_us = now_us();
+
+        let mut encoder = Encoder::new();
+
+        encoder.collection(recv_time_us, collection);
+
+        let offset = self.write_record(RECORD_COLLECTION, &encoder.buf)?;
+
+        if self.collections % INDEX_INTERVAL == 0 {
+            self.index.push(IndexEntry { recv_time_us, offset });
+        }
+
+        self.collections += 1;
+
+        Ok(())
+    }
+
+    fn write_index(&mut self) -> Result<(), CaptureError> {
+        self.finished = true;
+
+        let mut encoder = Encoder::new();
+
+        encoder.index(&self.index);
+
+        let index_offset = self.write_record(RECORD_INDEX, &encoder.buf)?;
+
+        self.file.write_all(TRAILER_MAGIC)?;
+        self.file.write_all(&index_offset.to_le_bytes())?;
+        self.file.flush()?;
+
+        Ok(())
+    }
+
+    /// Writes index and trailer, which marks the capture as cleanly closed.
+    pub fn finish(mut self) -> Result<(), CaptureError> {
+        self.write_index()
+    }
+}
+
+impl RecorderSink {
+    pub fn create(path: &Path) -> Result<RecorderSink, CaptureError> {
+        Ok(RecorderSink {
+            name: path.display().to_string(),
+            recorder: Some(CaptureRecorder::create(path)?),
+        })
+    }
+}
+
+#[async_trait]
+impl OutputSink for RecorderSink {
+    fn get_name(&self) -> String {
+        self.name.clone()
+    }
+
+    async fn consume(&mut self, processed: Arc<ProcessedCollection>) -> Result<(), SinkError> {
+        match self.recorder.as_mut() {
+            Some(recorder) => recorder
+                .record(&processed.collection)
+                .map_err(|err| SinkError::new(&format!("recording failed: {}", err.msg))),
+            None => Ok(()),
+        }
+    }
+
+    async fn close(&mut self) -> Result<(), SinkError> {
+        match self.recorder.take() {
+            Some(recorder) => recorder
+                .finish()
+                .map_err(|err| SinkError::new(&format!("finishing the recording failed: {}", err.msg))),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for CaptureRecorder {
+    fn drop(&mut self) {
+        if !self.finished {
+            // Nothing to report to, without an index the file is still readable by scanning
+            let _ = self.write_index();
+        }
+    }
+}
+
+
+#[test]
+fn capture_record_replay_test01() {
+    use crate::common::metric::{Metric, MetricKind, MetricRawUnit, MetricUnit, MetricValue, OrderOfMagnitude};
+
+    let path = std::env::temp_dir().join(format!("capture_record_replay_test01_{}.cap", std::process::id()));
+
+    let _ = std::fs::remove_file(&path);
+
+    let metrics = vec![
+        Metric::new("rx_bytes".to_string(), MetricUnit::new(MetricRawUnit::Bytes, MetricRawUnit::None, OrderOfMagnitude::Kilo), MetricValue::Integer(-42))
+            .with_kind(MetricKind::Counter)
+            .with_labels([("device", "eth0")].into_iter().collect())
+            .with_help(Some(String::from("Received bytes")))
+            .with_timestamp(Some(-1000)),
+        Metric::new("state".to_string(), MetricUnit::empty(), MetricValue::String(String::from("up"))),
+        Metric::new("load".to_string(), MetricUnit::empty(), MetricValue::Number(0.25)),
+    ];
+
+    let mut recorder = CaptureRecorder::create(&path).unwrap();
+
+    for idx in 0..100 {
+        recorder.record(&MetricCollection::new(String::from("tcp://host-a:5555"), String::from("stats"), idx, metrics.clone())).unwrap();
+    }
+
+    recorder.finish().unwrap();
+
+    let mut reader = CaptureReader::open(&path).unwrap();
+
+    assert!(reader.is_complete());
+    assert_eq!(reader.get_index().len(), 2);
+
+    let captured = reader.next_collection().unwrap().unwrap();
+
+    assert_eq!(captured.collection.get_src(), "tcp://host-a:5555");
+    assert_eq!(captured.collection.get_subscription(), "stats");
+    assert_eq!(captured.collection.get_metrics_ref(), &metrics);
+
+    // Continue the capture, then cut it off in the middle of the last record
+    let mut recorder = CaptureRecorder::create(&path).unwrap();
+
+    recorder.record(&MetricCollection::new(String::from("tcp://host-a:5555"), String::from("stats"), 100, metrics.clone())).unwrap();
+    recorder.record(&MetricCollection::new(String::from("tcp://host-a:5555"), String::from("stats"), 101, metrics)).unwrap();
+
+    let last_offset = recorder.offset;
+
+    recorder.finish().unwrap();
+
+    OpenOptions::new().write(true).open(&path).unwrap().set_len(last_offset - 5).unwrap();
+
+    let mut reader = CaptureReader::open(&path).unwrap();
+
+    assert!(!reader.is_complete());
+
+    let mut timestamps = Vec::new();
+
+    while let Some(captured) = reader.next_collection().unwrap() {
+        timestamps.push(captured.collection.get_timestamp());
+    }
+
+    assert_eq!(timestamps, (0..101).collect::<Vec<u64>>());
+
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,196 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::fmt::{Display, Formatter};
+
+use crate::aggregator::aggregator::MetricKey;
+use crate::common::metric::{Metric, MetricLabels};
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum LabelMatcher {
+    Present { key: String },
+    Equal { key: String, value: String },
+    NotEqual { key: String, value: String },
+}
+
+/// Conjunction of label matchers, parsed from e.g. `device=eth0,queue!=1,vlan`.
+///
+/// A bare key only requires the label to be present.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct LabelFilter {
+    matchers: Vec<LabelMatcher>,
+}
+
+/// Metrics rearranged so that the values of one label key become columns.
+pub struct PivotTable {
+    pub columns: Vec<String>,
+
+    pub rows: Vec<PivotRow>,
+}
+
+/// Metric of one pivot table cell, None if there is no metric for that column.
+pub type PivotCell = Option<(MetricKey, Metric)>;
+
+/// One row of a pivot table, all metrics in it share source, name and all labels except the pivot label.
+pub struct PivotRow {
+    pub source: String,
+
+    pub name: String,
+
+    pub labels: MetricLabels,
+
+    // One entry per pivot table column
+    pub cells: Vec<PivotCell>,
+}
+
+impl LabelMatcher {
+    pub fn matches(&self, labels: &MetricLabels) -> bool {
+        match self {
+            LabelMatcher::Present { key } => labels.get(key).is_some(),
+            LabelMatcher::Equal { key, value } => labels.get(key) == Some(value.as_str()),
+            LabelMatcher::NotEqual { key, value } => labels.get(key) != Some(value.as_str()),
+        }
+    }
+}
+
+impl Display for LabelMatcher {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            LabelMatcher::Present { key } => write!(f, "{}", key),
+            LabelMatcher::Equal { key, value } => write!(f, "{}={}", key, value),
+            LabelMatcher::NotEqual { key, value } => write!(f, "{}!={}", key, value),
+        }
+    }
+}
+
+impl TryFrom<&str> for LabelMatcher {
+    type Error = String;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        let (key, matcher) = if let Some((key, label_value)) = value.split_once("!=") {
+            (key.trim(), LabelMatcher::NotEqual { key: key.trim().to_string(), value: label_value.trim().to_string() })
+        } else if let Some((key, label_value)) = value.split_once('=') {
+            (key.trim(), LabelMatcher::Equal { key: key.trim().to_string(), value: label_value.trim().to_string() })
+        } else {
+            (value.trim(), LabelMatcher::Present { key: value.trim().to_string() })
+        };
+
+        if key.is_empty() {
+            return Err(format!("missing label key in '{}'", value));
+        }
+
+        Ok(matcher)
+    }
+}
+
+impl LabelFilter {
+    pub fn new(matchers: Vec<LabelMatcher>) -> LabelFilter {
+        LabelFilter { matchers }
+    }
+
+    pub fn matches(&self, labels: &MetricLabels) -> bool {
+        self.matchers.iter().all(|m| m.matches(labels))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.matchers.is_empty()
+    }
+}
+
+impl Display for LabelFilter {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for (idx, matcher) in self.matchers.iter().enumerate() {
+            if idx > 0 {
+                write!(f, ",")?;
+            }
+
+            write!(f, "{}", matcher)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl TryFrom<&str> for LabelFilter {
+    type Error = String;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        let matchers = value
+            .split(',')
+            .filter(|m| !m.trim().is_empty())
+            .map(LabelMatcher::try_from)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(LabelFilter::new(matchers))
+    }
+}
+
+/// Builds a pivot table with one column per distinct value of `pivot_key`.
+///
+/// Metrics without the pivot label are left out.
+pub fn pivot(metrics: &[(MetricKey, Metric)], pivot_key: &str) -> PivotTable {
+    let columns: Vec<String> = metrics
+        .iter()
+        .filter_map(|(_, m)| m.get_labels().get(pivot_key))
+        .collect::<BTreeSet<&str>>()
+        .into_iter()
+        .map(|v| v.to_string())
+        .collect();
+
+    let mut rows: BTreeMap<(String, String, MetricLabels), Vec<PivotCell>> = BTreeMap::new();
+
+    for (key, metric) in metrics {
+        if let Some(pivot_value) = metric.get_labels().get(pivot_key) {
+            let remaining_labels: MetricLabels = metric.get_labels().iter().filter(|(k, _)| *k != pivot_key).collect();
+
+            let row_key = (key.get_source().to_string(), metric.get_label().to_string(), remaining_labels);
+
+            let cells = rows.entry(row_key).or_insert_with(|| vec![None; columns.len()]);
+
+            // Columns are sorted, so the lookup can't fail
+            let column = columns.binary_search_by(|c| c.as_str().cmp(pivot_value)).unwrap();
+
+            cells[column] = Some((key.clone(), metric.clone()));
+        }
+    }
+
+    PivotTable {
+        columns,
+        rows: rows
+            .into_iter()
+            .map(|((source, name, labels), cells)| PivotRow { source, name, labels, cells })
+            .collect(),
+    }
+}
+
+
+#[test]
+fn label_view_filter_pivot_test01() {
+    use crate::common::metric::{MetricUnit, MetricValue};
+
+    let filter = LabelFilter::try_from("device=eth0, queue!=1,vlan").unwrap();
+
+    assert_eq!(filter.to_string(), "device=eth0,queue!=1,vlan");
+    assert!(filter.matches(&[("device", "eth0"), ("vlan", "10")].into_iter().collect()));
+    assert!(!filter.matches(&[("device", "eth0"), ("vlan", "10"), ("queue", "1")].into_iter().collect()));
+    assert!(!filter.matches(&[("device", "eth0")].into_iter().collect()));
+    assert!(LabelFilter::try_from("=eth0").is_err());
+    assert!(LabelFilter::try_from("").unwrap().is_empty());
+
+    let metrics: Vec<(MetricKey, Metric)> = [("eth0", "0", 1), ("eth0", "1", 2), ("eth1", "0", 3)]
+        .iter()
+        .map(|(device, queue, value)| {
+            let metric = Metric::new("rx_packets".to_string(), MetricUnit::empty(), MetricValue::Integer(*value))
+                .with_labels([("device", *device), ("queue", *queue)].into_iter().collect());
+
+            (MetricKey::from_metric("tcp://host-a:5555", &metric), metric)
+        })
+        .collect();
+
+    let table = pivot(&metrics, "queue");
+
+    assert_eq!(table.columns, vec!["0", "1"]);
+    assert_eq!(table.rows.len(), 2);
+    assert_eq!(table.rows[0].labels.get("device"), Some("eth0"));
+    assert_eq!(table.rows[0].cells[1].as_ref().unwrap().1.get_value(), &MetricValue::Integer(2));
+    assert!(table.rows[1].cells[1].is_none());
+}
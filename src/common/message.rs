@@ -1,9 +1,5 @@
 use crate::common::metric::{Metric};
 
-#[allow(unused_variables)]
-
-
-
 pub struct MetricCollection {
     src: String,
     subscription: String,
@@ -21,6 +17,24 @@ impl MetricCollection {
         }
     }
 
+    pub fn get_src(&self) -> &str {
+        &self.src
+    }
+
+    pub fn get_subscription(&self) -> &str {
+        &self.subscription
+    }
+
+    /// Source the metrics are kept under, the destination followed by `#<subscription>` for collections
+    /// received on a topic, so equally named metrics of different topics don't mix.
+    pub fn get_namespace(&self) -> String {
+        if self.subscription.is_empty() {
+            self.src.clone()
+        } else {
+            format!("{}#{}", self.src, self.subscription)
+        }
+    }
+
     pub fn get_timestamp(&self) -> u64 {
         self.timestamp
     }
@@ -29,6 +43,23 @@ impl MetricCollection {
         &self.metrics
     }
 
+    /// Moves the collection and the samples carrying their own timestamp `offset_us` later.
+    pub fn shift_timestamps(mut self, offset_us: u64) -> MetricCollection {
+        self.timestamp += offset_us;
+
+        self.metrics = self
+            .metrics
+            .into_iter()
+            .map(|metric| {
+                let timestamp = metric.get_timestamp().map(|t| t.saturating_add(offset_us as i64));
+
+                metric.with_timestamp(timestamp)
+            })
+            .collect();
+
+        self
+    }
+
     pub fn get_metrics(self) -> Vec<Metric> {
         self.metrics
     }
@@ -45,11 +45,35 @@ pub struct MetricUnit {
     order_of_magnitude : OrderOfMagnitude
 }
 
+/// Metric type as announced by the source, e.g. via a prometheus `# TYPE` line.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, Default)]
+pub enum MetricKind {
+    #[default]
+    Untyped,
+    Counter,
+    Gauge,
+    Histogram,
+    Summary,
+    // Constant value, the information is in the labels (e.g. version or build info)
+    Info,
+}
+
+/// Label (dimension) key/value pairs of a metric, kept sorted by key.
+#[derive(Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Clone, Default)]
+pub struct MetricLabels {
+    labels: Vec<(String, String)>,
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct Metric {
     label: String,
     unit : MetricUnit,
     value: MetricValue,
+    kind: MetricKind,
+    labels: MetricLabels,
+    help: Option<String>,
+    // Source provided sample time in microseconds since epoch
+    timestamp: Option<i64>,
 }
 
 impl OrderOfMagnitude {
@@ -164,13 +188,13 @@ impl fmt::Display for MetricUnit {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         match self.raw_unit_den {
             MetricRawUnit::None => {
-                write!(f, "{}{}", self.order_of_magnitude.get_abbr(), self.raw_unit_num.to_string())
+                write!(f, "{}{}", self.order_of_magnitude.get_abbr(), self.raw_unit_num)
             },
             _ => {
                 if self.raw_unit_num == MetricRawUnit::None && self.raw_unit_den == MetricRawUnit::Seconds {
                     write!(f, "{}Hz", self.order_of_magnitude.get_abbr())
                 } else {
-                    write!(f, "{}{}/{}", self.order_of_magnitude.get_abbr(), self.raw_unit_num.to_string(), self.raw_unit_den.to_string())
+                    write!(f, "{}{}/{}", self.order_of_magnitude.get_abbr(), self.raw_unit_num, self.raw_unit_den)
                 }
             }
         }
@@ -258,7 +282,7 @@ impl TryFrom<&json::JsonValue> for MetricValue {
                 let type_field = o["type"].as_str();
                 let value_field = &o["value"];
 
-                return if let Some(type_str) = type_field {
+                if let Some(type_str) = type_field {
                     match type_str {
                         "empty" => Ok(MetricValue::Empty),
                         "string" => Ok(MetricValue::String(
@@ -279,16 +303,147 @@ impl TryFrom<&json::JsonValue> for MetricValue {
                     Err(JsonError::WrongType(
                         "type field is missing or has wrong type".to_string(),
                     ))
-                };
+                }
             }
             _ => Err(Self::Error::WrongType("invalid json type".to_string())),
         }
     }
 }
 
+impl fmt::Display for MetricKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            MetricKind::Untyped => write!(f, "untyped"),
+            MetricKind::Counter => write!(f, "counter"),
+            MetricKind::Gauge => write!(f, "gauge"),
+            MetricKind::Histogram => write!(f, "histogram"),
+            MetricKind::Summary => write!(f, "summary"),
+            MetricKind::Info => write!(f, "info"),
+        }
+    }
+}
+
+impl TryFrom<&str> for MetricKind {
+    type Error = String;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "untyped" | "unknown" => Ok(MetricKind::Untyped),
+            "counter" => Ok(MetricKind::Counter),
+            "gauge" => Ok(MetricKind::Gauge),
+            "histogram" => Ok(MetricKind::Histogram),
+            "summary" => Ok(MetricKind::Summary),
+            "info" => Ok(MetricKind::Info),
+            _ => Err(format!("unknown metric kind '{}'", value)),
+        }
+    }
+}
+
+/// Reads an object of label key/value pairs, a missing (null) object yields no labels.
+impl TryFrom<&json::JsonValue> for MetricLabels {
+    type Error = json::JsonError;
+
+    fn try_from(value: &JsonValue) -> Result<Self, Self::Error> {
+        match value {
+            json::JsonValue::Null => Ok(MetricLabels::new()),
+            json::JsonValue::Object(obj) => {
+                let mut labels = MetricLabels::new();
+
+                for (key, label_value) in obj.iter() {
+                    match label_value {
+                        json::JsonValue::Short(_) | json::JsonValue::String(_) => labels.insert(key, label_value.as_str().unwrap()),
+                        json::JsonValue::Number(_) | json::JsonValue::Boolean(_) => labels.insert(key, &label_value.dump()),
+                        _ => return Err(Self::Error::WrongType(format!("invalid value type for label {}", key))),
+                    }
+                }
+
+                Ok(labels)
+            }
+            _ => Err(Self::Error::WrongType("labels must be an object".to_string())),
+        }
+    }
+}
+
+impl MetricLabels {
+    pub fn new() -> MetricLabels {
+        MetricLabels { labels: Vec::new() }
+    }
+
+    /// Sets the value of `key`, replacing an existing value.
+    pub fn insert(&mut self, key: &str, value: &str) {
+        match self.labels.binary_search_by(|(k, _)| k.as_str().cmp(key)) {
+            Ok(idx) => self.labels[idx].1 = value.to_string(),
+            Err(idx) => self.labels.insert(idx, (key.to_string(), value.to_string())),
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.labels
+            .binary_search_by(|(k, _)| k.as_str().cmp(key))
+            .ok()
+            .map(|idx| self.labels[idx].1.as_str())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.labels.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.labels.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.labels.is_empty()
+    }
+}
+
+impl<K: AsRef<str>, V: AsRef<str>> FromIterator<(K, V)> for MetricLabels {
+    fn from_iter<T: IntoIterator<Item = (K, V)>>(iter: T) -> Self {
+        let mut labels = MetricLabels::new();
+
+        for (k, v) in iter {
+            labels.insert(k.as_ref(), v.as_ref());
+        }
+
+        labels
+    }
+}
+
+/// Formats as prometheus label set (`{key="value",...}`), nothing at all if there are no labels.
+impl fmt::Display for MetricLabels {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if self.labels.is_empty() {
+            return Ok(());
+        }
+
+        write!(f, "{{")?;
+
+        for (idx, (k, v)) in self.labels.iter().enumerate() {
+            if idx > 0 {
+                write!(f, ",")?;
+            }
+
+            write!(f, "{}=\"", k)?;
+
+            for c in v.chars() {
+                match c {
+                    '\\' => write!(f, "\\\\")?,
+                    '"' => write!(f, "\\\"")?,
+                    '\n' => write!(f, "\\n")?,
+                    _ => write!(f, "{}", c)?,
+                }
+            }
+
+            write!(f, "\"")?;
+        }
+
+        write!(f, "}}")
+    }
+}
+
 impl fmt::Display for Metric {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}: {} {}", &self.label, &self.value, self.unit.to_string())
+        write!(f, "{}{}: {} {}", &self.label, &self.labels, &self.value, self.unit)
     }
 }
 
@@ -303,11 +458,16 @@ impl TryFrom<&json::JsonValue> for Metric {
                 let unit_field = &obj["unit"];
 
                 if let Some(label_str) = label_field {
-                    Ok(Metric {
-                        label: label_str.to_string(),
-                        unit: MetricUnit::try_from(unit_field)?,
-                        value: MetricValue::try_from(metric_value_obj)?,
-                    })
+                    Ok(Metric::new(
+                        label_str.to_string(),
+                        MetricUnit::try_from(unit_field)?,
+                        MetricValue::try_from(metric_value_obj)?,
+                    )
+                    .with_labels(MetricLabels::try_from(&obj["labels"])?)
+                    .with_kind(match obj["kind"].as_str() {
+                        Some(kind_str) => MetricKind::try_from(kind_str).map_err(Self::Error::WrongType)?,
+                        None => MetricKind::Untyped,
+                    }))
                 } else {
                     Err(Self::Error::WrongType("invalid json type".to_string()))
                 }
@@ -322,14 +482,60 @@ impl Metric {
         Metric {
             label,
             unit,
-            value
+            value,
+            kind: MetricKind::Untyped,
+            labels: MetricLabels::new(),
+            help: None,
+            timestamp: None,
         }
     }
 
+    pub fn with_kind(mut self, kind: MetricKind) -> Metric {
+        self.kind = kind;
+        self
+    }
+
+    pub fn with_labels(mut self, labels: MetricLabels) -> Metric {
+        self.labels = labels;
+        self
+    }
+
+    pub fn with_help(mut self, help: Option<String>) -> Metric {
+        self.help = help;
+        self
+    }
+
+    pub fn with_timestamp(mut self, timestamp: Option<i64>) -> Metric {
+        self.timestamp = timestamp;
+        self
+    }
+
+    // Name of the metric, without labels
     pub fn get_label(&self) -> &str {
         &self.label
     }
 
+    /// Name plus label set, unique for every time series of a source.
+    pub fn get_series_id(&self) -> String {
+        format!("{}{}", self.label, self.labels)
+    }
+
+    pub fn get_kind(&self) -> MetricKind {
+        self.kind
+    }
+
+    pub fn get_labels(&self) -> &MetricLabels {
+        &self.labels
+    }
+
+    pub fn get_help(&self) -> Option<&str> {
+        self.help.as_deref()
+    }
+
+    pub fn get_timestamp(&self) -> Option<i64> {
+        self.timestamp
+    }
+
     pub fn get_unit(&self) -> &MetricUnit {
         &self.unit
     }
@@ -352,4 +558,25 @@ fn metric_value_creation_test01() {
     assert_eq!(value, MetricValue::Integer(123456));
 
 
-}
\ No newline at end of file
+}
+
+#[test]
+fn metric_json_labels_test01() {
+    let mobj = json::parse(r#"{
+                "label": "rx_pkts",
+                "unit": "pkts",
+                "labels": {"port": 1, "queue": "3"},
+                "kind": "counter",
+                "value": {"type": "integer", "value": 42}}"#).unwrap();
+
+    let metric = Metric::try_from(&mobj).unwrap();
+
+    assert_eq!(metric.get_labels().get("port"), Some("1"));
+    assert_eq!(metric.get_kind(), MetricKind::Counter);
+    assert_eq!(metric.get_series_id(), "rx_pkts{port=\"1\",queue=\"3\"}");
+
+    let mut invalid = mobj.clone();
+    invalid["labels"] = json::parse("[1, 2]").unwrap();
+
+    assert!(Metric::try_from(&invalid).is_err());
+}
@@ -0,0 +1,81 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// Stand-in for an HTTP receiver like a webhook or InfluxDB, shared by the tests of the senders.
+pub struct MockHttpServer {
+    addr: SocketAddr,
+}
+
+impl MockHttpServer {
+    /// Serves on a free local port until the runtime ends. `respond` is called with the body of every
+    /// request and returns the status of the response, e.g. `200 OK`.
+    pub async fn start<F>(respond: F) -> MockHttpServer
+    where
+        F: Fn(String) -> &'static str + Send + Sync + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let respond = Arc::new(respond);
+
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+
+                tokio::spawn(Self::handle_connection(stream, Arc::clone(&respond)));
+            }
+        });
+
+        MockHttpServer { addr }
+    }
+
+    pub fn get_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    // Answers the requests of a keep-alive connection one after another
+    async fn handle_connection<F>(mut stream: TcpStream, respond: Arc<F>)
+    where
+        F: Fn(String) -> &'static str,
+    {
+        let mut data = Vec::new();
+        let mut buf = [0u8; 4096];
+
+        loop {
+            let n = stream.read(&mut buf).await.unwrap_or(0);
+
+            if n == 0 {
+                return;
+            }
+
+            data.extend_from_slice(&buf[..n]);
+
+            let text = String::from_utf8_lossy(&data).to_string();
+
+            let header_end = match text.find("\r\n\r\n") {
+                Some(pos) => pos + 4,
+                None => continue,
+            };
+
+            let content_length: usize = text[..header_end]
+                .lines()
+                .find_map(|l| l.to_ascii_lowercase().strip_prefix("content-length:").map(|v| v.trim().parse().unwrap()))
+                .unwrap_or(0);
+
+            if data.len() < header_end + content_length {
+                continue;
+            }
+
+            let body = String::from_utf8_lossy(&data[header_end..header_end + content_length]).to_string();
+
+            data.drain(..header_end + content_length);
+
+            let status = respond(body);
+
+            stream.write_all(format!("HTTP/1.1 {}\r\nContent-Length: 0\r\n\r\n", status).as_bytes()).await.unwrap();
+        }
+    }
+}
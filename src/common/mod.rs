@@ -1,7 +1,13 @@
 use std::collections::VecDeque;
+use std::str::FromStr;
+use std::time::Duration;
 
 pub mod message;
 pub mod metric;
+pub mod label_view;
+
+#[cfg(test)]
+pub mod mock_http;
 
 
 pub fn vec_shift<T>(data : &mut VecDeque<T>, new_element : T, max_size : usize) {
@@ -15,3 +21,24 @@ pub fn vec_shift<T>(data : &mut VecDeque<T>, new_element : T, max_size : usize)
     }
 }
 
+/// Parses a duration like `500ms`, `30s`, `15m` or `1h`, a plain number is taken as seconds.
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+
+    let unit_start = s.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(s.len());
+
+    let (value_str, unit) = s.split_at(unit_start);
+
+    let value = f64::from_str(value_str).map_err(|_| format!("invalid duration '{}'", s))?;
+
+    let factor = match unit.trim() {
+        "ms" => 0.001,
+        "" | "s" => 1.0,
+        "m" => 60.0,
+        "h" => 3600.0,
+        "d" => 86400.0,
+        _ => return Err(format!("invalid duration unit in '{}', expected ms, s, m, h or d", s)),
+    };
+
+    Duration::try_from_secs_f64(value * factor).map_err(|_| format!("duration '{}' out of range", s))
+}
@@ -0,0 +1,710 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use std::time::Duration;
+
+use json::JsonValue;
+
+use crate::aggregator::aggregator::{CounterWrap, DEFAULT_DELTAT, DEFAULT_RATE_WINDOW};
+use crate::aggregator::alert::AlertRule;
+use crate::aggregator::expression::DerivedMetric;
+use crate::aggregator::history::{parse_rollup_specs, HistoryConfig};
+use crate::aggregator::user_rule::UserMetricRule;
+use crate::alert_notifier::{NotifierConfig, NotifyChannel};
+use crate::common::parse_duration;
+use crate::headless_frontend::{HeadlessOptions, StreamFormat};
+use crate::source::connection::ReconnectPolicy;
+use crate::source::zmq_endpoint::TopicFilter;
+
+// Environment variables are named <prefix><SECTION>_<KEY>, e.g. FOTC_TUI_TICK
+pub const CONFIG_ENV_PREFIX: &str = "FOTC_";
+
+// Names the config file if none is given on the command line
+pub const CONFIG_PATH_ENV: &str = "FOTC_CONFIG";
+
+// Separates the items of list settings given in the environment, commas occur within expressions
+const ENV_LIST_SEPARATOR: char = ';';
+
+// Sections and their keys, anything else in a config file is rejected
+const KNOWN_KEYS: &[(&str, &[&str])] = &[
+    ("endpoints", &["urls", "topics"]),
+    ("connection", &["recv_timeout", "initial_delay", "max_delay", "multiplier", "jitter", "max_attempts"]),
+    ("aggregator", &["deltat", "rate_window", "counter_wrap", "history", "rollups", "rules", "derive"]),
+    ("alerts", &["rules", "command", "webhook", "bell", "repeat", "rate_limit", "notify_resolved", "timeout"]),
+    ("tui", &["tick", "plot_points"]),
+    ("gui", &["width", "height", "plot_points"]),
+    ("headless", &["format", "interval", "filter", "count", "duration"]),
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AggregatorConfig {
+    // Shortest time span a rate is computed over
+    pub deltat: Duration,
+
+    pub rate_window: Duration,
+
+    pub counter_wrap: CounterWrap,
+
+    pub history: HistoryConfig,
+
+    pub rules: Vec<UserMetricRule>,
+
+    pub derived_metrics: Vec<DerivedMetric>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AlertsConfig {
+    pub rules: Vec<AlertRule>,
+
+    // Notification channels, see NotifyChannel
+    pub command: Option<String>,
+    pub webhook: Option<String>,
+    pub bell: bool,
+
+    pub repeat: Duration,
+
+    // Notifications per minute at most
+    pub rate_limit: usize,
+
+    pub notify_resolved: bool,
+
+    pub timeout: Duration,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TuiConfig {
+    // Interval the table and graph are refreshed at
+    pub tick: Duration,
+
+    // Points of the history graph
+    pub plot_points: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GuiConfig {
+    // Initial window size in logical pixels
+    pub width: f32,
+    pub height: f32,
+
+    // Points per plotted metric
+    pub plot_points: usize,
+}
+
+/// All settings, built from the defaults with the config file, the environment and the command line on top.
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub endpoints: Vec<String>,
+
+    // Topics ZMQ endpoints subscribe to
+    pub topics: Vec<TopicFilter>,
+
+    pub connection: ReconnectPolicy,
+
+    pub aggregator: AggregatorConfig,
+
+    pub alerts: AlertsConfig,
+
+    pub tui: TuiConfig,
+
+    pub gui: GuiConfig,
+
+    pub headless: HeadlessOptions,
+
+    // Where each setting that isn't a default was last set, as `describe` of its layer or a command line option
+    origins: HashMap<String, String>,
+}
+
+/// Invalid setting, `key` names it as written in its source, e.g. `tui.tick` or `FOTC_TUI_TICK`.
+#[derive(Debug, Clone)]
+pub struct ConfigError {
+    pub key: String,
+    pub msg: String,
+
+    // Further invalid settings found along with this one
+    pub others: Vec<ConfigError>,
+}
+
+/// One source of settings, applied on top of the ones before it.
+pub enum ConfigLayer {
+    File {
+        path: String,
+        table: toml::value::Table,
+    },
+    Env {
+        vars: HashMap<String, String>,
+    },
+}
+
+// A setting as found in a layer, environment variables are always text
+enum RawValue<'a> {
+    Toml(&'a toml::Value),
+    Text(&'a str),
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.key, self.msg)?;
+
+        for other in &self.others {
+            write!(f, "; {}", other)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl ConfigError {
+    pub fn new(key: &str, msg: &str) -> ConfigError {
+        ConfigError { key: key.to_string(), msg: msg.to_string(), others: Vec::new() }
+    }
+
+    // The first error with the others attached, all of them are reported at once, not one per attempt
+    fn combine(errors: Vec<ConfigError>) -> Result<(), ConfigError> {
+        let mut errors = errors.into_iter();
+
+        match errors.next() {
+            Some(first) => Err(ConfigError { others: errors.collect(), ..first }),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Default for AggregatorConfig {
+    fn default() -> Self {
+        AggregatorConfig {
+            deltat: Duration::from_micros(DEFAULT_DELTAT),
+            rate_window: DEFAULT_RATE_WINDOW,
+            counter_wrap: CounterWrap::None,
+            history: HistoryConfig::default(),
+            rules: Vec::new(),
+            derived_metrics: Vec::new(),
+        }
+    }
+}
+
+impl Default for AlertsConfig {
+    fn default() -> Self {
+        let notifier_config = NotifierConfig::new(Vec::new());
+
+        AlertsConfig {
+            rules: Vec::new(),
+            command: None,
+            webhook: None,
+            bell: false,
+            repeat: notifier_config.repeat_interval,
+            rate_limit: notifier_config.max_per_minute,
+            notify_resolved: notifier_config.notify_resolved,
+            timeout: notifier_config.timeout,
+        }
+    }
+}
+
+impl AlertsConfig {
+    /// None if no notification channel is configured.
+    pub fn get_notifier_config(&self) -> Option<NotifierConfig> {
+        let mut channels = Vec::new();
+
+        if let Some(command) = &self.command {
+            channels.push(NotifyChannel::Command { command: command.clone() });
+        }
+
+        if let Some(url) = &self.webhook {
+            channels.push(NotifyChannel::Webhook { url: url.clone() });
+        }
+
+        if self.bell {
+            channels.push(NotifyChannel::Bell);
+        }
+
+        if channels.is_empty() {
+            return None;
+        }
+
+        let mut notifier_config = NotifierConfig::new(channels);
+
+        notifier_config.repeat_interval = self.repeat;
+        notifier_config.max_per_minute = self.rate_limit;
+        notifier_config.notify_resolved = self.notify_resolved;
+        notifier_config.timeout = self.timeout;
+
+        Some(notifier_config)
+    }
+}
+
+impl Default for TuiConfig {
+    fn default() -> Self {
+        TuiConfig {
+            tick: Duration::from_millis(250),
+            plot_points: 400,
+        }
+    }
+}
+
+impl Default for GuiConfig {
+    fn default() -> Self {
+        GuiConfig {
+            width: 800.0,
+            height: 600.0,
+            plot_points: 1000,
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            endpoints: Vec::new(),
+            topics: TopicFilter::default_topics(),
+            connection: ReconnectPolicy::default(),
+            aggregator: AggregatorConfig::default(),
+            alerts: AlertsConfig::default(),
+            tui: TuiConfig::default(),
+            gui: GuiConfig::default(),
+            headless: HeadlessOptions::default(),
+            origins: HashMap::new(),
+        }
+    }
+}
+
+fn toml_to_json(value: &toml::Value) -> JsonValue {
+    match value {
+        toml::Value::String(s) => s.as_str().into(),
+        toml::Value::Integer(i) => (*i).into(),
+        toml::Value::Float(f) => (*f).into(),
+        toml::Value::Boolean(b) => (*b).into(),
+        toml::Value::Datetime(d) => d.to_string().into(),
+        toml::Value::Array(items) => JsonValue::Array(items.iter().map(toml_to_json).collect()),
+        toml::Value::Table(table) => {
+            let mut obj = JsonValue::new_object();
+
+            for (key, value) in table {
+                obj[key.as_str()] = toml_to_json(value);
+            }
+
+            obj
+        }
+    }
+}
+
+fn parse_number<T: FromStr>(s: &str) -> Result<T, String> {
+    T::from_str(s.trim()).map_err(|_| format!("invalid number '{}'", s))
+}
+
+fn parse_bool(s: &str) -> Result<bool, String> {
+    match s.trim() {
+        "true" | "1" | "yes" => Ok(true),
+        "false" | "0" | "no" => Ok(false),
+        _ => Err(format!("invalid boolean '{}', expected true or false", s)),
+    }
+}
+
+/// Items of `old` missing in `new` and items of `new` missing in `old`.
+pub fn diff_lists<T: PartialEq + Clone>(old: &[T], new: &[T]) -> (Vec<T>, Vec<T>) {
+    let removed = old.iter().filter(|item| !new.contains(item)).cloned().collect();
+
+    let added = new.iter().filter(|item| !old.contains(item)).cloned().collect();
+
+    (removed, added)
+}
+
+impl RawValue<'_> {
+    // Scalars may be given as strings or as native toml values
+    fn parse<T>(&self, parse: impl Fn(&str) -> Result<T, String>) -> Result<T, String> {
+        let text = match self {
+            RawValue::Text(s) => s.to_string(),
+            RawValue::Toml(toml::Value::String(s)) => s.clone(),
+            RawValue::Toml(toml::Value::Integer(i)) => i.to_string(),
+            RawValue::Toml(toml::Value::Float(f)) => f.to_string(),
+            RawValue::Toml(toml::Value::Boolean(b)) => b.to_string(),
+            RawValue::Toml(_) => return Err(String::from("expected a single value")),
+        };
+
+        parse(&text)
+    }
+
+    // Items of a list, table items are passed to `parse_table` as json objects
+    fn parse_list<T>(
+        &self,
+        parse: impl Fn(&str) -> Result<T, String>,
+        parse_table: impl Fn(&JsonValue) -> Result<T, String>,
+    ) -> Result<Vec<T>, String> {
+        match self {
+            RawValue::Text(s) => s
+                .split(ENV_LIST_SEPARATOR)
+                .filter(|item| !item.trim().is_empty())
+                .map(|item| parse(item.trim()))
+                .collect(),
+            RawValue::Toml(toml::Value::Array(items)) => items
+                .iter()
+                .enumerate()
+                .map(|(idx, item)| {
+                    match item {
+                        toml::Value::String(s) => parse(s),
+                        toml::Value::Table(_) => parse_table(&toml_to_json(item)),
+                        _ => Err(String::from("expected a string or a table")),
+                    }
+                    .map_err(|msg| format!("item {}: {}", idx + 1, msg))
+                })
+                .collect(),
+            RawValue::Toml(_) => Err(String::from("expected a list")),
+        }
+    }
+
+    fn parse_strings(&self) -> Result<Vec<String>, String> {
+        self.parse_list(|s| Ok(s.to_string()), |_| Err(String::from("expected a string")))
+    }
+}
+
+impl ConfigLayer {
+    /// Parses the content of a config file, `path` is only used in error messages.
+    pub fn from_toml(path: &str, content: &str) -> Result<ConfigLayer, ConfigError> {
+        let table = match toml::Value::from_str(content) {
+            Ok(toml::Value::Table(table)) => table,
+            Ok(_) => return Err(ConfigError::new(path, "expected a table")),
+            Err(err) => return Err(ConfigError::new(path, &err.to_string())),
+        };
+
+        Ok(ConfigLayer::File { path: path.to_string(), table })
+    }
+
+    pub fn from_file(path: &str) -> Result<ConfigLayer, ConfigError> {
+        let content = std::fs::read_to_string(path).map_err(|err| ConfigError::new(path, &err.to_string()))?;
+
+        ConfigLayer::from_toml(path, &content)
+    }
+
+    /// Keeps the variables starting with CONFIG_ENV_PREFIX.
+    pub fn from_env(vars: impl Iterator<Item = (String, String)>) -> ConfigLayer {
+        ConfigLayer::Env {
+            vars: vars.filter(|(name, _)| name.starts_with(CONFIG_ENV_PREFIX)).collect(),
+        }
+    }
+
+    fn get_env_name(section: &str, key: &str) -> String {
+        format!("{}{}_{}", CONFIG_ENV_PREFIX, section.to_uppercase(), key.to_uppercase())
+    }
+
+    fn get(&self, section: &str, key: &str) -> Option<RawValue<'_>> {
+        match self {
+            ConfigLayer::File { table, .. } => table.get(section)?.get(key).map(RawValue::Toml),
+            ConfigLayer::Env { vars } => vars.get(&Self::get_env_name(section, key)).map(|value| RawValue::Text(value)),
+        }
+    }
+
+    // The name of a setting as the user wrote it
+    fn describe(&self, section: &str, key: &str) -> String {
+        match self {
+            ConfigLayer::File { path, .. } => format!("{}.{} in {}", section, key, path),
+            ConfigLayer::Env { .. } => Self::get_env_name(section, key),
+        }
+    }
+
+    // Typos in a file would otherwise be silently ignored, unknown variables can't be told apart
+    // from ones meant for something else
+    fn check_known_keys(&self) -> Vec<ConfigError> {
+        let (path, table) = match self {
+            ConfigLayer::File { path, table } => (path, table),
+            ConfigLayer::Env { .. } => return Vec::new(),
+        };
+
+        let mut errors = Vec::new();
+
+        for (section, value) in table {
+            let known_keys = match KNOWN_KEYS.iter().find(|(name, _)| name == section) {
+                Some((_, known_keys)) => known_keys,
+                None => {
+                    errors.push(ConfigError::new(&format!("{} in {}", section, path), "unknown section"));
+
+                    continue;
+                }
+            };
+
+            let section_table = match value.as_table() {
+                Some(section_table) => section_table,
+                None => {
+                    errors.push(ConfigError::new(&format!("{} in {}", section, path), "expected a table"));
+
+                    continue;
+                }
+            };
+
+            for key in section_table.keys().filter(|key| !known_keys.contains(&key.as_str())) {
+                errors.push(ConfigError::new(&self.describe(section, key), "unknown key"));
+            }
+        }
+
+        errors
+    }
+
+    fn set<T>(&self, section: &str, key: &str, target: &mut T, parse: impl Fn(&RawValue) -> Result<T, String>) -> Result<(), ConfigError> {
+        if let Some(raw_value) = self.get(section, key) {
+            *target = parse(&raw_value).map_err(|msg| ConfigError::new(&self.describe(section, key), &msg))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Config {
+    /// The defaults with the config file, if any, and the environment on top.
+    pub fn load(path: Option<&str>, env: impl Iterator<Item = (String, String)>) -> Result<Config, ConfigError> {
+        let mut config = Config::default();
+
+        if let Some(path) = path {
+            config.apply(&ConfigLayer::from_file(path)?)?;
+        }
+
+        config.apply(&ConfigLayer::from_env(env))?;
+
+        Ok(config)
+    }
+
+    /// Overrides the settings present in `layer`, lists included. Reports all invalid settings of the layer.
+    pub fn apply(&mut self, layer: &ConfigLayer) -> Result<(), ConfigError> {
+        let mut errors = layer.check_known_keys();
+
+        let mut collect = |result: Result<(), ConfigError>| {
+            if let Err(err) = result {
+                errors.push(err);
+            }
+        };
+
+        collect(layer.set("endpoints", "urls", &mut self.endpoints, |v| v.parse_strings()));
+        collect(layer.set("endpoints", "topics", &mut self.topics, |v| {
+            v.parse_list(TopicFilter::from_str, |_| Err(String::from("expected a topic")))
+        }));
+
+        let connection = &mut self.connection;
+
+        collect(layer.set("connection", "recv_timeout", &mut connection.recv_timeout, |v| v.parse(parse_duration)));
+        collect(layer.set("connection", "initial_delay", &mut connection.initial_delay, |v| v.parse(parse_duration)));
+        collect(layer.set("connection", "max_delay", &mut connection.max_delay, |v| v.parse(parse_duration)));
+        collect(layer.set("connection", "multiplier", &mut connection.multiplier, |v| v.parse(parse_number)));
+        collect(layer.set("connection", "jitter", &mut connection.jitter, |v| v.parse(parse_number)));
+        collect(layer.set("connection", "max_attempts", &mut connection.max_attempts, |v| v.parse(parse_number).map(Some)));
+
+        let aggregator = &mut self.aggregator;
+
+        collect(layer.set("aggregator", "deltat", &mut aggregator.deltat, |v| v.parse(parse_duration)));
+        collect(layer.set("aggregator", "rate_window", &mut aggregator.rate_window, |v| v.parse(parse_duration)));
+        collect(layer.set("aggregator", "counter_wrap", &mut aggregator.counter_wrap, |v| v.parse(CounterWrap::from_str)));
+        collect(layer.set("aggregator", "history", &mut aggregator.history.raw_retention, |v| v.parse(parse_duration)));
+        collect(layer.set("aggregator", "rollups", &mut aggregator.history.rollups, |v| v.parse(parse_rollup_specs)));
+        collect(layer.set("aggregator", "rules", &mut aggregator.rules, |v| v.parse_list(UserMetricRule::from_str, |j| UserMetricRule::try_from(j))));
+        collect(layer.set("aggregator", "derive", &mut aggregator.derived_metrics, |v| {
+            v.parse_list(DerivedMetric::from_str, |_| Err(String::from("expected '<name> = <expression>'")))
+        }));
+
+        let alerts = &mut self.alerts;
+
+        collect(layer.set("alerts", "rules", &mut alerts.rules, |v| v.parse_list(AlertRule::from_str, |j| AlertRule::try_from(j))));
+        collect(layer.set("alerts", "command", &mut alerts.command, |v| v.parse(|s| Ok(Some(s.to_string())))));
+        collect(layer.set("alerts", "webhook", &mut alerts.webhook, |v| v.parse(|s| Ok(Some(s.to_string())))));
+        collect(layer.set("alerts", "bell", &mut alerts.bell, |v| v.parse(parse_bool)));
+        collect(layer.set("alerts", "repeat", &mut alerts.repeat, |v| v.parse(parse_duration)));
+        collect(layer.set("alerts", "rate_limit", &mut alerts.rate_limit, |v| v.parse(parse_number)));
+        collect(layer.set("alerts", "notify_resolved", &mut alerts.notify_resolved, |v| v.parse(parse_bool)));
+        collect(layer.set("alerts", "timeout", &mut alerts.timeout, |v| v.parse(parse_duration)));
+
+        collect(layer.set("tui", "tick", &mut self.tui.tick, |v| v.parse(parse_duration)));
+        collect(layer.set("tui", "plot_points", &mut self.tui.plot_points, |v| v.parse(parse_number)));
+
+        collect(layer.set("gui", "width", &mut self.gui.width, |v| v.parse(parse_number)));
+        collect(layer.set("gui", "height", &mut self.gui.height, |v| v.parse(parse_number)));
+        collect(layer.set("gui", "plot_points", &mut self.gui.plot_points, |v| v.parse(parse_number)));
+
+        let headless = &mut self.headless;
+
+        collect(layer.set("headless", "format", &mut headless.format, |v| v.parse(StreamFormat::from_str)));
+        collect(layer.set("headless", "interval", &mut headless.interval, |v| v.parse(parse_duration)));
+        collect(layer.set("headless", "filter", &mut headless.filters, |v| v.parse_strings()));
+        collect(layer.set("headless", "count", &mut headless.count, |v| v.parse(parse_number).map(Some)));
+        collect(layer.set("headless", "duration", &mut headless.duration, |v| v.parse(parse_duration).map(Some)));
+
+        // Errors found later, e.g. by `validate`, name the layer that set the value
+        for (section, keys) in KNOWN_KEYS {
+            for key in keys.iter().filter(|key| layer.get(section, key).is_some()) {
+                self.origins.insert(format!("{}.{}", section, key), layer.describe(section, key));
+            }
+        }
+
+        ConfigError::combine(errors)
+    }
+
+    /// Records a command line option overriding `key`, e.g. `headless.interval`, errors about it name the option.
+    pub fn set_origin(&mut self, key: &str, origin: &str) {
+        self.origins.insert(key.to_string(), origin.to_string());
+    }
+
+    // A setting as the user wrote it, the bare key for defaults
+    fn describe(&self, key: &str) -> String {
+        self.origins.get(key).cloned().unwrap_or_else(|| key.to_string())
+    }
+
+    /// Checks the values that parse but make no sense, once all layers are applied.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let mut errors = Vec::new();
+
+        let mut error = |key: &str, msg: &str| errors.push(ConfigError::new(&self.describe(key), msg));
+
+        if self.topics.is_empty() {
+            error("endpoints.topics", "must not be empty, use * to subscribe to all topics");
+        }
+
+        let connection = &self.connection;
+
+        if connection.recv_timeout.is_zero() {
+            error("connection.recv_timeout", "must be greater than zero");
+        }
+
+        if connection.max_delay < connection.initial_delay {
+            error("connection.max_delay", "must not be less than connection.initial_delay");
+        }
+
+        if connection.multiplier < 1.0 {
+            error("connection.multiplier", "must be at least 1");
+        }
+
+        if !(0.0..=1.0).contains(&connection.jitter) {
+            error("connection.jitter", "must be within 0 and 1");
+        }
+
+        if self.aggregator.rate_window < self.aggregator.deltat {
+            error("aggregator.rate_window", "must not be less than aggregator.deltat");
+        }
+
+        if self.aggregator.history.raw_retention.is_zero() {
+            error("aggregator.history", "must be greater than zero");
+        }
+
+        if self.alerts.rate_limit == 0 {
+            error("alerts.rate_limit", "must be at least 1");
+        }
+
+        if self.tui.tick.is_zero() {
+            error("tui.tick", "must be greater than zero");
+        }
+
+        if self.tui.plot_points < 2 {
+            error("tui.plot_points", "must be at least 2");
+        }
+
+        if !(100.0..).contains(&self.gui.width) {
+            error("gui.width", "must be at least 100");
+        }
+
+        if !(100.0..).contains(&self.gui.height) {
+            error("gui.height", "must be at least 100");
+        }
+
+        if self.gui.plot_points < 2 {
+            error("gui.plot_points", "must be at least 2");
+        }
+
+        if self.headless.interval.is_zero() {
+            error("headless.interval", "must be greater than zero");
+        }
+
+        ConfigError::combine(errors)
+    }
+}
+
+
+#[test]
+fn config_layers_test01() {
+    let file = ConfigLayer::from_toml("test.toml", r#"
+        [endpoints]
+        urls = ["tcp://host-a:5555", "http://host-b/metrics"]
+
+        [aggregator]
+        deltat = "500ms"
+        history = 600
+        rules = ["rx_* rate", { metric = "queue_depth", type = "avg", depth = 8 }]
+        derive = ["drop_rate = clamp(rx_dropped / rx_packets, 0, 1)"]
+
+        [alerts]
+        rules = ["hot: temp > 90 clear 80 for 30s", { metric = "temp", absent = "10s" }]
+        bell = true
+
+        [gui]
+        width = 1024
+    "#).unwrap();
+
+    let env = ConfigLayer::from_env(
+        [("FOTC_TUI_TICK", "1s"), ("FOTC_ENDPOINTS_URLS", "tcp://a:1;tcp://b:2"), ("HOME", "/root")]
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), value.to_string())),
+    );
+
+    let mut config = Config::default();
+
+    config.apply(&file).unwrap();
+
+    assert_eq!(config.endpoints.len(), 2);
+    assert_eq!(config.aggregator.deltat, Duration::from_millis(500));
+    assert_eq!(config.aggregator.history.raw_retention, Duration::from_secs(600));
+    assert_eq!(config.aggregator.rules[1], UserMetricRule::from_str("queue_depth avg:8").unwrap());
+    assert_eq!(config.alerts.rules.len(), 2);
+    assert_eq!(config.gui.width, 1024.0);
+    assert_eq!(config.gui.height, 600.0);
+    assert!(config.alerts.get_notifier_config().is_some());
+
+    // The environment overrides the file
+    config.apply(&env).unwrap();
+
+    assert_eq!(config.endpoints, vec![String::from("tcp://a:1"), String::from("tcp://b:2")]);
+    assert_eq!(config.tui.tick, Duration::from_secs(1));
+    assert!(config.validate().is_ok());
+
+    let key_of = |content: &str| {
+        let mut config = Config::default();
+
+        ConfigLayer::from_toml("test.toml", content).and_then(|layer| config.apply(&layer)).and_then(|_| config.validate()).unwrap_err().key
+    };
+
+    assert_eq!(key_of("[tui]\ntick = \"fast\""), "tui.tick in test.toml");
+    assert_eq!(key_of("[tui]\ntik = \"1s\""), "tui.tik in test.toml");
+    assert_eq!(key_of("[alerts]\nrules = [\"temp ~ 1\"]"), "alerts.rules in test.toml");
+    assert_eq!(key_of("[gui]\nheight = 10"), "gui.height in test.toml");
+    assert_eq!(key_of("[colors]"), "colors in test.toml");
+
+    // Validation reports every invalid setting, not just the first
+    let mut config = Config::default();
+
+    config.apply(&ConfigLayer::from_toml("test.toml", "[tui]\nplot_points = 1\n[gui]\nwidth = 10\nheight = 10").unwrap()).unwrap();
+
+    let err = config.validate().unwrap_err();
+
+    let keys: Vec<&str> = std::iter::once(&err).chain(&err.others).map(|e| e.key.as_str()).collect();
+
+    assert_eq!(keys, vec!["tui.plot_points in test.toml", "gui.width in test.toml", "gui.height in test.toml"]);
+
+    // The layer that set a value last is the one named
+    config.apply(&ConfigLayer::from_env([(String::from("FOTC_GUI_WIDTH"), String::from("50"))].into_iter())).unwrap();
+    config.headless.interval = Duration::ZERO;
+    config.set_origin("headless.interval", "--interval");
+
+    let err = config.validate().unwrap_err();
+
+    assert_eq!(
+        err.to_string(),
+        "tui.plot_points in test.toml: must be at least 2; FOTC_GUI_WIDTH: must be at least 100; \
+         gui.height in test.toml: must be at least 100; --interval: must be greater than zero"
+    );
+
+    // Applying a layer reports all its errors too
+    let err = Config::default().apply(&ConfigLayer::from_toml("test.toml", "[tui]\ntick = \"fast\"\nplot_points = \"x\"\ntik = 1").unwrap()).unwrap_err();
+
+    let keys: Vec<&str> = std::iter::once(&err).chain(&err.others).map(|e| e.key.as_str()).collect();
+
+    assert_eq!(keys, vec!["tui.tik in test.toml", "tui.tick in test.toml", "tui.plot_points in test.toml"]);
+
+    let mut config = Config::default();
+
+    let env = ConfigLayer::from_env([(String::from("FOTC_CONNECTION_JITTER"), String::from("x"))].into_iter());
+
+    assert_eq!(config.apply(&env).unwrap_err().key, "FOTC_CONNECTION_JITTER");
+}
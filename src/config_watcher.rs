@@ -0,0 +1,314 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use tokio::{select, time};
+
+use crate::backend::Backend;
+use crate::config::{Config, ConfigError};
+use crate::event_log::{BackendEvent, BackendEventKind, EventLog};
+
+// How often the config file is checked for changes, also gives editors time to finish writing
+const CONFIG_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// What a reload changed in the running backend.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ReloadOutcome {
+    pub endpoints_added: Vec<String>,
+
+    pub endpoints_removed: Vec<String>,
+
+    // Rules, derived metrics and alert rules added or removed
+    pub rules_changed: usize,
+
+    // Groups of aggregator, connection and notification settings that changed
+    pub settings_changed: usize,
+
+    // Sections that changed but are only read at startup
+    pub restart_required: Vec<String>,
+
+    // Endpoints that could not be connected, they stay pending and are tried again on the next reload
+    pub endpoints_failed: Vec<String>,
+
+    // Why the failed endpoints could not be connected
+    pub errors: Vec<String>,
+}
+
+/// Task reloading the config when its file changes or SIGHUP is received.
+pub struct ConfigWatcher {
+    task_join_handle: JoinHandle<()>,
+
+    quit_signal: oneshot::Sender<()>,
+}
+
+// Resolves on every SIGHUP, never where there are no signals
+struct HangupListener {
+    #[cfg(unix)]
+    signal: Option<tokio::signal::unix::Signal>,
+}
+
+impl HangupListener {
+    fn new() -> HangupListener {
+        HangupListener {
+            #[cfg(unix)]
+            signal: tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup()).ok(),
+        }
+    }
+
+    async fn recv(&mut self) {
+        #[cfg(unix)]
+        if let Some(signal) = &mut self.signal {
+            signal.recv().await;
+
+            return;
+        }
+
+        std::future::pending::<()>().await
+    }
+}
+
+// Config file and how it looked when last checked
+struct WatchedFile {
+    path: String,
+
+    // Modification time, length and content hash
+    stamp: Option<(SystemTime, u64, u64)>,
+}
+
+impl WatchedFile {
+    fn new(path: String) -> WatchedFile {
+        let stamp = Self::get_stamp(&path);
+
+        WatchedFile { path, stamp }
+    }
+
+    // Changes whenever the file is written, None while it can't be read. The content is part of it
+    // as coarse modification times miss a rewrite of the same length within the same tick
+    fn get_stamp(path: &str) -> Option<(SystemTime, u64, u64)> {
+        let metadata = std::fs::metadata(path).ok()?;
+
+        let mut hasher = DefaultHasher::new();
+
+        std::fs::read(path).ok()?.hash(&mut hasher);
+
+        Some((metadata.modified().ok()?, metadata.len(), hasher.finish()))
+    }
+
+    // A file that is gone, e.g. while an editor replaces it, is picked up once it's back
+    fn check_changed(&mut self) -> bool {
+        let stamp = Self::get_stamp(&self.path);
+
+        if stamp.is_none() || stamp == self.stamp {
+            return false;
+        }
+
+        self.stamp = stamp;
+
+        true
+    }
+}
+
+impl ConfigWatcher {
+    pub fn start<F>(path: Option<String>, running: Config, load: F, backend: Backend, event_log: Arc<EventLog>) -> ConfigWatcher
+    where
+        F: Fn() -> Result<Config, ConfigError> + Send + 'static,
+    {
+        let (quit_signal, quit_signal_receiver) = oneshot::channel::<()>();
+
+        // Both taken before spawning, a SIGHUP right after startup would terminate the process otherwise
+        // and a change made before the task runs would go unnoticed
+        let hangup = HangupListener::new();
+
+        let file = path.map(WatchedFile::new);
+
+        let task_join_handle = tokio::spawn(Self::watch_handler(file, running, load, backend, hangup, quit_signal_receiver, event_log));
+
+        ConfigWatcher {
+            task_join_handle,
+            quit_signal,
+        }
+    }
+
+    pub fn stop(self) {
+        let _ = self.quit_signal.send(());
+
+        drop(self.task_join_handle);
+    }
+
+    async fn watch_handler<F>(
+        mut file: Option<WatchedFile>,
+        mut running: Config,
+        load: F,
+        mut backend: Backend,
+        mut hangup: HangupListener,
+        quit_signal_receiver: oneshot::Receiver<()>,
+        event_log: Arc<EventLog>,
+    ) where
+        F: Fn() -> Result<Config, ConfigError> + Send + 'static,
+    {
+        let mut quit_signal_receiver = quit_signal_receiver;
+
+        let source = file.as_ref().map(|f| f.path.clone()).unwrap_or_else(|| String::from("config"));
+
+        let mut poll_interval = time::interval(CONFIG_POLL_INTERVAL);
+
+        loop {
+            select! {
+                _ = &mut quit_signal_receiver => break,
+                _ = poll_interval.tick() => {
+                    if !file.as_mut().is_some_and(|f| f.check_changed()) {
+                        continue;
+                    }
+                }
+                _ = hangup.recv() => {}
+            }
+
+            let config = match load() {
+                Ok(config) => config,
+                Err(err) => {
+                    event_log.publish(BackendEvent::new(&source, BackendEventKind::ConfigReloadFailed { msg: err.to_string() }));
+
+                    continue;
+                }
+            };
+
+            let outcome = backend.apply_config(&running, &config).await;
+
+            for msg in outcome.errors {
+                event_log.publish(BackendEvent::new(&source, BackendEventKind::ConfigReloadFailed { msg }));
+            }
+
+            event_log.publish(BackendEvent::new(&source, BackendEventKind::ConfigReloaded {
+                endpoints_added: outcome.endpoints_added.len(),
+                endpoints_removed: outcome.endpoints_removed.len(),
+                rules_changed: outcome.rules_changed,
+                settings_changed: outcome.settings_changed,
+            }));
+
+            if !outcome.restart_required.is_empty() {
+                event_log.publish(BackendEvent::new(&source, BackendEventKind::RestartRequired { sections: outcome.restart_required }));
+            }
+
+            // The running config holds only what is in effect, failed endpoints stay pending
+            running = config;
+
+            running.endpoints.retain(|url| !outcome.endpoints_failed.contains(url));
+        }
+    }
+}
+
+
+#[test]
+fn config_watcher_reload_test01() {
+    use std::str::FromStr;
+
+    use crate::aggregator::alert::AlertRule;
+    use crate::config::ConfigLayer;
+
+    let path = std::env::temp_dir().join(format!("fotc-config-watcher-{}.toml", std::process::id()));
+    let path = path.to_str().unwrap().to_string();
+
+    let load_path = path.clone();
+
+    let load = move || {
+        let mut config = Config::default();
+
+        config.apply(&ConfigLayer::from_file(&load_path)?)?;
+
+        config.validate()?;
+
+        Ok(config)
+    };
+
+    std::fs::write(&path, "[alerts]\nrules = [\"temp > 90\", \"load > 4\"]\n").unwrap();
+
+    async fn next_reload_event(events: &mut tokio::sync::broadcast::Receiver<BackendEvent>) -> BackendEvent {
+        loop {
+            let event = time::timeout(Duration::from_secs(10), events.recv()).await.unwrap().unwrap();
+
+            if let BackendEventKind::ConfigReloaded { .. } | BackendEventKind::ConfigReloadFailed { .. } = event.get_kind() {
+                return event;
+            }
+        }
+    }
+
+    let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+
+    runtime.block_on(async {
+        let running = load().unwrap();
+
+        let mut backend = Backend::new();
+
+        for alert_rule in &running.alerts.rules {
+            backend.add_alert_rule(alert_rule.clone());
+        }
+
+        // Kept across reloads as it's not part of the config
+        backend.add_alert_rule(AlertRule::from_str("rpm < 100").unwrap());
+
+        let mut events = backend.subscribe_events();
+
+        backend.watch_config(Some(path.clone()), running, load).await;
+
+        std::fs::write(&path, "[alerts]\nrules = [\"load > 4\", \"fan_rpm absent 10s\"]\n[tui]\ntick = \"1s\"\n").unwrap();
+
+        let event = next_reload_event(&mut events).await;
+
+        assert_eq!(event.get_kind(), &BackendEventKind::ConfigReloaded {
+            endpoints_added: 0,
+            endpoints_removed: 0,
+            rules_changed: 2,
+            settings_changed: 0,
+        });
+
+        let alert_rules: Vec<String> = backend.get_alert_rules().iter().map(|(_, r)| r.to_string()).collect();
+
+        assert_eq!(alert_rules.len(), 3);
+        assert!(alert_rules.contains(&AlertRule::from_str("rpm < 100").unwrap().to_string()));
+        assert!(alert_rules.contains(&AlertRule::from_str("fan_rpm absent 10s").unwrap().to_string()));
+        assert!(!alert_rules.contains(&AlertRule::from_str("temp > 90").unwrap().to_string()));
+
+        // Same length as before, noticed by the content even where modification times are coarse.
+        // The endpoint that can't be connected is reported and doesn't hold up the rest of the reload
+        std::fs::write(&path, "[alerts]\nrules = [\"load > 5\", \"fan_rpm absent 10s\"]\n[tui]\ntick = \"1s\"\n").unwrap();
+
+        let event = next_reload_event(&mut events).await;
+
+        assert_eq!(event.get_kind(), &BackendEventKind::ConfigReloaded {
+            endpoints_added: 0,
+            endpoints_removed: 0,
+            rules_changed: 2,
+            settings_changed: 0,
+        });
+
+        std::fs::write(&path, "[endpoints]\nurls = [\"bogus://host:1\"]\n[alerts]\nrules = [\"load > 5\", \"fan_rpm absent 10s\"]\n").unwrap();
+
+        let event = next_reload_event(&mut events).await;
+
+        assert!(matches!(event.get_kind(), BackendEventKind::ConfigReloadFailed { .. }));
+
+        let event = next_reload_event(&mut events).await;
+
+        assert_eq!(event.get_kind(), &BackendEventKind::ConfigReloaded {
+            endpoints_added: 0,
+            endpoints_removed: 0,
+            rules_changed: 0,
+            settings_changed: 0,
+        });
+
+        // An invalid config leaves the running state alone
+        std::fs::write(&path, "[tui]\ntick = \"never\"\n").unwrap();
+
+        let event = next_reload_event(&mut events).await;
+
+        assert!(matches!(event.get_kind(), BackendEventKind::ConfigReloadFailed { msg } if msg.starts_with("tui.tick in ")));
+        assert_eq!(backend.get_alert_rules().len(), 3);
+
+        backend.disconnect().await;
+    });
+
+    let _ = std::fs::remove_file(&path);
+}
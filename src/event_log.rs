@@ -0,0 +1,280 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use tokio::sync::broadcast;
+
+use crate::aggregator::aggregator::CounterDiscontinuity;
+use crate::aggregator::alert::AlertState;
+use crate::source::connection::ConnectionState;
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub enum EventSeverity {
+    Info,
+    Warning,
+    Error,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum BackendEventKind {
+    Connected,
+    EndpointError { msg: String },
+    DecodeError { msg: String },
+    Timeout,
+    StateChanged { state: ConnectionState },
+    ReconnectFailed { attempt: u32, msg: String },
+    Reconnected,
+    MessageStats { messages: u64, metrics: u64, interval_s: f64 },
+    CounterDiscontinuity { metric: String, discontinuity: CounterDiscontinuity },
+    Disconnected,
+    Exported { rows: usize },
+    ExportFailed { msg: String },
+    ExporterStarted,
+    ExporterError { msg: String },
+    ForwardFailed { msg: String },
+    ForwardDropped { lines: u64 },
+    SinkFailed { msg: String },
+    Alert { rule: String, metric: String, state: AlertState, value: Option<f64> },
+    NotificationFailed { msg: String },
+    NotificationsDropped { count: u64 },
+    NotifierLagged { events: u64 },
+    ConfigReloaded { endpoints_added: usize, endpoints_removed: usize, rules_changed: usize, settings_changed: usize },
+    ConfigReloadFailed { msg: String },
+    RestartRequired { sections: Vec<String> },
+    Subscribed { topic: String },
+    Unsubscribed { topic: String },
+}
+
+/// Something that happened in the backend, `source` is the destination of the endpoint it concerns.
+#[derive(Debug, PartialEq, Clone)]
+pub struct BackendEvent {
+    timestamp: SystemTime,
+    source: String,
+    kind: BackendEventKind,
+}
+
+/// Bounded in-memory log of backend events which also forwards every event to subscribers.
+pub struct EventLog {
+    events: Mutex<VecDeque<BackendEvent>>,
+
+    max_len: usize,
+
+    sender: broadcast::Sender<BackendEvent>,
+}
+
+pub const DEFAULT_EVENT_LOG_LEN: usize = 512;
+
+impl Display for EventSeverity {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            EventSeverity::Info => write!(f, "INFO"),
+            EventSeverity::Warning => write!(f, "WARN"),
+            EventSeverity::Error => write!(f, "ERROR"),
+        }
+    }
+}
+
+impl Display for BackendEventKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            BackendEventKind::Connected => write!(f, "connected"),
+            BackendEventKind::EndpointError { msg } => write!(f, "endpoint error: {}", msg),
+            BackendEventKind::DecodeError { msg } => write!(f, "message skipped: {}", msg),
+            BackendEventKind::Timeout => write!(f, "receive timeout elapsed"),
+            BackendEventKind::StateChanged { state } => write!(f, "connection state: {}", state),
+            BackendEventKind::ReconnectFailed { attempt, msg } => {
+                write!(f, "reconnect attempt {} failed: {}", attempt, msg)
+            }
+            BackendEventKind::Reconnected => write!(f, "reconnected"),
+            BackendEventKind::MessageStats { messages, metrics, interval_s } => {
+                write!(f, "received {} messages with {} metrics in {:.1}s", messages, metrics, interval_s)
+            }
+            BackendEventKind::CounterDiscontinuity { metric, discontinuity } => {
+                write!(f, "counter {} detected on {}", discontinuity, metric)
+            }
+            BackendEventKind::Disconnected => write!(f, "disconnected"),
+            BackendEventKind::Exported { rows } => write!(f, "exported {} rows", rows),
+            BackendEventKind::ExportFailed { msg } => write!(f, "export failed: {}", msg),
+            BackendEventKind::ExporterStarted => write!(f, "serving prometheus metrics on /metrics"),
+            BackendEventKind::ExporterError { msg } => write!(f, "prometheus exporter error: {}", msg),
+            BackendEventKind::ForwardFailed { msg } => write!(f, "forwarding failed: {}", msg),
+            BackendEventKind::ForwardDropped { lines } => write!(f, "forward buffer full, dropped {} lines", lines),
+            BackendEventKind::SinkFailed { msg } => write!(f, "output sink failed: {}", msg),
+            BackendEventKind::Alert { rule, metric, state, value } => {
+                write!(f, "alert {} {} on {}", rule, state, metric)?;
+
+                match value {
+                    Some(value) => write!(f, " (value {})", value),
+                    None => Ok(()),
+                }
+            }
+            BackendEventKind::NotificationFailed { msg } => write!(f, "alert notification failed: {}", msg),
+            BackendEventKind::NotificationsDropped { count } => {
+                write!(f, "rate limit or delivery queue full, dropped {} notifications", count)
+            }
+            BackendEventKind::NotifierLagged { events } => {
+                write!(f, "alert notifier fell behind, {} events were skipped unchecked", events)
+            }
+            BackendEventKind::ConfigReloaded { endpoints_added, endpoints_removed, rules_changed, settings_changed } => write!(
+                f,
+                "config reloaded: {} endpoints added, {} removed, {} rules and {} settings changed",
+                endpoints_added, endpoints_removed, rules_changed, settings_changed
+            ),
+            BackendEventKind::ConfigReloadFailed { msg } => write!(f, "config reload failed: {}", msg),
+            BackendEventKind::RestartRequired { sections } => {
+                write!(f, "changes to {} take effect after a restart", sections.join(", "))
+            }
+            BackendEventKind::Subscribed { topic } => write!(f, "subscribed to topic {}", topic),
+            BackendEventKind::Unsubscribed { topic } => write!(f, "unsubscribed from topic {}", topic),
+        }
+    }
+}
+
+impl BackendEvent {
+    pub fn new(source: &str, kind: BackendEventKind) -> BackendEvent {
+        BackendEvent {
+            timestamp: SystemTime::now(),
+            source: source.to_string(),
+            kind,
+        }
+    }
+
+    pub fn get_timestamp(&self) -> SystemTime {
+        self.timestamp
+    }
+
+    pub fn get_source(&self) -> &str {
+        &self.source
+    }
+
+    pub fn get_kind(&self) -> &BackendEventKind {
+        &self.kind
+    }
+
+    pub fn get_severity(&self) -> EventSeverity {
+        match &self.kind {
+            BackendEventKind::EndpointError { .. }
+            | BackendEventKind::ReconnectFailed { .. }
+            | BackendEventKind::ExportFailed { .. }
+            | BackendEventKind::ExporterError { .. }
+            | BackendEventKind::ForwardFailed { .. }
+            | BackendEventKind::SinkFailed { .. }
+            | BackendEventKind::NotificationFailed { .. }
+            | BackendEventKind::ConfigReloadFailed { .. } => EventSeverity::Error,
+            BackendEventKind::Timeout
+            | BackendEventKind::DecodeError { .. }
+            | BackendEventKind::ForwardDropped { .. }
+            | BackendEventKind::NotificationsDropped { .. }
+            | BackendEventKind::NotifierLagged { .. }
+            | BackendEventKind::RestartRequired { .. } => EventSeverity::Warning,
+            BackendEventKind::CounterDiscontinuity { discontinuity, .. } => match discontinuity {
+                CounterDiscontinuity::Reset => EventSeverity::Warning,
+                CounterDiscontinuity::Wrap => EventSeverity::Info,
+            },
+            BackendEventKind::Alert { state, .. } => match state {
+                AlertState::Firing => EventSeverity::Error,
+                AlertState::Pending => EventSeverity::Warning,
+                AlertState::Resolved => EventSeverity::Info,
+            },
+            BackendEventKind::StateChanged { state } => {
+                if state.is_healthy() {
+                    EventSeverity::Info
+                } else if *state == ConnectionState::Failed {
+                    EventSeverity::Error
+                } else {
+                    EventSeverity::Warning
+                }
+            }
+            _ => EventSeverity::Info,
+        }
+    }
+}
+
+impl Display for BackendEvent {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let secs = self.timestamp.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+
+        let time_of_day = secs % 86400;
+
+        write!(
+            f,
+            "{:02}:{:02}:{:02} {:<5} [{}] {}",
+            time_of_day / 3600,
+            (time_of_day / 60) % 60,
+            time_of_day % 60,
+            self.get_severity(),
+            self.source,
+            self.kind
+        )
+    }
+}
+
+impl EventLog {
+    pub fn new(max_len: usize) -> EventLog {
+        let (sender, _) = broadcast::channel(max_len.max(1));
+
+        EventLog {
+            events: Mutex::new(VecDeque::with_capacity(max_len)),
+            max_len,
+            sender,
+        }
+    }
+
+    pub fn publish(&self, event: BackendEvent) {
+        {
+            let mut events = self.events.lock().unwrap();
+
+            if events.len() >= self.max_len {
+                events.pop_front();
+            }
+
+            events.push_back(event.clone());
+        }
+
+        // Having no subscribers is fine, the event is still kept in the log
+        let _ = self.sender.send(event);
+    }
+
+    /// Receives every event published after subscribing. Slow receivers lose the oldest events.
+    pub fn subscribe(&self) -> broadcast::Receiver<BackendEvent> {
+        self.sender.subscribe()
+    }
+
+    /// Returns up to `max_count` of the most recent events, oldest first.
+    pub fn get_recent_events(&self, max_count: usize) -> Vec<BackendEvent> {
+        let events = self.events.lock().unwrap();
+
+        let skip = events.len().saturating_sub(max_count);
+
+        events.iter().skip(skip).cloned().collect()
+    }
+}
+
+
+#[test]
+fn event_log_bounded_test01() {
+    let event_log = EventLog::new(4);
+
+    let mut receiver = event_log.subscribe();
+
+    for attempt in 0..6 {
+        event_log.publish(BackendEvent::new("tcp://localhost:5555", BackendEventKind::ReconnectFailed {
+            attempt,
+            msg: String::from("refused"),
+        }));
+    }
+
+    let events = event_log.get_recent_events(10);
+
+    assert_eq!(events.len(), 4);
+    assert_eq!(events[0].get_kind(), &BackendEventKind::ReconnectFailed { attempt: 2, msg: String::from("refused") });
+    assert_eq!(events[3].get_severity(), EventSeverity::Error);
+
+    assert_eq!(event_log.get_recent_events(1).len(), 1);
+
+    // The receiver lags behind by two events, which were dropped from the channel
+    assert!(receiver.try_recv().is_err());
+    assert_eq!(receiver.try_recv().unwrap().get_kind(), events[0].get_kind());
+}
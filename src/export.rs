@@ -0,0 +1,369 @@
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::io::Write;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+
+use json::JsonValue;
+
+use crate::aggregator::aggregator::MetricKey;
+use crate::aggregator::user_rule::glob_match;
+use crate::common::metric::MetricValue;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    // One column per series, one row per timestamp
+    CsvWide,
+    // One row per sample
+    CsvLong,
+    // One json object per sample and line
+    Jsonl,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExportScope {
+    // The current value of every metric
+    Current,
+    // The history of the given time span before the newest sample of each metric
+    History(Duration),
+}
+
+/// What to export and how. Metrics are selected by key and by name glob, an empty list selects all.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExportOptions {
+    pub format: ExportFormat,
+
+    pub scope: ExportScope,
+
+    pub keys: Vec<MetricKey>,
+
+    pub name_patterns: Vec<String>,
+}
+
+/// Samples of one metric to export, timestamps in microseconds since epoch.
+pub struct ExportSeries {
+    pub key: MetricKey,
+
+    pub unit: String,
+
+    pub samples: Vec<(u64, MetricValue)>,
+}
+
+impl ExportFormat {
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            ExportFormat::CsvWide => "csv-wide",
+            ExportFormat::CsvLong => "csv-long",
+            ExportFormat::Jsonl => "jsonl",
+        }
+    }
+
+    /// Format matching the extension of `path`, `.jsonl` and `.json` for json lines, `.csv` for long csv.
+    pub fn from_path(path: &Path) -> Option<ExportFormat> {
+        match path.extension()?.to_str()? {
+            "jsonl" | "json" => Some(ExportFormat::Jsonl),
+            "csv" => Some(ExportFormat::CsvLong),
+            _ => None,
+        }
+    }
+}
+
+impl Display for ExportFormat {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.get_name())
+    }
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv-wide" | "wide" => Ok(ExportFormat::CsvWide),
+            "csv-long" | "csv" | "long" => Ok(ExportFormat::CsvLong),
+            "jsonl" => Ok(ExportFormat::Jsonl),
+            _ => Err(format!("unknown export format '{}', expected csv-wide, csv-long or jsonl", s)),
+        }
+    }
+}
+
+impl Display for ExportScope {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ExportScope::Current => write!(f, "current values"),
+            ExportScope::History(range) => write!(f, "history of {}s", range.as_secs_f64()),
+        }
+    }
+}
+
+impl ExportOptions {
+    pub fn new(format: ExportFormat, scope: ExportScope) -> ExportOptions {
+        ExportOptions {
+            format,
+            scope,
+            keys: Vec::new(),
+            name_patterns: Vec::new(),
+        }
+    }
+
+    pub fn selects(&self, key: &MetricKey) -> bool {
+        (self.keys.is_empty() || self.keys.contains(key))
+            && (self.name_patterns.is_empty() || self.name_patterns.iter().any(|p| glob_match(p, key.get_name())))
+    }
+}
+
+// Quotes a csv field if it contains a separator, quote or line break
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+// Strings are written without the quotes Display adds
+fn value_text(value: &MetricValue) -> String {
+    match value {
+        MetricValue::String(s) => s.clone(),
+        _ => value.to_string(),
+    }
+}
+
+fn json_value(value: &MetricValue) -> JsonValue {
+    match value {
+        MetricValue::Empty => JsonValue::Null,
+        MetricValue::Integer(value) => (*value).into(),
+        MetricValue::Number(value) => (*value).into(),
+        MetricValue::String(value) => value.as_str().into(),
+    }
+}
+
+/// Writes the series in the given format, returns the number of rows (lines for json) written.
+pub fn write_series(series: &[ExportSeries], format: ExportFormat, out: &mut impl Write) -> std::io::Result<usize> {
+    match format {
+        ExportFormat::CsvLong => write_csv_long(series, out),
+        ExportFormat::CsvWide => write_csv_wide(series, out),
+        ExportFormat::Jsonl => write_jsonl(series, out),
+    }
+}
+
+pub const CSV_LONG_HEADER: &str = "timestamp_us,source,metric,labels,value,unit";
+
+fn write_csv_long(series: &[ExportSeries], out: &mut impl Write) -> std::io::Result<usize> {
+    writeln!(out, "{}", CSV_LONG_HEADER)?;
+
+    write_csv_long_rows(series, out)
+}
+
+/// Writes the rows of the long csv format without header, for output that is appended to over time.
+pub fn write_csv_long_rows(series: &[ExportSeries], out: &mut impl Write) -> std::io::Result<usize> {
+    let mut rows = 0;
+
+    for s in series {
+        for (timestamp, value) in &s.samples {
+            writeln!(
+                out,
+                "{},{},{},{},{},{}",
+                timestamp,
+                csv_field(s.key.get_source()),
+                csv_field(s.key.get_name()),
+                csv_field(&s.key.get_labels().to_string()),
+                csv_field(&value_text(value)),
+                csv_field(&s.unit)
+            )?;
+
+            rows += 1;
+        }
+    }
+
+    Ok(rows)
+}
+
+// The unit is part of the column name, series without a sample at a timestamp get an empty cell
+fn write_csv_wide(series: &[ExportSeries], out: &mut impl Write) -> std::io::Result<usize> {
+    let mut header = String::from("timestamp_us");
+
+    for s in series {
+        let column = if s.unit.is_empty() { s.key.to_string() } else { format!("{} ({})", s.key, s.unit) };
+
+        header.push(',');
+        header.push_str(&csv_field(&column));
+    }
+
+    writeln!(out, "{}", header)?;
+
+    let mut timestamps: Vec<u64> = series.iter().flat_map(|s| s.samples.iter().map(|(t, _)| *t)).collect();
+
+    timestamps.sort_unstable();
+    timestamps.dedup();
+
+    // Samples are ordered by time, so one cursor per series is enough
+    let mut cursors = vec![0usize; series.len()];
+
+    for timestamp in &timestamps {
+        let mut row = timestamp.to_string();
+
+        for (s, cursor) in series.iter().zip(cursors.iter_mut()) {
+            row.push(',');
+
+            if let Some((t, value)) = s.samples.get(*cursor) {
+                if t == timestamp {
+                    row.push_str(&csv_field(&value_text(value)));
+
+                    *cursor += 1;
+                }
+            }
+        }
+
+        writeln!(out, "{}", row)?;
+    }
+
+    Ok(timestamps.len())
+}
+
+// Escapes the characters that delimit measurements, tag keys and tag values in the line protocol
+fn influx_escape(value: &str, special: &[char]) -> String {
+    let mut escaped = String::with_capacity(value.len());
+
+    for c in value.chars() {
+        if special.contains(&c) {
+            escaped.push('\\');
+        }
+
+        escaped.push(c);
+    }
+
+    escaped
+}
+
+/// Formats one sample in the InfluxDB line protocol, None for empty values.
+///
+/// The metric name is the measurement, source, labels and unit become tags and the value is stored
+/// in the field `value`. Timestamps are written in nanoseconds.
+pub fn influx_line(key: &MetricKey, unit: &str, timestamp_us: u64, value: &MetricValue) -> Option<String> {
+    let field = match value {
+        MetricValue::Empty => return None,
+        MetricValue::Integer(value) => format!("{}i", value),
+        MetricValue::Number(value) if value.is_finite() => format!("{}", value),
+        // The line protocol has no representation for NaN and infinity
+        MetricValue::Number(_) => return None,
+        MetricValue::String(value) => format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\"")),
+    };
+
+    let mut line = influx_escape(key.get_name(), &[',', ' ']);
+
+    line.push_str(&format!(",source={}", influx_escape(key.get_source(), &[',', '=', ' '])));
+
+    // Empty tag values aren't allowed
+    for (label_key, label_value) in key.get_labels().iter().filter(|(_, v)| !v.is_empty()) {
+        line.push_str(&format!(",{}={}", influx_escape(label_key, &[',', '=', ' ']), influx_escape(label_value, &[',', '=', ' '])));
+    }
+
+    if !unit.is_empty() {
+        line.push_str(&format!(",unit={}", influx_escape(unit, &[',', '=', ' '])));
+    }
+
+    line.push_str(&format!(" value={} {}", field, timestamp_us.saturating_mul(1000)));
+
+    Some(line)
+}
+
+/// Writes every sample as line protocol, returns the number of lines written.
+pub fn write_influx(series: &[ExportSeries], out: &mut impl Write) -> std::io::Result<usize> {
+    let mut rows = 0;
+
+    for s in series {
+        for (timestamp, value) in &s.samples {
+            if let Some(line) = influx_line(&s.key, &s.unit, *timestamp, value) {
+                writeln!(out, "{}", line)?;
+
+                rows += 1;
+            }
+        }
+    }
+
+    Ok(rows)
+}
+
+fn write_jsonl(series: &[ExportSeries], out: &mut impl Write) -> std::io::Result<usize> {
+    let mut rows = 0;
+
+    for s in series {
+        let mut labels = JsonValue::new_object();
+
+        for (label_key, label_value) in s.key.get_labels().iter() {
+            labels[label_key] = label_value.into();
+        }
+
+        for (timestamp, value) in &s.samples {
+            let mut obj = JsonValue::new_object();
+
+            obj["timestamp_us"] = (*timestamp).into();
+            obj["source"] = s.key.get_source().into();
+            obj["metric"] = s.key.get_name().into();
+            obj["labels"] = labels.clone();
+            obj["value"] = json_value(value);
+            obj["unit"] = s.unit.as_str().into();
+
+            writeln!(out, "{}", obj.dump())?;
+
+            rows += 1;
+        }
+    }
+
+    Ok(rows)
+}
+
+
+#[test]
+fn export_formats_test01() {
+    let series = vec![
+        ExportSeries {
+            key: MetricKey::with_labels("src", "rx_bytes", [("device", "eth,0")].into_iter().collect()),
+            unit: String::from("kbytes"),
+            samples: vec![(1000, MetricValue::Integer(1)), (2000, MetricValue::Integer(2))],
+        },
+        ExportSeries {
+            key: MetricKey::new("src", "state"),
+            unit: String::new(),
+            samples: vec![(2000, MetricValue::String(String::from("up")))],
+        },
+    ];
+
+    let mut out = Vec::new();
+
+    assert_eq!(write_series(&series, ExportFormat::CsvLong, &mut out).unwrap(), 3);
+    assert_eq!(
+        String::from_utf8(out).unwrap().lines().nth(1),
+        Some("1000,src,rx_bytes,\"{device=\"\"eth,0\"\"}\",1,kbytes")
+    );
+
+    let mut out = Vec::new();
+
+    assert_eq!(write_series(&series, ExportFormat::CsvWide, &mut out).unwrap(), 2);
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "timestamp_us,\"[src] rx_bytes{device=\"\"eth,0\"\"} (kbytes)\",[src] state\n1000,1,\n2000,2,up\n"
+    );
+
+    let mut out = Vec::new();
+
+    write_series(&series[1..], ExportFormat::Jsonl, &mut out).unwrap();
+
+    let obj = json::parse(String::from_utf8(out).unwrap().trim()).unwrap();
+
+    assert_eq!(obj["value"].as_str(), Some("up"));
+    assert_eq!(obj["timestamp_us"].as_u64(), Some(2000));
+
+    let mut out = Vec::new();
+
+    assert_eq!(write_influx(&series, &mut out).unwrap(), 3);
+    assert_eq!(
+        String::from_utf8(out).unwrap().lines().next(),
+        Some("rx_bytes,source=src,device=eth\\,0,unit=kbytes value=1i 1000000")
+    );
+
+    assert_eq!(ExportFormat::from_path(Path::new("out.jsonl")), Some(ExportFormat::Jsonl));
+    assert_eq!(ExportFormat::from_str("wide").unwrap(), ExportFormat::CsvWide);
+}
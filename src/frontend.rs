@@ -1,4 +1,13 @@
 use std::error::Error;
+use std::time::Duration;
+
+/// Time ranges the history plots can be switched between.
+pub const HISTORY_RANGES: [(&str, Duration); 4] = [
+    ("1m", Duration::from_secs(60)),
+    ("5m", Duration::from_secs(5 * 60)),
+    ("15m", Duration::from_secs(15 * 60)),
+    ("1h", Duration::from_secs(60 * 60)),
+];
 
 
 pub trait MetricFrontend {
@@ -1,19 +1,30 @@
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
 use egui_glow::{glow, EguiGlow};
 use glutin::event::Event;
 use glutin::event_loop::{ControlFlow, EventLoop};
 use std::error::Error;
 use std::fmt::{Display, Formatter};
-use egui::{ScrollArea, Ui, WidgetText};
-use egui::Key::P;
+use egui::{Color32, RichText, ScrollArea, Ui, WidgetText};
 use egui::plot::{Corner, Legend, Line, Plot, Value, Values};
 use glutin::platform::run_return::EventLoopExtRunReturn;
-use glutin::platform::unix::x11::util::modifiers::Modifier;
 
 use crate::MetricFrontend;
+use crate::frontend::HISTORY_RANGES;
 
+use crate::aggregator::aggregator::{AutoMetricRuleType, MetricKey};
+use crate::aggregator::alert::{Alert, AlertState};
+use crate::aggregator::user_rule::UserMetricRule;
 use crate::backend::Backend;
+use crate::common::label_view::{self, LabelFilter};
 use crate::common::metric::Metric;
+use crate::config::GuiConfig;
+use crate::event_log::{BackendEvent, EventSeverity};
+use crate::export::{ExportFormat, ExportOptions, ExportScope};
+use crate::source::connection::ConnectionState;
+use crate::source::replay_endpoint::{ReplayControl, ReplaySpeed};
+use crate::source::zmq_endpoint::TopicFilter;
+
+const MAX_LOG_EVENTS: usize = 256;
 
 pub trait View {
     fn ui(&mut self, ui: &mut egui::Ui);
@@ -36,6 +47,22 @@ pub struct GraphicalFrontendInternal {
     selection: usize,
 
     metric_list: MetricWidget,
+
+    log_visible: bool,
+
+    alerts_visible: bool,
+
+    // Points per plotted metric, longer ranges are shown from rollups
+    plot_points: usize,
+
+    // Index into HISTORY_RANGES
+    history_range_idx: usize,
+
+    rule_form: RuleForm,
+
+    subscription_form: SubscriptionForm,
+
+    export_form: ExportForm,
 }
 
 pub struct GraphicalFrontend {
@@ -45,10 +72,89 @@ pub struct GraphicalFrontend {
 }
 
 
+#[derive(Default)]
 struct MetricWidget {
-    metrics: Vec<Metric>,
+    metrics: Vec<(MetricKey, Metric)>,
+
+    selected_metric: BTreeSet<MetricKey>,
 
-    selected_metric: BTreeSet<String>,
+    hidden_sources: BTreeSet<String>,
+
+    label_filter_input: String,
+
+    label_filter: LabelFilter,
+
+    label_filter_error: Option<String>,
+
+    group_label: Option<String>,
+
+    pivot_label: Option<String>,
+}
+
+/// Input state of the form adding user rules.
+struct RuleForm {
+    source: String,
+
+    metric: String,
+
+    dst_metric_name: String,
+
+    type_name: &'static str,
+
+    // Depth for averages, alpha for exponential averages
+    param: f64,
+
+    error: Option<String>,
+}
+
+/// Input state of the topic subscription below the endpoints.
+#[derive(Default)]
+struct SubscriptionForm {
+    topic: String,
+
+    error: Option<String>,
+}
+
+/// State of the export window opened from "File > Export".
+struct ExportForm {
+    open: bool,
+
+    path: String,
+
+    format: ExportFormat,
+
+    current_only: bool,
+
+    selected_only: bool,
+
+    // Outcome of the last export
+    result: Option<Result<String, String>>,
+}
+
+impl Default for ExportForm {
+    fn default() -> Self {
+        ExportForm {
+            open: false,
+            path: String::from("metrics.csv"),
+            format: ExportFormat::CsvLong,
+            current_only: false,
+            selected_only: true,
+            result: None,
+        }
+    }
+}
+
+impl Default for RuleForm {
+    fn default() -> Self {
+        RuleForm {
+            source: String::from("*"),
+            metric: String::new(),
+            dst_metric_name: String::new(),
+            type_name: "rate",
+            param: 32.0,
+            error: None,
+        }
+    }
 }
 
 impl From<&dyn std::error::Error> for FrontendError {
@@ -73,10 +179,10 @@ impl std::error::Error for FrontendError {
 
 
 impl GraphicalFrontend {
-    pub fn create(metric_backend: Backend) -> Result<GraphicalFrontend, FrontendError> {
+    pub fn create(metric_backend: Backend, config: GuiConfig) -> Result<GraphicalFrontend, FrontendError> {
         let event_loop = glutin::event_loop::EventLoop::with_user_event();
 
-        let (gl_window, context) = GraphicalFrontend::create_display(&event_loop);
+        let (gl_window, context) = GraphicalFrontend::create_display(&event_loop, &config);
 
         let egui = egui_glow::EguiGlow::new(&gl_window, &context);
 
@@ -99,12 +205,20 @@ impl GraphicalFrontend {
                 egui,
                 selection: 0,
                 metric_list: MetricWidget::default(),
+                log_visible: true,
+                alerts_visible: true,
+                plot_points: config.plot_points,
+                history_range_idx: 0,
+                rule_form: RuleForm::default(),
+                subscription_form: SubscriptionForm::default(),
+                export_form: ExportForm::default(),
             },
         })
     }
 
     fn create_display(
         event_loop: &glutin::event_loop::EventLoop<()>,
+        config: &GuiConfig,
     ) -> (
         glutin::WindowedContext<glutin::PossiblyCurrent>,
         glow::Context,
@@ -112,8 +226,8 @@ impl GraphicalFrontend {
         let window_builder = glutin::window::WindowBuilder::new()
             .with_resizable(true)
             .with_inner_size(glutin::dpi::LogicalSize {
-                width: 800.0,
-                height: 600.0,
+                width: config.width,
+                height: config.height,
             })
             .with_title("DamnUglyMetricClient");
 
@@ -140,15 +254,6 @@ impl GraphicalFrontend {
     }
 }
 
-impl Default for MetricWidget {
-    fn default() -> Self {
-        MetricWidget {
-            metrics: vec!(),
-            selected_metric: BTreeSet::new(),
-        }
-    }
-}
-
 impl View for MetricWidget {
     fn ui(&mut self, ui: &mut Ui) {
         let scroll_area = ScrollArea::vertical()
@@ -158,43 +263,71 @@ impl View for MetricWidget {
         scroll_area.show(ui, |ui| {
             ui.vertical(|ui| {
                 let mut new_selection = None;
-                let mut add_to_clipboard = false;
 
                 {
-                    //let selected_name = self.selected_metric.as_ref().map(|s| { s.as_str() }).unwrap_or("");
-
-                    for metric in &self.metrics {
-                        let response =
-                            ui.selectable_label(self.selected_metric.contains(metric.get_label()), WidgetText::from(metric.to_string()).monospace());
-
-                        if response.clicked() {
-                            if ui.input().modifiers.shift {
-                                if self.selected_metric.contains(metric.get_label()) {
-                                    self.selected_metric.remove(metric.get_label());
-                                } else {
-                                    self.selected_metric.insert(metric.get_label().to_string());
-                                }
-                            } else if !self.selected_metric.contains(metric.get_label()) {
-                                self.selected_metric.clear();
-                                self.selected_metric.insert(metric.get_label().to_string());
+                    let hidden_sources = &self.hidden_sources;
+                    let label_filter = &self.label_filter;
+                    let selected_metric = &mut self.selected_metric;
+
+                    let visible_metrics: Vec<(MetricKey, Metric)> = self
+                        .metrics
+                        .iter()
+                        .filter(|(key, _)| !hidden_sources.contains(key.get_source()) && label_filter.matches(key.get_labels()))
+                        .cloned()
+                        .collect();
+
+                    if let Some(pivot_label) = &self.pivot_label {
+                        pivot_ui(ui, &visible_metrics, pivot_label, selected_metric, &mut new_selection);
+                    } else {
+                        // Metrics are sorted by key, so each source forms one contiguous group
+                        let mut groups: Vec<(&str, Vec<&(MetricKey, Metric)>)> = Vec::new();
+
+                        for entry in &visible_metrics {
+                            match groups.last_mut() {
+                                Some((source, group)) if *source == entry.0.get_source() => group.push(entry),
+                                _ => groups.push((entry.0.get_source(), vec![entry])),
                             }
+                        }
 
-                            new_selection = Some(metric.get_label());
-
-                            if response.double_clicked() {
-                                add_to_clipboard = true
-                            }
+                        for (source, group) in groups {
+                            egui::CollapsingHeader::new(source).default_open(true).show(ui, |ui| {
+                                match &self.group_label {
+                                    Some(group_label) => {
+                                        let mut label_groups: BTreeMap<Option<&str>, Vec<&(MetricKey, Metric)>> = BTreeMap::new();
+
+                                        for entry in group {
+                                            label_groups.entry(entry.0.get_labels().get(group_label)).or_default().push(entry);
+                                        }
+
+                                        for (label_value, label_group) in label_groups {
+                                            let title = match label_value {
+                                                Some(label_value) => format!("{}={}", group_label, label_value),
+                                                None => format!("no {}", group_label),
+                                            };
+
+                                            egui::CollapsingHeader::new(title).id_source((source, label_value)).default_open(true).show(ui, |ui| {
+                                                for (key, metric) in label_group {
+                                                    metric_label_ui(ui, key, &metric.to_string(), selected_metric, &mut new_selection);
+                                                }
+                                            });
+                                        }
+                                    }
+                                    None => {
+                                        for (key, metric) in group {
+                                            metric_label_ui(ui, key, &metric.to_string(), selected_metric, &mut new_selection);
+                                        }
+                                    }
+                                }
+                            });
                         }
                     }
                 }
 
-                if let Some(new_selection) = new_selection {
-                    //self.selected_metric = Some(new_selection.to_string());
-
+                if let Some((new_selection, add_to_clipboard)) = new_selection {
                     if add_to_clipboard {
                         let mut o = ui.output();
 
-                        o.copied_text = new_selection.to_string();
+                        o.copied_text = new_selection;
                     }
                 }
             });
@@ -204,17 +337,481 @@ impl View for MetricWidget {
     }
 }
 
+// Selectable metric entry, shift-click adds to the selection and a double click copies the metric name
+fn metric_label_ui(
+    ui: &mut Ui,
+    key: &MetricKey,
+    text: &str,
+    selected_metric: &mut BTreeSet<MetricKey>,
+    new_selection: &mut Option<(String, bool)>,
+) {
+    let response = ui.selectable_label(selected_metric.contains(key), WidgetText::from(text).monospace());
+
+    if response.clicked() {
+        if ui.input().modifiers.shift {
+            if selected_metric.contains(key) {
+                selected_metric.remove(key);
+            } else {
+                selected_metric.insert(key.clone());
+            }
+        } else if !selected_metric.contains(key) {
+            selected_metric.clear();
+            selected_metric.insert(key.clone());
+        }
+
+        *new_selection = Some((format!("{}{}", key.get_name(), key.get_labels()), response.double_clicked()));
+    }
+}
+
+fn pivot_ui(
+    ui: &mut Ui,
+    metrics: &[(MetricKey, Metric)],
+    pivot_label: &str,
+    selected_metric: &mut BTreeSet<MetricKey>,
+    new_selection: &mut Option<(String, bool)>,
+) {
+    let table = label_view::pivot(metrics, pivot_label);
+
+    egui::Grid::new("metric_pivot_grid").striped(true).show(ui, |ui| {
+        ui.label(RichText::new("Source").strong());
+        ui.label(RichText::new("Name").strong());
+
+        for column in &table.columns {
+            ui.label(RichText::new(format!("{}={}", pivot_label, column)).strong());
+        }
+
+        ui.end_row();
+
+        for row in &table.rows {
+            ui.label(&row.source);
+            ui.label(RichText::new(format!("{}{}", row.name, row.labels)).monospace());
+
+            for cell in &row.cells {
+                match cell {
+                    Some((key, metric)) => {
+                        metric_label_ui(ui, key, &format!("{} {}", metric.get_value(), metric.get_unit()), selected_metric, new_selection)
+                    }
+                    None => {
+                        ui.label("-");
+                    }
+                }
+            }
+
+            ui.end_row();
+        }
+    });
+}
+
 impl MetricWidget {
-    pub fn update_metrics(&mut self, metrics: Vec<Metric>) {
+    pub fn update_metrics(&mut self, metrics: Vec<(MetricKey, Metric)>) {
         self.metrics = metrics;
         self.metrics.sort_by(|a, b| {
-            a.get_label().cmp(b.get_label())
+            a.0.cmp(&b.0)
         })
     }
 
-    pub fn get_selection(&self) -> &BTreeSet<String> {
+    pub fn get_selection(&self) -> &BTreeSet<MetricKey> {
         &self.selected_metric
     }
+
+    /// Keys of the metrics passing the source and label filters.
+    pub fn get_visible_keys(&self) -> Vec<MetricKey> {
+        self.metrics
+            .iter()
+            .filter(|(key, _)| !self.hidden_sources.contains(key.get_source()) && self.label_filter.matches(key.get_labels()))
+            .map(|(key, _)| key.clone())
+            .collect()
+    }
+
+    /// Label filter input plus group and pivot selection, `label_keys` are the keys to choose from.
+    pub fn label_view_ui(&mut self, ui: &mut Ui, label_keys: &[String]) {
+        ui.heading("Labels");
+
+        ui.label("Filter (key=value,key!=value,key)");
+
+        if ui.text_edit_singleline(&mut self.label_filter_input).changed() {
+            match LabelFilter::try_from(self.label_filter_input.as_str()) {
+                Ok(label_filter) => {
+                    self.label_filter = label_filter;
+                    self.label_filter_error = None;
+                }
+                Err(msg) => self.label_filter_error = Some(msg),
+            }
+        }
+
+        if let Some(msg) = &self.label_filter_error {
+            ui.colored_label(Color32::RED, msg);
+        }
+
+        for (title, selection) in [("Group by", &mut self.group_label), ("Pivot by", &mut self.pivot_label)] {
+            egui::ComboBox::from_label(title)
+                .selected_text(selection.as_deref().unwrap_or("none"))
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(selection, None, "none");
+
+                    for label_key in label_keys {
+                        ui.selectable_value(selection, Some(label_key.clone()), label_key);
+                    }
+                });
+        }
+
+        ui.separator();
+    }
+
+    pub fn source_filter_ui(&mut self, ui: &mut Ui, sources: &[String]) {
+        ui.heading("Sources");
+
+        for source in sources {
+            let mut shown = !self.hidden_sources.contains(source);
+
+            if ui.checkbox(&mut shown, source).changed() {
+                if shown {
+                    self.hidden_sources.remove(source);
+                } else {
+                    self.hidden_sources.insert(source.clone());
+
+                    self.selected_metric.retain(|key| key.get_source() != source);
+                }
+            }
+        }
+    }
+}
+
+/// Endpoints with their state and topics, topics can be added to all ZMQ endpoints and removed per endpoint.
+fn connection_state_ui(
+    ui: &mut Ui,
+    connection_states: &[(String, ConnectionState)],
+    subscriptions: &[(String, Vec<TopicFilter>)],
+    subscription_form: &mut SubscriptionForm,
+    metric_backend: &Backend,
+) {
+    ui.heading("Endpoints");
+
+    for (destination, state) in connection_states {
+        let color = match state {
+            ConnectionState::Connected => Color32::GREEN,
+            ConnectionState::Connecting | ConnectionState::Reconnecting { .. } => Color32::YELLOW,
+            ConnectionState::Stalled => Color32::from_rgb(255, 128, 0),
+            ConnectionState::Failed => Color32::RED,
+        };
+
+        ui.horizontal(|ui| {
+            ui.colored_label(color, "\u{25cf}");
+            ui.label(format!("{}: {}", destination, state));
+        });
+
+        if let Some((_, topics)) = subscriptions.iter().find(|(subscribed_destination, _)| subscribed_destination == destination) {
+            ui.horizontal_wrapped(|ui| {
+                ui.label("Topics");
+
+                for topic in topics {
+                    ui.label(RichText::new(topic.to_string()).monospace());
+
+                    if ui.small_button("x").clicked() {
+                        let _ = metric_backend.unsubscribe_topic(destination, topic);
+                    }
+                }
+            });
+        }
+    }
+
+    if !subscriptions.is_empty() {
+        ui.horizontal(|ui| {
+            ui.text_edit_singleline(&mut subscription_form.topic);
+
+            if ui.button("Subscribe").clicked() {
+                let result = subscription_form.topic.parse::<TopicFilter>().and_then(|topic| {
+                    for (destination, _) in subscriptions {
+                        metric_backend.subscribe_topic(destination, topic.clone()).map_err(|err| err.msg)?;
+                    }
+
+                    Ok(())
+                });
+
+                match result {
+                    Ok(()) => {
+                        subscription_form.topic.clear();
+                        subscription_form.error = None;
+                    }
+                    Err(msg) => subscription_form.error = Some(msg),
+                }
+            }
+        });
+
+        if let Some(msg) = &subscription_form.error {
+            ui.colored_label(Color32::RED, msg);
+        }
+    }
+
+    ui.separator();
+}
+
+/// Transport controls of one replay endpoint.
+fn replay_ui(ui: &mut Ui, destination: &str, replay_control: &ReplayControl) {
+    let status = replay_control.get_status();
+
+    ui.label(RichText::new(destination).monospace());
+
+    ui.horizontal(|ui| {
+        if ui.button(if status.paused { "\u{25b6}" } else { "\u{23f8}" }).clicked() {
+            replay_control.toggle_paused();
+        }
+
+        if ui.button("\u{23ee}").clicked() {
+            replay_control.seek(std::time::Duration::ZERO);
+        }
+
+        if ui.small_button("-").clicked() {
+            replay_control.set_speed(status.speed.slower());
+        }
+
+        ui.label(status.speed.to_string());
+
+        if ui.small_button("+").clicked() {
+            replay_control.set_speed(status.speed.faster());
+        }
+
+        if ui.small_button("max").clicked() {
+            replay_control.set_speed(ReplaySpeed::AsFastAsPossible);
+        }
+
+        let mut looping = status.looping;
+
+        if ui.checkbox(&mut looping, "Loop").changed() {
+            replay_control.set_looping(looping);
+        }
+    });
+
+    let mut position_s = status.position.as_secs_f64();
+
+    let slider = egui::Slider::new(&mut position_s, 0.0..=status.duration.as_secs_f64().max(0.001))
+        .show_value(false)
+        .text(status.to_string());
+
+    if ui.add(slider).changed() {
+        replay_control.seek(std::time::Duration::from_secs_f64(position_s));
+    }
+}
+
+fn replays_ui(ui: &mut Ui, replay_controls: &[(String, ReplayControl)]) {
+    if replay_controls.is_empty() {
+        return;
+    }
+
+    ui.heading("Replay");
+
+    for (destination, replay_control) in replay_controls {
+        replay_ui(ui, destination, replay_control);
+    }
+
+    ui.separator();
+}
+
+/// Lists the user rules with a remove button each, followed by a form to add a rule.
+fn user_rules_ui(ui: &mut Ui, rule_form: &mut RuleForm, metric_backend: &Backend) {
+    ui.heading("Rules");
+
+    for (user_rule_id, user_rule) in metric_backend.get_user_rules() {
+        ui.horizontal(|ui| {
+            if ui.small_button("x").clicked() {
+                metric_backend.remove_user_rule(user_rule_id);
+            }
+
+            ui.label(RichText::new(user_rule.to_string()).monospace());
+        });
+    }
+
+    egui::Grid::new("rule_form").num_columns(2).show(ui, |ui| {
+        ui.label("Source");
+        ui.text_edit_singleline(&mut rule_form.source);
+        ui.end_row();
+
+        ui.label("Metric");
+        ui.text_edit_singleline(&mut rule_form.metric);
+        ui.end_row();
+
+        ui.label("Name");
+        ui.text_edit_singleline(&mut rule_form.dst_metric_name);
+        ui.end_row();
+
+        ui.label("Type");
+        ui.horizontal(|ui| {
+            egui::ComboBox::from_id_source("rule_type")
+                .selected_text(rule_form.type_name)
+                .show_ui(ui, |ui| {
+                    for type_name in ["rate", "avg", "ewma"] {
+                        if ui.selectable_value(&mut rule_form.type_name, type_name, type_name).clicked() {
+                            rule_form.param = if type_name == "ewma" { 0.2 } else { 32.0 };
+                        }
+                    }
+                });
+
+            match rule_form.type_name {
+                "avg" => {
+                    ui.add(egui::DragValue::new(&mut rule_form.param).clamp_range(1.0..=4096.0).speed(1.0).prefix("depth "));
+                }
+                "ewma" => {
+                    ui.add(egui::DragValue::new(&mut rule_form.param).clamp_range(0.01..=1.0).speed(0.01).prefix("alpha "));
+                }
+                _ => {}
+            }
+        });
+        ui.end_row();
+    });
+
+    if ui.button("Add rule").clicked() {
+        let param = if rule_form.type_name == "avg" { rule_form.param.round() } else { rule_form.param };
+
+        let result = AutoMetricRuleType::from_name(rule_form.type_name, Some(param)).and_then(|rule_type| {
+            if rule_form.metric.trim().is_empty() {
+                return Err(String::from("missing metric name or glob"));
+            }
+
+            let mut user_rule = UserMetricRule::new(rule_form.metric.trim(), rule_type);
+
+            if !rule_form.source.trim().is_empty() {
+                user_rule.source = rule_form.source.trim().to_string();
+            }
+
+            if !rule_form.dst_metric_name.trim().is_empty() {
+                user_rule.dst_metric_name = rule_form.dst_metric_name.trim().to_string();
+            }
+
+            Ok(user_rule)
+        });
+
+        match result {
+            Ok(user_rule) => {
+                metric_backend.add_user_rule(user_rule);
+
+                rule_form.error = None;
+            }
+            Err(msg) => rule_form.error = Some(msg),
+        }
+    }
+
+    if let Some(msg) = &rule_form.error {
+        ui.colored_label(Color32::RED, msg);
+    }
+
+    ui.separator();
+}
+
+/// Export of the selected or all visible metrics, the history covers the range selected for the plot.
+fn export_ui(ui: &mut Ui, export_form: &mut ExportForm, metric_list: &MetricWidget, history_range_idx: usize, metric_backend: &Backend) {
+    egui::Grid::new("export_form").num_columns(2).show(ui, |ui| {
+        ui.label("File");
+        ui.text_edit_singleline(&mut export_form.path);
+        ui.end_row();
+
+        ui.label("Format");
+        egui::ComboBox::from_id_source("export_format")
+            .selected_text(export_form.format.get_name())
+            .show_ui(ui, |ui| {
+                for format in [ExportFormat::CsvWide, ExportFormat::CsvLong, ExportFormat::Jsonl] {
+                    ui.selectable_value(&mut export_form.format, format, format.get_name());
+                }
+            });
+        ui.end_row();
+    });
+
+    ui.checkbox(&mut export_form.current_only, "Current values only");
+    ui.checkbox(&mut export_form.selected_only, "Selected metrics only");
+
+    if !export_form.current_only {
+        ui.label(format!("History of the last {}", HISTORY_RANGES[history_range_idx].0));
+    }
+
+    if ui.button("Export").clicked() {
+        let scope = if export_form.current_only {
+            ExportScope::Current
+        } else {
+            ExportScope::History(HISTORY_RANGES[history_range_idx].1)
+        };
+
+        let mut export_options = ExportOptions::new(export_form.format, scope);
+
+        export_options.keys = if export_form.selected_only && !metric_list.get_selection().is_empty() {
+            metric_list.get_selection().iter().cloned().collect()
+        } else {
+            metric_list.get_visible_keys()
+        };
+
+        let path = std::path::Path::new(export_form.path.trim());
+
+        export_form.result = Some(
+            metric_backend
+                .export_metrics(path, &export_options)
+                .map(|rows| format!("Exported {} rows to {}", rows, path.display()))
+                .map_err(|err| err.msg),
+        );
+    }
+
+    match &export_form.result {
+        Some(Ok(msg)) => {
+            ui.label(msg);
+        }
+        Some(Err(msg)) => {
+            ui.colored_label(Color32::RED, msg);
+        }
+        None => {}
+    }
+}
+
+fn event_log_ui(ui: &mut Ui, events: &[BackendEvent]) {
+    ScrollArea::vertical()
+        .auto_shrink([false, false])
+        .stick_to_bottom()
+        .show(ui, |ui| {
+            for event in events {
+                let color = match event.get_severity() {
+                    EventSeverity::Info => Color32::GRAY,
+                    EventSeverity::Warning => Color32::YELLOW,
+                    EventSeverity::Error => Color32::RED,
+                };
+
+                ui.label(RichText::new(event.to_string()).monospace().color(color));
+            }
+        });
+}
+
+/// Lists the alerts, firing ones first, followed by the alert rules with a remove button each.
+fn alerts_ui(ui: &mut Ui, alerts: &[Alert], metric_backend: &Backend) {
+    let mut alerts: Vec<&Alert> = alerts.iter().collect();
+
+    alerts.sort_by_key(|alert| match alert.state {
+        AlertState::Firing => 0,
+        AlertState::Pending => 1,
+        AlertState::Resolved => 2,
+    });
+
+    ScrollArea::vertical().id_source("alert_list").max_height(300.0).show(ui, |ui| {
+        if alerts.is_empty() {
+            ui.label("No alerts");
+        }
+
+        for alert in alerts {
+            let color = match alert.state {
+                AlertState::Firing => Color32::RED,
+                AlertState::Pending => Color32::YELLOW,
+                AlertState::Resolved => Color32::GRAY,
+            };
+
+            ui.label(RichText::new(format!("{} [{}]", alert, alert.key.get_source())).monospace().color(color));
+        }
+    });
+
+    ui.separator();
+
+    for (alert_rule_id, alert_rule) in metric_backend.get_alert_rules() {
+        ui.horizontal(|ui| {
+            if ui.small_button("x").clicked() {
+                metric_backend.remove_alert_rule(alert_rule_id);
+            }
+
+            ui.label(RichText::new(alert_rule.to_string()).monospace());
+        });
+    }
 }
 
 impl GraphicalFrontendInternal {
@@ -256,30 +853,107 @@ impl GraphicalFrontendInternal {
 
         let mut quit = false;
 
-        self.metric_list.update_metrics(self.metric_backend.map_metrics(|m| { m.clone() }));
+        self.metric_list.update_metrics(self.metric_backend.map_metrics(|k, m| { (k.clone(), m.clone()) }));
+
+        let sources = self.metric_backend.get_sources();
+
+        let label_keys = self.metric_backend.get_label_keys();
+
+        let connection_states = self.metric_backend.get_connection_states();
+
+        let subscriptions = self.metric_backend.get_subscriptions();
+
+        let replay_controls = self.metric_backend.get_replay_controls();
+
+        let events = if self.log_visible {
+            self.metric_backend.get_recent_events(MAX_LOG_EVENTS)
+        } else {
+            Vec::new()
+        };
+
+        let alerts = self.metric_backend.get_alerts();
+
+        let log_visible = &mut self.log_visible;
+
+        let alerts_visible = &mut self.alerts_visible;
 
         let needs_repaint = self.egui.run(self.window.window(), |egui_ctx| {
             egui::TopBottomPanel::top("top_panel").show(egui_ctx, |ui| {
                 egui::menu::bar(ui, |ui| {
                     ui.menu_button("File", |ui| {
+                        if ui.button("Export...").clicked() {
+                            self.export_form.open = true;
+
+                            ui.close_menu();
+                        }
+
                         if ui.button("Quit").clicked() {
                             quit = true;
                         }
                     });
+                    ui.menu_button("View", |ui| {
+                        ui.checkbox(log_visible, "Event log");
+                        ui.checkbox(alerts_visible, "Alerts");
+                    });
                 });
             });
 
+            let mut export_open = self.export_form.open;
+
+            egui::Window::new("Export").open(&mut export_open).show(egui_ctx, |ui| {
+                export_ui(ui, &mut self.export_form, &self.metric_list, self.history_range_idx, &self.metric_backend);
+            });
+
+            self.export_form.open = export_open;
+
+            if *log_visible {
+                egui::TopBottomPanel::bottom("event_log_panel")
+                    .resizable(true)
+                    .default_height(120.0)
+                    .show(egui_ctx, |ui| {
+                        ui.heading("Event Log");
+
+                        event_log_ui(ui, &events);
+                    });
+            }
+
+            if *alerts_visible {
+                let firing = alerts.iter().filter(|alert| alert.is_firing()).count();
+
+                egui::SidePanel::right("alert_panel").show(egui_ctx, |ui| {
+                    ui.heading(format!("Alerts ({} firing)", firing));
+
+                    alerts_ui(ui, &alerts, &self.metric_backend);
+                });
+            }
+
             egui::SidePanel::left("side_panel").show(egui_ctx, |ui| {
-                //ui.heading("Flow Orchestrator Metric Client");
+                connection_state_ui(ui, &connection_states, &subscriptions, &mut self.subscription_form, &self.metric_backend);
+
+                replays_ui(ui, &replay_controls);
+
+                self.metric_list.source_filter_ui(ui, &sources);
+
+                self.metric_list.label_view_ui(ui, &label_keys);
+
+                user_rules_ui(ui, &mut self.rule_form, &self.metric_backend);
             });
 
-            egui::CentralPanel::default().show(&egui_ctx, |ui| {
+            egui::CentralPanel::default().show(egui_ctx, |ui| {
                 ui.vertical_centered_justified(|ui| {
                     self.metric_list.ui(ui);
 
                     if !self.metric_list.get_selection().is_empty() {
                         //let mut history_data = vec!();
-                        let max_history_len = 256;
+                        ui.horizontal(|ui| {
+                            ui.label("Range");
+
+                            for (idx, (range_name, _)) in HISTORY_RANGES.iter().enumerate() {
+                                ui.selectable_value(&mut self.history_range_idx, idx, *range_name);
+                            }
+                        });
+
+                        let history_range = HISTORY_RANGES[self.history_range_idx].1;
 
                         let plot = Plot::new("metric_plot")
                                 .legend(Legend::default().position(Corner::RightBottom))
@@ -302,13 +976,13 @@ impl GraphicalFrontendInternal {
                         plot.show(ui, |plot_ui| {
                             let mut history_data = vec!();
 
-                            for selected_metric_name in selected_metrics {
-                                if let Some(_limits) = self.metric_backend.get_metric_history(selected_metric_name, &mut history_data, max_history_len) {
+                            for selected_metric_key in selected_metrics {
+                                if let Some(_limits) = self.metric_backend.get_metric_history(selected_metric_key, &mut history_data, history_range, self.plot_points) {
                                     let plot_data: Vec<_> = history_data.iter().map(|m| { Value::new(m.0, m.1) }).collect();
 
                                     let lines = Line::new(Values::from_values(plot_data));
 
-                                    plot_ui.line(lines.name(selected_metric_name));
+                                    plot_ui.line(lines.name(selected_metric_key));
                                 }
                             }
                         });
@@ -0,0 +1,330 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::io::{ErrorKind, Write};
+use std::str::FromStr;
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+use tokio::runtime::Handle;
+use tokio::sync::broadcast::error::TryRecvError;
+
+use crate::aggregator::aggregator::MetricKey;
+use crate::aggregator::alert::Alert;
+use crate::backend::Backend;
+use crate::export::{self, ExportFormat, ExportOptions, ExportScope, ExportSeries};
+use crate::frontend::MetricFrontend;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamFormat {
+    // Aligned table of all selected metrics per interval
+    Table,
+    // One json object per metric and interval
+    Jsonl,
+    // Long csv, the header is written once
+    Csv,
+    // InfluxDB line protocol
+    Influx,
+}
+
+/// What the headless frontend prints and for how long.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HeadlessOptions {
+    pub format: StreamFormat,
+
+    pub interval: Duration,
+
+    // Metric name globs, an empty list selects all metrics
+    pub filters: Vec<String>,
+
+    // Stop after this many intervals
+    pub count: Option<u64>,
+
+    // Stop once this much time has passed
+    pub duration: Option<Duration>,
+}
+
+/// Frontend without user interface, periodically writes the current metric values to stdout.
+///
+/// Backend events, alert state changes among them, are written to stderr so they don't mix with the
+/// metric output. Runs until the count or duration limit is reached, stdout is closed or SIGINT/SIGTERM is received.
+pub struct HeadlessFrontend {
+    backend: Backend,
+
+    options: HeadlessOptions,
+
+    stop_receiver: mpsc::Receiver<()>,
+}
+
+impl Display for StreamFormat {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            StreamFormat::Table => write!(f, "table"),
+            StreamFormat::Jsonl => write!(f, "jsonl"),
+            StreamFormat::Csv => write!(f, "csv"),
+            StreamFormat::Influx => write!(f, "influx"),
+        }
+    }
+}
+
+impl FromStr for StreamFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "table" => Ok(StreamFormat::Table),
+            "jsonl" | "json" => Ok(StreamFormat::Jsonl),
+            "csv" => Ok(StreamFormat::Csv),
+            "influx" | "line" => Ok(StreamFormat::Influx),
+            _ => Err(format!("unknown output format '{}', expected table, jsonl, csv or influx", s)),
+        }
+    }
+}
+
+impl Default for HeadlessOptions {
+    fn default() -> Self {
+        HeadlessOptions {
+            format: StreamFormat::Table,
+            interval: Duration::from_millis(250),
+            filters: Vec::new(),
+            count: None,
+            duration: None,
+        }
+    }
+}
+
+// Resolves once SIGINT or, on unix, SIGTERM is received
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = terminate.recv() => {}
+                }
+            }
+            Err(_) => {
+                let _ = tokio::signal::ctrl_c().await;
+            }
+        }
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
+}
+
+/// Writes one interval worth of series in the given format.
+pub fn write_interval(
+    series: &[ExportSeries],
+    format: StreamFormat,
+    first: bool,
+    out: &mut impl Write,
+) -> std::io::Result<()> {
+    match format {
+        StreamFormat::Table => write_table(series, first, out),
+        StreamFormat::Jsonl => export::write_series(series, ExportFormat::Jsonl, out).map(|_| ()),
+        StreamFormat::Csv => {
+            if first {
+                writeln!(out, "{}", export::CSV_LONG_HEADER)?;
+            }
+
+            export::write_csv_long_rows(series, out).map(|_| ())
+        }
+        StreamFormat::Influx => export::write_influx(series, out).map(|_| ()),
+    }
+}
+
+/// Keeps the series with a sample not written yet, `last_written` holds the newest timestamp written per series.
+///
+/// A timestamp older than the one written counts as new too, the source restarted then.
+pub fn take_updated(series: Vec<ExportSeries>, last_written: &mut HashMap<MetricKey, u64>) -> Vec<ExportSeries> {
+    series
+        .into_iter()
+        .filter(|s| match s.samples.last() {
+            Some((timestamp, _)) => last_written.insert(s.key.clone(), *timestamp) != Some(*timestamp),
+            None => false,
+        })
+        .collect()
+}
+
+// Intervals are separated by a blank line, the columns are sized to the longest metric key
+fn write_table(series: &[ExportSeries], first: bool, out: &mut impl Write) -> std::io::Result<()> {
+    let keys: Vec<String> = series.iter().map(|s| s.key.to_string()).collect();
+
+    let key_width = keys.iter().map(|k| k.chars().count()).max().unwrap_or(0).max(6);
+
+    if !first {
+        writeln!(out)?;
+    }
+
+    writeln!(out, "{:<key_width$}  {:>16}  UNIT", "METRIC", "VALUE", key_width = key_width)?;
+
+    for (s, key) in series.iter().zip(keys.iter()) {
+        let value = s.samples.last().map(|(_, value)| value.to_string()).unwrap_or_default();
+
+        writeln!(out, "{:<key_width$}  {:>16}  {}", key, value, s.unit, key_width = key_width)?;
+    }
+
+    Ok(())
+}
+
+// Firing alerts are listed below the table, the other formats only get them as events on stderr
+fn write_firing_alerts(alerts: &[Alert], out: &mut impl Write) -> std::io::Result<()> {
+    for alert in alerts.iter().filter(|alert| alert.is_firing()) {
+        writeln!(out, "ALERT {} [{}]", alert, alert.key.get_source())?;
+    }
+
+    Ok(())
+}
+
+impl HeadlessFrontend {
+    /// Creates the frontend, the signal handlers are installed on the backend's runtime.
+    pub fn create(backend: Backend, options: HeadlessOptions, runtime: &Handle) -> HeadlessFrontend {
+        let (stop_sender, stop_receiver) = mpsc::channel();
+
+        runtime.spawn(async move {
+            shutdown_signal().await;
+
+            let _ = stop_sender.send(());
+        });
+
+        HeadlessFrontend {
+            backend,
+            options,
+            stop_receiver,
+        }
+    }
+}
+
+impl MetricFrontend for HeadlessFrontend {
+    fn run(self) -> Result<(), Box<dyn std::error::Error>> {
+        let mut events = self.backend.subscribe_events();
+
+        let mut export_options = ExportOptions::new(ExportFormat::CsvLong, ExportScope::Current);
+
+        export_options.name_patterns = self.options.filters.clone();
+
+        let started = Instant::now();
+
+        let mut intervals = 0u64;
+
+        // The streaming formats only get samples that weren't written before, the table shows all values
+        let mut last_written = HashMap::new();
+
+        loop {
+            loop {
+                match events.try_recv() {
+                    Ok(event) => eprintln!("{}", event),
+                    Err(TryRecvError::Lagged(skipped)) => eprintln!("{} events skipped", skipped),
+                    Err(_) => break,
+                }
+            }
+
+            let mut series = self.backend.get_export_series(&export_options);
+
+            if self.options.format != StreamFormat::Table {
+                series = take_updated(series, &mut last_written);
+            }
+
+            let mut stdout = std::io::stdout().lock();
+
+            let mut result = write_interval(&series, self.options.format, intervals == 0, &mut stdout);
+
+            if self.options.format == StreamFormat::Table {
+                result = result.and_then(|_| write_firing_alerts(&self.backend.get_alerts(), &mut stdout));
+            }
+
+            let result = result.and_then(|_| stdout.flush());
+
+            match result {
+                Ok(_) => {}
+                // The reading end went away, e.g. `| head`
+                Err(err) if err.kind() == ErrorKind::BrokenPipe => break,
+                Err(err) => return Err(Box::new(err)),
+            }
+
+            intervals += 1;
+
+            if matches!(self.options.count, Some(count) if intervals >= count) {
+                break;
+            }
+
+            let mut timeout = self.options.interval;
+
+            if let Some(duration) = self.options.duration {
+                match duration.checked_sub(started.elapsed()) {
+                    Some(remaining) if !remaining.is_zero() => timeout = timeout.min(remaining),
+                    _ => break,
+                }
+            }
+
+            match self.stop_receiver.recv_timeout(timeout) {
+                Err(mpsc::RecvTimeoutError::Timeout) => {}
+                _ => break,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+
+#[test]
+fn headless_table_test01() {
+    use crate::common::metric::MetricValue;
+
+    let series = vec![
+        ExportSeries {
+            key: MetricKey::new("src", "rx_bytes"),
+            unit: String::from("kbytes"),
+            samples: vec![(1000, MetricValue::Integer(42))],
+        },
+        ExportSeries {
+            key: MetricKey::new("src", "state"),
+            unit: String::new(),
+            samples: vec![(1000, MetricValue::String(String::from("up")))],
+        },
+    ];
+
+    let mut out = Vec::new();
+
+    write_interval(&series, StreamFormat::Table, true, &mut out).unwrap();
+    write_interval(&series, StreamFormat::Table, false, &mut out).unwrap();
+
+    let text = String::from_utf8(out).unwrap();
+    let lines: Vec<&str> = text.lines().collect();
+
+    assert_eq!(lines.len(), 7);
+    assert_eq!(lines[1], "[src] rx_bytes                42  kbytes");
+    assert_eq!(lines[3], "");
+
+    let mut out = Vec::new();
+
+    write_interval(&series[..1], StreamFormat::Csv, true, &mut out).unwrap();
+    write_interval(&series[..1], StreamFormat::Csv, false, &mut out).unwrap();
+
+    assert_eq!(String::from_utf8(out).unwrap().lines().filter(|l| *l == export::CSV_LONG_HEADER).count(), 1);
+
+    // A series is streamed again once it has a newer sample
+    let sample = |key: &str, timestamp: u64| ExportSeries {
+        key: MetricKey::new("src", key),
+        unit: String::new(),
+        samples: vec![(timestamp, MetricValue::Integer(1))],
+    };
+
+    let mut last_written = HashMap::new();
+
+    assert_eq!(take_updated(vec![sample("a", 1000), sample("b", 1000)], &mut last_written).len(), 2);
+
+    let updated = take_updated(vec![sample("a", 1000), sample("b", 2000)], &mut last_written);
+
+    assert_eq!(updated.iter().map(|s| s.key.get_name()).collect::<Vec<_>>(), vec!["b"]);
+
+    assert_eq!(StreamFormat::from_str("influx").unwrap(), StreamFormat::Influx);
+    assert!(StreamFormat::from_str("xml").is_err());
+}
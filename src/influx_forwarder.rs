@@ -0,0 +1,513 @@
+use std::collections::VecDeque;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use tokio::net::UdpSocket;
+use tokio::sync::{oneshot, Notify};
+use tokio::task::JoinHandle;
+use tokio::{select, time};
+
+use crate::aggregator::aggregator::MetricKey;
+use crate::common::metric::Metric;
+use crate::event_log::{BackendEvent, BackendEventKind, EventLog};
+use crate::export;
+use crate::sink::{OutputSink, ProcessedCollection, SinkError};
+
+// Keeps datagrams below the usual ethernet MTU
+const MAX_DATAGRAM_LEN: usize = 1400;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InfluxTransport {
+    // Write endpoint of the HTTP API including its query parameters, e.g. `http://host:8086/write?db=flows`
+    Http { url: String },
+    // Host and port of a UDP listener
+    Udp { addr: String },
+}
+
+/// Where and how line protocol is forwarded to.
+#[derive(Debug, Clone)]
+pub struct InfluxForwarderConfig {
+    pub transport: InfluxTransport,
+
+    // Sent as `Authorization: Token <token>` with every HTTP request
+    pub token: Option<String>,
+
+    // Lines per request, a full batch is sent right away
+    pub batch_size: usize,
+
+    // Longest time a line waits for its batch to fill up
+    pub flush_interval: Duration,
+
+    // Attempts per batch, a batch that failed all of them is kept and sent with the next flush
+    pub max_attempts: u32,
+
+    // Delay before the first retry, doubled with every further one
+    pub retry_delay: Duration,
+
+    // Lines buffered at most, the oldest ones are dropped beyond that
+    pub buffer_len: usize,
+
+    // Time an HTTP request may take, also bounds the final flush when the forwarder is closed
+    pub timeout: Duration,
+}
+
+struct ForwardBuffer {
+    lines: VecDeque<String>,
+
+    capacity: usize,
+
+    // Lines dropped since the last report
+    dropped: u64,
+}
+
+enum Transport {
+    Http {
+        client: reqwest::Client,
+        url: String,
+        token: Option<String>,
+        timeout: Duration,
+    },
+    Udp {
+        socket: UdpSocket,
+    },
+}
+
+struct SendError {
+    msg: String,
+
+    // False if the server rejected the data, sending it again won't help
+    retriable: bool,
+}
+
+/// Sink forwarding metrics as InfluxDB line protocol, batched and sent by a task of its own.
+///
+/// Lines are pushed into a bounded buffer, so a slow or unreachable server never holds up the receivers.
+pub struct InfluxForwarder {
+    destination: String,
+
+    buffer: Arc<Mutex<ForwardBuffer>>,
+
+    batch_ready: Arc<Notify>,
+
+    batch_size: usize,
+
+    // Stops the forwarding task, taken by close
+    quit_signal: Option<oneshot::Sender<()>>,
+
+    // Awaited by close, so the final flush is done before the sink counts as closed
+    task_join_handle: Option<JoinHandle<()>>,
+
+    timeout: Duration,
+}
+
+impl FromStr for InfluxTransport {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.starts_with("http://") || s.starts_with("https://") {
+            Ok(InfluxTransport::Http { url: s.to_string() })
+        } else if let Some(addr) = s.strip_prefix("udp://") {
+            Ok(InfluxTransport::Udp { addr: addr.to_string() })
+        } else {
+            Err(format!("invalid influx destination '{}', expected an http(s):// or udp:// url", s))
+        }
+    }
+}
+
+impl InfluxForwarderConfig {
+    pub fn new(transport: InfluxTransport) -> InfluxForwarderConfig {
+        InfluxForwarderConfig {
+            transport,
+            token: None,
+            batch_size: 500,
+            flush_interval: Duration::from_secs(1),
+            max_attempts: 3,
+            retry_delay: Duration::from_millis(500),
+            buffer_len: 100000,
+            timeout: Duration::from_secs(10),
+        }
+    }
+
+    pub fn get_destination(&self) -> String {
+        match &self.transport {
+            InfluxTransport::Http { url } => url.clone(),
+            InfluxTransport::Udp { addr } => format!("udp://{}", addr),
+        }
+    }
+}
+
+impl ForwardBuffer {
+    fn push(&mut self, line: String) {
+        if self.lines.len() >= self.capacity {
+            self.lines.pop_front();
+
+            self.dropped += 1;
+        }
+
+        self.lines.push_back(line);
+    }
+
+    // Puts a batch that couldn't be sent back in front, the newest lines win if that overflows the buffer
+    fn requeue(&mut self, batch: Vec<String>) {
+        for line in batch.into_iter().rev() {
+            if self.lines.len() >= self.capacity {
+                self.dropped += 1;
+            } else {
+                self.lines.push_front(line);
+            }
+        }
+    }
+}
+
+/// Line protocol for a processed collection, the received metrics followed by the ones computed from them.
+///
+/// Received metrics keep their own timestamp if they carry one, all others get the collection timestamp.
+pub fn collection_lines(src: &str, timestamp_us: u64, metrics: &[Metric], generated_metrics: &[Metric]) -> Vec<String> {
+    let received = metrics.iter().map(|m| (m, m.get_timestamp().map(|t| t.max(0) as u64).unwrap_or(timestamp_us)));
+    let generated = generated_metrics.iter().map(|m| (m, timestamp_us));
+
+    received
+        .chain(generated)
+        .filter_map(|(metric, timestamp)| {
+            export::influx_line(
+                &MetricKey::from_metric(src, metric),
+                &metric.get_unit().to_string(),
+                timestamp,
+                metric.get_value(),
+            )
+        })
+        .collect()
+}
+
+// Splits the lines into datagrams of at most MAX_DATAGRAM_LEN bytes, longer lines are sent on their own
+fn pack_datagrams(lines: &[String]) -> Vec<String> {
+    let mut datagrams = Vec::new();
+    let mut datagram = String::new();
+
+    for line in lines {
+        if !datagram.is_empty() && datagram.len() + line.len() + 1 > MAX_DATAGRAM_LEN {
+            datagrams.push(std::mem::take(&mut datagram));
+        }
+
+        datagram.push_str(line);
+        datagram.push('\n');
+    }
+
+    if !datagram.is_empty() {
+        datagrams.push(datagram);
+    }
+
+    datagrams
+}
+
+impl Transport {
+    async fn send(&self, lines: &[String]) -> Result<(), SendError> {
+        match self {
+            Transport::Http { client, url, token, timeout } => {
+                let mut body = lines.join("\n");
+
+                body.push('\n');
+
+                let mut request = client.post(url).header("Content-Type", "text/plain; charset=utf-8").body(body).timeout(*timeout);
+
+                if let Some(token) = token {
+                    request = request.header("Authorization", format!("Token {}", token));
+                }
+
+                let response = request.send().await.map_err(|err| SendError {
+                    msg: err.to_string(),
+                    retriable: true,
+                })?;
+
+                let status = response.status();
+
+                if status.is_success() {
+                    Ok(())
+                } else {
+                    Err(SendError {
+                        msg: format!("server responded with {}", status),
+                        // Overload and server side errors may pass, malformed data or missing permissions won't
+                        retriable: status.is_server_error() || status.as_u16() == 429,
+                    })
+                }
+            }
+            Transport::Udp { socket } => {
+                for datagram in pack_datagrams(lines) {
+                    socket.send(datagram.as_bytes()).await.map_err(|err| SendError {
+                        msg: err.to_string(),
+                        retriable: true,
+                    })?;
+                }
+
+                Ok(())
+            }
+        }
+    }
+}
+
+impl InfluxForwarder {
+    /// Sets up the transport and starts the forwarding task.
+    pub async fn start(config: InfluxForwarderConfig, event_log: Arc<EventLog>) -> Result<InfluxForwarder, String> {
+        let destination = config.get_destination();
+
+        let transport = match &config.transport {
+            InfluxTransport::Http { url } => Transport::Http {
+                client: reqwest::Client::new(),
+                url: url.clone(),
+                token: config.token.clone(),
+                timeout: config.timeout,
+            },
+            InfluxTransport::Udp { addr } => {
+                let target = tokio::net::lookup_host(addr.as_str())
+                    .await
+                    .ok()
+                    .and_then(|mut addrs| addrs.next())
+                    .ok_or_else(|| format!("could not resolve {}", addr))?;
+
+                let local_addr = if target.is_ipv6() { "[::]:0" } else { "0.0.0.0:0" };
+
+                let socket = UdpSocket::bind(local_addr).await.map_err(|err| err.to_string())?;
+
+                socket.connect(target).await.map_err(|err| err.to_string())?;
+
+                Transport::Udp { socket }
+            }
+        };
+
+        let buffer = Arc::new(Mutex::new(ForwardBuffer {
+            lines: VecDeque::new(),
+            capacity: config.buffer_len.max(1),
+            dropped: 0,
+        }));
+
+        let batch_ready = Arc::new(Notify::new());
+
+        let (quit_signal, quit_signal_receiver) = oneshot::channel::<()>();
+
+        let batch_size = config.batch_size.max(1);
+
+        let timeout = config.timeout;
+
+        let task_join_handle = tokio::spawn(Self::forward_handler(
+            transport,
+            config,
+            destination.clone(),
+            Arc::clone(&buffer),
+            Arc::clone(&batch_ready),
+            event_log,
+            quit_signal_receiver,
+        ));
+
+        Ok(InfluxForwarder {
+            destination,
+            buffer,
+            batch_ready,
+            batch_size,
+            quit_signal: Some(quit_signal),
+            task_join_handle: Some(task_join_handle),
+            timeout,
+        })
+    }
+
+    /// Appends lines to the buffer, never blocks on the server.
+    pub fn push(&self, lines: impl IntoIterator<Item = String>) {
+        let mut buffer = self.buffer.lock().unwrap();
+
+        for line in lines {
+            buffer.push(line);
+        }
+
+        if buffer.lines.len() >= self.batch_size {
+            self.batch_ready.notify_one();
+        }
+    }
+
+    async fn forward_handler(
+        transport: Transport,
+        config: InfluxForwarderConfig,
+        destination: String,
+        buffer: Arc<Mutex<ForwardBuffer>>,
+        batch_ready: Arc<Notify>,
+        event_log: Arc<EventLog>,
+        quit_signal_receiver: oneshot::Receiver<()>,
+    ) {
+        let mut quit_signal_receiver = quit_signal_receiver;
+
+        let batch_size = config.batch_size.max(1);
+
+        let mut flush_interval = time::interval(config.flush_interval);
+
+        let publish = |kind: BackendEventKind| event_log.publish(BackendEvent::new(&destination, kind));
+
+        loop {
+            // Full batches go out right away, the rest waits for the flush interval
+            let (flush_all, quitting) = select! {
+                _ = &mut quit_signal_receiver => (true, true),
+                _ = batch_ready.notified() => (false, false),
+                _ = flush_interval.tick() => (true, false),
+            };
+
+            loop {
+                let batch: Vec<String> = {
+                    let mut buffer = buffer.lock().unwrap();
+
+                    if buffer.dropped > 0 {
+                        publish(BackendEventKind::ForwardDropped { lines: buffer.dropped });
+
+                        buffer.dropped = 0;
+                    }
+
+                    if buffer.lines.is_empty() || (!flush_all && buffer.lines.len() < batch_size) {
+                        break;
+                    }
+
+                    let batch_len = buffer.lines.len().min(batch_size);
+
+                    buffer.lines.drain(..batch_len).collect()
+                };
+
+                // On shutdown there is no time for retries
+                let max_attempts = if quitting { 1 } else { config.max_attempts.max(1) };
+
+                let mut attempt = 0;
+
+                let result = loop {
+                    match transport.send(&batch).await {
+                        Err(err) if err.retriable && attempt + 1 < max_attempts => {
+                            time::sleep(config.retry_delay * 2u32.saturating_pow(attempt)).await;
+
+                            attempt += 1;
+                        }
+                        result => break result,
+                    }
+                };
+
+                if let Err(err) = result {
+                    publish(BackendEventKind::ForwardFailed { msg: err.msg });
+
+                    if !err.retriable {
+                        continue;
+                    }
+
+                    // Keep the batch for the next flush instead of hammering an unreachable server
+                    buffer.lock().unwrap().requeue(batch);
+
+                    break;
+                }
+            }
+
+            if quitting {
+                break;
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl OutputSink for InfluxForwarder {
+    fn get_name(&self) -> String {
+        self.destination.clone()
+    }
+
+    async fn consume(&mut self, processed: Arc<ProcessedCollection>) -> Result<(), SinkError> {
+        let collection = &processed.collection;
+
+        self.push(collection_lines(
+            &collection.get_namespace(),
+            collection.get_timestamp(),
+            collection.get_metrics_ref(),
+            &processed.generated_metrics,
+        ));
+
+        Ok(())
+    }
+
+    /// Stops the forwarding task after it attempted to send the buffered lines once more, waits
+    /// for that at most the configured timeout.
+    async fn close(&mut self) -> Result<(), SinkError> {
+        if let Some(quit_signal) = self.quit_signal.take() {
+            let _ = quit_signal.send(());
+        }
+
+        if let Some(mut task_join_handle) = self.task_join_handle.take() {
+            if time::timeout(self.timeout, &mut task_join_handle).await.is_err() {
+                task_join_handle.abort();
+
+                return Err(SinkError::new(&format!("buffered lines not sent within {:?} of closing, dropped", self.timeout)));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+
+#[test]
+fn influx_forwarder_http_test01() {
+    use crate::common::mock_http::MockHttpServer;
+
+    let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+
+    runtime.block_on(async {
+        // Bodies of the accepted writes, the first request is answered with 503
+        let received = Arc::new(Mutex::new(Vec::<String>::new()));
+        let requests = Arc::new(Mutex::new(0usize));
+
+        let mock_received = Arc::clone(&received);
+        let mock_requests = Arc::clone(&requests);
+
+        let server = MockHttpServer::start(move |body| {
+            let mut requests = mock_requests.lock().unwrap();
+
+            *requests += 1;
+
+            if *requests == 1 {
+                return "503 Service Unavailable";
+            }
+
+            mock_received.lock().unwrap().push(body);
+
+            "204 No Content"
+        }).await;
+
+        let event_log = Arc::new(EventLog::new(16));
+
+        let mut config = InfluxForwarderConfig::new(InfluxTransport::from_str(&format!("http://{}/write?db=test", server.get_addr())).unwrap());
+
+        config.batch_size = 2;
+        config.flush_interval = Duration::from_millis(50);
+        config.retry_delay = Duration::from_millis(10);
+        config.buffer_len = 3;
+
+        let mut forwarder = InfluxForwarder::start(config, Arc::clone(&event_log)).await.unwrap();
+
+        // The buffer only holds three lines, the two oldest are dropped
+        forwarder.push((1..=5).map(|i| format!("m value={}i {}", i, i)));
+
+        for _ in 0..100 {
+            if received.lock().unwrap().concat().lines().count() >= 3 {
+                break;
+            }
+
+            time::sleep(Duration::from_millis(10)).await;
+        }
+
+        assert_eq!(received.lock().unwrap().concat(), "m value=3i 3\nm value=4i 4\nm value=5i 5\n");
+        assert_eq!(*requests.lock().unwrap(), 3);
+
+        let events = event_log.get_recent_events(16);
+
+        assert!(events.iter().any(|e| e.get_kind() == &BackendEventKind::ForwardDropped { lines: 2 }));
+
+        // Lines still buffered are sent before close returns
+        forwarder.push([String::from("m value=6i 6")]);
+
+        forwarder.close().await.unwrap();
+
+        assert!(received.lock().unwrap().concat().ends_with("m value=6i 6\n"));
+    });
+
+    assert_eq!(pack_datagrams(&["x".repeat(1000), "y".repeat(1000), "z".repeat(10)]).len(), 2);
+}
@@ -1,22 +1,44 @@
 #![allow(dead_code)]
 
 use std::error::Error;
+use std::net::SocketAddr;
 
+use std::path::PathBuf;
 use std::time::{Duration};
 
 use clap::{ArgEnum, Parser};
 
-use crate::aggregator::aggregator::MetricAggregator;
+use crate::aggregator::aggregator::{CounterWrap, MetricAggregator};
+use crate::aggregator::alert::{self, AlertRule};
+use crate::aggregator::expression::DerivedMetric;
+use crate::aggregator::history::RollupSpec;
+use crate::aggregator::user_rule;
+use crate::config::{Config, ConfigError, CONFIG_PATH_ENV};
+use crate::export::{ExportFormat, ExportOptions, ExportScope};
 use crate::frontend::MetricFrontend;
 use crate::gui_frontend::GraphicalFrontend;
+use crate::influx_forwarder::{InfluxForwarderConfig, InfluxTransport};
+use crate::headless_frontend::{HeadlessFrontend, StreamFormat};
+use crate::source::replay_endpoint::{ReplayOptions, ReplaySpeed};
+use crate::source::zmq_endpoint::TopicFilter;
 use crate::terminal_frontend::{TerminalFrontend};
 
 mod aggregator;
+mod alert_notifier;
 
 mod backend;
+mod capture;
+mod config;
+mod config_watcher;
+mod event_log;
+mod export;
 mod common;
 mod source;
 mod frontend;
+mod headless_frontend;
+mod influx_forwarder;
+mod sink;
+mod prometheus_exporter;
 
 #[cfg(feature = "terminal_frontend")]
 mod terminal_frontend;
@@ -24,9 +46,11 @@ mod terminal_frontend;
 #[cfg(feature = "graphical_frontend")]
 mod gui_frontend;
 
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ArgEnum)]
 enum FrontEndOption {
-    TEST,
+    #[clap(alias = "test")]
+    HEADLESS,
 
     #[cfg(feature = "terminal_frontend")]
     TUI,
@@ -35,22 +59,288 @@ enum FrontEndOption {
     GUI
 }
 
-#[derive(Parser)]
+#[derive(Parser, Clone)]
 #[clap(author, version, about, long_about = None)]
 struct Cli {
 
+    /// TOML config file, FOTC_CONFIG is used if not given. Settings in the environment (FOTC_<SECTION>_<KEY>)
+    /// override the file, command line options override both. The file is reloaded when it changes or on SIGHUP
+    #[clap(long)]
+    pub config : Option<String>,
+
+    /// Endpoint URL, the scheme selects the protocol (tcp:// or zmq:// for ZMQ, http(s):// for Prometheus).
+    /// May be given multiple times to receive from several endpoints at once
     #[clap(short, long)]
-    pub endpoint_addr : String,
+    pub endpoint_addr : Vec<String>,
+
+    /// Topic ZMQ endpoints subscribe to, a trailing * subscribes to all topics starting with the rest,
+    /// e.g. "metrics" or "stats/*". May be given multiple times [default: metrics]
+    #[clap(long)]
+    pub topic : Vec<TopicFilter>,
 
     #[clap(arg_enum, short, long, default_value_t = FrontEndOption::TUI)]
-    pub frontend : FrontEndOption
+    pub frontend : FrontEndOption,
+
+    /// JSON file with user defined auto rules
+    #[clap(short, long)]
+    pub rules : Option<String>,
+
+    /// Derived metric as "<name> = <expression>", e.g. "drop_rate = rx_dropped / rx_packets".
+    /// May be given multiple times, later expressions can refer to earlier ones
+    #[clap(short, long)]
+    pub derive : Vec<DerivedMetric>,
+
+    /// Alert as "[<name>:] <metric glob> <condition> [clear <level>] [for <duration>] [@<source glob>]", the condition
+    /// being "<op> <threshold>", "rate <op> <threshold>" or "absent <duration>", e.g. "hot: temp_* > 90 clear 80 for 30s".
+    /// May be given multiple times
+    #[clap(long)]
+    pub alert : Vec<AlertRule>,
+
+    /// JSON file with alert rules
+    #[clap(long)]
+    pub alerts : Option<String>,
+
+    /// Shell command run when an alert fires or resolves, the alert is passed in the environment variables
+    /// ALERT_RULE, ALERT_STATE, ALERT_METRIC, ALERT_ENDPOINT, ALERT_VALUE and ALERT_TIMESTAMP_MS
+    #[clap(long)]
+    pub alert_command : Option<String>,
+
+    /// URL a json object describing the alert is POSTed to when an alert fires or resolves
+    #[clap(long)]
+    pub alert_webhook : Option<String>,
+
+    /// Ring the terminal bell when an alert fires or resolves
+    #[clap(long)]
+    pub alert_bell : bool,
+
+    /// An alert that fires again is only notified again after this time span [default: 5m]
+    #[clap(long, parse(try_from_str = common::parse_duration))]
+    pub alert_repeat : Option<Duration>,
+
+    /// Alert notifications per minute at most, further ones are dropped [default: 20]
+    #[clap(long)]
+    pub alert_rate_limit : Option<usize>,
+
+    /// Bit width at which counters wrap around (none, 32 or 64). With none every decreasing counter is taken as reset
+    /// [default: none]
+    #[clap(long)]
+    pub counter_wrap : Option<CounterWrap>,
+
+    /// How long raw samples are kept, e.g. 90s, 5m or 1h [default: 5m]
+    #[clap(long, parse(try_from_str = common::parse_duration))]
+    pub history : Option<Duration>,
+
+    /// Downsampled history tiers as comma separated <resolution>:<retention> pairs, used for long time ranges
+    /// [default: 1s:1h,1m:24h]
+    #[clap(long, use_value_delimiter = true)]
+    pub rollups : Vec<RollupSpec>,
+
+    /// Record every received collection to a capture file, an existing capture is continued
+    #[clap(long, parse(from_os_str))]
+    pub record : Option<PathBuf>,
+
+    /// Play back a capture file with its original timing, may be combined with live endpoints
+    #[clap(long, parse(from_os_str))]
+    pub replay : Option<PathBuf>,
+
+    /// Replay speed factor, e.g. 0.5 or 4, "max" replays as fast as possible
+    #[clap(long, default_value = "1")]
+    pub replay_speed : ReplaySpeed,
+
+    /// Start the replay over once the end of the capture is reached
+    #[clap(long)]
+    pub replay_loop : bool,
+
+    /// Run without frontend, collect for --export-after, write the metrics to this file and exit
+    #[clap(long, parse(from_os_str))]
+    pub export : Option<PathBuf>,
+
+    /// Export format (csv-wide, csv-long or jsonl), taken from the file extension by default
+    #[clap(long)]
+    pub export_format : Option<ExportFormat>,
+
+    /// Export the history of this time span instead of the current values, e.g. 5m
+    #[clap(long, parse(try_from_str = common::parse_duration))]
+    pub export_range : Option<Duration>,
+
+    /// How long to collect before exporting, a replay that reaches its end is exported right away
+    #[clap(long, default_value = "10s", parse(try_from_str = common::parse_duration))]
+    pub export_after : Duration,
+
+    /// Only export metrics whose name matches this glob, may be given multiple times
+    #[clap(long)]
+    pub export_metric : Vec<String>,
+
+    /// Output format of the headless frontend (table, jsonl, csv or influx) [default: table]
+    #[clap(long)]
+    pub output_format : Option<StreamFormat>,
+
+    /// How often the headless frontend writes the current values, e.g. 250ms or 5s [default: 250ms]
+    #[clap(long, parse(try_from_str = common::parse_duration))]
+    pub interval : Option<Duration>,
+
+    /// Only print metrics whose name matches this glob in headless mode, may be given multiple times
+    #[clap(long)]
+    pub filter : Vec<String>,
+
+    /// Stop the headless frontend after this many intervals
+    #[clap(long)]
+    pub count : Option<u64>,
+
+    /// Stop the headless frontend after this time span, e.g. 30s
+    #[clap(long, parse(try_from_str = common::parse_duration))]
+    pub duration : Option<Duration>,
+
+    /// Serve all metrics including derived ones in the prometheus text format on http://<addr>/metrics,
+    /// e.g. 0.0.0.0:9464
+    #[clap(long)]
+    pub prometheus_listen : Option<SocketAddr>,
+
+    /// Forward all metrics as InfluxDB line protocol, either to the write endpoint of the HTTP API
+    /// (e.g. "http://localhost:8086/api/v2/write?org=o&bucket=b") or to a UDP listener ("udp://host:8089")
+    #[clap(long)]
+    pub influx : Option<InfluxTransport>,
+
+    /// API token sent with every HTTP write request
+    #[clap(long)]
+    pub influx_token : Option<String>,
+
+    /// Lines per write request
+    #[clap(long, default_value = "500")]
+    pub influx_batch : usize,
+
+    /// Longest time a line waits for its batch to fill up before it is sent
+    #[clap(long, default_value = "1s", parse(try_from_str = common::parse_duration))]
+    pub influx_flush : Duration,
+
+    /// Lines buffered while InfluxDB is unreachable, the oldest ones are dropped beyond that
+    #[clap(long, default_value = "100000")]
+    pub influx_buffer : usize
+}
+
+
+fn get_config_path(args: &Cli) -> Option<String> {
+    args.config.clone().or_else(|| std::env::var(CONFIG_PATH_ENV).ok())
 }
 
+// Command line options override the config file and the environment, lists of rules are appended
+fn load_config(args: &Cli) -> Result<Config, ConfigError> {
+    let mut config = Config::load(get_config_path(args).as_deref(), std::env::vars())?;
+
+    if !args.endpoint_addr.is_empty() {
+        config.endpoints = args.endpoint_addr.clone();
+    }
+
+    if !args.topic.is_empty() {
+        config.topics = args.topic.clone();
+
+        config.set_origin("endpoints.topics", "--topic");
+    }
+
+    if let Some(rules_path) = &args.rules {
+        let rules = std::fs::read_to_string(rules_path)
+            .map_err(|e| e.to_string())
+            .and_then(|content| user_rule::parse_rules_file(&content))
+            .map_err(|msg| ConfigError::new(&format!("--rules {}", rules_path), &msg))?;
+
+        config.aggregator.rules.extend(rules);
+    }
+
+    config.aggregator.derived_metrics.extend(args.derive.iter().cloned());
+
+    if let Some(counter_wrap) = args.counter_wrap {
+        config.aggregator.counter_wrap = counter_wrap;
+    }
+
+    if let Some(history) = args.history {
+        config.aggregator.history.raw_retention = history;
+
+        config.set_origin("aggregator.history", "--history");
+    }
+
+    if !args.rollups.is_empty() {
+        config.aggregator.history.rollups = args.rollups.clone();
+    }
+
+    if let Some(alerts_path) = &args.alerts {
+        let alert_rules = std::fs::read_to_string(alerts_path)
+            .map_err(|e| e.to_string())
+            .and_then(|content| alert::parse_alerts_file(&content))
+            .map_err(|msg| ConfigError::new(&format!("--alerts {}", alerts_path), &msg))?;
+
+        config.alerts.rules.extend(alert_rules);
+    }
+
+    config.alerts.rules.extend(args.alert.iter().cloned());
+
+    if args.alert_command.is_some() {
+        config.alerts.command = args.alert_command.clone();
+    }
+
+    if args.alert_webhook.is_some() {
+        config.alerts.webhook = args.alert_webhook.clone();
+    }
+
+    if args.alert_bell {
+        config.alerts.bell = true;
+    }
+
+    if let Some(repeat) = args.alert_repeat {
+        config.alerts.repeat = repeat;
+    }
+
+    if let Some(rate_limit) = args.alert_rate_limit {
+        config.alerts.rate_limit = rate_limit;
+
+        config.set_origin("alerts.rate_limit", "--alert-rate-limit");
+    }
+
+    if let Some(interval) = args.interval {
+        config.headless.interval = interval;
+
+        config.set_origin("headless.interval", "--interval");
+    }
+
+    let headless = &mut config.headless;
+
+    if let Some(format) = args.output_format {
+        headless.format = format;
+    }
+
+    if !args.filter.is_empty() {
+        headless.filters = args.filter.clone();
+    }
+
+    if args.count.is_some() {
+        headless.count = args.count;
+    }
+
+    if args.duration.is_some() {
+        headless.duration = args.duration;
+    }
+
+    if config.endpoints.is_empty() && args.replay.is_none() {
+        return Err(ConfigError::new("endpoints.urls", "no endpoint given, use --endpoint-addr, --replay or the config"));
+    }
+
+    config.validate()?;
+
+    Ok(config)
+}
 
 fn main() -> Result<(), Box<dyn Error>> {
 
     let args = Cli::parse();
 
+    let config = match load_config(&args) {
+        Ok(config) => config,
+        Err(err) => {
+            println!("Invalid configuration: {}", err);
+
+            return Err(Box::new(err));
+        }
+    };
+
     let runtime = tokio::runtime::Builder::new_multi_thread()
                 .enable_io()
                 .enable_time()
@@ -60,37 +350,151 @@ fn main() -> Result<(), Box<dyn Error>> {
 
     let mut metric_backend = backend::Backend::new();
 
+    metric_backend.set_reconnect_policy(config.connection.clone());
+
+    metric_backend.set_default_topics(config.topics.clone());
+
+    metric_backend.set_deltat(config.aggregator.deltat);
+
+    metric_backend.set_rate_window(config.aggregator.rate_window);
+
+    metric_backend.set_counter_wrap(config.aggregator.counter_wrap);
+
+    metric_backend.set_history_retention(config.aggregator.history.raw_retention);
+
+    metric_backend.set_rollups(config.aggregator.history.rollups.clone());
+
+    for rule in &config.aggregator.rules {
+        metric_backend.add_user_rule(rule.clone());
+    }
+
+    for derived_metric in &config.aggregator.derived_metrics {
+        metric_backend.add_derived_metric(derived_metric.clone());
+    }
+
+    for alert_rule in &config.alerts.rules {
+        metric_backend.add_alert_rule(alert_rule.clone());
+    }
+
     let connect_result = runtime.block_on(async {
-        metric_backend.connect(args.endpoint_addr).await
+        if let Some(record_path) = &args.record {
+            metric_backend.start_recording(record_path).await?;
+        }
+
+        // Started before connecting, so no alert of the first collections is missed
+        if let Some(notifier_config) = config.alerts.get_notifier_config() {
+            metric_backend.notify_alerts(notifier_config).await;
+        }
+
+        metric_backend.connect_urls(&config.endpoints).await?;
+
+        if let Some(replay_path) = &args.replay {
+            let replay_options = ReplayOptions {
+                speed: args.replay_speed,
+                looping: args.replay_loop,
+            };
+
+            metric_backend.connect_replay(replay_path, replay_options).await?;
+        }
+
+        if let Some(transport) = &args.influx {
+            let mut config = InfluxForwarderConfig::new(transport.clone());
+
+            config.token = args.influx_token.clone();
+            config.batch_size = args.influx_batch;
+            config.flush_interval = args.influx_flush;
+            config.buffer_len = args.influx_buffer;
+
+            metric_backend.forward_influx(config).await?;
+        }
+
+        if let Some(listen_addr) = args.prometheus_listen {
+            metric_backend.serve_prometheus(listen_addr).await?;
+        }
+
+        let reload_args = args.clone();
+
+        metric_backend.watch_config(get_config_path(&args), config.clone(), move || load_config(&reload_args)).await;
+
+        Ok::<(), backend::Error>(())
     });
 
-    if connect_result.is_err() {
-        println!("Connection failed: {:?}", connect_result.err());
+    if let Err(err) = connect_result {
+        println!("Connection failed: {}", err);
 
-        return Err(Box::new(backend::Error{msg: "Nix connection".to_string()}))
+        return Err(Box::new(err))
     }
 
-    if args.frontend == FrontEndOption::TEST {
-        loop {
-            println!("last timestamp: {}", metric_backend.get_last_timestamp());
+    if let Some(export_path) = &args.export {
+        let format = args.export_format
+            .or_else(|| ExportFormat::from_path(export_path))
+            .unwrap_or(ExportFormat::CsvLong);
 
-            metric_backend.visit_metrics(|m| {
-                println!("{}", m);
-            });
+        let scope = match args.export_range {
+            Some(range) => ExportScope::History(range),
+            None => ExportScope::Current,
+        };
 
-            std::thread::sleep(Duration::from_millis(250));
+        let mut export_options = ExportOptions::new(format, scope);
+
+        export_options.name_patterns = args.export_metric.clone();
+
+        let started = std::time::Instant::now();
+
+        // Replays that reached their end have nothing more to deliver
+        while started.elapsed() < args.export_after {
+            let replay_controls = metric_backend.get_replay_controls();
+
+            if !replay_controls.is_empty()
+                && config.endpoints.is_empty()
+                && replay_controls.iter().all(|(_, c)| c.get_status().finished)
+            {
+                break;
+            }
+
+            std::thread::sleep(Duration::from_millis(100));
         }
-    } else if args.frontend == FrontEndOption::TUI {
-        let frontend = TerminalFrontend::create(metric_backend)?;
 
-        frontend.run()?;
-    } else if args.frontend == FrontEndOption::GUI {
-        let frontend = GraphicalFrontend::create(metric_backend)?;
+        let export_result = metric_backend.export_metrics(export_path, &export_options);
+
+        runtime.block_on(metric_backend.disconnect());
 
-        frontend.run()?;
+        runtime.shutdown_timeout(Duration::from_secs(5));
+
+        return match export_result {
+            Ok(rows) => {
+                println!("Exported {} rows ({}, {}) to {}", rows, format, scope, export_path.display());
+
+                Ok(())
+            }
+            Err(err) => {
+                println!("{}", err.msg);
+
+                Err(Box::new(err))
+            }
+        };
     }
 
+    let frontend_backend = metric_backend.clone();
+
+    let frontend_result = if args.frontend == FrontEndOption::HEADLESS {
+        HeadlessFrontend::create(frontend_backend, config.headless.clone(), runtime.handle()).run()
+    } else if args.frontend == FrontEndOption::TUI {
+        TerminalFrontend::create(frontend_backend, config.tui.clone())
+            .map_err(Box::<dyn Error>::from)
+            .and_then(|frontend| frontend.run())
+    } else if args.frontend == FrontEndOption::GUI {
+        GraphicalFrontend::create(frontend_backend, config.gui.clone())
+            .map_err(Box::<dyn Error>::from)
+            .and_then(|frontend| frontend.run())
+    } else {
+        Ok(())
+    };
+
+    // Also when the frontend failed, so the sinks get to flush
+    runtime.block_on(metric_backend.disconnect());
+
     runtime.shutdown_timeout(Duration::from_secs(5));
 
-    Ok(())
+    frontend_result
 }
@@ -0,0 +1,509 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::oneshot;
+use tokio::{select, time};
+
+use crate::aggregator::aggregator::MetricKey;
+use crate::common::metric::{Metric, MetricKind, MetricLabels, MetricValue};
+use crate::event_log::{BackendEvent, BackendEventKind, EventLog};
+use crate::sink::{OutputSink, ProcessedCollection, SinkError};
+
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+// Requests are tiny, anything larger isn't a scrape
+const MAX_REQUEST_LEN: usize = 8192;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Like the staleness period of prometheus itself
+pub const DEFAULT_SERIES_TTL: Duration = Duration::from_secs(5 * 60);
+
+// Current value of every metric seen and when it was last updated, shared between the sink and the server task
+type MetricSnapshot = Arc<Mutex<SeriesSnapshot>>;
+
+struct SeriesSnapshot {
+    series: HashMap<MetricKey, (Metric, Instant)>,
+
+    // Series not updated for this long are dropped, e.g. of disconnected endpoints or removed rules
+    ttl: Duration,
+}
+
+/// Sink keeping the newest value of every metric, which is served to prometheus on `/metrics`.
+pub struct PrometheusSink {
+    name: String,
+
+    metrics: MetricSnapshot,
+
+    // Stops the server task, taken by close
+    quit_signal: Option<oneshot::Sender<()>>,
+}
+
+// Samples of one metric family, families are written in one block each
+struct Family {
+    kind: MetricKind,
+    help: String,
+    samples: Vec<String>,
+}
+
+/// Replaces every character that isn't allowed in a metric name with '_', e.g. `rx_bytes-ps` becomes `rx_bytes_ps`.
+pub fn sanitize_name(name: &str, allow_colon: bool) -> String {
+    let mut sanitized: String = name
+        .chars()
+        .enumerate()
+        .map(|(idx, c)| {
+            if c.is_ascii_alphabetic() || c == '_' || (allow_colon && c == ':') || (idx > 0 && c.is_ascii_digit()) {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+
+    if sanitized.is_empty() {
+        sanitized.push('_');
+    }
+
+    sanitized
+}
+
+// Histogram and summary samples are reported under the name of their family
+fn get_family_name(metric: &Metric) -> &str {
+    let name = metric.get_label();
+
+    get_sample_suffixes(metric.get_kind()).iter().find_map(|suffix| name.strip_suffix(suffix)).unwrap_or(name)
+}
+
+fn get_type_name(kind: MetricKind) -> &'static str {
+    match kind {
+        MetricKind::Untyped => "untyped",
+        MetricKind::Counter => "counter",
+        MetricKind::Gauge => "gauge",
+        MetricKind::Histogram => "histogram",
+        MetricKind::Summary => "summary",
+        // The text format has no info type, info metrics are gauges with a constant value
+        MetricKind::Info => "gauge",
+    }
+}
+
+fn get_sample_suffixes(kind: MetricKind) -> &'static [&'static str] {
+    match kind {
+        MetricKind::Histogram => &["_bucket", "_sum", "_count"],
+        MetricKind::Summary => &["_sum", "_count"],
+        _ => &[],
+    }
+}
+
+// Strings can't be represented in the exposition format
+fn format_value(value: &MetricValue) -> Option<String> {
+    match value {
+        MetricValue::Integer(value) => Some(value.to_string()),
+        MetricValue::Number(value) if value.is_nan() => Some(String::from("NaN")),
+        MetricValue::Number(value) if value.is_infinite() => {
+            Some(String::from(if *value > 0.0 { "+Inf" } else { "-Inf" }))
+        }
+        MetricValue::Number(value) => Some(value.to_string()),
+        MetricValue::Empty | MetricValue::String(_) => None,
+    }
+}
+
+fn escape_help(help: &str) -> String {
+    help.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+/// Renders metrics in the prometheus text exposition format (version 0.0.4).
+///
+/// The source and the unit of a metric are added as `source` and `unit` labels unless the metric
+/// carries labels of that name itself. Metrics without help text are described by their original
+/// name, the first help text seen for a family is used for all its samples.
+///
+/// A family name belongs to the kind of the first metric using it. Metrics of another kind, or whose
+/// family name is a sample name of a histogram or summary (e.g. an untyped `foo_count` next to the
+/// histogram `foo`), get the type appended, e.g. `foo_gauge`. They are left out if that is taken, too.
+pub fn render_exposition<'a>(metrics: impl Iterator<Item = (&'a MetricKey, &'a Metric)>) -> String {
+    let mut families: BTreeMap<String, Family> = BTreeMap::new();
+
+    let mut metrics: Vec<(&MetricKey, &Metric, String)> = metrics
+        .filter_map(|(key, metric)| format_value(metric.get_value()).map(|value| (key, metric, value)))
+        .collect();
+
+    metrics.sort_by(|a, b| a.0.cmp(b.0));
+
+    let mut claimed: HashMap<String, MetricKind> = HashMap::new();
+
+    for (_, metric, _) in &metrics {
+        claimed.entry(sanitize_name(get_family_name(metric), true)).or_insert_with(|| metric.get_kind());
+    }
+
+    let reserved: HashSet<String> = claimed
+        .iter()
+        .flat_map(|(name, kind)| get_sample_suffixes(*kind).iter().map(move |suffix| format!("{}{}", name, suffix)))
+        .collect();
+
+    for (key, metric, value) in metrics {
+        let mut family_name = sanitize_name(get_family_name(metric), true);
+
+        let mut sample_name = sanitize_name(metric.get_label(), true);
+
+        if claimed[&family_name] != metric.get_kind() || reserved.contains(&family_name) {
+            let renamed = format!("{}_{}", family_name, get_type_name(metric.get_kind()));
+
+            if claimed.contains_key(&renamed) || reserved.contains(&renamed) {
+                continue;
+            }
+
+            sample_name = format!("{}{}", renamed, &sample_name[family_name.len()..]);
+
+            family_name = renamed;
+        }
+
+        let unit = metric.get_unit().to_string();
+
+        let family = families.entry(family_name).or_insert_with(|| Family {
+            kind: metric.get_kind(),
+            help: match metric.get_help() {
+                Some(help) => escape_help(help),
+                None if unit.is_empty() => escape_help(get_family_name(metric)),
+                None => escape_help(&format!("{} in {}", get_family_name(metric), unit)),
+            },
+            samples: Vec::new(),
+        });
+
+        let mut labels: MetricLabels = metric.get_labels().iter().map(|(k, v)| (sanitize_name(k, false), v)).collect();
+
+        if labels.get("source").is_none() {
+            labels.insert("source", key.get_source());
+        }
+
+        if !unit.is_empty() && labels.get("unit").is_none() {
+            labels.insert("unit", &unit);
+        }
+
+        family.samples.push(format!("{}{} {}", sample_name, labels, value));
+    }
+
+    let mut text = String::new();
+
+    for (name, family) in families {
+        text.push_str(&format!("# HELP {} {}\n", name, family.help));
+        text.push_str(&format!("# TYPE {} {}\n", name, get_type_name(family.kind)));
+
+        for sample in family.samples {
+            text.push_str(&sample);
+            text.push('\n');
+        }
+    }
+
+    text
+}
+
+impl PrometheusSink {
+    /// Starts serving on `listener`, `metrics` are the values known so far.
+    pub fn start(listener: TcpListener, metrics: HashMap<MetricKey, Metric>, event_log: Arc<EventLog>) -> PrometheusSink {
+        let name = match listener.local_addr() {
+            Ok(addr) => format!("http://{}/metrics", addr),
+            Err(_) => String::from("prometheus exporter"),
+        };
+
+        let now = Instant::now();
+
+        let metrics = Arc::new(Mutex::new(SeriesSnapshot {
+            series: metrics.into_iter().map(|(key, metric)| (key, (metric, now))).collect(),
+            ttl: DEFAULT_SERIES_TTL,
+        }));
+
+        let (quit_signal, quit_signal_receiver) = oneshot::channel::<()>();
+
+        tokio::spawn(serve(listener, Arc::clone(&metrics), event_log, quit_signal_receiver));
+
+        PrometheusSink {
+            name,
+            metrics,
+            quit_signal: Some(quit_signal),
+        }
+    }
+
+    /// Sets how long a series is served after its last update.
+    pub fn with_series_ttl(self, ttl: Duration) -> PrometheusSink {
+        self.metrics.lock().unwrap().ttl = ttl;
+
+        self
+    }
+}
+
+impl SeriesSnapshot {
+    fn evict_stale(&mut self) {
+        let ttl = self.ttl;
+
+        self.series.retain(|_, (_, updated)| updated.elapsed() < ttl);
+    }
+}
+
+#[async_trait]
+impl OutputSink for PrometheusSink {
+    fn get_name(&self) -> String {
+        self.name.clone()
+    }
+
+    async fn consume(&mut self, processed: Arc<ProcessedCollection>) -> Result<(), SinkError> {
+        let src = processed.collection.get_namespace();
+
+        let now = Instant::now();
+
+        let mut metrics = self.metrics.lock().unwrap();
+
+        for metric in processed.collection.get_metrics_ref().iter().chain(processed.generated_metrics.iter()) {
+            metrics.series.insert(MetricKey::from_metric(&src, metric), (metric.clone(), now));
+        }
+
+        metrics.evict_stale();
+
+        Ok(())
+    }
+
+    async fn close(&mut self) -> Result<(), SinkError> {
+        if let Some(quit_signal) = self.quit_signal.take() {
+            let _ = quit_signal.send(());
+        }
+
+        Ok(())
+    }
+}
+
+/// Accepts scrapes on `listener` until the quit signal is received.
+async fn serve(
+    listener: TcpListener,
+    metrics: MetricSnapshot,
+    event_log: Arc<EventLog>,
+    quit_signal_receiver: oneshot::Receiver<()>,
+) {
+    let mut quit_signal_receiver = quit_signal_receiver;
+
+    let local_addr = listener.local_addr().map(|a| a.to_string()).unwrap_or_default();
+
+    loop {
+        select! {
+            _ = &mut quit_signal_receiver => {
+                break;
+            }
+            accepted = listener.accept() => {
+                match accepted {
+                    Ok((stream, _)) => {
+                        let metrics = Arc::clone(&metrics);
+
+                        tokio::spawn(async move {
+                            // A scraper that goes away mid request is nothing to report
+                            let _ = time::timeout(REQUEST_TIMEOUT, handle_request(stream, metrics)).await;
+                        });
+                    }
+                    Err(err) => {
+                        event_log.publish(BackendEvent::new(
+                            &local_addr,
+                            BackendEventKind::ExporterError { msg: err.to_string() },
+                        ));
+
+                        // Accepting fails e.g. if the process runs out of file descriptors, give it a moment
+                        time::sleep(Duration::from_millis(100)).await;
+                    }
+                }
+            }
+        }
+    }
+}
+
+async fn handle_request(mut stream: TcpStream, metrics: MetricSnapshot) -> std::io::Result<()> {
+    let mut request = Vec::new();
+    let mut buf = [0u8; 1024];
+
+    // Only the request line matters, the headers are read to not reset the connection on close
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+        let n = stream.read(&mut buf).await?;
+
+        if n == 0 || request.len() + n > MAX_REQUEST_LEN {
+            return Ok(());
+        }
+
+        request.extend_from_slice(&buf[..n]);
+    }
+
+    let request = String::from_utf8_lossy(&request);
+
+    let mut request_line = request.lines().next().unwrap_or_default().split(' ');
+
+    let method = request_line.next().unwrap_or_default();
+    let path = request_line.next().unwrap_or_default();
+
+    let path = path.split('?').next().unwrap_or_default();
+
+    let (status, content_type, body) = match (method, path) {
+        ("GET" | "HEAD", "/metrics") => {
+            let body = {
+                let mut metrics = metrics.lock().unwrap();
+
+                // Nothing may be consumed anymore once all endpoints are gone
+                metrics.evict_stale();
+
+                render_exposition(metrics.series.iter().map(|(key, (metric, _))| (key, metric)))
+            };
+
+            ("200 OK", CONTENT_TYPE, body)
+        }
+        ("GET" | "HEAD", _) => ("404 Not Found", "text/plain", String::from("metrics are served on /metrics\n")),
+        _ => ("405 Method Not Allowed", "text/plain", String::from("only GET and HEAD are supported\n")),
+    };
+
+    let mut response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        content_type,
+        body.len()
+    );
+
+    if method != "HEAD" {
+        response.push_str(&body);
+    }
+
+    stream.write_all(response.as_bytes()).await?;
+
+    stream.shutdown().await
+}
+
+
+#[test]
+fn prometheus_exporter_render_test01() {
+    use crate::aggregator::aggregator::MetricAggregator;
+    use crate::source::prometheus_parser::parse_exposition;
+
+    let text = "# HELP req_duration_seconds Request duration.\n\
+                # TYPE req_duration_seconds histogram\n\
+                req_duration_seconds_bucket{le=\"0.1\"} 3\n\
+                req_duration_seconds_bucket{le=\"+Inf\"} 5\n\
+                req_duration_seconds_sum 1.5\n\
+                req_duration_seconds_count 5\n\
+                # TYPE rx_bytes counter\n\
+                rx_bytes{device=\"eth0\"} 1000\n\
+                state 1\n";
+
+    let mut aggregator = MetricAggregator::new();
+
+    aggregator.handle_metrics("orch", 1000000, &parse_exposition(text).metrics);
+    aggregator.handle_metrics("orch", 2000000, &parse_exposition(&text.replace("1000", "3000")).metrics);
+
+    let exposition = render_exposition(aggregator.metric_iter());
+
+    // The family is announced once, its samples follow in one block
+    assert_eq!(exposition.matches("# TYPE req_duration_seconds histogram").count(), 1);
+    assert!(exposition.contains("# HELP req_duration_seconds Request duration.\n# TYPE req_duration_seconds histogram\nreq_duration_seconds_bucket{le=\"+Inf\",source=\"orch\"} 5\n"));
+
+    assert!(exposition.contains("rx_bytes{device=\"eth0\",source=\"orch\",unit=\"bytes\"} 3000\n"));
+    assert!(exposition.contains("# HELP rx_bytes_ps rx_bytes-ps in kbytes/sec\n# TYPE rx_bytes_ps gauge\nrx_bytes_ps{device=\"eth0\",source=\"orch\",unit=\"kbytes/sec\"} 2\n"));
+    assert!(exposition.contains("# TYPE state untyped\nstate{source=\"orch\"} 1\n"));
+
+    // The output parses as exposition data again
+    assert_eq!(parse_exposition(&exposition).metrics.len(), aggregator.metric_iter().count());
+
+    // Colliding families of another kind are renamed
+    let colliding = "# TYPE rx_bytes gauge\n\
+                     rx_bytes{device=\"eth1\"} 7\n\
+                     req_duration_seconds_count 9\n";
+
+    aggregator.handle_metrics("other", 3000000, &parse_exposition(colliding).metrics);
+
+    let exposition = render_exposition(aggregator.metric_iter());
+
+    assert_eq!(exposition.matches("# TYPE rx_bytes ").count(), 1);
+    assert!(exposition.contains("# TYPE rx_bytes_gauge gauge\nrx_bytes_gauge{device=\"eth1\",source=\"other\",unit=\"bytes\"} 7\n"));
+    assert!(exposition.contains("# TYPE req_duration_seconds_count_untyped untyped\nreq_duration_seconds_count_untyped{source=\"other\"} 9\n"));
+
+    let reparsed = parse_exposition(&exposition);
+
+    assert!(reparsed.skipped.is_empty());
+    assert_eq!(reparsed.metrics.len(), aggregator.metric_iter().count());
+
+    assert_eq!(sanitize_name("1st-rate", true), "_st_rate");
+}
+
+#[test]
+fn prometheus_exporter_serve_test01() {
+    use crate::common::message::MetricCollection;
+    use crate::common::metric::MetricUnit;
+
+    let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+
+    runtime.block_on(async {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let mut sink = PrometheusSink::start(listener, HashMap::new(), Arc::new(EventLog::new(16)))
+            .with_series_ttl(Duration::from_millis(200));
+
+        assert_eq!(sink.get_name(), format!("http://{}/metrics", addr));
+
+        let up = Metric::new(String::from("up"), MetricUnit::empty(), MetricValue::Integer(1));
+
+        let processed = ProcessedCollection {
+            collection: MetricCollection::new(String::from("orch"), String::new(), 1000000, vec![up.clone()]),
+            generated_metrics: vec![up.with_labels([("derived", "1")].into_iter().collect())],
+        };
+
+        sink.consume(Arc::new(processed)).await.unwrap();
+
+        let scrape = |path: &'static str| async move {
+            let mut stream = TcpStream::connect(addr).await.unwrap();
+
+            stream.write_all(format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).as_bytes()).await.unwrap();
+
+            let mut response = String::new();
+
+            stream.read_to_string(&mut response).await.unwrap();
+
+            response
+        };
+
+        let response = scrape("/metrics").await;
+
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains(CONTENT_TYPE));
+        assert!(response.ends_with("# TYPE up untyped\nup{source=\"orch\"} 1\nup{derived=\"1\",source=\"orch\"} 1\n"));
+
+        assert!(scrape("/other").await.starts_with("HTTP/1.1 404"));
+
+        // Series that aren't updated anymore expire
+        time::sleep(Duration::from_millis(250)).await;
+
+        let down = Metric::new(String::from("down"), MetricUnit::empty(), MetricValue::Integer(0));
+
+        let processed = ProcessedCollection {
+            collection: MetricCollection::new(String::from("orch"), String::new(), 2000000, vec![down]),
+            generated_metrics: Vec::new(),
+        };
+
+        sink.consume(Arc::new(processed)).await.unwrap();
+
+        let response = scrape("/metrics").await;
+
+        assert!(response.ends_with("# TYPE down untyped\ndown{source=\"orch\"} 0\n"));
+        assert!(!response.contains("up{"));
+
+        sink.close().await.unwrap();
+
+        // The listener is closed once the server task processed the quit signal
+        let mut closed = false;
+
+        for _ in 0..100 {
+            closed = TcpStream::connect(addr).await.is_err();
+
+            if closed {
+                break;
+            }
+
+            time::sleep(Duration::from_millis(10)).await;
+        }
+
+        assert!(closed);
+    });
+}
@@ -0,0 +1,255 @@
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+use tokio::select;
+
+use crate::common::message::MetricCollection;
+use crate::common::metric::Metric;
+use crate::event_log::{BackendEvent, BackendEventKind, EventLog};
+
+// Collections queued per sink, a receiver waits for a sink that falls this far behind
+pub const SINK_QUEUE_LEN: usize = 64;
+
+/// A received collection together with the metrics the aggregator computed from it.
+pub struct ProcessedCollection {
+    pub collection: MetricCollection,
+
+    pub generated_metrics: Vec<Metric>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SinkError {
+    pub msg: String,
+}
+
+/// Receives every processed collection, e.g. to store or forward it.
+///
+/// Each sink runs on a task of its own and is fed through a bounded queue, a sink that can't keep
+/// up slows the receivers down instead of losing data. A sink that fails is closed and removed.
+#[async_trait]
+pub trait OutputSink: Send {
+    /// Unique among the sinks of a backend, used as event source and to remove the sink.
+    fn get_name(&self) -> String;
+
+    async fn consume(&mut self, processed: Arc<ProcessedCollection>) -> Result<(), SinkError>;
+
+    /// Called once after the last collection, when the sink is removed or the backend disconnects.
+    async fn close(&mut self) -> Result<(), SinkError> {
+        Ok(())
+    }
+}
+
+/// Sink that hands the processed collections to a channel, for consumers outside the backend.
+pub struct ChannelSink {
+    name: String,
+
+    sender: mpsc::Sender<Arc<ProcessedCollection>>,
+}
+
+/// A sink registered on the backend together with the task feeding it.
+pub struct SinkTask {
+    name: String,
+
+    sender: mpsc::Sender<Arc<ProcessedCollection>>,
+
+    task_join_handle: JoinHandle<()>,
+
+    quit_signal: oneshot::Sender<()>,
+}
+
+impl SinkError {
+    pub fn new(msg: &str) -> SinkError {
+        SinkError { msg: msg.to_string() }
+    }
+}
+
+impl Display for SinkError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.msg)
+    }
+}
+
+impl std::error::Error for SinkError {}
+
+impl ChannelSink {
+    pub fn new(name: &str, capacity: usize) -> (ChannelSink, mpsc::Receiver<Arc<ProcessedCollection>>) {
+        let (sender, receiver) = mpsc::channel(capacity.max(1));
+
+        (ChannelSink { name: name.to_string(), sender }, receiver)
+    }
+}
+
+#[async_trait]
+impl OutputSink for ChannelSink {
+    fn get_name(&self) -> String {
+        self.name.clone()
+    }
+
+    async fn consume(&mut self, processed: Arc<ProcessedCollection>) -> Result<(), SinkError> {
+        self.sender.send(processed).await.map_err(|_| SinkError::new("the receiving end was closed"))
+    }
+}
+
+impl SinkTask {
+    /// Starts the task feeding `sink`, must be called from within the runtime.
+    pub fn spawn(sink: Box<dyn OutputSink>, event_log: Arc<EventLog>) -> SinkTask {
+        let name = sink.get_name();
+
+        let (sender, receiver) = mpsc::channel(SINK_QUEUE_LEN);
+
+        let (quit_signal, quit_signal_receiver) = oneshot::channel::<()>();
+
+        let task_join_handle = tokio::spawn(Self::sink_handler(sink, receiver, quit_signal_receiver, event_log));
+
+        SinkTask {
+            name,
+            sender,
+            task_join_handle,
+            quit_signal,
+        }
+    }
+
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    /// False once the sink failed and its task ended.
+    pub fn is_running(&self) -> bool {
+        !self.sender.is_closed()
+    }
+
+    pub fn get_sender(&self) -> mpsc::Sender<Arc<ProcessedCollection>> {
+        self.sender.clone()
+    }
+
+    /// Lets the sink consume what is queued, closes it and ends the task.
+    pub fn stop(self) {
+        // The task may already have ended because the sink failed
+        let _ = self.quit_signal.send(());
+
+        drop(self.task_join_handle);
+    }
+
+    /// Like `stop`, but returns only once the sink is closed.
+    pub async fn shutdown(self) {
+        let _ = self.quit_signal.send(());
+
+        // A sink task that panicked has nothing left to close
+        let _ = self.task_join_handle.await;
+    }
+
+    async fn sink_handler(
+        mut sink: Box<dyn OutputSink>,
+        mut receiver: mpsc::Receiver<Arc<ProcessedCollection>>,
+        quit_signal_receiver: oneshot::Receiver<()>,
+        event_log: Arc<EventLog>,
+    ) {
+        let mut quit_signal_receiver = quit_signal_receiver;
+
+        let name = sink.get_name();
+
+        let publish = |msg: String| event_log.publish(BackendEvent::new(&name, BackendEventKind::SinkFailed { msg }));
+
+        let mut result = Ok(());
+
+        loop {
+            select! {
+                processed = receiver.recv() => {
+                    match processed {
+                        Some(processed) => result = sink.consume(processed).await,
+                        None => break,
+                    }
+                },
+                _ = &mut quit_signal_receiver => {
+                    receiver.close();
+
+                    while let (Ok(()), Some(processed)) = (&result, receiver.recv().await) {
+                        result = sink.consume(processed).await;
+                    }
+
+                    break;
+                }
+            }
+
+            if result.is_err() {
+                break;
+            }
+        }
+
+        // No more collections are accepted once the sink failed
+        receiver.close();
+
+        if let Err(err) = result {
+            publish(err.msg);
+        }
+
+        if let Err(err) = sink.close().await {
+            publish(err.msg);
+        }
+    }
+}
+
+
+#[test]
+fn sink_task_test01() {
+    use crate::common::metric::{MetricUnit, MetricValue};
+
+    let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+
+    runtime.block_on(async {
+        let event_log = Arc::new(EventLog::new(16));
+
+        let (sink, mut receiver) = ChannelSink::new("test", 1);
+
+        let sink_task = SinkTask::spawn(Box::new(sink), Arc::clone(&event_log));
+
+        let sender = sink_task.get_sender();
+
+        for timestamp in 0..3u64 {
+            let metric = Metric::new(String::from("rx"), MetricUnit::empty(), MetricValue::Integer(timestamp as i64));
+
+            let processed = ProcessedCollection {
+                collection: MetricCollection::new(String::from("src"), String::new(), timestamp, vec![metric.clone()]),
+                generated_metrics: vec![metric],
+            };
+
+            sender.send(Arc::new(processed)).await.unwrap();
+        }
+
+        // Queued collections are still delivered after the stop
+        sink_task.stop();
+
+        let mut timestamps = Vec::new();
+
+        while let Some(processed) = receiver.recv().await {
+            timestamps.push(processed.collection.get_timestamp());
+        }
+
+        assert_eq!(timestamps, vec![0, 1, 2]);
+
+        // A sink whose consumer went away fails and is taken out
+        let (sink, receiver) = ChannelSink::new("closed", 1);
+
+        drop(receiver);
+
+        let sink_task = SinkTask::spawn(Box::new(sink), Arc::clone(&event_log));
+
+        let processed = ProcessedCollection {
+            collection: MetricCollection::new(String::from("src"), String::new(), 0, Vec::new()),
+            generated_metrics: Vec::new(),
+        };
+
+        let _ = sink_task.get_sender().send(Arc::new(processed)).await;
+
+        while sink_task.is_running() {
+            tokio::task::yield_now().await;
+        }
+
+        assert!(event_log.get_recent_events(16).iter().any(|e| e.get_source() == "closed"
+            && matches!(e.get_kind(), BackendEventKind::SinkFailed { .. })));
+    });
+}
@@ -0,0 +1,129 @@
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::time::Duration;
+
+use rand::Rng;
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum ConnectionState {
+    Connecting,
+    Connected,
+    Stalled,
+    Reconnecting { attempt: u32 },
+    Failed,
+}
+
+/// Controls how a backend receiver task detects stalled endpoints and how it retries.
+///
+/// The delay before reconnect attempt `n` (starting at 0) is
+/// `min(initial_delay * multiplier^n, max_delay)`, randomly spread by `+-jitter` (a fraction of the delay)
+/// without exceeding `max_delay`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReconnectPolicy {
+    pub recv_timeout: Duration,
+
+    pub initial_delay: Duration,
+
+    pub max_delay: Duration,
+
+    pub multiplier: f64,
+
+    pub jitter: f64,
+
+    // None retries forever
+    pub max_attempts: Option<u32>,
+}
+
+impl ConnectionState {
+    pub fn is_healthy(&self) -> bool {
+        matches!(self, ConnectionState::Connecting | ConnectionState::Connected)
+    }
+}
+
+impl Display for ConnectionState {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ConnectionState::Connecting => write!(f, "connecting"),
+            ConnectionState::Connected => write!(f, "connected"),
+            ConnectionState::Stalled => write!(f, "stalled"),
+            ConnectionState::Reconnecting { attempt } => write!(f, "reconnecting (attempt {})", attempt),
+            ConnectionState::Failed => write!(f, "failed"),
+        }
+    }
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        ReconnectPolicy {
+            recv_timeout: Duration::from_secs(1),
+            initial_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+            multiplier: 2.0,
+            jitter: 0.2,
+            max_attempts: None,
+        }
+    }
+}
+
+impl ReconnectPolicy {
+    pub fn get_base_delay(&self, attempt: u32) -> Duration {
+        let factor = self.multiplier.max(1.0).powi(attempt.min(i32::MAX as u32) as i32);
+
+        let delay_s = (self.initial_delay.as_secs_f64() * factor).min(self.max_delay.as_secs_f64());
+
+        Duration::from_secs_f64(delay_s)
+    }
+
+    pub fn get_delay(&self, attempt: u32) -> Duration {
+        let base_delay = self.get_base_delay(attempt);
+
+        let jitter = self.jitter.clamp(0.0, 1.0);
+
+        if jitter == 0.0 {
+            return base_delay;
+        }
+
+        let spread = rand::thread_rng().gen_range(-jitter..=jitter);
+
+        // Clamped after spreading, so jitter never pushes the delay beyond the maximum
+        base_delay.mul_f64(1.0 + spread).min(self.max_delay)
+    }
+
+    pub fn attempts_exhausted(&self, attempts: u32) -> bool {
+        self.max_attempts.map(|max| attempts >= max).unwrap_or(false)
+    }
+}
+
+
+#[test]
+fn reconnect_policy_backoff_test01() {
+    let policy = ReconnectPolicy {
+        initial_delay: Duration::from_millis(100),
+        max_delay: Duration::from_secs(1),
+        multiplier: 2.0,
+        jitter: 0.0,
+        max_attempts: Some(3),
+        ..ReconnectPolicy::default()
+    };
+
+    assert_eq!(policy.get_delay(0), Duration::from_millis(100));
+    assert_eq!(policy.get_delay(1), Duration::from_millis(200));
+    assert_eq!(policy.get_delay(3), Duration::from_millis(800));
+    assert_eq!(policy.get_delay(4), Duration::from_secs(1));
+    assert_eq!(policy.get_delay(100), Duration::from_secs(1));
+
+    assert!(!policy.attempts_exhausted(2));
+    assert!(policy.attempts_exhausted(3));
+
+    let jittered = ReconnectPolicy { jitter: 0.5, ..policy };
+
+    for _ in 0..32 {
+        let delay = jittered.get_delay(1);
+
+        assert!(delay >= Duration::from_millis(100) && delay <= Duration::from_millis(300));
+
+        let delay = jittered.get_delay(10);
+
+        assert!(delay >= Duration::from_millis(500) && delay <= Duration::from_secs(1));
+    }
+}
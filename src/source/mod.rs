@@ -1,18 +1,177 @@
+use std::collections::BTreeMap;
 use std::error::Error;
 use std::fmt::{Debug, Display, Formatter};
 
+use async_trait::async_trait;
+
+use crate::common::message::MetricCollection;
+use crate::source::prometheus_poll_endpoint::PrometheusPollEndpoint;
+use crate::source::zmq_endpoint::{SubscriptionControl, ZmqEndpoint, ZMQ_SCHEMES};
+
+pub mod connection;
 pub mod zmq_endpoint;
 pub mod prometheus_poll_endpoint;
+pub mod prometheus_parser;
+pub mod replay_endpoint;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EndpointErrorKind {
+    // The connection itself failed, the receiver task reconnects
+    Transport,
+
+    // A single message could not be decoded, the connection stays up
+    Decode,
+}
 
 #[derive(Debug, Clone)]
 pub struct EndpointError {
-    pub msg: String
+    pub msg: String,
+
+    pub kind: EndpointErrorKind,
+}
+
+/// A source of metric collections the backend can drive.
+///
+/// Implementors are owned by a backend receiver task, which calls `connect` once, then `recv_msg` in a loop
+/// and `try_reconnect` whenever receiving fails with a transport error or stalls. Messages that fail with
+/// a decode error are skipped.
+#[async_trait]
+pub trait MetricEndpoint: Send {
+
+    async fn connect(&mut self) -> Result<(), EndpointError>;
+
+    async fn try_reconnect(&mut self) -> Result<(), EndpointError>;
+
+    async fn recv_msg(&mut self) -> Result<MetricCollection, EndpointError>;
+
+    fn get_destination(&self) -> &str;
+
+    /// False for endpoints that may legitimately stay silent, e.g. a paused replay. The receiver task
+    /// then doesn't take an elapsed receive timeout as a stall.
+    fn detects_stalls(&self) -> bool {
+        true
+    }
+
+    /// Changes the topics subscribed to at runtime, None for endpoints without topics.
+    fn get_subscription_control(&self) -> Option<SubscriptionControl> {
+        None
+    }
+
+    /// Parts of the last received message that were skipped, e.g. unparsable lines of a scrape. The
+    /// receiver task publishes them as decode errors.
+    fn take_decode_errors(&mut self) -> Vec<EndpointError> {
+        Vec::new()
+    }
 }
 
-pub trait MetricEndpoint {
+#[async_trait]
+impl<T: MetricEndpoint + ?Sized> MetricEndpoint for Box<T> {
+    async fn connect(&mut self) -> Result<(), EndpointError> {
+        (**self).connect().await
+    }
+
+    async fn try_reconnect(&mut self) -> Result<(), EndpointError> {
+        (**self).try_reconnect().await
+    }
+
+    async fn recv_msg(&mut self) -> Result<MetricCollection, EndpointError> {
+        (**self).recv_msg().await
+    }
+
+    fn get_destination(&self) -> &str {
+        (**self).get_destination()
+    }
+
+    fn detects_stalls(&self) -> bool {
+        (**self).detects_stalls()
+    }
+
+    fn get_subscription_control(&self) -> Option<SubscriptionControl> {
+        (**self).get_subscription_control()
+    }
 
+    fn take_decode_errors(&mut self) -> Vec<EndpointError> {
+        (**self).take_decode_errors()
+    }
 }
 
+pub type EndpointFactory = Box<dyn Fn(&str) -> Result<Box<dyn MetricEndpoint>, EndpointError> + Send + Sync>;
+
+/// Maps URL schemes (the part in front of `://`) to endpoint constructors.
+///
+/// The factory receives the complete URL, including the scheme.
+pub struct EndpointRegistry {
+    factories: BTreeMap<String, EndpointFactory>,
+}
+
+impl EndpointRegistry {
+    pub fn new() -> EndpointRegistry {
+        EndpointRegistry {
+            factories: BTreeMap::new(),
+        }
+    }
+
+    pub fn with_default_endpoints() -> EndpointRegistry {
+        let mut registry = EndpointRegistry::new();
+
+        for scheme in ZMQ_SCHEMES {
+            registry.register(scheme, |url| {
+                Ok(Box::new(ZmqEndpoint::new(url)?))
+            });
+        }
+
+        for scheme in ["http", "https"] {
+            registry.register(scheme, |url| {
+                Ok(Box::new(PrometheusPollEndpoint::new(url)?))
+            });
+        }
+
+        registry
+    }
+
+    pub fn register<F>(&mut self, scheme: &str, factory: F)
+    where
+        F: Fn(&str) -> Result<Box<dyn MetricEndpoint>, EndpointError> + Send + Sync + 'static,
+    {
+        self.factories.insert(scheme.to_ascii_lowercase(), Box::new(factory));
+    }
+
+    pub fn get_schemes(&self) -> Vec<&str> {
+        self.factories.keys().map(|s| s.as_str()).collect()
+    }
+
+    pub fn create(&self, url: &str) -> Result<Box<dyn MetricEndpoint>, EndpointError> {
+        let scheme = get_url_scheme(url).ok_or_else(|| {
+            EndpointError::new(&format!(
+                "endpoint address '{}' has no scheme, expected one of: {}",
+                url,
+                self.get_schemes().join(", ")
+            ))
+        })?;
+
+        match self.factories.get(&scheme.to_ascii_lowercase()) {
+            Some(factory) => factory(url),
+            None => Err(EndpointError::new(&format!(
+                "unsupported scheme '{}' in endpoint address '{}', expected one of: {}",
+                scheme,
+                url,
+                self.get_schemes().join(", ")
+            ))),
+        }
+    }
+}
+
+impl Default for EndpointRegistry {
+    fn default() -> Self {
+        EndpointRegistry::with_default_endpoints()
+    }
+}
+
+pub fn get_url_scheme(url: &str) -> Option<&str> {
+    url.split_once("://")
+        .map(|(scheme, _)| scheme)
+        .filter(|scheme| !scheme.is_empty())
+}
 
 impl Display for EndpointError {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
@@ -26,6 +185,34 @@ impl Error for EndpointError {
 
 impl EndpointError {
     pub fn new(msg: &str) -> EndpointError {
-        EndpointError {msg: msg.to_string()}
+        EndpointError {msg: msg.to_string(), kind: EndpointErrorKind::Transport}
     }
+
+    pub fn decode(msg: &str) -> EndpointError {
+        EndpointError {msg: msg.to_string(), kind: EndpointErrorKind::Decode}
+    }
+}
+
+
+#[test]
+fn endpoint_registry_scheme_selection_test01() {
+    let registry = EndpointRegistry::with_default_endpoints();
+
+    let endpoint = registry.create("http://localhost:9100/metrics").unwrap();
+    assert_eq!(endpoint.get_destination(), "http://localhost:9100/metrics");
+
+    let endpoint = registry.create("zmq://localhost:5555").unwrap();
+    assert_eq!(endpoint.get_destination(), "tcp://localhost:5555");
+
+    let endpoint = registry.create("tcp://localhost:5555").unwrap();
+    assert_eq!(endpoint.get_destination(), "tcp://localhost:5555");
+
+    let endpoint = registry.create("ZMQ://localhost:5555").unwrap();
+    assert_eq!(endpoint.get_destination(), "tcp://localhost:5555");
+
+    let endpoint = registry.create("Tcp://localhost:5555").unwrap();
+    assert_eq!(endpoint.get_destination(), "tcp://localhost:5555");
+
+    assert!(registry.create("udp://localhost:5555").is_err());
+    assert!(registry.create("localhost:5555").is_err());
 }
@@ -0,0 +1,484 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+use crate::common::metric::{Metric, MetricKind, MetricLabels, MetricRawUnit, MetricUnit, MetricValue, OrderOfMagnitude};
+
+/// Error while parsing prometheus text exposition data, `line` starts at 1.
+#[derive(Debug, PartialEq, Clone)]
+pub struct ParseError {
+    pub line: usize,
+    pub msg: String,
+}
+
+/// Samples of an exposition together with the lines that were skipped because they could not be parsed.
+#[derive(Debug, Default)]
+pub struct Exposition {
+    pub metrics: Vec<Metric>,
+
+    pub skipped: Vec<ParseError>,
+}
+
+#[derive(Default)]
+struct MetricFamily {
+    kind: MetricKind,
+    help: Option<String>,
+}
+
+struct LineCursor<'a> {
+    line: &'a str,
+    pos: usize,
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.msg)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl<'a> LineCursor<'a> {
+    fn new(line: &'a str) -> LineCursor<'a> {
+        LineCursor { line, pos: 0 }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.line[self.pos..].chars().next()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+
+        self.pos += c.len_utf8();
+
+        Some(c)
+    }
+
+    fn is_at_end(&self) -> bool {
+        self.pos >= self.line.len()
+    }
+
+    // Returns true if at least one whitespace character was skipped
+    fn skip_whitespace(&mut self) -> bool {
+        let start = self.pos;
+
+        while matches!(self.peek(), Some(' ') | Some('\t')) {
+            self.bump();
+        }
+
+        self.pos > start
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        match self.bump() {
+            Some(c) if c == expected => Ok(()),
+            Some(c) => Err(format!("expected '{}' but found '{}'", expected, c)),
+            None => Err(format!("expected '{}' but found end of line", expected)),
+        }
+    }
+
+    fn take_name(&mut self, allow_colon: bool) -> Result<&'a str, String> {
+        let start = self.pos;
+
+        while let Some(c) = self.peek() {
+            let valid = c.is_ascii_alphabetic()
+                || c == '_'
+                || (allow_colon && c == ':')
+                || (self.pos > start && c.is_ascii_digit());
+
+            if !valid {
+                break;
+            }
+
+            self.bump();
+        }
+
+        if self.pos == start {
+            return Err(match self.peek() {
+                Some(c) => format!("expected a name but found '{}'", c),
+                None => String::from("expected a name but found end of line"),
+            });
+        }
+
+        Ok(&self.line[start..self.pos])
+    }
+
+    fn take_token(&mut self) -> &'a str {
+        let start = self.pos;
+
+        while matches!(self.peek(), Some(c) if c != ' ' && c != '\t') {
+            self.bump();
+        }
+
+        &self.line[start..self.pos]
+    }
+
+    fn take_rest(&mut self) -> &'a str {
+        let rest = &self.line[self.pos..];
+
+        self.pos = self.line.len();
+
+        rest
+    }
+
+    fn take_label_value(&mut self) -> Result<String, String> {
+        self.expect('"')?;
+
+        let mut value = String::new();
+
+        loop {
+            match self.bump() {
+                Some('"') => return Ok(value),
+                Some('\\') => match self.bump() {
+                    Some('\\') => value.push('\\'),
+                    Some('"') => value.push('"'),
+                    Some('n') => value.push('\n'),
+                    Some(c) => return Err(format!("invalid escape sequence '\\{}' in label value", c)),
+                    None => return Err(String::from("unterminated label value")),
+                },
+                Some(c) => value.push(c),
+                None => return Err(String::from("unterminated label value")),
+            }
+        }
+    }
+
+    fn take_labels(&mut self) -> Result<MetricLabels, String> {
+        self.expect('{')?;
+
+        let mut labels = MetricLabels::new();
+
+        loop {
+            self.skip_whitespace();
+
+            // A trailing comma before the closing brace is allowed
+            if self.peek() == Some('}') {
+                self.bump();
+                return Ok(labels);
+            }
+
+            let name = self.take_name(false)?;
+
+            if labels.get(name).is_some() {
+                return Err(format!("duplicate label '{}'", name));
+            }
+
+            self.skip_whitespace();
+            self.expect('=')?;
+            self.skip_whitespace();
+
+            let value = self.take_label_value()?;
+
+            labels.insert(name, &value);
+
+            self.skip_whitespace();
+
+            match self.bump() {
+                Some(',') => continue,
+                Some('}') => return Ok(labels),
+                Some(c) => return Err(format!("expected ',' or '}}' after label but found '{}'", c)),
+                None => return Err(String::from("unterminated label set")),
+            }
+        }
+    }
+}
+
+fn unescape_help(help: &str) -> String {
+    let mut result = String::with_capacity(help.len());
+
+    let mut chars = help.chars();
+
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            match chars.next() {
+                Some('n') => result.push('\n'),
+                Some('\\') => result.push('\\'),
+                Some(other) => {
+                    result.push('\\');
+                    result.push(other);
+                }
+                None => result.push('\\'),
+            }
+        } else {
+            result.push(c);
+        }
+    }
+
+    result
+}
+
+fn parse_value(value_str: &str) -> Result<MetricValue, String> {
+    match value_str {
+        "NaN" => return Ok(MetricValue::Number(f64::NAN)),
+        "+Inf" | "Inf" => return Ok(MetricValue::Number(f64::INFINITY)),
+        "-Inf" => return Ok(MetricValue::Number(f64::NEG_INFINITY)),
+        _ => {}
+    }
+
+    if let Ok(v) = i64::from_str(value_str) {
+        Ok(MetricValue::Integer(v))
+    } else if let Ok(v) = f64::from_str(value_str) {
+        Ok(MetricValue::Number(v))
+    } else {
+        Err(format!("invalid sample value '{}'", value_str))
+    }
+}
+
+// Converts the millisecond timestamp of the exposition format to microseconds
+fn parse_timestamp(timestamp_str: &str) -> Result<i64, String> {
+    let timestamp_ms = i64::from_str(timestamp_str).map_err(|_| format!("invalid timestamp '{}'", timestamp_str))?;
+
+    timestamp_ms
+        .checked_mul(1000)
+        .ok_or_else(|| format!("timestamp '{}' out of range", timestamp_str))
+}
+
+pub fn get_metric_unit(family_name: &str) -> MetricUnit {
+    let family_name_lc = family_name.to_ascii_lowercase();
+
+    let mut unit_num = MetricRawUnit::None;
+
+    if family_name_lc.contains("packets") {
+        unit_num = MetricRawUnit::Packets;
+    } else if family_name_lc.contains("bytes") {
+        unit_num = MetricRawUnit::Bytes;
+    } else if family_name_lc.contains("bits") {
+        unit_num = MetricRawUnit::Bits;
+    }
+
+    MetricUnit::new(unit_num, MetricRawUnit::None, OrderOfMagnitude::One)
+}
+
+/// Parses the prometheus text exposition format (version 0.0.4).
+///
+/// Every sample becomes one metric. Samples of histograms and summaries (`_bucket`, `_sum`, `_count`)
+/// keep their own name but inherit type and help text of their family. A line that can't be parsed is
+/// skipped, so one odd line doesn't cost the rest of the scrape.
+pub fn parse_exposition(text: &str) -> Exposition {
+    let mut families: HashMap<String, MetricFamily> = HashMap::new();
+
+    let mut exposition = Exposition::default();
+
+    for (idx, line) in text.lines().enumerate() {
+        let line = line.trim();
+
+        if line.is_empty() {
+            continue;
+        }
+
+        let result = if let Some(comment) = line.strip_prefix('#') {
+            parse_comment(comment, &mut families)
+        } else {
+            parse_sample(line, &families).map(|metric| exposition.metrics.push(metric))
+        };
+
+        if let Err(msg) = result {
+            exposition.skipped.push(ParseError { line: idx + 1, msg });
+        }
+    }
+
+    exposition
+}
+
+// Handles `# HELP` and `# TYPE` lines, all other comments are ignored
+fn parse_comment(comment: &str, families: &mut HashMap<String, MetricFamily>) -> Result<(), String> {
+    let mut cursor = LineCursor::new(comment);
+
+    cursor.skip_whitespace();
+
+    let keyword = cursor.take_token();
+
+    if keyword != "HELP" && keyword != "TYPE" {
+        return Ok(());
+    }
+
+    if !cursor.skip_whitespace() {
+        return Err(format!("expected metric name after {}", keyword));
+    }
+
+    let name = cursor.take_name(true)?;
+
+    let family = families.entry(name.to_string()).or_default();
+
+    if keyword == "HELP" {
+        // Exactly one separating whitespace, the rest is the docstring
+        cursor.bump();
+
+        family.help = Some(unescape_help(cursor.take_rest()));
+    } else {
+        cursor.skip_whitespace();
+
+        family.kind = MetricKind::try_from(cursor.take_token())?;
+
+        cursor.skip_whitespace();
+
+        if !cursor.is_at_end() {
+            return Err(format!("unexpected trailing data '{}' after TYPE", cursor.take_rest()));
+        }
+    }
+
+    Ok(())
+}
+
+fn find_family<'a>(name: &str, families: &'a HashMap<String, MetricFamily>) -> Option<(&'a str, &'a MetricFamily)> {
+    if let Some((family_name, family)) = families.get_key_value(name) {
+        return Some((family_name, family));
+    }
+
+    for suffix in ["_bucket", "_sum", "_count"] {
+        if let Some((family_name, family)) = name.strip_suffix(suffix).and_then(|base| families.get_key_value(base)) {
+            let has_suffix = match family.kind {
+                MetricKind::Histogram => true,
+                MetricKind::Summary => suffix != "_bucket",
+                _ => false,
+            };
+
+            if has_suffix {
+                return Some((family_name, family));
+            }
+        }
+    }
+
+    None
+}
+
+fn parse_sample(line: &str, families: &HashMap<String, MetricFamily>) -> Result<Metric, String> {
+    let mut cursor = LineCursor::new(line);
+
+    let name = cursor.take_name(true)?;
+
+    cursor.skip_whitespace();
+
+    let labels = if cursor.peek() == Some('{') {
+        cursor.take_labels()?
+    } else {
+        MetricLabels::new()
+    };
+
+    cursor.skip_whitespace();
+
+    let value = parse_value(cursor.take_token())?;
+
+    cursor.skip_whitespace();
+
+    // OpenMetrics exemplars follow the value or timestamp after a '#', they are ignored
+    let timestamp = if cursor.is_at_end() || cursor.peek() == Some('#') {
+        None
+    } else {
+        Some(parse_timestamp(cursor.take_token())?)
+    };
+
+    cursor.skip_whitespace();
+
+    if !cursor.is_at_end() && cursor.peek() != Some('#') {
+        return Err(format!("unexpected trailing data '{}'", cursor.take_rest()));
+    }
+
+    let (family_name, kind, help) = match find_family(name, families) {
+        Some((family_name, family)) => (family_name, family.kind, family.help.clone()),
+        None => (name, MetricKind::Untyped, None),
+    };
+
+    Ok(Metric::new(name.to_string(), get_metric_unit(family_name), value)
+        .with_kind(kind)
+        .with_labels(labels)
+        .with_help(help)
+        .with_timestamp(timestamp))
+}
+
+
+#[test]
+fn prometheus_parser_sample_test01() {
+    let text = "# HELP http_requests_total The total number of HTTP requests.\n\
+                # TYPE http_requests_total counter\n\
+                http_requests_total{method=\"post\",code=\"200\"} 1027 1395066363000\n\
+                http_requests_total{code=\"400\", method=\"post\",} 3 1395066363000\n\
+                \n\
+                # Escaping in label values:\n\
+                msdos_file_access_time_seconds{path=\"C:\\\\DIR\\\\FILE.TXT\",error=\"Cannot find file:\\n\\\"FILE.TXT\\\"\"} 1.458255915e9\n\
+                metric_without_timestamp_and_labels 12.47\n\
+                something_weird{problem=\"division by zero\"} +Inf -3982045\n\
+                # UNIT something_weird seconds\n\
+                rpc_duration_seconds_bucket{le=\"0.5\"} 129 # {trace_id=\"KOO5S4vxi0o\"} 0.41\n";
+
+    let exposition = parse_exposition(text);
+
+    assert!(exposition.skipped.is_empty());
+
+    let metrics = exposition.metrics;
+
+    assert_eq!(metrics.len(), 6);
+
+    assert_eq!(metrics[0].get_label(), "http_requests_total");
+    assert_eq!(metrics[0].get_kind(), MetricKind::Counter);
+    assert_eq!(metrics[0].get_help(), Some("The total number of HTTP requests."));
+    assert_eq!(metrics[0].get_labels().get("code"), Some("200"));
+    assert_eq!(metrics[0].get_value(), &MetricValue::Integer(1027));
+    assert_eq!(metrics[0].get_timestamp(), Some(1395066363000000));
+
+    // Label order does not matter for the series identity
+    assert_eq!(metrics[1].get_series_id(), "http_requests_total{code=\"400\",method=\"post\"}");
+
+    assert_eq!(metrics[2].get_labels().get("path"), Some("C:\\DIR\\FILE.TXT"));
+    assert_eq!(metrics[2].get_labels().get("error"), Some("Cannot find file:\n\"FILE.TXT\""));
+    assert_eq!(metrics[2].get_value(), &MetricValue::Number(1.458255915e9));
+    assert_eq!(metrics[2].get_kind(), MetricKind::Untyped);
+
+    assert_eq!(metrics[3].get_timestamp(), None);
+    assert_eq!(metrics[4].get_value(), &MetricValue::Number(f64::INFINITY));
+    assert_eq!(metrics[4].get_timestamp(), Some(-3982045000));
+
+    assert!(matches!(parse_value("NaN"), Ok(MetricValue::Number(v)) if v.is_nan()));
+
+    assert_eq!(metrics[5].get_value(), &MetricValue::Integer(129));
+    assert_eq!(metrics[5].get_timestamp(), None);
+
+    // The lines around an invalid one are still taken
+    let exposition = parse_exposition("ok 1\nfoo{bar=\"1} 1\nalso_ok 2");
+
+    assert_eq!(exposition.metrics.len(), 2);
+    assert_eq!(exposition.skipped.len(), 1);
+    assert_eq!(exposition.skipped[0].line, 2);
+
+    for invalid in ["foo{bar=\"1} 1", "foo{bar=1} 1", "foo 1 2 3", "foo abc", "foo{a=\"\\x\"} 1", "# TYPE foo bar"] {
+        assert_eq!(parse_exposition(invalid).skipped.len(), 1, "{}", invalid);
+    }
+}
+
+#[test]
+fn prometheus_parser_corpus_test01() {
+    let exposition = parse_exposition(include_str!("../../testdata/prometheus/node_exporter.prom"));
+
+    assert!(exposition.skipped.is_empty());
+
+    let metrics = exposition.metrics;
+
+    let rx_bytes: Vec<&Metric> = metrics.iter().filter(|m| m.get_label() == "node_network_receive_bytes_total").collect();
+
+    assert_eq!(rx_bytes.len(), 3);
+    assert!(rx_bytes.iter().all(|m| m.get_kind() == MetricKind::Counter));
+    assert_eq!(rx_bytes[0].get_unit().get_raw_unit().0, &MetricRawUnit::Bytes);
+    assert_eq!(rx_bytes[1].get_labels().get("device"), Some("eth0"));
+
+    let exposition = parse_exposition(include_str!("../../testdata/prometheus/go_client.prom"));
+
+    assert!(exposition.skipped.is_empty());
+
+    let metrics = exposition.metrics;
+
+    let buckets: Vec<&Metric> = metrics.iter().filter(|m| m.get_label() == "http_request_duration_seconds_bucket").collect();
+
+    assert_eq!(buckets.len(), 6);
+    assert!(buckets.iter().all(|m| m.get_kind() == MetricKind::Histogram));
+    assert_eq!(buckets[5].get_labels().get("le"), Some("+Inf"));
+
+    let summary_count = metrics.iter().find(|m| m.get_label() == "go_gc_duration_seconds_count").unwrap();
+
+    assert_eq!(summary_count.get_kind(), MetricKind::Summary);
+    assert_eq!(summary_count.get_help(), Some("A summary of the pause duration of garbage collection cycles."));
+
+    let quantile = metrics.iter().find(|m| m.get_labels().get("quantile") == Some("0.5")).unwrap();
+
+    assert_eq!(quantile.get_label(), "go_gc_duration_seconds");
+}
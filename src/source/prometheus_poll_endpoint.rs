@@ -1,47 +1,41 @@
-use std::error::Error;
-use std::str::FromStr;
 use std::time;
 use std::time::Duration;
-use reqwest::Response;
+use async_trait::async_trait;
 use crate::common::message::MetricCollection;
-use crate::common::metric::{Metric, MetricRawUnit, MetricUnit, MetricValue, OrderOfMagnitude};
-use crate::source::{EndpointError, MetricEndpoint};
+use crate::source::{prometheus_parser, EndpointError, MetricEndpoint};
 
 
 
 pub struct PrometheusPollEndpoint {
     dst: String,
-    client: reqwest::Client
-}
+    client: reqwest::Client,
 
-impl MetricEndpoint for PrometheusPollEndpoint {
+    // Skipped lines of the last scrape, only reported when they change to not repeat them every poll
+    skipped_report: Option<String>,
 
+    decode_errors: Vec<EndpointError>,
 }
 
-impl TryFrom<&str> for PrometheusPollEndpoint {
-    type Error = EndpointError;
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
 
-    fn try_from(dst: &str) -> Result<Self, Self::Error> {
-        Ok(PrometheusPollEndpoint {
-            dst: dst.to_string(),
-            client: reqwest::Client::new()
-        })
+#[async_trait]
+impl MetricEndpoint for PrometheusPollEndpoint {
+    async fn connect(&mut self) -> Result<(), EndpointError> {
+        // Plain HTTP polling is connectionless, a request is only issued on recv_msg
+        Ok(())
     }
-}
 
-impl PrometheusPollEndpoint {
-    pub fn new(dst: &str) -> Result<Self, EndpointError> {
-        PrometheusPollEndpoint::try_from(dst)
-    }
+    async fn try_reconnect(&mut self) -> Result<(), EndpointError> {
+        self.client = reqwest::Client::new();
 
-    pub async fn recv_msg(&mut self) -> Result<MetricCollection, EndpointError> {
-        let req = self.client.get(&self.dst).build();
+        Ok(())
+    }
 
-        if req.is_err() {
-            return Err(EndpointError::new(&req.unwrap_err().to_string()))
-        }
+    async fn recv_msg(&mut self) -> Result<MetricCollection, EndpointError> {
+        let req = self.client.get(&self.dst).build()
+            .map_err(|e| EndpointError::new(&e.to_string()))?;
 
-        let response = self.client.execute(req.unwrap()).await;
+        let response = self.client.execute(req).await;
 
         match response {
             Ok(resp) => {
@@ -51,9 +45,9 @@ impl PrometheusPollEndpoint {
 
                 let body_data = resp.text().await;
 
-                std::thread::sleep(Duration::from_millis(250));
+                tokio::time::sleep(POLL_INTERVAL).await;
 
-                self.build_metric_msg(body_data.unwrap_or(String::new()))
+                self.build_metric_msg(body_data.unwrap_or_default())
             },
             Err(e) => {
                 Err(EndpointError::new(&e.to_string()))
@@ -61,81 +55,50 @@ impl PrometheusPollEndpoint {
         }
     }
 
-    fn get_metric_unit(line_data: &str) -> MetricUnit {
-        let line_data_lc = line_data.to_ascii_lowercase();
-
-        let mut unit_num = MetricRawUnit::None;
-
-        if line_data_lc.contains("packets") {
-            unit_num = MetricRawUnit::Packets;
-        } else if line_data_lc.contains("bytes") {
-            unit_num = MetricRawUnit::Bytes;
-        } else if line_data_lc.contains("bits") {
-            unit_num = MetricRawUnit::Bits;
-        }
-
-        MetricUnit::new(unit_num, MetricRawUnit::None, OrderOfMagnitude::One)
+    fn get_destination(&self) -> &str {
+        &self.dst
     }
 
-    fn build_metric(line_data: &str, unit: MetricUnit) -> Option<Metric> {
-        let mut name : Option<&str> = None;
-        let mut value_str : Option<&str> = None;
-
-        for (idx, element) in line_data.split_whitespace().enumerate() {
-            if idx == 0 {
-                name = Some(element);
-            } else if idx == 1 {
-                value_str = Some(element);
-                break;
-            }
-        }
-
-        if name.is_none() || value_str.is_none() {
-            return None
-        }
-
-        if let Ok(v) = i64::from_str(value_str.unwrap()) {
-            return Some(Metric::new(name.unwrap().to_string(), unit, MetricValue::Integer(v)))
-        } else if let Ok(v) = f64::from_str(value_str.unwrap()) {
-            return Some(Metric::new(name.unwrap().to_string(), unit, MetricValue::Number(v)))
-        } else {
-            return Some(Metric::new(name.unwrap().to_string(), unit, MetricValue::String(value_str.unwrap().to_string())))
-        }
+    fn take_decode_errors(&mut self) -> Vec<EndpointError> {
+        std::mem::take(&mut self.decode_errors)
     }
+}
 
+impl TryFrom<&str> for PrometheusPollEndpoint {
+    type Error = EndpointError;
 
+    fn try_from(dst: &str) -> Result<Self, Self::Error> {
+        Ok(PrometheusPollEndpoint {
+            dst: dst.to_string(),
+            client: reqwest::Client::new(),
+            skipped_report: None,
+            decode_errors: Vec::new(),
+        })
+    }
+}
 
-    fn build_metric_msg(&self, body_data: String) -> Result<MetricCollection, EndpointError> {
-
-        let mut lines = body_data.lines();
-
-        let mut metric_unit =  None;
+impl PrometheusPollEndpoint {
+    pub fn new(dst: &str) -> Result<Self, EndpointError> {
+        PrometheusPollEndpoint::try_from(dst)
+    }
 
-        let mut metrics = Vec::new();
+    // Samples with a timestamp of their own keep it, the aggregator records them at that time
+    fn build_metric_msg(&mut self, body_data: String) -> Result<MetricCollection, EndpointError> {
         let timestamp = time::SystemTime::now().duration_since(time::UNIX_EPOCH).unwrap().as_micros() as u64;
 
-        //println!("Beginning parsing of prometheus data at : {}", timestamp);
+        let exposition = prometheus_parser::parse_exposition(&body_data);
 
-        while let Some(line) = lines.next() {
-            //println!("Processing line: {}", line);
+        let skipped_report = exposition.skipped.first().map(|first| {
+            format!("skipped {} unparsable line(s) of the scrape, first at {}", exposition.skipped.len(), first)
+        });
 
-            if line.starts_with("#TYPE") {
-                metric_unit = Some(Self::get_metric_unit(line));
-            } else if line.starts_with("#HELP") {
-                continue;
-            } else if !line.starts_with("#") {
-                let metric = Self::build_metric(line, metric_unit.take().unwrap_or_else(||{
-                    MetricUnit::new(MetricRawUnit::None, MetricRawUnit::None, OrderOfMagnitude::One)
-                }));
-
-                if let Some(metric) = metric {
-                    //println!("Parsed metric: {:?}", metric);
-
-                    metrics.push(metric);
-                }
-            }
+        match &skipped_report {
+            Some(report) if skipped_report != self.skipped_report => self.decode_errors.push(EndpointError::decode(report)),
+            _ => {}
         }
 
-        Ok(MetricCollection::new(self.dst.clone(), String::new(), timestamp, metrics))
+        self.skipped_report = skipped_report;
+
+        Ok(MetricCollection::new(self.dst.clone(), String::new(), timestamp, exposition.metrics))
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,528 @@
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use tokio::select;
+use tokio::sync::Notify;
+use tokio::time::{self, Instant};
+
+use crate::capture::reader::CaptureReader;
+use crate::capture::{CaptureError, CapturedCollection};
+use crate::common::message::MetricCollection;
+use crate::source::{EndpointError, MetricEndpoint};
+
+/// Playback speed of a replay, a factor of the recorded speed or as fast as the collections can be read.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReplaySpeed {
+    Factor(f64),
+    AsFastAsPossible,
+}
+
+// Speeds stepped through by `ReplaySpeed::faster` and `ReplaySpeed::slower`
+const SPEED_STEPS: [f64; 9] = [0.125, 0.25, 0.5, 1.0, 2.0, 4.0, 8.0, 16.0, 64.0];
+
+// Capture time between the last collection of a looped replay and the first one of the next round
+const LOOP_GAP: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone)]
+pub struct ReplayOptions {
+    pub speed: ReplaySpeed,
+
+    // Starts over at the beginning once the end of the capture is reached
+    pub looping: bool,
+}
+
+/// Playback state of a replay as shown by the frontends.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReplayStatus {
+    // Receive time offset of the last emitted collection relative to the first one
+    pub position: Duration,
+
+    pub duration: Duration,
+
+    pub speed: ReplaySpeed,
+
+    pub paused: bool,
+
+    pub looping: bool,
+
+    // Set once the end of a capture that isn't looped was reached
+    pub finished: bool,
+}
+
+struct ReplayState {
+    speed: ReplaySpeed,
+
+    paused: bool,
+
+    looping: bool,
+
+    // Receive time to continue at, consumed by the endpoint
+    seek_to: Option<u64>,
+
+    // Receive times of the first and last collection of the capture
+    start_us: u64,
+    end_us: u64,
+
+    position_us: u64,
+
+    finished: bool,
+}
+
+/// Transport controls of a replay endpoint, cheap to clone and shared with the frontends.
+#[derive(Clone)]
+pub struct ReplayControl {
+    state: Arc<Mutex<ReplayState>>,
+
+    // Wakes the endpoint after every change, a change made while it isn't waiting is kept as permit
+    changed: Arc<Notify>,
+}
+
+/// Plays back a capture file, emitting the recorded collections with their original timing scaled by the speed.
+pub struct ReplayEndpoint {
+    destination: String,
+
+    path: PathBuf,
+
+    reader: CaptureReader,
+
+    control: ReplayControl,
+
+    // Read but not yet emitted, kept here so a cancelled `recv_msg` doesn't lose it
+    pending: Option<CapturedCollection>,
+
+    // Wall clock time and receive time playback is timed against, reset on every control change
+    anchor: Option<(Instant, u64)>,
+
+    // Added to the collection timestamps, each round of a looped replay continues after the previous one
+    timestamp_offset_us: u64,
+
+    // Timestamp the first collection of the next round is moved to
+    next_round_us: Option<u64>,
+
+    // Timestamp of the last emitted collection, offset included
+    last_timestamp_us: u64,
+}
+
+impl ReplaySpeed {
+    pub fn faster(&self) -> ReplaySpeed {
+        match self {
+            ReplaySpeed::Factor(factor) => match SPEED_STEPS.iter().find(|step| *step > factor) {
+                Some(step) => ReplaySpeed::Factor(*step),
+                None => ReplaySpeed::AsFastAsPossible,
+            },
+            ReplaySpeed::AsFastAsPossible => ReplaySpeed::AsFastAsPossible,
+        }
+    }
+
+    pub fn slower(&self) -> ReplaySpeed {
+        match self {
+            ReplaySpeed::Factor(factor) => {
+                ReplaySpeed::Factor(SPEED_STEPS.iter().rev().find(|step| *step < factor).cloned().unwrap_or(SPEED_STEPS[0]))
+            }
+            ReplaySpeed::AsFastAsPossible => ReplaySpeed::Factor(SPEED_STEPS[SPEED_STEPS.len() - 1]),
+        }
+    }
+}
+
+impl Display for ReplaySpeed {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ReplaySpeed::Factor(factor) => write!(f, "x{}", factor),
+            ReplaySpeed::AsFastAsPossible => write!(f, "max"),
+        }
+    }
+}
+
+fn format_position(position: Duration) -> String {
+    let secs = position.as_secs();
+
+    if secs >= 3600 {
+        format!("{}:{:02}:{:02}", secs / 3600, (secs / 60) % 60, secs % 60)
+    } else {
+        format!("{:02}:{:02}", secs / 60, secs % 60)
+    }
+}
+
+/// Formats like `paused 01:05/12:30 x2 loop`.
+impl Display for ReplayStatus {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let state = if self.finished {
+            "finished"
+        } else if self.paused {
+            "paused"
+        } else {
+            "playing"
+        };
+
+        write!(f, "{} {}/{} {}", state, format_position(self.position), format_position(self.duration), self.speed)?;
+
+        if self.looping {
+            write!(f, " loop")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Parses a speed factor like `2` or `0.5`, `max` replays as fast as possible.
+impl FromStr for ReplaySpeed {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+
+        if s == "max" {
+            return Ok(ReplaySpeed::AsFastAsPossible);
+        }
+
+        match f64::from_str(s.strip_prefix('x').unwrap_or(s)) {
+            Ok(factor) if factor > 0.0 && factor.is_finite() => Ok(ReplaySpeed::Factor(factor)),
+            _ => Err(format!("invalid replay speed '{}', expected a positive factor or max", s)),
+        }
+    }
+}
+
+impl Default for ReplayOptions {
+    fn default() -> Self {
+        ReplayOptions {
+            speed: ReplaySpeed::Factor(1.0),
+            looping: false,
+        }
+    }
+}
+
+fn duration_between(from_us: u64, to_us: u64) -> Duration {
+    Duration::from_micros(to_us.saturating_sub(from_us))
+}
+
+impl ReplayControl {
+    fn new(options: &ReplayOptions, start_us: u64, end_us: u64) -> ReplayControl {
+        ReplayControl {
+            state: Arc::new(Mutex::new(ReplayState {
+                speed: options.speed,
+                paused: false,
+                looping: options.looping,
+                seek_to: None,
+                start_us,
+                end_us,
+                position_us: start_us,
+                finished: false,
+            })),
+            changed: Arc::new(Notify::new()),
+        }
+    }
+
+    fn modify(&self, f: impl FnOnce(&mut ReplayState)) {
+        f(&mut self.state.lock().unwrap());
+
+        self.changed.notify_one();
+    }
+
+    pub fn get_status(&self) -> ReplayStatus {
+        let state = self.state.lock().unwrap();
+
+        ReplayStatus {
+            position: duration_between(state.start_us, state.position_us),
+            duration: duration_between(state.start_us, state.end_us),
+            speed: state.speed,
+            paused: state.paused,
+            looping: state.looping,
+            finished: state.finished,
+        }
+    }
+
+    pub fn set_speed(&self, speed: ReplaySpeed) {
+        self.modify(|state| state.speed = speed);
+    }
+
+    pub fn set_paused(&self, paused: bool) {
+        self.modify(|state| state.paused = paused);
+    }
+
+    pub fn toggle_paused(&self) {
+        self.modify(|state| state.paused = !state.paused);
+    }
+
+    pub fn set_looping(&self, looping: bool) {
+        self.modify(|state| state.looping = looping);
+    }
+
+    /// Continues playback at `position` relative to the start of the capture, clamped to its duration.
+    pub fn seek(&self, position: Duration) {
+        self.modify(|state| {
+            let target = state.start_us.saturating_add(position.as_micros() as u64).min(state.end_us);
+
+            state.seek_to = Some(target);
+            state.position_us = target;
+            state.finished = false;
+        });
+    }
+
+    /// Seeks `offset_s` seconds forward, or backward if negative, from the current position.
+    pub fn seek_relative(&self, offset_s: f64) {
+        let position = self.get_status().position.as_secs_f64();
+
+        self.seek(Duration::from_secs_f64((position + offset_s).max(0.0)));
+    }
+}
+
+impl From<CaptureError> for EndpointError {
+    fn from(error: CaptureError) -> Self {
+        EndpointError::new(&error.msg)
+    }
+}
+
+impl ReplayEndpoint {
+    /// Opens the capture at `path`, playback starts with the first call to `recv_msg`.
+    pub fn open(path: &Path, options: ReplayOptions) -> Result<ReplayEndpoint, EndpointError> {
+        let mut reader = CaptureReader::open(path)
+            .map_err(|err| EndpointError::new(&format!("Could not open capture {}: {}", path.display(), err)))?;
+
+        let (start_us, end_us) = reader.get_time_range()?.unwrap_or((0, 0));
+
+        Ok(ReplayEndpoint {
+            destination: format!("replay://{}", path.display()),
+            path: path.to_path_buf(),
+            reader,
+            control: ReplayControl::new(&options, start_us, end_us),
+            pending: None,
+            anchor: None,
+            timestamp_offset_us: 0,
+            next_round_us: None,
+            last_timestamp_us: 0,
+        })
+    }
+
+    pub fn get_control(&self) -> ReplayControl {
+        self.control.clone()
+    }
+
+    // Applies a pending seek and returns speed and pause state
+    fn take_control_state(&mut self) -> Result<(ReplaySpeed, bool, bool), CaptureError> {
+        let (seek_to, speed, paused, looping) = {
+            let mut state = self.control.state.lock().unwrap();
+
+            (state.seek_to.take(), state.speed, state.paused, state.looping)
+        };
+
+        if let Some(seek_to) = seek_to {
+            self.reader.seek(seek_to)?;
+
+            self.pending = None;
+            self.anchor = None;
+        }
+
+        Ok((speed, paused, looping))
+    }
+
+    fn set_position(&self, position_us: u64, finished: bool) {
+        let mut state = self.control.state.lock().unwrap();
+
+        state.position_us = position_us;
+        state.finished = finished;
+    }
+}
+
+#[async_trait]
+impl MetricEndpoint for ReplayEndpoint {
+    async fn connect(&mut self) -> Result<(), EndpointError> {
+        // The capture was already opened, there is nothing to connect to
+        Ok(())
+    }
+
+    async fn try_reconnect(&mut self) -> Result<(), EndpointError> {
+        self.reader = CaptureReader::open(&self.path)?;
+
+        let position_us = self.control.state.lock().unwrap().position_us;
+
+        self.reader.seek(position_us)?;
+
+        self.pending = None;
+        self.anchor = None;
+
+        Ok(())
+    }
+
+    async fn recv_msg(&mut self) -> Result<MetricCollection, EndpointError> {
+        let changed = Arc::clone(&self.control.changed);
+
+        loop {
+            let (speed, paused, looping) = self.take_control_state()?;
+
+            if paused {
+                self.anchor = None;
+
+                changed.notified().await;
+
+                continue;
+            }
+
+            if self.pending.is_none() {
+                self.pending = self.reader.next_collection()?;
+            }
+
+            let recv_time_us = match &self.pending {
+                Some(captured) => captured.recv_time_us,
+                // A capture without collections would be looped over without ever waiting
+                None if looping && !self.reader.get_index().is_empty() => {
+                    let (start_us, position_us) = {
+                        let state = self.control.state.lock().unwrap();
+
+                        (state.start_us, state.position_us)
+                    };
+
+                    self.reader.seek(start_us)?;
+
+                    // Replaying the original timestamps would look like a clock going backwards
+                    self.next_round_us = Some(self.last_timestamp_us + LOOP_GAP.as_micros() as u64);
+
+                    // The next round keeps the timing, it follows the last collection after LOOP_GAP
+                    if let (Some((anchor_instant, anchor_us)), ReplaySpeed::Factor(factor)) = (self.anchor, speed) {
+                        let round_end = anchor_instant + duration_between(anchor_us, position_us).div_f64(factor);
+
+                        self.anchor = Some((round_end + LOOP_GAP.div_f64(factor), start_us));
+                    }
+
+                    continue;
+                }
+                None => {
+                    let position_us = self.control.state.lock().unwrap().position_us;
+
+                    self.set_position(position_us, true);
+
+                    changed.notified().await;
+
+                    continue;
+                }
+            };
+
+            match speed {
+                ReplaySpeed::Factor(factor) => {
+                    let (anchor_instant, anchor_us) = *self.anchor.get_or_insert((Instant::now(), recv_time_us));
+
+                    // Looping or seeking backwards can go before the anchor, that collection is due at once
+                    let due = anchor_instant + duration_between(anchor_us, recv_time_us).div_f64(factor);
+
+                    select! {
+                        _ = time::sleep_until(due) => {},
+                        _ = changed.notified() => {
+                            // Continue timed from the last emitted collection with the new settings
+                            let position_us = self.control.state.lock().unwrap().position_us;
+
+                            self.anchor = Some((Instant::now(), position_us.min(recv_time_us)));
+
+                            continue;
+                        }
+                    }
+                }
+                ReplaySpeed::AsFastAsPossible => {
+                    self.anchor = None;
+
+                    // Gives the other tasks, e.g. the frontends reading the aggregator, a chance to run
+                    tokio::task::yield_now().await;
+                }
+            }
+
+            let captured = self.pending.take().unwrap();
+
+            self.set_position(captured.recv_time_us, false);
+
+            if let Some(next_round_us) = self.next_round_us.take() {
+                self.timestamp_offset_us = next_round_us.saturating_sub(captured.collection.get_timestamp());
+            }
+
+            let collection = captured.collection.shift_timestamps(self.timestamp_offset_us);
+
+            self.last_timestamp_us = collection.get_timestamp();
+
+            return Ok(collection);
+        }
+    }
+
+    fn get_destination(&self) -> &str {
+        &self.destination
+    }
+
+    fn detects_stalls(&self) -> bool {
+        // Pauses and gaps stretched by a slow speed are expected, silence doesn't mean the capture is gone
+        false
+    }
+}
+
+
+#[test]
+fn replay_speed_parse_test01() {
+    assert_eq!(ReplaySpeed::from_str("2").unwrap(), ReplaySpeed::Factor(2.0));
+    assert_eq!(ReplaySpeed::from_str("x0.5").unwrap(), ReplaySpeed::Factor(0.5));
+    assert_eq!(ReplaySpeed::from_str("max").unwrap(), ReplaySpeed::AsFastAsPossible);
+    assert!(ReplaySpeed::from_str("0").is_err());
+    assert!(ReplaySpeed::from_str("-1").is_err());
+
+    assert_eq!(ReplaySpeed::Factor(1.0).faster(), ReplaySpeed::Factor(2.0));
+    assert_eq!(ReplaySpeed::Factor(3.0).slower(), ReplaySpeed::Factor(2.0));
+    assert_eq!(ReplaySpeed::Factor(64.0).faster(), ReplaySpeed::AsFastAsPossible);
+    assert_eq!(ReplaySpeed::AsFastAsPossible.slower(), ReplaySpeed::Factor(64.0));
+    assert_eq!(ReplaySpeed::Factor(0.125).slower(), ReplaySpeed::Factor(0.125));
+}
+
+#[test]
+fn replay_endpoint_playback_test01() {
+    use crate::capture::recorder::CaptureRecorder;
+
+    let path = std::env::temp_dir().join(format!("replay_endpoint_playback_test01_{}.cap", std::process::id()));
+
+    let _ = std::fs::remove_file(&path);
+
+    let mut recorder = CaptureRecorder::create(&path).unwrap();
+
+    for timestamp in 0..3 {
+        recorder.record(&MetricCollection::new(String::from("tcp://host-a:5555"), String::from("metrics"), timestamp, Vec::new())).unwrap();
+    }
+
+    recorder.finish().unwrap();
+
+    let runtime = tokio::runtime::Builder::new_current_thread().enable_time().build().unwrap();
+
+    runtime.block_on(async {
+        let options = ReplayOptions { speed: ReplaySpeed::AsFastAsPossible, looping: true };
+
+        let mut endpoint = ReplayEndpoint::open(&path, options).unwrap();
+
+        let control = endpoint.get_control();
+
+        let mut timestamps = Vec::new();
+
+        for _ in 0..5 {
+            timestamps.push(endpoint.recv_msg().await.unwrap().get_timestamp());
+        }
+
+        // Every round continues one LOOP_GAP after the previous one
+        assert_eq!(timestamps, vec![0, 1, 2, 1000002, 1000003]);
+
+        // Seeking keeps the offset of the current round
+        control.seek(Duration::ZERO);
+        control.set_looping(false);
+
+        assert_eq!(endpoint.recv_msg().await.unwrap().get_timestamp(), 1000002);
+
+        // Nothing arrives while paused
+        control.set_paused(true);
+
+        assert!(time::timeout(Duration::from_millis(50), endpoint.recv_msg()).await.is_err());
+
+        control.set_paused(false);
+
+        assert_eq!(endpoint.recv_msg().await.unwrap().get_timestamp(), 1000003);
+        assert_eq!(endpoint.recv_msg().await.unwrap().get_timestamp(), 1000004);
+
+        assert!(time::timeout(Duration::from_millis(50), endpoint.recv_msg()).await.is_err());
+        assert!(control.get_status().finished);
+    });
+
+    std::fs::remove_file(&path).unwrap();
+}
@@ -1,15 +1,166 @@
 use std::cell::Cell;
+use std::collections::BTreeSet;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use async_trait::async_trait;
 use crate::common::message::MetricCollection;
 use crate::common::metric::{Metric};
+use tokio::select;
+use tokio::sync::Notify;
 use tokio::sync::mpsc::error::TrySendError;
 use zeromq::{Socket, SocketRecv, ZmqError};
-use crate::source::EndpointError;
+use crate::source::{EndpointError, MetricEndpoint};
 
-const SUB_NAME_METRICS: &str = "metrics";
+// Subscribed to unless other topics are configured
+pub const SUB_NAME_METRICS: &str = "metrics";
+
+// URL schemes served by ZmqEndpoint
+pub const ZMQ_SCHEMES: [&str; 2] = ["tcp", "zmq"];
+
+/// Topic a ZMQ endpoint subscribes to. `metrics` only accepts that very topic, `metrics*` every topic
+/// starting with `metrics` and `*` all topics.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TopicFilter {
+    topic: String,
+
+    // Accepts every topic starting with `topic`
+    prefix: bool,
+}
+
+/// Topics of a connected ZMQ endpoint, cheap to clone and shared with the frontends.
+#[derive(Clone)]
+pub struct SubscriptionControl {
+    topics: Arc<Mutex<Vec<TopicFilter>>>,
+
+    // Wakes the endpoint after every change, a change made while it isn't waiting is kept as permit
+    changed: Arc<Notify>,
+}
 
 pub struct ZmqEndpoint {
     destination: String,
     socket: Cell<zeromq::SubSocket>,
+    control: SubscriptionControl,
+    // Prefixes the socket is subscribed to, ZMQ itself only filters by prefix
+    subscribed: BTreeSet<String>,
+}
+
+impl TopicFilter {
+    pub fn new(topic: &str) -> TopicFilter {
+        TopicFilter {
+            topic: topic.to_string(),
+            prefix: false,
+        }
+    }
+
+    pub fn with_prefix(prefix: &str) -> TopicFilter {
+        TopicFilter {
+            topic: prefix.to_string(),
+            prefix: true,
+        }
+    }
+
+    pub fn default_topics() -> Vec<TopicFilter> {
+        vec![TopicFilter::new(SUB_NAME_METRICS)]
+    }
+
+    pub fn get_topic(&self) -> &str {
+        &self.topic
+    }
+
+    pub fn is_prefix(&self) -> bool {
+        self.prefix
+    }
+
+    pub fn matches(&self, topic: &str) -> bool {
+        if self.prefix {
+            topic.starts_with(&self.topic)
+        } else {
+            topic == self.topic
+        }
+    }
+}
+
+impl Display for TopicFilter {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", self.topic, if self.prefix { "*" } else { "" })
+    }
+}
+
+impl FromStr for TopicFilter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+
+        if s.is_empty() {
+            return Err(String::from("empty topic, use * to subscribe to all topics"));
+        }
+
+        let (topic, prefix) = match s.strip_suffix('*') {
+            Some(topic) => (topic, true),
+            None => (s, false),
+        };
+
+        if topic.contains('*') {
+            return Err(format!("'*' is only allowed at the end of topic '{}'", s));
+        }
+
+        Ok(TopicFilter {
+            topic: topic.to_string(),
+            prefix,
+        })
+    }
+}
+
+impl SubscriptionControl {
+    fn new(topics: Vec<TopicFilter>) -> SubscriptionControl {
+        SubscriptionControl {
+            topics: Arc::new(Mutex::new(topics)),
+            changed: Arc::new(Notify::new()),
+        }
+    }
+
+    pub fn get_topics(&self) -> Vec<TopicFilter> {
+        self.topics.lock().unwrap().clone()
+    }
+
+    /// Returns false if the endpoint is subscribed to `topic` already.
+    pub fn subscribe(&self, topic: TopicFilter) -> bool {
+        let mut topics = self.topics.lock().unwrap();
+
+        if topics.contains(&topic) {
+            return false;
+        }
+
+        topics.push(topic);
+
+        self.changed.notify_one();
+
+        true
+    }
+
+    /// Returns false if the endpoint isn't subscribed to `topic`.
+    pub fn unsubscribe(&self, topic: &TopicFilter) -> bool {
+        let mut topics = self.topics.lock().unwrap();
+
+        let len = topics.len();
+
+        topics.retain(|t| t != topic);
+
+        if topics.len() == len {
+            return false;
+        }
+
+        self.changed.notify_one();
+
+        true
+    }
+
+    fn matches(&self, topic: &str) -> bool {
+        self.topics.lock().unwrap().iter().any(|t| t.matches(topic))
+    }
 }
 
 impl TryFrom<&String> for ZmqEndpoint {
@@ -21,6 +172,8 @@ impl TryFrom<&String> for ZmqEndpoint {
         Ok(ZmqEndpoint {
             destination: dst.clone(),
             socket,
+            control: SubscriptionControl::new(TopicFilter::default_topics()),
+            subscribed: BTreeSet::new(),
         })
     }
 }
@@ -35,61 +188,72 @@ impl MessageSink for tokio::sync::mpsc::Sender<MetricCollection> {
     }
 }
 
-impl ZmqEndpoint {
-    pub fn new(dst: &str) -> Result<Self, zeromq::ZmqError> {
-        Self::try_from(&format!("tcp://{}", dst))
+impl From<ZmqError> for EndpointError {
+    fn from(error: ZmqError) -> Self {
+        EndpointError::new(&error.to_string())
     }
+}
 
-    pub async fn connect(&mut self) -> Result<(), zeromq::ZmqError> {
+#[async_trait]
+impl MetricEndpoint for ZmqEndpoint {
+    async fn connect(&mut self) -> Result<(), EndpointError> {
         self.socket.get_mut().connect(&self.destination).await?;
 
-        self.socket.get_mut().subscribe(SUB_NAME_METRICS).await?;
+        self.subscribed.clear();
+
+        self.sync_subscriptions().await?;
 
         Ok(())
     }
 
-    pub async fn try_reconnect(&mut self) -> Result<(), zeromq::ZmqError> {
+    async fn try_reconnect(&mut self) -> Result<(), EndpointError> {
         let new_socket = zeromq::SubSocket::new();
 
         self.socket.replace(new_socket);
 
-        self.socket.get_mut().connect(&self.destination).await
-    }
-
-    fn convert_to_metrics(json_obj: json::JsonValue) -> Result<Vec<Metric>, EndpointError> {
-        let values_entry = &json_obj["values"];
+        self.socket.get_mut().connect(&self.destination).await?;
 
-        let mut local_metrics = Vec::new();
+        // A fresh socket has no subscriptions, without this nothing would be received anymore
+        self.subscribed.clear();
 
-        for value_entry in values_entry.members() {
-            if let Ok(metric) = Metric::try_from(value_entry) {
-                local_metrics.push(metric);
-            }
-        }
+        self.sync_subscriptions().await?;
 
-        Ok(local_metrics)
+        Ok(())
     }
 
-    pub async fn recv_msg(&mut self) -> Result<MetricCollection, EndpointError> {
-        let msg_result = self.socket.get_mut().recv().await;
+    async fn recv_msg(&mut self) -> Result<MetricCollection, EndpointError> {
+        let changed = Arc::clone(&self.control.changed);
 
-        if msg_result.is_err() {
-            return Err(EndpointError::new("Receive failed"));
-        }
+        let msg = loop {
+            let msg_result = select! {
+                msg_result = self.socket.get_mut().recv() => msg_result,
+                _ = changed.notified() => {
+                    self.sync_subscriptions().await?;
+
+                    continue;
+                }
+            };
 
-        let msg = msg_result.unwrap();
+            let msg = msg_result.map_err(|_| EndpointError::new("Receive failed"))?;
 
-        if msg.is_empty() {
-            return Err(EndpointError::new("Empty message received"));
-        }
+            if msg.is_empty() {
+                return Err(EndpointError::decode("Empty message received"));
+            }
+
+            // Prefixes subscribed for exact topics let through longer topics as well
+            if self.control.matches(&String::from_utf8_lossy(msg.get(0).unwrap())) {
+                break msg;
+            }
+        };
 
-        let pub_name: String = String::from_utf8(msg.get(0).unwrap().to_vec()).unwrap();
+        let pub_name: String = String::from_utf8_lossy(msg.get(0).unwrap()).to_string();
 
-        return match msg.get(1) {
+        match msg.get(1) {
             Some(msg_data) => {
-                let s = String::from_utf8(msg_data.to_vec());
+                let s = String::from_utf8(msg_data.to_vec())
+                    .map_err(|_| EndpointError::decode("message data is not valid UTF-8"))?;
 
-                let json_obj = json::parse(&s.unwrap());
+                let json_obj = json::parse(&s);
 
                 match json_obj {
                     Ok(json_obj) => {
@@ -97,27 +261,111 @@ impl ZmqEndpoint {
 
                         let timestamp = timestamp_entry.as_u64().unwrap_or(0);
 
-                        let converted_metrics = ZmqEndpoint::convert_to_metrics(json_obj);
-
-                        if converted_metrics.is_err() {
-                            return Err(converted_metrics.err().unwrap());
-                        }
+                        let converted_metrics = ZmqEndpoint::convert_to_metrics(json_obj)?;
 
                         Ok(MetricCollection::new(
                             self.destination.clone(),
                             pub_name,
                             timestamp,
-                            converted_metrics.unwrap(),
+                            converted_metrics,
                         ))
                     }
                     Err(json_err) => {
-                        Err(EndpointError::new(&json_err.to_string()))
+                        Err(EndpointError::decode(&json_err.to_string()))
                     }
                 }
             }
             None => {
-                Err(EndpointError::new("no message data"))
+                Err(EndpointError::decode("no message data"))
             }
-        };
+        }
+    }
+
+    fn get_destination(&self) -> &str {
+        &self.destination
+    }
+
+    fn get_subscription_control(&self) -> Option<SubscriptionControl> {
+        Some(self.control.clone())
     }
 }
+
+impl ZmqEndpoint {
+    /// Accepts `tcp://host:port` as well as `zmq://host:port`, the latter being an alias for tcp. Like in the
+    /// registry, the scheme is matched case-insensitively.
+    pub fn new(dst: &str) -> Result<Self, zeromq::ZmqError> {
+        match dst.split_once("://") {
+            Some((scheme, addr)) if ZMQ_SCHEMES.iter().any(|s| s.eq_ignore_ascii_case(scheme)) => {
+                Self::try_from(&format!("tcp://{}", addr))
+            }
+            _ => Self::try_from(&dst.to_string()),
+        }
+    }
+
+    pub fn with_topics(dst: &str, topics: Vec<TopicFilter>) -> Result<Self, zeromq::ZmqError> {
+        let mut endpoint = Self::new(dst)?;
+
+        endpoint.control = SubscriptionControl::new(topics);
+
+        Ok(endpoint)
+    }
+
+    // Subscribes and unsubscribes the socket so it matches the topics of the control
+    async fn sync_subscriptions(&mut self) -> Result<(), ZmqError> {
+        let prefixes: BTreeSet<String> = self.control.get_topics().iter().map(|t| t.get_topic().to_string()).collect();
+
+        for prefix in self.subscribed.difference(&prefixes) {
+            self.socket.get_mut().unsubscribe(prefix).await?;
+        }
+
+        for prefix in prefixes.difference(&self.subscribed) {
+            self.socket.get_mut().subscribe(prefix).await?;
+        }
+
+        self.subscribed = prefixes;
+
+        Ok(())
+    }
+
+    fn convert_to_metrics(json_obj: json::JsonValue) -> Result<Vec<Metric>, EndpointError> {
+        let values_entry = &json_obj["values"];
+
+        let mut local_metrics = Vec::new();
+
+        for value_entry in values_entry.members() {
+            if let Ok(metric) = Metric::try_from(value_entry) {
+                local_metrics.push(metric);
+            }
+        }
+
+        Ok(local_metrics)
+    }
+}
+
+
+#[test]
+fn topic_filter_test01() {
+    let exact = TopicFilter::from_str("metrics").unwrap();
+    let prefix = TopicFilter::from_str("metrics/*").unwrap();
+    let all = TopicFilter::from_str("*").unwrap();
+
+    assert!(exact.matches("metrics"));
+    assert!(!exact.matches("metrics/cpu"));
+    assert!(prefix.matches("metrics/cpu"));
+    assert!(!prefix.matches("metrics"));
+    assert!(all.matches("anything"));
+
+    assert_eq!(prefix.to_string(), "metrics/*");
+    assert_eq!(all, TopicFilter::with_prefix(""));
+    assert!(TopicFilter::from_str("met*rics").is_err());
+    assert!(TopicFilter::from_str(" ").is_err());
+
+    let control = SubscriptionControl::new(TopicFilter::default_topics());
+
+    assert!(control.subscribe(prefix.clone()));
+    assert!(!control.subscribe(prefix.clone()));
+    assert!(control.matches("metrics/cpu"));
+    assert!(control.unsubscribe(&prefix));
+    assert!(!control.unsubscribe(&prefix));
+    assert!(!control.matches("metrics/cpu"));
+}
@@ -7,27 +7,65 @@ use std::borrow::BorrowMut;
 use std::fmt::{Display, Formatter};
 use std::io;
 use std::io::{Error, Stdout};
+use std::path::PathBuf;
+use std::str::FromStr;
 use std::time::{Duration, Instant};
 use tui::backend::CrosstermBackend;
 use tui::layout::{Constraint, Direction, Layout};
 use tui::style::{Color, Modifier, Style};
-use tui::text::Span;
-use tui::widgets::{Axis, Block, Borders, Cell, Chart, Dataset, GraphType, Row, Table, TableState};
+use tui::text::{Span, Spans};
+use tui::widgets::{Axis, Block, Borders, Cell, Chart, Dataset, GraphType, List, ListItem, Paragraph, Row, Table, TableState};
 use tui::{Frame, Terminal};
 
+use crate::aggregator::aggregator::MetricKey;
+use crate::aggregator::alert::AlertState;
+use crate::aggregator::user_rule::UserMetricRule;
 use crate::backend::{Backend, MetricAdapter};
+use crate::common::label_view::{self, LabelFilter};
 use crate::common::metric::Metric;
-use crate::frontend::MetricFrontend;
+use crate::config::TuiConfig;
+use crate::event_log::{BackendEvent, EventSeverity};
+use crate::export::{ExportFormat, ExportOptions, ExportScope};
+use crate::frontend::{MetricFrontend, HISTORY_RANGES};
+use crate::source::connection::ConnectionState;
+use crate::source::replay_endpoint::{ReplayControl, ReplayStatus};
+use crate::source::zmq_endpoint::TopicFilter;
 
 pub struct TerminalFrontendOptions {}
 
+const MAX_LOG_LINES: usize = 128;
+
+// Seconds the replay position moves with '[' and ']'
+const REPLAY_SEEK_STEP_S: f64 = 10.0;
+
 #[derive(Debug)]
 pub struct FrontendError {
     msg: String,
 }
 
 struct MetricTableRowState {
-    cells: [String; 3],
+    key: MetricKey,
+
+    cells: Vec<String>,
+
+    // Index of the value cell, the unit follows right after it. None for pivot rows
+    value_column: Option<usize>,
+
+    // Keys of all cells of a pivot row, empty otherwise
+    pivot_keys: Vec<MetricKey>,
+
+    // Most severe active alert on the row's metrics
+    alert_state: Option<AlertState>,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum InputMode {
+    LabelFilter,
+    AddRule,
+    RemoveRule,
+    Export,
+    Subscribe,
+    Unsubscribe,
 }
 
 struct UiState {
@@ -44,11 +82,54 @@ struct UiState {
     current_metric_history_time_range: (f64, f64),
 
     graph_active: bool,
+
+    // Index into HISTORY_RANGES
+    history_range_idx: usize,
+
+    sources: Vec<String>,
+
+    source_filter: Option<String>,
+
+    connection_states: Vec<(String, ConnectionState)>,
+
+    subscriptions: Vec<(String, Vec<TopicFilter>)>,
+
+    replay_states: Vec<(String, ReplayStatus)>,
+
+    events: Vec<BackendEvent>,
+
+    log_active: bool,
+
+    label_keys: Vec<String>,
+
+    label_filter: LabelFilter,
+
+    // Some while a line of input is being edited in the status bar
+    input: Option<(InputMode, String)>,
+
+    input_error: Option<String>,
+
+    user_rules: Vec<(usize, UserMetricRule)>,
+
+    // Shows the user rules instead of the event log
+    rules_active: bool,
+
+    group_label: Option<String>,
+
+    pivot_label: Option<String>,
+
+    header: Vec<String>,
+
+    column_widths: Vec<Constraint>,
+
+    // Points of the history chart, longer ranges are shown from rollups
+    plot_points: usize,
 }
 
 pub struct TerminalFrontend {
     terminal: Terminal<CrosstermBackend<Stdout>>,
     backend: Backend,
+    config: TuiConfig,
 }
 
 impl From<std::io::Error> for FrontendError {
@@ -68,14 +149,15 @@ impl Display for FrontendError {
 impl std::error::Error for FrontendError {}
 
 impl MetricAdapter for MetricTableRowState {
-    fn get_name(&self) -> &String {
-        &self.cells[0]
+    fn get_key(&self) -> &MetricKey {
+        &self.key
     }
 
     fn update_current(&mut self, metric: &Metric) {
-        self.cells[0] = metric.get_label().to_string();
-        self.cells[1] = metric.get_value().to_string();
-        self.cells[2] = metric.get_unit().to_string();
+        if let Some(value_column) = self.value_column {
+            self.cells[value_column] = metric.get_value().to_string();
+            self.cells[value_column + 1] = metric.get_unit().to_string();
+        }
     }
 }
 
@@ -88,14 +170,20 @@ impl MetricAdapter for MetricTableRowState {
 //     }
 // }
 
-impl<'a> Into<Row<'a>> for &MetricTableRowState {
-    fn into(self) -> Row<'a> {
-        Row::new(self.cells.clone())
+impl<'a> From<&MetricTableRowState> for Row<'a> {
+    fn from(row_state: &MetricTableRowState) -> Self {
+        let row = Row::new(row_state.cells.clone());
+
+        match row_state.alert_state {
+            Some(AlertState::Firing) => row.style(Style::default().fg(Color::Red).add_modifier(Modifier::BOLD)),
+            Some(AlertState::Pending) => row.style(Style::default().fg(Color::Yellow)),
+            _ => row,
+        }
     }
 }
 
 impl UiState {
-    pub fn new() -> UiState {
+    pub fn new(plot_points: usize) -> UiState {
         UiState {
             selection_id: 0usize,
             table_state: TableState::default(),
@@ -104,23 +192,182 @@ impl UiState {
             current_metric_history_range: (1.0f64, 1.0f64),
             current_metric_history_time_range: (0.0f64, 0.0f64),
             graph_active: false,
+            history_range_idx: 0,
+            sources: Vec::new(),
+            source_filter: None,
+            connection_states: Vec::new(),
+            subscriptions: Vec::new(),
+            replay_states: Vec::new(),
+            events: Vec::new(),
+            log_active: true,
+            label_keys: Vec::new(),
+            label_filter: LabelFilter::default(),
+            input: None,
+            input_error: None,
+            user_rules: Vec::new(),
+            rules_active: false,
+            group_label: None,
+            pivot_label: None,
+            header: Vec::new(),
+            column_widths: Vec::new(),
+            plot_points,
         }
     }
 
+    // Steps from None through all options and back to None
+    fn cycle_option(current: &Option<String>, options: &[String]) -> Option<String> {
+        let next_idx = match current {
+            None => 0,
+            Some(current) => options.iter().position(|s| s == current).map(|idx| idx + 1).unwrap_or(0),
+        };
+
+        options.get(next_idx).cloned()
+    }
+
+    /// Cycles through showing all sources and showing one source at a time.
+    pub fn cycle_source_filter(&mut self) {
+        self.source_filter = Self::cycle_option(&self.source_filter, &self.sources);
+
+        self.select_none();
+    }
+
+    pub fn cycle_group_label(&mut self) {
+        self.group_label = Self::cycle_option(&self.group_label, &self.label_keys);
+
+        self.select_none();
+    }
+
+    pub fn cycle_pivot_label(&mut self) {
+        self.pivot_label = Self::cycle_option(&self.pivot_label, &self.label_keys);
+
+        self.select_none();
+    }
+
+    pub fn begin_input(&mut self, mode: InputMode) {
+        let initial = match mode {
+            InputMode::LabelFilter => self.label_filter.to_string(),
+            InputMode::AddRule | InputMode::RemoveRule | InputMode::Subscribe | InputMode::Unsubscribe => String::new(),
+            InputMode::Export => String::from("metrics.csv"),
+        };
+
+        self.input = Some((mode, initial));
+        self.input_error = None;
+
+        // Rules are listed while they are edited
+        if mode == InputMode::AddRule || mode == InputMode::RemoveRule {
+            self.rules_active = true;
+        }
+    }
+
+    /// Applies the edited input, it stays open if it doesn't parse.
+    pub fn apply_input(&mut self, metric_backend: &Backend) {
+        let result = match &self.input {
+            Some((InputMode::LabelFilter, input)) => LabelFilter::try_from(input.as_str()).map(|label_filter| {
+                self.label_filter = label_filter;
+
+                self.select_none();
+            }),
+            Some((InputMode::AddRule, input)) => UserMetricRule::from_str(input).map(|user_rule| {
+                metric_backend.add_user_rule(user_rule);
+            }),
+            Some((InputMode::RemoveRule, input)) => match usize::from_str(input.trim()) {
+                Ok(user_rule_id) if metric_backend.remove_user_rule(user_rule_id) => Ok(()),
+                _ => Err(format!("no rule with id '{}'", input.trim())),
+            },
+            Some((InputMode::Export, input)) => self.get_export_options(input).and_then(|(path, export_options)| {
+                metric_backend.export_metrics(&path, &export_options).map(|_| ()).map_err(|err| err.msg)
+            }),
+            Some((InputMode::Subscribe, input)) => self.change_subscriptions(input, metric_backend, true),
+            Some((InputMode::Unsubscribe, input)) => self.change_subscriptions(input, metric_backend, false),
+            None => Ok(()),
+        };
+
+        match result {
+            Ok(()) => {
+                self.input = None;
+                self.input_error = None;
+            }
+            Err(msg) => self.input_error = Some(msg),
+        }
+    }
+
+    /// Parses `<topic> [destination]` and subscribes or unsubscribes the endpoint with that destination,
+    /// all endpoints with topics without destination.
+    fn change_subscriptions(&self, input: &str, metric_backend: &Backend, subscribe: bool) -> Result<(), String> {
+        let mut tokens = input.split_whitespace();
+
+        let topic = TopicFilter::from_str(tokens.next().unwrap_or_default())?;
+
+        let destinations: Vec<&String> = match tokens.next() {
+            Some(destination) => self.subscriptions.iter().map(|(d, _)| d).filter(|d| *d == destination).collect(),
+            None => self.subscriptions.iter().map(|(d, _)| d).collect(),
+        };
+
+        if destinations.is_empty() {
+            return Err(String::from("no such endpoint with topics"));
+        }
+
+        for destination in destinations {
+            let result = if subscribe {
+                metric_backend.subscribe_topic(destination, topic.clone())
+            } else {
+                metric_backend.unsubscribe_topic(destination, &topic)
+            };
+
+            result.map_err(|err| err.msg)?;
+        }
+
+        Ok(())
+    }
+
+    /// Parses `<path> [csv-wide|csv-long|jsonl] [current]`. Exports the selected metric or, without
+    /// selection, all metrics in the table, by default their history over the range of the chart.
+    fn get_export_options(&self, input: &str) -> Result<(PathBuf, ExportOptions), String> {
+        let mut tokens = input.split_whitespace();
+
+        let path = PathBuf::from(tokens.next().ok_or_else(|| String::from("missing file name"))?);
+
+        let mut format = ExportFormat::from_path(&path).unwrap_or(ExportFormat::CsvLong);
+        let mut scope = ExportScope::History(HISTORY_RANGES[self.history_range_idx].1);
+
+        for token in tokens {
+            if token == "current" {
+                scope = ExportScope::Current;
+            } else {
+                format = ExportFormat::from_str(token)?;
+            }
+        }
+
+        let mut export_options = ExportOptions::new(format, scope);
+
+        let selected_row = self.table_state.selected().and_then(|selection| self.rows.get(selection));
+
+        export_options.keys = match selected_row {
+            Some(row) if self.pivot_label.is_none() => vec![row.key.clone()],
+            _ => self
+                .rows
+                .iter()
+                .flat_map(|row| if row.pivot_keys.is_empty() { vec![row.key.clone()] } else { row.pivot_keys.clone() })
+                .collect(),
+        };
+
+        Ok((path, export_options))
+    }
+
     pub fn select_next(&mut self) {
-        if self.table_state.selected() == None {
+        if self.table_state.selected().is_none() {
             self.table_state.select(Some(0));
         } else {
             let current = self.table_state.selected().unwrap();
 
-            if current < (self.rows.len() - 1) {
+            if current + 1 < self.rows.len() {
                 self.table_state.select(Some(current + 1));
             }
         }
     }
 
     pub fn select_prev(&mut self) {
-        if self.table_state.selected() == None {
+        if self.table_state.selected().is_none() {
             self.table_state.select(Some(0));
         } else {
             let current = self.table_state.selected().unwrap();
@@ -137,20 +384,141 @@ impl UiState {
     }
 
     pub fn update_from_backend(&mut self, metric_backend: &Backend) {
-        self.rows = metric_backend.map_metrics(|metric| {
-            let cells = [
-                metric.get_label().to_string(),
-                metric.get_value().to_string(),
-                metric.get_unit().to_string(),
-            ];
+        self.sources = metric_backend.get_sources();
+
+        self.connection_states = metric_backend.get_connection_states();
+
+        self.subscriptions = metric_backend.get_subscriptions();
+
+        self.replay_states = metric_backend
+            .get_replay_controls()
+            .into_iter()
+            .map(|(destination, replay_control)| (destination, replay_control.get_status()))
+            .collect();
+
+        self.events = metric_backend.get_recent_events(MAX_LOG_LINES);
 
-            MetricTableRowState { cells }
+        self.label_keys = metric_backend.get_label_keys();
+
+        self.user_rules = metric_backend.get_user_rules();
+
+        let mut metrics = metric_backend.map_metrics(|key, metric| (key.clone(), metric.clone()));
+
+        metrics.retain(|(key, _)| {
+            self.source_filter.as_ref().map(|s| s == key.get_source()).unwrap_or(true) && self.label_filter.matches(key.get_labels())
         });
 
-        if let Some(selection) = self.table_state.selected() {
+        // Sorting by key keeps the metrics of one source grouped together
+        metrics.sort_by(|a, b| a.0.cmp(&b.0));
+
+        if let Some(pivot_label) = &self.pivot_label {
+            let table = label_view::pivot(&metrics, pivot_label);
+
+            self.header = vec![String::from("Source"), String::from("Name")];
+            self.header.extend(table.columns.iter().cloned());
+
+            self.column_widths = vec![Constraint::Percentage(20), Constraint::Percentage(30)];
+            self.column_widths.extend(table.columns.iter().map(|_| Constraint::Min(12)));
+
+            self.rows = table
+                .rows
+                .into_iter()
+                .filter_map(|row| {
+                    let key = row.cells.iter().flatten().next()?.0.clone();
+
+                    let mut cells = vec![row.source, format!("{}{}", row.name, row.labels)];
+
+                    cells.extend(row.cells.iter().map(|cell| match cell {
+                        Some((_, metric)) => format!("{} {}", metric.get_value(), metric.get_unit()),
+                        None => String::from("-"),
+                    }));
+
+                    let pivot_keys = row.cells.iter().flatten().map(|(key, _)| key.clone()).collect();
+
+                    Some(MetricTableRowState { key, cells, value_column: None, pivot_keys, alert_state: None })
+                })
+                .collect();
+        } else if let Some(group_label) = &self.group_label {
+            // Metrics without the group label go last
+            metrics.sort_by(|a, b| {
+                let a_group = a.0.get_labels().get(group_label);
+                let b_group = b.0.get_labels().get(group_label);
+
+                (a_group.is_none(), a_group).cmp(&(b_group.is_none(), b_group)).then_with(|| a.0.cmp(&b.0))
+            });
+
+            self.header = [group_label.as_str(), "Source", "Name", "Value", "Unit"].iter().map(|h| h.to_string()).collect();
+
+            self.column_widths = vec![
+                Constraint::Percentage(15),
+                Constraint::Percentage(20),
+                Constraint::Percentage(35),
+                Constraint::Length(20),
+                Constraint::Min(10),
+            ];
+
+            self.rows = metrics
+                .iter()
+                .map(|(key, metric)| MetricTableRowState {
+                    key: key.clone(),
+                    cells: vec![
+                        key.get_labels().get(group_label).unwrap_or_default().to_string(),
+                        key.get_source().to_string(),
+                        metric.get_series_id(),
+                        metric.get_value().to_string(),
+                        metric.get_unit().to_string(),
+                    ],
+                    value_column: Some(3),
+                    pivot_keys: Vec::new(),
+                    alert_state: None,
+                })
+                .collect();
+        } else {
+            self.header = ["Source", "Name", "Value", "Unit"].iter().map(|h| h.to_string()).collect();
+
+            self.column_widths = vec![
+                Constraint::Percentage(20),
+                Constraint::Percentage(40),
+                Constraint::Length(30),
+                Constraint::Min(10),
+            ];
+
+            self.rows = metrics
+                .iter()
+                .map(|(key, metric)| MetricTableRowState {
+                    key: key.clone(),
+                    cells: vec![
+                        key.get_source().to_string(),
+                        metric.get_series_id(),
+                        metric.get_value().to_string(),
+                        metric.get_unit().to_string(),
+                    ],
+                    value_column: Some(2),
+                    pivot_keys: Vec::new(),
+                    alert_state: None,
+                })
+                .collect();
+        }
+
+        let alerts = metric_backend.get_alerts();
+
+        for row in &mut self.rows {
+            // Firing outranks pending if several alerts apply to a row
+            row.alert_state = alerts
+                .iter()
+                .filter(|alert| alert.state != AlertState::Resolved)
+                .filter(|alert| alert.key == row.key || row.pivot_keys.contains(&alert.key))
+                .map(|alert| alert.state)
+                .max();
+        }
+
+        // Pivot rows combine several series, there is no single history to show
+        if self.pivot_label.is_some() {
+            self.graph_active = false;
+        } else if let Some(selection) = self.table_state.selected() {
             if let Some(row_data) = self.rows.get(selection) {
                 if let Some(limits) = metric_backend
-                    .get_metric_history(&row_data.cells[0], &mut self.current_metric_history_data, 64)
+                    .get_metric_history(&row_data.key, &mut self.current_metric_history_data, HISTORY_RANGES[self.history_range_idx].1, self.plot_points)
                 {
                     self.current_metric_history_range = limits;
                     self.current_metric_history_time_range.0 = self.current_metric_history_data[0].0;
@@ -163,24 +531,127 @@ impl UiState {
 }
 
 impl TerminalFrontend {
+    // Transport controls apply to all replays, there is usually just one
+    fn control_replays(metric_backend: &Backend, f: impl Fn(&ReplayControl)) {
+        for (_, replay_control) in metric_backend.get_replay_controls() {
+            f(&replay_control);
+        }
+    }
+
     fn on_tick(ui_state: &mut UiState, metric_backend: &Backend) {
         ui_state.update_from_backend(metric_backend);
     }
 
+    fn connection_state_style(state: &ConnectionState) -> Style {
+        match state {
+            ConnectionState::Connected => Style::default().fg(Color::Green),
+            ConnectionState::Connecting | ConnectionState::Reconnecting { .. } => Style::default().fg(Color::Yellow),
+            ConnectionState::Stalled => Style::default().fg(Color::Magenta),
+            ConnectionState::Failed => Style::default().fg(Color::Red).add_modifier(Modifier::BOLD),
+        }
+    }
+
+    fn event_severity_style(severity: EventSeverity) -> Style {
+        match severity {
+            EventSeverity::Info => Style::default(),
+            EventSeverity::Warning => Style::default().fg(Color::Yellow),
+            EventSeverity::Error => Style::default().fg(Color::Red),
+        }
+    }
+
+    fn render_event_log<B: tui::backend::Backend>(f: &mut Frame<B>, area: tui::layout::Rect, ui_state: &UiState) {
+        // Only the newest events fit, the block border takes up two lines
+        let visible_lines = area.height.saturating_sub(2) as usize;
+
+        let skip = ui_state.events.len().saturating_sub(visible_lines);
+
+        let items: Vec<ListItem> = ui_state
+            .events
+            .iter()
+            .skip(skip)
+            .map(|e| ListItem::new(e.to_string()).style(Self::event_severity_style(e.get_severity())))
+            .collect();
+
+        let list = List::new(items).block(Block::default().borders(Borders::ALL).title("Event Log ('l' to toggle)"));
+
+        f.render_widget(list, area);
+    }
+
+    fn render_user_rules<B: tui::backend::Backend>(f: &mut Frame<B>, area: tui::layout::Rect, ui_state: &UiState) {
+        let items: Vec<ListItem> = ui_state
+            .user_rules
+            .iter()
+            .map(|(user_rule_id, user_rule)| ListItem::new(format!("#{:<3} {}", user_rule_id, user_rule)))
+            .collect();
+
+        let list = List::new(items)
+            .block(Block::default().borders(Borders::ALL).title("User Rules ('a' add, 'd' remove, 'u' to toggle)"));
+
+        f.render_widget(list, area);
+    }
+
     fn ui<B: tui::backend::Backend>(f: &mut Frame<B>, ui_state: &mut UiState) {
         let size = f.size();
 
+        let outer_chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Length(1), Constraint::Min(0)].as_ref())
+            .split(size);
+
         let chunks = Layout::default()
             .direction(Direction::Vertical)
             .constraints([Constraint::Percentage(50), Constraint::Percentage(50)].as_ref())
-            .split(size);
+            .split(outer_chunks[1]);
+
+        let mut status_spans = Vec::new();
+
+        if let Some((mode, input)) = &ui_state.input {
+            let (prompt, help) = match mode {
+                InputMode::LabelFilter => ("Label filter: ", "key=value,key!=value,key"),
+                InputMode::AddRule => ("Add rule: ", "<metric glob> <rate|avg[:depth]|ewma[:alpha]> [name] [@source glob]"),
+                InputMode::RemoveRule => ("Remove rule #", "rule id"),
+                InputMode::Export => ("Export to: ", "<file> [csv-wide|csv-long|jsonl] [current], selected metric or whole table"),
+                InputMode::Subscribe => ("Subscribe to: ", "<topic>[*] [endpoint], all ZMQ endpoints by default"),
+                InputMode::Unsubscribe => ("Unsubscribe from: ", "<topic>[*] [endpoint], all ZMQ endpoints by default"),
+            };
+
+            status_spans.push(Span::styled(prompt, Style::default().add_modifier(Modifier::BOLD)));
+            status_spans.push(Span::raw(format!("{}_   ", input)));
+
+            match &ui_state.input_error {
+                Some(msg) => status_spans.push(Span::styled(msg.clone(), Style::default().fg(Color::Red))),
+                None => status_spans.push(Span::raw(format!("({}; Enter to apply, Esc to cancel)", help))),
+            }
+        } else {
+            for (destination, state) in &ui_state.connection_states {
+                status_spans.push(Span::styled("\u{25cf} ", Self::connection_state_style(state)));
+
+                match ui_state.replay_states.iter().find(|(replay_destination, _)| replay_destination == destination) {
+                    Some((_, replay_status)) => status_spans.push(Span::raw(format!(
+                        "{}: {} (space pause, +/- speed, [/] seek, o loop)   ",
+                        destination, replay_status
+                    ))),
+                    None => match ui_state.subscriptions.iter().find(|(subscribed_destination, _)| subscribed_destination == destination) {
+                        Some((_, topics)) => {
+                            let topics: Vec<String> = topics.iter().map(|t| t.to_string()).collect();
+
+                            status_spans.push(Span::raw(format!("{} [{}]: {}   ", destination, topics.join(", "), state)))
+                        }
+                        None => status_spans.push(Span::raw(format!("{}: {}   ", destination, state))),
+                    },
+                }
+            }
+        }
+
+        f.render_widget(Paragraph::new(Spans::from(status_spans)), outer_chunks[0]);
 
         let selected_style = Style::default().add_modifier(Modifier::REVERSED);
         let normal_style = Style::default().bg(Color::Blue);
 
-        let header_cells = ["Name", "Value", "Unit"]
+        let header_cells = ui_state
+            .header
             .iter()
-            .map(|h| Cell::from(*h).style(Style::default().fg(Color::Red)));
+            .map(|h| Cell::from(h.as_str()).style(Style::default().fg(Color::Red)));
 
         let header = Row::new(header_cells)
             .style(normal_style)
@@ -189,18 +660,53 @@ impl TerminalFrontend {
 
         let rows: Vec<Row> = ui_state.rows.iter().map(|e| e.into()).collect();
 
+        let mut table_title = match &ui_state.source_filter {
+            Some(source) => format!("Metrics (source: {}", source),
+            None => String::from("Metrics (all sources"),
+        };
+
+        if !ui_state.label_filter.is_empty() {
+            table_title.push_str(&format!(", filter: {}", ui_state.label_filter));
+        }
+
+        if let Some(pivot_label) = &ui_state.pivot_label {
+            table_title.push_str(&format!(", pivot: {}", pivot_label));
+        } else if let Some(group_label) = &ui_state.group_label {
+            table_title.push_str(&format!(", group: {}", group_label));
+        }
+
+        table_title.push_str(") 's' source, '/' filter, 'g' group, 'p' pivot, 'e' export");
+
         let t = Table::new(rows)
             .header(header)
-            .block(Block::default().borders(Borders::ALL).title("Metrics"))
+            .block(Block::default().borders(Borders::ALL).title(table_title))
             .highlight_style(selected_style)
-            .widths(&[
-                Constraint::Percentage(50),
-                Constraint::Length(30),
-                Constraint::Min(10),
-            ]);
+            .widths(&ui_state.column_widths);
 
         f.render_stateful_widget(t, chunks[0], &mut ui_state.table_state);
 
+        let mut graph_area = chunks[1];
+
+        if ui_state.log_active || ui_state.rules_active {
+            let mut side_area = chunks[1];
+
+            if ui_state.graph_active {
+                let bottom_chunks = Layout::default()
+                    .direction(Direction::Horizontal)
+                    .constraints([Constraint::Percentage(60), Constraint::Percentage(40)].as_ref())
+                    .split(chunks[1]);
+
+                graph_area = bottom_chunks[0];
+                side_area = bottom_chunks[1];
+            }
+
+            if ui_state.rules_active {
+                Self::render_user_rules(f, side_area, ui_state);
+            } else {
+                Self::render_event_log(f, side_area, ui_state);
+            }
+        }
+
         if ui_state.graph_active {
             let dataset = Dataset::default()
                 .name("History")//.marker(symbols::Marker::Dot)
@@ -231,7 +737,7 @@ impl TerminalFrontend {
                 .block(
                     Block::default()
                         .title(Span::styled(
-                            "History Data",
+                            format!("History Data (last {}, 't' to change)", HISTORY_RANGES[ui_state.history_range_idx].0),
                             Style::default().fg(Color::Red).add_modifier(Modifier::BOLD),
                         ))
                         .borders(Borders::ALL),
@@ -263,11 +769,11 @@ impl TerminalFrontend {
                         ]),
                 );
 
-            f.render_widget(chart, chunks[1]);
+            f.render_widget(chart, graph_area);
         }
     }
 
-    pub fn create(metric_backend: Backend) -> Result<TerminalFrontend, FrontendError> {
+    pub fn create(metric_backend: Backend, config: TuiConfig) -> Result<TerminalFrontend, FrontendError> {
         let mut stdout = io::stdout();
 
         enable_raw_mode()?;
@@ -281,6 +787,7 @@ impl TerminalFrontend {
         Ok(TerminalFrontend {
             terminal,
             backend: metric_backend,
+            config,
         })
     }
 }
@@ -300,11 +807,11 @@ impl Drop for TerminalFrontend {
 
 impl MetricFrontend for TerminalFrontend {
     fn run(mut self) -> Result<(), Box<dyn std::error::Error>> {
-        let tick_rate = Duration::from_millis(250);
+        let tick_rate = self.config.tick;
 
         let mut last_tick = Instant::now();
 
-        let mut ui_state = UiState::new();
+        let mut ui_state = UiState::new(self.config.plot_points);
 
         loop {
             {
@@ -321,6 +828,20 @@ impl MetricFrontend for TerminalFrontend {
 
             if crossterm::event::poll(timeout).unwrap_or(false) {
                 if let Event::Key(key) = event::read().unwrap() {
+                    if let Some((_, input)) = &mut ui_state.input {
+                        match key.code {
+                            KeyCode::Enter => ui_state.apply_input(&self.backend),
+                            KeyCode::Esc => ui_state.input = None,
+                            KeyCode::Backspace => {
+                                input.pop();
+                            }
+                            KeyCode::Char(c) => input.push(c),
+                            _ => {}
+                        }
+
+                        continue;
+                    }
+
                     match key.code {
                         KeyCode::Char('q') | KeyCode::Esc => break,
                         KeyCode::Down => {
@@ -332,6 +853,60 @@ impl MetricFrontend for TerminalFrontend {
                         KeyCode::Left => {
                             ui_state.select_none();
                         }
+                        KeyCode::Char('s') => {
+                            ui_state.cycle_source_filter();
+                        }
+                        KeyCode::Char('l') => {
+                            ui_state.log_active = !ui_state.log_active;
+                        }
+                        KeyCode::Char('/') => {
+                            ui_state.begin_input(InputMode::LabelFilter);
+                        }
+                        KeyCode::Char('g') => {
+                            ui_state.cycle_group_label();
+                        }
+                        KeyCode::Char('p') => {
+                            ui_state.cycle_pivot_label();
+                        }
+                        KeyCode::Char('t') => {
+                            ui_state.history_range_idx = (ui_state.history_range_idx + 1) % HISTORY_RANGES.len();
+                        }
+                        KeyCode::Char('u') => {
+                            ui_state.rules_active = !ui_state.rules_active;
+                        }
+                        KeyCode::Char('a') => {
+                            ui_state.begin_input(InputMode::AddRule);
+                        }
+                        KeyCode::Char('d') => {
+                            ui_state.begin_input(InputMode::RemoveRule);
+                        }
+                        KeyCode::Char('e') => {
+                            ui_state.begin_input(InputMode::Export);
+                        }
+                        KeyCode::Char('b') => {
+                            ui_state.begin_input(InputMode::Subscribe);
+                        }
+                        KeyCode::Char('B') => {
+                            ui_state.begin_input(InputMode::Unsubscribe);
+                        }
+                        KeyCode::Char(' ') => {
+                            Self::control_replays(&self.backend, |c| c.toggle_paused());
+                        }
+                        KeyCode::Char('+') => {
+                            Self::control_replays(&self.backend, |c| c.set_speed(c.get_status().speed.faster()));
+                        }
+                        KeyCode::Char('-') => {
+                            Self::control_replays(&self.backend, |c| c.set_speed(c.get_status().speed.slower()));
+                        }
+                        KeyCode::Char('[') => {
+                            Self::control_replays(&self.backend, |c| c.seek_relative(-REPLAY_SEEK_STEP_S));
+                        }
+                        KeyCode::Char(']') => {
+                            Self::control_replays(&self.backend, |c| c.seek_relative(REPLAY_SEEK_STEP_S));
+                        }
+                        KeyCode::Char('o') => {
+                            Self::control_replays(&self.backend, |c| c.set_looping(!c.get_status().looping));
+                        }
                         _ => {}
                     }
                 }
@@ -343,8 +918,6 @@ impl MetricFrontend for TerminalFrontend {
             }
         }
 
-        self.backend.disconnect();
-
         Ok(())
     }
 }